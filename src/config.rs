@@ -0,0 +1,156 @@
+/*
+Configuration for opening a graph.
+
+A GraphConfig is built once and handed to GraphAgdb, GraphFiles or
+GraphCommands. New options are added here instead of to the constructors. The
+config can be saved next to a vault and loaded again, so that the vault is
+reopened the same way.
+*/
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::GraphError;
+
+/// Where the db of a graph is stored.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum StoragePath {
+    /// The data directory of the operating system, as determined by the directories crate.
+    Default,
+    Custom(PathBuf),
+}
+
+impl StoragePath {
+    pub fn new(path: PathBuf) -> Self {
+        Self::Custom(path)
+    }
+
+    pub fn strg_path(&self) -> Option<PathBuf> {
+        match self {
+            Self::Default => None,
+            Self::Custom(path) => Some(path.clone()),
+        }
+    }
+}
+
+/// Which agdb implementation stores the graph.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DbBackend {
+    /// Kept only in memory, for tests and previews that shouldn't be saved.
+    /// Nothing is written to the storage path except readable files.
+    Memory,
+    /// Stored in a file that is mapped to memory.
+    #[default]
+    MemoryMapped,
+    /// Stored in a file without keeping it in memory, for large vaults on
+    /// machines with little memory. Slower than MemoryMapped.
+    File,
+}
+
+/// Everything needed to open a graph.
+///
+/// ```ignore
+/// let config = GraphConfig::new("my_app", PathBuf::from("/home/me/vault"))
+///     .with_storage(PathBuf::from("/home/me/vault/.graph"))
+///     .with_readable_files(true);
+/// let graph = GraphAgdb::open(config)?;
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GraphConfig {
+    /// The name of the graph, usually the name of the application using it.
+    /// The db file is named after it.
+    pub name: String,
+    /// The directory the graph mirrors. Its name becomes the user_root of the graph.
+    pub root_path: PathBuf,
+    /// Where the db is stored.
+    pub storage_path: StoragePath,
+    /// Whether to maintain readable files. If None, the setting stored in the
+    /// graph is used.
+    #[serde(default)]
+    pub maintain_readable_files: Option<bool>,
+    /// How the db is stored.
+    #[serde(default)]
+    pub backend: DbBackend,
+}
+
+impl GraphConfig {
+    /// A config that stores the db in the default data directory of the
+    /// operating system.
+    pub fn new(name: &str, root_path: PathBuf) -> Self {
+        GraphConfig {
+            name: name.to_string(),
+            root_path,
+            storage_path: StoragePath::Default,
+            maintain_readable_files: None,
+            backend: DbBackend::default(),
+        }
+    }
+
+    /// Stores the db in the given directory instead of the default one.
+    pub fn with_storage(mut self, path: PathBuf) -> Self {
+        self.storage_path = StoragePath::Custom(path);
+        self
+    }
+
+    /// Stores the db in the default data directory of the operating system.
+    pub fn with_default_storage(mut self) -> Self {
+        self.storage_path = StoragePath::Default;
+        self
+    }
+
+    /// Turns the readable files on or off when the graph is opened, overriding
+    /// the stored setting.
+    pub fn with_readable_files(mut self, maintain: bool) -> Self {
+        self.maintain_readable_files = Some(maintain);
+        self
+    }
+
+    pub fn with_backend(mut self, backend: DbBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Writes the config as RON.
+    pub fn save(&self, path: &Path) -> Result<(), GraphError> {
+        let ron = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, ron)?;
+        Ok(())
+    }
+
+    /// Reads a config written by save.
+    pub fn load(path: &Path) -> Result<Self, GraphError> {
+        let ron = std::fs::read_to_string(path)?;
+        Ok(ron::from_str(&ron)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{prelude::*, utils::utils::TestContext};
+
+    #[test]
+    fn config_is_built_and_restored() {
+        let func_name = "config_is_built_and_restored";
+        let ctx = TestContext::new(func_name);
+        let root = ctx.graph.user_root_dirpath();
+
+        let config = GraphConfig::new("configured", root.clone())
+            .with_storage(root.join("storage"))
+            .with_readable_files(true);
+        assert_eq!(config.storage_path, StoragePath::Custom(root.join("storage")));
+
+        let saved = root.join("graph.ron");
+        config.save(&saved).unwrap();
+        let loaded = GraphConfig::load(&saved).unwrap();
+        assert_eq!(loaded, config);
+
+        let mut graph = GraphAgdb::open(loaded.clone()).unwrap();
+        assert_eq!(graph.storage_path(), loaded.storage_path);
+        assert_eq!(graph.get_setting("maintain_readable_files").unwrap(), Some(AttributeValue::Bool(true)));
+        drop(graph);
+
+        let commands = GraphCommands::new(loaded.with_readable_files(false));
+        assert_eq!(commands.get_setting("maintain_readable_files").unwrap(), Some(AttributeValue::Bool(false)));
+    }
+}
@@ -1,16 +1,364 @@
-use agdb::DbKeyValue;
+use agdb::{DbError, DbF64, DbKeyValue, DbValue};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq)]
+use crate::error::GraphError;
+
+use super::{node::Node, node_path::NodePath, nodetype::NodeType};
+
+/// The value of an attribute. 
+/// 
+/// Values that agdb can represent natively (strings, ints, floats and float
+/// vectors of length 2 to 4) are stored as such, so that they can be used in
+/// query conditions. Bools, colors, raw bytes, lists and node path references
+/// have no unambiguous native representation, so they are stored as tagged
+/// bytes. agdb would store a bool as an unsigned int, which can't be told apart
+/// from a number written by another tool.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Float(f32),
+    Bool(bool),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    /// RGBA color, 4 f32s.
+    Color([f32; 4]),
+    Bytes(Vec<u8>),
+    List(Vec<AttributeValue>),
+    /// A reference to another node in the graph.
+    NodePath(NodePath),
+}
+
+// Tags for the byte encoding of values. The byte encoding is used directly for 
+// the variants that don't map to a native DbValue, and for the elements of lists. 
+// Stored values start with the marker, so that bytes written by other tools
+// aren't mistaken for tagged bytes. The last byte of the marker is the version
+// of the encoding.
+const BYTES_MARKER: [u8; 4] = [0xF5, b'f', b's', 1];
+const TAG_BYTES: u8 = 0;
+const TAG_COLOR: u8 = 1;
+const TAG_NODEPATH: u8 = 2;
+const TAG_LIST: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_INT: u8 = 5;
+const TAG_FLOAT: u8 = 6;
+const TAG_BOOL: u8 = 7;
+const TAG_VEC2: u8 = 8;
+const TAG_VEC3: u8 = 9;
+const TAG_VEC4: u8 = 10;
+
+impl AttributeValue {
+    /// Name of the type of the value. Mostly useful for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            AttributeValue::String(_) => "String",
+            AttributeValue::Int(_) => "Int",
+            AttributeValue::Float(_) => "Float",
+            AttributeValue::Bool(_) => "Bool",
+            AttributeValue::Vec2(_) => "Vec2",
+            AttributeValue::Vec3(_) => "Vec3",
+            AttributeValue::Vec4(_) => "Vec4",
+            AttributeValue::Color(_) => "Color",
+            AttributeValue::Bytes(_) => "Bytes",
+            AttributeValue::List(_) => "List",
+            AttributeValue::NodePath(_) => "NodePath",
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            AttributeValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            AttributeValue::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Gets the value as a float. Ints are converted.
+    pub fn as_float(&self) -> Option<f32> {
+        match self {
+            AttributeValue::Float(f) => Some(*f),
+            AttributeValue::Int(i) => Some(*i as f32),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            AttributeValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_nodepath(&self) -> Option<&NodePath> {
+        match self {
+            AttributeValue::NodePath(p) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// Encode the value into tagged bytes.
+    fn to_tagged_bytes(&self) -> Vec<u8> {
+        fn floats(tag: u8, fs: &[f32]) -> Vec<u8> {
+            let mut bytes = vec![tag];
+            fs.iter().for_each(|f| bytes.extend_from_slice(&f.to_le_bytes()));
+            bytes
+        }
+
+        match self {
+            AttributeValue::Bytes(b) => {
+                let mut bytes = vec![TAG_BYTES];
+                bytes.extend_from_slice(b);
+                bytes
+            }
+            AttributeValue::Color(c) => floats(TAG_COLOR, c),
+            AttributeValue::NodePath(p) => {
+                let mut bytes = vec![TAG_NODEPATH];
+                bytes.extend_from_slice(p.alias().as_bytes());
+                bytes
+            }
+            AttributeValue::List(list) => {
+                let mut bytes = vec![TAG_LIST];
+                bytes.extend_from_slice(&(list.len() as u64).to_le_bytes());
+                list.iter().for_each(|item| {
+                    let item = item.to_tagged_bytes();
+                    bytes.extend_from_slice(&(item.len() as u64).to_le_bytes());
+                    bytes.extend(item);
+                });
+                bytes
+            }
+            AttributeValue::String(s) => {
+                let mut bytes = vec![TAG_STRING];
+                bytes.extend_from_slice(s.as_bytes());
+                bytes
+            }
+            AttributeValue::Int(i) => {
+                let mut bytes = vec![TAG_INT];
+                bytes.extend_from_slice(&i.to_le_bytes());
+                bytes
+            }
+            AttributeValue::Float(f) => floats(TAG_FLOAT, &[*f]),
+            AttributeValue::Bool(b) => vec![TAG_BOOL, *b as u8],
+            AttributeValue::Vec2(v) => floats(TAG_VEC2, v),
+            AttributeValue::Vec3(v) => floats(TAG_VEC3, v),
+            AttributeValue::Vec4(v) => floats(TAG_VEC4, v),
+        }
+    }
+
+    /// Decode a value from tagged bytes. Returns None if the bytes are malformed.
+    fn from_tagged_bytes(bytes: &[u8]) -> Option<Self> {
+        fn floats<const N: usize>(payload: &[u8]) -> Option<[f32; N]> {
+            if payload.len() != N * 4 {
+                return None;
+            }
+            let mut out = [0.0; N];
+            for (i, chunk) in payload.chunks_exact(4).enumerate() {
+                out[i] = f32::from_le_bytes(chunk.try_into().ok()?);
+            }
+            Some(out)
+        }
+
+        fn read_u64(bytes: &[u8], at: usize) -> Option<u64> {
+            let slice = bytes.get(at..at + 8)?;
+            Some(u64::from_le_bytes(slice.try_into().ok()?))
+        }
+
+        let (tag, payload) = bytes.split_first()?;
+
+        match *tag {
+            TAG_BYTES => Some(AttributeValue::Bytes(payload.to_vec())),
+            TAG_COLOR => Some(AttributeValue::Color(floats::<4>(payload)?)),
+            TAG_NODEPATH => {
+                let alias = std::str::from_utf8(payload).ok()?;
+                Some(AttributeValue::NodePath(NodePath::from_alias(alias)))
+            }
+            TAG_LIST => {
+                let count = read_u64(payload, 0)? as usize;
+                let mut at = 8;
+                let mut list = Vec::new();
+                for _ in 0..count {
+                    let len = read_u64(payload, at)? as usize;
+                    at += 8;
+                    let item = payload.get(at..at + len)?;
+                    list.push(AttributeValue::from_tagged_bytes(item)?);
+                    at += len;
+                }
+                if at != payload.len() {
+                    return None;
+                }
+                Some(AttributeValue::List(list))
+            }
+            TAG_STRING => Some(AttributeValue::String(
+                String::from_utf8(payload.to_vec()).ok()?,
+            )),
+            TAG_INT => Some(AttributeValue::Int(i64::from_le_bytes(
+                payload.try_into().ok()?,
+            ))),
+            TAG_FLOAT => Some(AttributeValue::Float(floats::<1>(payload)?[0])),
+            TAG_BOOL => match payload {
+                [b] => Some(AttributeValue::Bool(*b != 0)),
+                _ => None,
+            },
+            TAG_VEC2 => Some(AttributeValue::Vec2(floats::<2>(payload)?)),
+            TAG_VEC3 => Some(AttributeValue::Vec3(floats::<3>(payload)?)),
+            TAG_VEC4 => Some(AttributeValue::Vec4(floats::<4>(payload)?)),
+            _ => None,
+        }
+    }
+}
+
+impl From<AttributeValue> for DbValue {
+    fn from(value: AttributeValue) -> Self {
+        match value {
+            AttributeValue::String(s) => DbValue::String(s),
+            AttributeValue::Int(i) => DbValue::I64(i),
+            AttributeValue::Float(f) => DbValue::F64(f.into()),
+            AttributeValue::Vec2(v) => DbValue::VecF64(v.iter().map(|f| DbF64::from(*f)).collect()),
+            AttributeValue::Vec3(v) => DbValue::VecF64(v.iter().map(|f| DbF64::from(*f)).collect()),
+            AttributeValue::Vec4(v) => DbValue::VecF64(v.iter().map(|f| DbF64::from(*f)).collect()),
+            other => {
+                let mut bytes = BYTES_MARKER.to_vec();
+                bytes.extend(other.to_tagged_bytes());
+                DbValue::Bytes(bytes)
+            }
+        }
+    }
+}
+
+/// Converting from a DbValue never fails. Values that were not written by this crate
+/// are mapped to the closest variant: unsigned ints become Int, or Float if they
+/// don't fit. Bytes without the marker, or that can't be decoded, are returned
+/// as raw Bytes.
+impl From<DbValue> for AttributeValue {
+    fn from(value: DbValue) -> Self {
+        match value {
+            DbValue::String(s) => AttributeValue::String(s),
+            DbValue::I64(i) => AttributeValue::Int(i),
+            DbValue::U64(u) => uint(u),
+            DbValue::F64(f) => AttributeValue::Float(f.to_f64() as f32),
+            DbValue::VecF64(v) => {
+                let fs: Vec<f32> = v.iter().map(|f| f.to_f64() as f32).collect();
+                match fs.len() {
+                    2 => AttributeValue::Vec2([fs[0], fs[1]]),
+                    3 => AttributeValue::Vec3([fs[0], fs[1], fs[2]]),
+                    4 => AttributeValue::Vec4([fs[0], fs[1], fs[2], fs[3]]),
+                    _ => AttributeValue::List(fs.into_iter().map(AttributeValue::Float).collect()),
+                }
+            }
+            DbValue::VecI64(v) => AttributeValue::List(v.into_iter().map(AttributeValue::Int).collect()),
+            DbValue::VecU64(v) => AttributeValue::List(v.into_iter().map(uint).collect()),
+            DbValue::VecString(v) => AttributeValue::List(v.into_iter().map(AttributeValue::String).collect()),
+            DbValue::Bytes(b) => match b.strip_prefix(&BYTES_MARKER).and_then(AttributeValue::from_tagged_bytes) {
+                Some(value) => value,
+                None => AttributeValue::Bytes(b),
+            },
+        }
+    }
+}
+
+fn uint(u: u64) -> AttributeValue {
+    match i64::try_from(u) {
+        Ok(i) => AttributeValue::Int(i),
+        Err(_) => AttributeValue::Float(u as f32),
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> Self {
+        AttributeValue::Int(value)
+    }
+}
+
+impl From<i32> for AttributeValue {
+    fn from(value: i32) -> Self {
+        AttributeValue::Int(value.into())
+    }
+}
+
+impl From<f32> for AttributeValue {
+    fn from(value: f32) -> Self {
+        AttributeValue::Float(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+}
+
+impl From<[f32; 2]> for AttributeValue {
+    fn from(value: [f32; 2]) -> Self {
+        AttributeValue::Vec2(value)
+    }
+}
+
+impl From<[f32; 3]> for AttributeValue {
+    fn from(value: [f32; 3]) -> Self {
+        AttributeValue::Vec3(value)
+    }
+}
+
+impl From<[f32; 4]> for AttributeValue {
+    fn from(value: [f32; 4]) -> Self {
+        AttributeValue::Vec4(value)
+    }
+}
+
+impl From<Vec<u8>> for AttributeValue {
+    fn from(value: Vec<u8>) -> Self {
+        AttributeValue::Bytes(value)
+    }
+}
+
+impl From<Vec<AttributeValue>> for AttributeValue {
+    fn from(value: Vec<AttributeValue>) -> Self {
+        AttributeValue::List(value)
+    }
+}
+
+impl From<NodePath> for AttributeValue {
+    fn from(value: NodePath) -> Self {
+        AttributeValue::NodePath(value)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Attribute {
     pub name: String,
-    pub value: f32,
+    pub value: AttributeValue,
 }
 
 impl Attribute {
+    pub fn new(name: &str, value: impl Into<AttributeValue>) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.into(),
+        }
+    }
+
     pub fn new_contains() -> Self {
         Self {
             name: "contains".to_string(),
-            value: 0.0,
+            value: AttributeValue::Float(0.0),
         }
     }
 }
@@ -31,7 +379,7 @@ impl Into<DbKeyValue> for Attribute {
 
 impl Into<DbKeyValue> for &Attribute {
     fn into(self) -> DbKeyValue {
-        DbKeyValue::from((self.name.clone(), self.value))
+        DbKeyValue::from((self.name.clone(), self.value.clone()))
     }
 }
 
@@ -39,13 +387,254 @@ impl Into<Attribute> for DbKeyValue {
     fn into(self) -> Attribute {
         Attribute {
             name: self.key.to_string(),
-            value: self.value.to_f64().unwrap().to_f64() as f32,
+            value: self.value.into(),
+        }
+    }
+}
+
+/// Names of the types an AttributeValue can have, as given by type_name.
+pub(crate) const ATTRIBUTE_TYPES: [&str; 11] = [
+    "String", "Int", "Float", "Bool", "Vec2", "Vec3", "Vec4", "Color", "Bytes", "List", "NodePath",
+];
+
+/// Type of the nodes that hold the attribute definitions.
+pub(crate) const ATTRIBUTEDEF_TYPE: &str = "AttributeDef";
+
+/// A user-defined attribute. Stored as a child node of the attributes archetype,
+/// so that every tool writing to the same vault agrees on what the attribute means.
+/// Attributes with a definition are validated against it whenever they are inserted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AttributeDef {
+    name: String,
+    value_type: String,
+    default: Option<AttributeValue>,
+    /// Inclusive bounds for Int and Float values.
+    min: Option<f32>,
+    max: Option<f32>,
+    /// If not empty, the value must be one of these.
+    allowed: Vec<AttributeValue>,
+    /// If not empty, the attribute can only be set on nodes of these types.
+    /// Edges have no types, so this doesn't limit edge attributes.
+    applies_to: Vec<NodeType>,
+}
+
+impl AttributeDef {
+    /// The value type is the name of an AttributeValue variant, such as "Int".
+    pub fn new(name: &str, value_type: &str) -> Self {
+        AttributeDef {
+            name: name.to_string(),
+            value_type: value_type.to_string(),
+            default: None,
+            min: None,
+            max: None,
+            allowed: Vec::new(),
+            applies_to: Vec::new(),
+        }
+    }
+
+    pub fn with_default(mut self, default: impl Into<AttributeValue>) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    /// Limits Int and Float values to the inclusive range.
+    pub fn with_range(mut self, min: Option<f32>, max: Option<f32>) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Limits the value to one of the given ones, like an enum.
+    pub fn with_allowed(mut self, allowed: Vec<AttributeValue>) -> Self {
+        self.allowed = allowed;
+        self
+    }
+
+    /// Limits the attribute to nodes of the given types.
+    pub fn with_ntypes(mut self, ntypes: Vec<NodeType>) -> Self {
+        self.applies_to = ntypes;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value_type(&self) -> &str {
+        &self.value_type
+    }
+
+    pub fn default(&self) -> Option<&AttributeValue> {
+        self.default.as_ref()
+    }
+
+    pub fn range(&self) -> (Option<f32>, Option<f32>) {
+        (self.min, self.max)
+    }
+
+    pub fn allowed(&self) -> &Vec<AttributeValue> {
+        &self.allowed
+    }
+
+    pub fn ntypes(&self) -> &Vec<NodeType> {
+        &self.applies_to
+    }
+
+    /// Checks a value against the definition. The error says what was expected.
+    pub fn check_value(&self, value: &AttributeValue) -> Result<(), GraphError> {
+        if value.type_name() != self.value_type {
+            return Err(GraphError::SchemaViolation(format!(
+                "Invalid value for {}: expected {}, got {}",
+                self.name, self.value_type, value.type_name()
+            )));
+        }
+
+        if let Some(number) = value.as_float() {
+            let below = self.min.map_or(false, |min| number < min);
+            let above = self.max.map_or(false, |max| number > max);
+            if below || above {
+                let bound = |b: Option<f32>| b.map_or("..".to_string(), |b| b.to_string());
+                return Err(GraphError::SchemaViolation(format!(
+                    "Invalid value for {}: {} is outside of the range {} to {}",
+                    self.name, number, bound(self.min), bound(self.max)
+                )));
+            }
+        }
+
+        if !self.allowed.is_empty() && !self.allowed.contains(value) {
+            return Err(GraphError::SchemaViolation(format!(
+                "Invalid value for {}: {:?} is not one of {:?}",
+                self.name, value, self.allowed
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the attribute can be set on a node of the given type.
+    pub fn check_ntype(&self, ntype: &NodeType) -> Result<(), GraphError> {
+        if !self.applies_to.is_empty() && !self.applies_to.contains(ntype) {
+            return Err(GraphError::SchemaViolation(format!(
+                "Attribute {} does not apply to nodes of type {}",
+                self.name, ntype.name()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the definition itself makes sense.
+    pub(crate) fn check(&self) -> Result<(), GraphError> {
+        if !ATTRIBUTE_TYPES.contains(&self.value_type.as_str()) {
+            return Err(GraphError::SchemaViolation(format!("Unknown attribute type: {}", self.value_type)));
+        }
+        let numeric = self.value_type == "Int" || self.value_type == "Float";
+        if !numeric && (self.min.is_some() || self.max.is_some()) {
+            return Err(GraphError::SchemaViolation(format!("Only Int and Float attributes can have a range, {} is {}", self.name, self.value_type)));
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(GraphError::SchemaViolation(format!("Invalid range for {}: {} is greater than {}", self.name, min, max)));
+            }
+        }
+        for value in &self.allowed {
+            if value.type_name() != self.value_type {
+                return Err(GraphError::SchemaViolation(format!(
+                    "Allowed values of {} must be of type {}, got {}",
+                    self.name, self.value_type, value.type_name()
+                )));
+            }
+        }
+        if let Some(default) = &self.default {
+            self.check_value(default)
+                .map_err(|err| GraphError::SchemaViolation(format!("Invalid default: {}", err)))?;
+        }
+        Ok(())
+    }
+
+    /// Path of the node that stores the definition.
+    pub(crate) fn path(name: &str) -> NodePath {
+        NodePath::atype("attributes").join(name)
+    }
+
+    pub(crate) fn to_node(&self) -> Node {
+        let mut node = Node::new(&AttributeDef::path(&self.name), NodeType::new(ATTRIBUTEDEF_TYPE.to_string()));
+
+        let mut attrs = vec![Attribute::new("value_type", self.value_type.clone())];
+        if let Some(default) = &self.default {
+            attrs.push(Attribute::new("default", default.clone()));
+        }
+        if let Some(min) = self.min {
+            attrs.push(Attribute::new("min", min));
+        }
+        if let Some(max) = self.max {
+            attrs.push(Attribute::new("max", max));
         }
+        if !self.allowed.is_empty() {
+            attrs.push(Attribute::new("allowed", self.allowed.clone()));
+        }
+        if !self.applies_to.is_empty() {
+            let ntypes: Vec<AttributeValue> = self
+                .applies_to
+                .iter()
+                .map(|ntype| ntype.name().into())
+                .collect();
+            attrs.push(Attribute::new("applies_to", ntypes));
+        }
+
+        node.insert_attributes(attrs);
+        node
     }
 }
 
+impl TryFrom<Node> for AttributeDef {
+    type Error = DbError;
+
+    fn try_from(node: Node) -> Result<Self, Self::Error> {
+        if node.ntype_name().name() != ATTRIBUTEDEF_TYPE {
+            return Err(DbError::from(format!("{} is not an attribute definition", node.path().alias())));
+        }
+
+        let attrs = node.attributes();
+        let value = |key: &str| attrs.iter().find(|attr| attr.name == key).map(|attr| attr.value.clone());
+        let invalid = |key: &str| DbError::from(format!("Invalid {} in attribute definition {}", key, node.name()));
+
+        let value_type = match value("value_type") {
+            Some(AttributeValue::String(value_type)) => value_type,
+            _ => return Err(invalid("value_type")),
+        };
+        let bound = |key: &str| match value(key) {
+            None => Ok(None),
+            Some(v) => v.as_float().map(Some).ok_or_else(|| invalid(key)),
+        };
+        let list = |key: &str| match value(key) {
+            None => Ok(Vec::new()),
+            Some(AttributeValue::List(values)) => Ok(values),
+            Some(_) => Err(invalid(key)),
+        };
+
+        let applies_to = list("applies_to")?
+            .into_iter()
+            .map(|v| v.as_str().map(|name| NodeType::new(name.to_string())).ok_or_else(|| invalid("applies_to")))
+            .collect::<Result<Vec<NodeType>, DbError>>()?;
+
+        Ok(AttributeDef {
+            name: node.name(),
+            value_type,
+            default: value("default"),
+            min: bound("min")?,
+            max: bound("max")?,
+            allowed: list("allowed")?,
+            applies_to,
+        })
+    }
+}
+
+/// Keys of the file fingerprint in the node values, used by the indexer to
+/// detect moves. They are reserved, and not treated as attributes.
+pub(crate) const FINGERPRINT_KEYS: [&str; 3] = ["fs_size", "fs_mtime", "fs_hash"];
+
 /// A list of reserved node attribute names that cannot be set by the user directly.
-pub const RESERVED_NODE_ATTRS: [&str; 12] = [
+pub const RESERVED_NODE_ATTRS: [&str; 15] = [
     "path", // The full path of the node, name included. Implemented as an alias, but still reserved.
     "name", // The name of the node, without the path. Maybe allows for different characters?
 
@@ -62,6 +651,10 @@ pub const RESERVED_NODE_ATTRS: [&str; 12] = [
     "color", // The absolute color of the node. Vec of 4 f32s
     "pins", // The absolute state pins of the node. 
 
+    "fs_size", // Size of the file, for detecting moves made outside of the app.
+    "fs_mtime", // Modification time of the file, for detecting moves.
+    "fs_hash", // Optional hash of the file contents, for detecting moves.
+
     // Reserved names with an underscore at the end are prefixes. 
     // All attributes with a name that starts with one of these prefixes are reserved.
     "param_", // Reserved prefix for any attribute that is a parameter, for operators for example. 
@@ -114,3 +707,67 @@ pub const RESERVED_EDGE_ATTRS: [&str; 22] = [
     // that there is a preferred order to them. Use cases are for compiling 
     // "sequence"
 ];
+
+#[cfg(test)]
+mod tests {
+    use agdb::DbValue;
+
+    use super::*;
+
+    fn round_trip(value: AttributeValue) {
+        let db_value: DbValue = value.clone().into();
+        let back: AttributeValue = db_value.into();
+        assert_eq!(back, value, "Value should survive the round trip through DbValue");
+    }
+
+    #[test]
+    fn attribute_values_round_trip_through_db_values() {
+        round_trip(AttributeValue::String("hello".to_string()));
+        round_trip(AttributeValue::Int(-42));
+        round_trip(AttributeValue::Float(1.5));
+        round_trip(AttributeValue::Bool(true));
+        round_trip(AttributeValue::Bool(false));
+        round_trip(AttributeValue::Vec2([1.0, 2.0]));
+        round_trip(AttributeValue::Vec3([1.0, 2.0, 3.0]));
+        round_trip(AttributeValue::Vec4([1.0, 2.0, 3.0, 4.0]));
+        round_trip(AttributeValue::Color([0.1, 0.2, 0.3, 1.0]));
+        round_trip(AttributeValue::Bytes(vec![0, 1, 2, 255]));
+        round_trip(AttributeValue::NodePath(NodePath::from("some/file.txt")));
+        round_trip(AttributeValue::List(vec![
+            AttributeValue::Int(1),
+            AttributeValue::String("two".to_string()),
+            AttributeValue::List(vec![AttributeValue::Color([1.0, 0.0, 0.0, 1.0])]),
+        ]));
+    }
+
+    #[test]
+    fn unsigned_ints_are_read_as_numbers() {
+        let value: AttributeValue = DbValue::U64(7).into();
+        assert_eq!(value, AttributeValue::Int(7));
+        let value: AttributeValue = DbValue::U64(u64::MAX).into();
+        assert_eq!(value, AttributeValue::Float(u64::MAX as f32));
+        let value: AttributeValue = DbValue::VecU64(vec![0, 1, 2]).into();
+        assert_eq!(value, AttributeValue::List(vec![AttributeValue::Int(0), AttributeValue::Int(1), AttributeValue::Int(2)]));
+
+        let stored: DbValue = AttributeValue::Bool(true).into();
+        assert_ne!(stored, DbValue::U64(1), "Bools shouldn't be stored like numbers");
+    }
+
+    #[test]
+    fn raw_bytes_starting_with_a_tag_are_kept() {
+        for tag in [TAG_BYTES, TAG_COLOR, TAG_STRING, TAG_BOOL, TAG_VEC4] {
+            let raw = vec![tag, 1];
+            let value: AttributeValue = DbValue::Bytes(raw.clone()).into();
+            assert_eq!(value, AttributeValue::Bytes(raw));
+        }
+        round_trip(AttributeValue::Bytes(vec![TAG_STRING, b'h', b'i']));
+        round_trip(AttributeValue::Bytes(BYTES_MARKER.to_vec()));
+    }
+
+    #[test]
+    fn undecodable_bytes_are_kept_as_raw_bytes() {
+        let raw = DbValue::Bytes(vec![200, 1, 2]);
+        let value: AttributeValue = raw.into();
+        assert_eq!(value, AttributeValue::Bytes(vec![200, 1, 2]));
+    }
+}
@@ -1,11 +1,13 @@
 use std::time::SystemTime;
 
 use agdb::{DbElement, DbError, DbId, DbKeyValue, DbUserValue, DbValue, QueryId};
+use serde::{Deserialize, Serialize};
 
 use super::{attribute::Attribute, node_path::NodePath, SysTime};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Edge {
+    #[serde(skip)]
     db_id: Option<DbId>,
     source: NodePath,
     target: NodePath,
@@ -45,6 +47,15 @@ impl Edge {
         }
     }
 
+    /// Adds attributes to the edge, replacing existing ones with the same name.
+    pub fn with_attributes(mut self, attrs: Vec<Attribute>) -> Self {
+        for attr in attrs {
+            self.attributes.retain(|a| a.name != attr.name);
+            self.attributes.push(attr);
+        }
+        self
+    }
+
     pub fn db_id(&self) -> Option<DbId> {
         self.db_id
     }
@@ -117,7 +128,7 @@ impl TryFrom<DbElement> for Edge {
     type Error = DbError;
     
     fn try_from(value: DbElement) -> Result<Self, Self::Error> {
-        let fixed: [&str; 2] = ["source", "target"];
+        let fixed: [&str; 4] = ["source", "target", "created_time", "modified_time"];
         let rest = value.values.iter().filter(|v|!fixed.contains(&v.key.string().unwrap().as_str())).collect::<Vec<_>>();
 
         let db_id = value.id;
@@ -127,9 +138,6 @@ impl TryFrom<DbElement> for Edge {
         let created_time = value.values.iter().find(|v| v.key == "created_time".into());
         let modified_time = value.values.iter().find(|v| v.key == "modified_time".into());
 
-        println!("source: {:?}", source);
-        println!("target: {:?}", target);
-
         if source.is_none() || target.is_none() {
             return Err(DbError::from("Edge must have source and target"));
         }
@@ -137,7 +145,7 @@ impl TryFrom<DbElement> for Edge {
         let attrs: Vec<Attribute> = rest.iter().map(|attr| {
             Attribute {
                 name: attr.key.to_string(),
-                value: attr.value.to_f64().unwrap().to_f64() as f32,
+                value: attr.value.clone().into(),
             }
         }).collect();
 
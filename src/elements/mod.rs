@@ -1,6 +1,7 @@
 use std::{path::PathBuf, time::SystemTime};
 
 use agdb::{DbElement, DbError, DbId, DbKeyValue, DbUserValue, DbValue, QueryId, UserValue};
+use serde::{Deserialize, Serialize};
 
 pub (crate) mod node;
 pub (crate) mod node_path;
@@ -10,7 +11,7 @@ pub (crate) mod attribute;
 
 
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SysTime(SystemTime);
 
 impl From<SysTime> for DbValue {
@@ -32,3 +33,16 @@ impl TryFrom<DbValue> for SysTime {
 
 
 
+
+impl From<SystemTime> for SysTime {
+    fn from(time: SystemTime) -> Self {
+        SysTime(time)
+    }
+}
+
+impl SysTime {
+    /// Seconds since the unix epoch, which is the precision stored in the db.
+    pub fn as_secs(&self) -> u64 {
+        self.0.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+    }
+}
@@ -1,10 +1,11 @@
 use std::time::SystemTime;
 
 use agdb::{DbElement, DbError, DbId, DbKeyValue, DbUserValue, DbValue, QueryId};
+use serde::{Deserialize, Serialize};
 
 use crate::elements::nodetype::{NodePhysicality, NodeType};
 
-use super::{attribute::Attribute, node_path::NodePath, SysTime};
+use super::{attribute::{Attribute, FINGERPRINT_KEYS}, node_path::NodePath, SysTime};
 
 /// The universal node type. 
 /// Nodes loaded for users of this crate should be in this type. 
@@ -15,9 +16,11 @@ use super::{attribute::Attribute, node_path::NodePath, SysTime};
 /// How exactly the other direction, the saving of data, should work, is, 
 /// as of writing this, undetermined. Likely in most cases Graph's methods will
 /// be used directly to make modifictions rather than creating a Node instance.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Node {
-    /// The id of the node in the database.
+    /// The id of the node in the database. Not serialized, as the ids are only
+    /// valid within one db.
+    #[serde(skip)]
     db_id: Option<DbId>,
     /// The path of the node relative to the root of the graph.
     /// The path is stored as a string in the database, but is converted to a PathBuf when
@@ -111,7 +114,10 @@ impl Node {
     /// Insert a vector of attibutes into the node. Not for library use. 
     /// Though perhaps not even this crate needs this function.
     pub(crate) fn insert_attributes(&mut self, attributes: Vec<Attribute>) {
-        unimplemented!();
+        for attr in attributes {
+            self.attributes.retain(|existing| existing.name != attr.name);
+            self.attributes.push(attr);
+        }
     }
     // Getters
     
@@ -136,6 +142,12 @@ impl Node {
         self.nphys.clone()
     }
 
+    /// Whether the node is alive. Physical nodes whose file has disappeared
+    /// from the file system are marked as dead.
+    pub fn alive(&self) -> bool {
+        self.alive
+    }
+
     pub fn created_time(&self) -> SysTime {
         self.created_time.clone()
     }
@@ -154,7 +166,10 @@ impl TryFrom<DbElement> for Node {
 
     fn try_from(value: DbElement) -> Result<Self, Self::Error> {
         let fixed: [&str; 6] = ["path", "ntype", "nphys", "alive", "created_time", "modified_time"];
-        let rest = value.values.iter().filter(|v|!fixed.contains(&v.key.string().unwrap().as_str())).collect::<Vec<_>>();
+        let rest = value.values.iter().filter(|v| {
+            let key = v.key.string().unwrap().as_str();
+            !fixed.contains(&key) && !FINGERPRINT_KEYS.contains(&key)
+        }).collect::<Vec<_>>();
 
         let db_id = value.id;
         let path = value.values.iter().find(|v| v.key == "path".into());
@@ -167,7 +182,7 @@ impl TryFrom<DbElement> for Node {
         let attrs: Vec<Attribute> = rest.iter().map(|v| {
             Attribute {
                 name: v.key.to_string(),
-                value: v.value.to_f64().unwrap().to_f64() as f32,
+                value: v.value.clone().into(),
             }
         }).collect();
 
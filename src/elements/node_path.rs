@@ -1,13 +1,14 @@
 use std::path::PathBuf;
 
 use agdb::{DbError, DbValue};
+use serde::{Deserialize, Serialize};
 
 use super::nodetype::ARCHETYPES;
 
 /// Newtype wrapper for the node path. Acts as the main struct for
 /// creating and modifying node paths, turning them into db aliases/strings and
 /// back. Path includes the name of the node itself.
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct NodePath(PathBuf);
 
 impl NodePath {
@@ -1,13 +1,16 @@
 use agdb::{DbError, DbValue};
+use serde::{Deserialize, Serialize};
 
 use crate::elements::node;
 
+use super::{attribute::{Attribute, AttributeValue}, node::Node, node_path::NodePath};
+
 // Some of the structs and enums in this file are currently not used.
 // Determining a sound architecture for node types is difficult and
 // not urgent quite yet.
 
 
-pub const ARCHETYPES: [&str; 5] = ["", "user_root", "attributes", "nodetypes", "settings"];
+pub const ARCHETYPES: [&str; 6] = ["", "user_root", "attributes", "nodetypes", "settings", "history"];
 
 pub struct NodeData;
 
@@ -16,7 +19,7 @@ pub enum NodeTyppe {
     Virtual(VirtualCategory),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum NodePhysicality {
     /// A node that only exists in the db and not in the file system.
     Virtual,
@@ -84,7 +87,7 @@ pub enum DataType {
     Other,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NodeType {
     type_name: String,
 }
@@ -148,3 +151,98 @@ impl From<NodeType> for agdb::DbValue {
         ntype.name().into()
     }
 }
+
+/// A user-defined node type. Stored as a child node of the nodetypes archetype,
+/// with the defaults of the expected attributes as the attributes of that node.
+///
+/// A type can inherit from another user-defined type. Its instances then get
+/// the attributes of the parent type as well, overridden by its own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeTypeDef {
+    ntype: NodeType,
+    parent: Option<NodeType>,
+    attributes: Vec<Attribute>,
+}
+
+/// Key under which the parent type is stored on the node of a type.
+pub(crate) const PARENT_TYPE_KEY: &str = "parent_type";
+
+/// Type of the nodes that hold the type definitions.
+pub(crate) const NODETYPE_TYPE: &str = "NodeType";
+
+impl NodeTypeDef {
+    pub fn new(ntype: NodeType) -> Self {
+        NodeTypeDef {
+            ntype,
+            parent: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn with_parent(mut self, parent: NodeType) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    /// Sets the expected attributes along with their defaults. Attributes with
+    /// the same name are replaced.
+    pub fn with_attributes(mut self, attrs: Vec<Attribute>) -> Self {
+        for attr in attrs {
+            self.attributes.retain(|existing| existing.name != attr.name);
+            self.attributes.push(attr);
+        }
+        self
+    }
+
+    pub fn ntype(&self) -> &NodeType {
+        &self.ntype
+    }
+
+    pub fn parent(&self) -> Option<&NodeType> {
+        self.parent.as_ref()
+    }
+
+    /// The expected attributes of this type, not including the inherited ones.
+    pub fn attributes(&self) -> &Vec<Attribute> {
+        &self.attributes
+    }
+
+    /// Path of the node that stores the type.
+    pub(crate) fn path(ntype: &NodeType) -> NodePath {
+        NodePath::atype("nodetypes").join(ntype.name())
+    }
+
+    pub(crate) fn to_node(&self) -> Node {
+        let mut node = Node::new(&NodeTypeDef::path(&self.ntype), NodeType::new(NODETYPE_TYPE.to_string()));
+        let mut attrs = self.attributes.clone();
+        if let Some(parent) = &self.parent {
+            attrs.push(Attribute::new(PARENT_TYPE_KEY, parent.name().to_string()));
+        }
+        node.insert_attributes(attrs);
+        node
+    }
+}
+
+impl TryFrom<Node> for NodeTypeDef {
+    type Error = DbError;
+
+    fn try_from(node: Node) -> Result<Self, Self::Error> {
+        if node.ntype_name().name() != NODETYPE_TYPE {
+            return Err(DbError::from(format!("{} is not a node type", node.path().alias())));
+        }
+
+        let mut def = NodeTypeDef::new(NodeType::new(node.name()));
+        for attr in node.attributes() {
+            if attr.name != PARENT_TYPE_KEY {
+                def.attributes.push(attr);
+                continue;
+            }
+            match attr.value {
+                AttributeValue::String(parent) => def.parent = Some(NodeType::new(parent)),
+                _ => return Err(DbError::from("Invalid parent type")),
+            }
+        }
+
+        Ok(def)
+    }
+}
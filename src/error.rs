@@ -0,0 +1,178 @@
+/*
+The error type of the graph.
+
+Every graph trait and command returns a GraphError, so that callers can tell
+the failures apart and show something specific for each. The variants that
+carry a message are the ones where the reason is only useful to a person, such
+as which part of a schema was violated.
+*/
+
+use std::{error::Error, fmt, io, path::PathBuf};
+
+use agdb::{DbError, QueryError};
+
+use crate::elements::node_path::NodePath;
+
+#[derive(Debug)]
+pub enum GraphError {
+    /// There is no node at the path.
+    NodeNotFound(NodePath),
+    /// There is no edge between the nodes.
+    EdgeNotFound { source: NodePath, target: NodePath },
+    /// A node, edge, node type or attribute definition is already in the graph,
+    /// or a path is already taken in the file system. Describes what exists.
+    AlreadyExists(String),
+    /// The attribute name is reserved for the graph itself.
+    ReservedAttribute(String),
+    /// Archetype nodes, such as the root, can't be changed this way.
+    ArchetypeProtected(NodePath),
+    /// The operation needs the node to exist in the file system.
+    NotPhysical(NodePath),
+    /// The path or name can't be used here.
+    InvalidPath(String),
+    /// A value doesn't fit an attribute definition, a node type or a setting.
+    SchemaViolation(String),
+    /// The operation isn't possible in the current state of the graph, such as
+    /// undoing with nothing to undo.
+    InvalidOperation(String),
+    /// A stored command or readable file couldn't be written or read.
+    Serialization(String),
+    /// The root directory of the graph doesn't exist, for example because the
+    /// drive it is on was unplugged.
+    RootNotFound(PathBuf),
+    /// The path can't be represented as UTF-8, which the db requires.
+    NonUtf8Path(PathBuf),
+    /// The db is already opened by another instance.
+    DbLocked(PathBuf),
+    /// The db file exists but can't be read as a graph.
+    DbCorrupted { path: PathBuf, reason: String },
+    /// The file system watcher couldn't be started, or reported an error.
+    Watcher(String),
+    Io(io::Error),
+    Db(DbError),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::NodeNotFound(path) => write!(f, "Node {} does not exist", path.alias()),
+            GraphError::EdgeNotFound { source, target } => {
+                write!(f, "Edge from {} to {} does not exist", source.alias(), target.alias())
+            }
+            GraphError::AlreadyExists(what) => write!(f, "{} already exists", what),
+            GraphError::ReservedAttribute(name) => write!(f, "{} is a reserved attribute name", name),
+            GraphError::ArchetypeProtected(path) => write!(f, "Archetype node {} is protected", path.alias()),
+            GraphError::NotPhysical(path) => write!(f, "Node {} does not exist in the file system", path.alias()),
+            GraphError::InvalidPath(msg)
+            | GraphError::SchemaViolation(msg)
+            | GraphError::InvalidOperation(msg)
+            | GraphError::Serialization(msg) => write!(f, "{}", msg),
+            GraphError::RootNotFound(path) => write!(f, "Root directory {} does not exist", path.display()),
+            GraphError::NonUtf8Path(path) => write!(f, "Path {} is not valid UTF-8", path.display()),
+            GraphError::DbLocked(path) => write!(f, "Db {} is already open elsewhere", path.display()),
+            GraphError::DbCorrupted { path, reason } => write!(f, "Db {} is corrupted: {}", path.display(), reason),
+            GraphError::Watcher(msg) => write!(f, "Watcher error: {}", msg),
+            GraphError::Io(err) => write!(f, "{}", err),
+            GraphError::Db(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for GraphError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GraphError::Io(err) => Some(err),
+            GraphError::Db(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GraphError {
+    fn from(err: io::Error) -> Self {
+        GraphError::Io(err)
+    }
+}
+
+impl From<DbError> for GraphError {
+    fn from(err: DbError) -> Self {
+        GraphError::Db(err)
+    }
+}
+
+impl From<QueryError> for GraphError {
+    fn from(err: QueryError) -> Self {
+        GraphError::Db(DbError::from(err.description))
+    }
+}
+
+impl From<ron::Error> for GraphError {
+    fn from(err: ron::Error) -> Self {
+        GraphError::Serialization(err.to_string())
+    }
+}
+
+impl From<ron::de::SpannedError> for GraphError {
+    fn from(err: ron::de::SpannedError) -> Self {
+        GraphError::Serialization(err.to_string())
+    }
+}
+
+#[cfg(feature = "watcher")]
+impl From<notify::Error> for GraphError {
+    fn from(err: notify::Error) -> Self {
+        GraphError::Watcher(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph_commands::TestCommandContext, prelude::*, utils::utils::TestContext};
+
+    #[test]
+    fn failures_can_be_told_apart() {
+        let func_name = "failures_can_be_told_apart";
+        let mut ctx = TestContext::new(func_name);
+
+        let missing = NodePath::from("missing");
+        assert!(matches!(ctx.graph.open_node(&missing), Err(GraphError::NodeNotFound(path)) if path == missing));
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+        assert!(matches!(ctx.graph.create_node_by_path(&a, None), Err(GraphError::AlreadyExists(_))));
+        assert!(matches!(ctx.graph.delete_edge(Edge::new(&a, &b)), Err(GraphError::EdgeNotFound { .. })));
+
+        let reserved = ctx.graph.insert_node_attrs(&a, vec![Attribute::new("ntype", "x")]);
+        assert!(matches!(reserved, Err(GraphError::ReservedAttribute(name)) if name == "ntype"));
+
+        let protected = ctx.graph.reparent_node(&NodePath::user_root(), &a);
+        assert!(matches!(protected, Err(GraphError::ArchetypeProtected(_))));
+        assert!(matches!(
+            ctx.graph.index_tree(&missing, &IndexOptions::default()),
+            Err(GraphError::NotPhysical(_))
+        ));
+
+        ctx.graph.create_attribute_def(AttributeDef::new("rating", "Int")).unwrap();
+        let err = ctx.graph.insert_node_attrs(&a, vec![Attribute::new("rating", "high")]).unwrap_err();
+        assert!(matches!(err, GraphError::SchemaViolation(_)));
+        assert_eq!(err.to_string(), "Invalid value for rating: expected Int, got String", "The message is kept for display");
+    }
+
+    #[test]
+    fn commands_return_the_same_errors() {
+        let func_name = "commands_return_the_same_errors";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        assert!(matches!(ctx.graph.undo(), Err(GraphError::InvalidOperation(_))));
+
+        let path = NodePath::from("node");
+        ctx.graph.create_node_by_path(&path, None).unwrap();
+        assert!(matches!(ctx.graph.create_node_by_path(&path, None), Err(GraphError::AlreadyExists(_))));
+        assert!(matches!(
+            ctx.graph.delete_node_attrs(&path, vec!["path"]),
+            Err(GraphError::ReservedAttribute(_))
+        ));
+    }
+}
@@ -0,0 +1,290 @@
+/*
+Event bus for changes made to the graph.
+
+Tools that embed the graph can subscribe to it to learn about changes without
+re-querying. Every subscriber gets its own channel, and the events are sent
+after the change has gone through, so a failed operation never sends anything.
+Command groups hold back the events of their commands until the whole group
+has gone through, and drop them if it fails.
+
+The events are sent by the graph functions themselves. Commands go through
+those same functions, so they produce the same events, and undo and redo are
+followed by an event of their own.
+*/
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::elements::node_path::NodePath;
+
+/// A change made to the graph.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GraphEvent {
+    NodeCreated(NodePath),
+    /// Removing a node also removes its edges. No separate events are sent for them.
+    NodeDeleted(NodePath),
+    /// The node was moved along with its descendants, edges and attributes.
+    NodeMoved { from: NodePath, to: NodePath },
+    /// Attributes of the node were inserted, updated or deleted.
+    AttributesChanged(NodePath),
+    /// The node was marked dead because its file is gone, or revived because
+    /// it is back. Dead nodes stay in the graph until cleanup_dead_nodes.
+    AliveChanged { path: NodePath, alive: bool },
+    /// A link was created. Contains edges are created along with their nodes.
+    EdgeCreated { source: NodePath, target: NodePath },
+    EdgeDeleted { source: NodePath, target: NodePath },
+    /// Attributes of an edge were inserted, updated or deleted.
+    EdgeAttributesChanged { source: NodePath, target: NodePath, contains: bool },
+    /// A command was undone. Carries the name of the command.
+    Undone(String),
+    /// A command was redone. Carries the name of the command.
+    Redone(String),
+    /// The readable file of the node couldn't be updated after a change. The
+    /// change itself went through. See GraphCore::rebuild_readable_files.
+    ReadableFileFailed { path: NodePath, reason: String },
+}
+
+/// The kind of a GraphEvent, for filtering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphEventKind {
+    NodeCreated,
+    NodeDeleted,
+    NodeMoved,
+    AttributesChanged,
+    AliveChanged,
+    EdgeCreated,
+    EdgeDeleted,
+    EdgeAttributesChanged,
+    Undone,
+    Redone,
+    ReadableFileFailed,
+}
+
+impl GraphEvent {
+    pub fn kind(&self) -> GraphEventKind {
+        match self {
+            GraphEvent::NodeCreated(_) => GraphEventKind::NodeCreated,
+            GraphEvent::NodeDeleted(_) => GraphEventKind::NodeDeleted,
+            GraphEvent::NodeMoved { .. } => GraphEventKind::NodeMoved,
+            GraphEvent::AttributesChanged(_) => GraphEventKind::AttributesChanged,
+            GraphEvent::AliveChanged { .. } => GraphEventKind::AliveChanged,
+            GraphEvent::EdgeCreated { .. } => GraphEventKind::EdgeCreated,
+            GraphEvent::EdgeDeleted { .. } => GraphEventKind::EdgeDeleted,
+            GraphEvent::EdgeAttributesChanged { .. } => GraphEventKind::EdgeAttributesChanged,
+            GraphEvent::Undone(_) => GraphEventKind::Undone,
+            GraphEvent::Redone(_) => GraphEventKind::Redone,
+            GraphEvent::ReadableFileFailed { .. } => GraphEventKind::ReadableFileFailed,
+        }
+    }
+
+    /// The nodes the event is about. Empty for undo and redo.
+    pub fn paths(&self) -> Vec<&NodePath> {
+        match self {
+            GraphEvent::NodeCreated(path)
+            | GraphEvent::NodeDeleted(path)
+            | GraphEvent::AttributesChanged(path)
+            | GraphEvent::AliveChanged { path, .. }
+            | GraphEvent::ReadableFileFailed { path, .. } => vec![path],
+            GraphEvent::NodeMoved { from, to } => vec![from, to],
+            GraphEvent::EdgeCreated { source, target }
+            | GraphEvent::EdgeDeleted { source, target }
+            | GraphEvent::EdgeAttributesChanged { source, target, .. } => vec![source, target],
+            GraphEvent::Undone(_) | GraphEvent::Redone(_) => vec![],
+        }
+    }
+}
+
+/// Limits the events a subscriber receives. An empty filter lets everything through.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventFilter {
+    kinds: Vec<GraphEventKind>,
+    under: Option<NodePath>,
+}
+
+impl EventFilter {
+    pub fn new() -> Self {
+        EventFilter::default()
+    }
+
+    /// Only let events of this kind through. Can be called several times to
+    /// allow several kinds.
+    pub fn kind(mut self, kind: GraphEventKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Only let events about the node at this path or its descendants through.
+    /// Undo and redo events don't concern any node, so they are filtered out.
+    pub fn under(mut self, path: &NodePath) -> Self {
+        self.under = Some(path.clone());
+        self
+    }
+
+    pub fn matches(&self, event: &GraphEvent) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind()) {
+            return false;
+        }
+        match &self.under {
+            Some(under) => event.paths().iter().any(|path| path.buf().starts_with(under.buf())),
+            None => true,
+        }
+    }
+}
+
+/// Handle to a subscription. Pass it to unsubscribe to stop receiving events.
+/// Dropping the receiving end of the channel also ends the subscription.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Subscription(u64);
+
+struct Subscriber {
+    id: u64,
+    filter: Option<EventFilter>,
+    sender: Sender<GraphEvent>,
+}
+
+#[derive(Default)]
+pub(crate) struct EventBus {
+    subscribers: Vec<Subscriber>,
+    next_id: u64,
+    /// Events emitted while the bus is held, waiting to be sent.
+    held: Vec<GraphEvent>,
+    holds: usize,
+}
+
+impl EventBus {
+    pub(crate) fn subscribe(&mut self, filter: Option<EventFilter>) -> (Subscription, Receiver<GraphEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscribers.push(Subscriber { id, filter, sender });
+        (Subscription(id), receiver)
+    }
+
+    /// Returns false if there was no such subscription.
+    pub(crate) fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        let before = self.subscribers.len();
+        self.subscribers.retain(|subscriber| subscriber.id != subscription.0);
+        self.subscribers.len() != before
+    }
+
+    /// Sends the event to every subscriber whose filter matches. Subscribers
+    /// whose receivers have been dropped are removed. While the bus is held,
+    /// the event is kept until it is released.
+    pub(crate) fn emit(&mut self, event: GraphEvent) {
+        if self.holds > 0 {
+            self.held.push(event);
+        } else {
+            self.send(event);
+        }
+    }
+
+    /// Keeps the events emitted from now on until release is called. Holds can
+    /// be nested. Returns the mark to release them with.
+    pub(crate) fn hold(&mut self) -> usize {
+        self.holds += 1;
+        self.held.len()
+    }
+
+    /// Ends a hold. The events emitted since its mark are dropped unless they
+    /// are sent, and they are only sent once the outermost hold has ended.
+    pub(crate) fn release(&mut self, mark: usize, send: bool) {
+        if !send {
+            self.held.truncate(mark);
+        }
+        self.holds = self.holds.saturating_sub(1);
+        if self.holds == 0 {
+            for event in std::mem::take(&mut self.held) {
+                self.send(event);
+            }
+        }
+    }
+
+    fn send(&mut self, event: GraphEvent) {
+        self.subscribers.retain(|subscriber| {
+            let wanted = subscriber.filter.as_ref().map_or(true, |filter| filter.matches(&event));
+            !wanted || subscriber.sender.send(event.clone()).is_ok()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::TryRecvError;
+
+    use crate::{graph_commands::TestCommandContext, prelude::*, utils::utils::TestContext};
+
+    #[test]
+    fn direct_calls_send_events_to_subscribers() {
+        let func_name = "direct_calls_send_events_to_subscribers";
+        let mut ctx = TestContext::new(func_name);
+        let (_, events) = ctx.graph.subscribe(None);
+
+        let a = NodePath::new("a".into());
+        let b = NodePath::new("b".into());
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+        ctx.graph.insert_node_attrs(&a, vec![Attribute::new("rating", 3)]).unwrap();
+        ctx.graph.create_edge(&a, &b).unwrap();
+        ctx.graph.delete_edge(Edge::new(&a, &b)).unwrap();
+        ctx.graph.delete_nodes(&vec![b.clone()], false, false).unwrap();
+
+        let received: Vec<GraphEvent> = events.try_iter().collect();
+        assert_eq!(received, vec![
+            GraphEvent::NodeCreated(a.clone()),
+            GraphEvent::NodeCreated(b.clone()),
+            GraphEvent::AttributesChanged(a.clone()),
+            GraphEvent::EdgeCreated { source: a.clone(), target: b.clone() },
+            GraphEvent::EdgeDeleted { source: a.clone(), target: b.clone() },
+            GraphEvent::NodeDeleted(b.clone()),
+        ]);
+
+        // Failed operations send nothing.
+        assert!(ctx.graph.create_node_by_path(&a, None).is_err());
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn subscriptions_can_be_filtered_and_ended() {
+        let func_name = "subscriptions_can_be_filtered_and_ended";
+        let mut ctx = TestContext::new(func_name);
+
+        let a = NodePath::new("a".into());
+        let filter = EventFilter::new().kind(GraphEventKind::NodeCreated).under(&a);
+        let (subscription, events) = ctx.graph.subscribe(Some(filter));
+
+        ctx.graph.create_node_by_path(&a.join("x"), None).unwrap();
+        ctx.graph.create_node_by_path(&NodePath::new("b".into()), None).unwrap();
+        ctx.graph.insert_node_attrs(&a, vec![Attribute::new("rating", 3)]).unwrap();
+
+        // Creating a/x also created a.
+        let received: Vec<GraphEvent> = events.try_iter().collect();
+        assert_eq!(received, vec![
+            GraphEvent::NodeCreated(a.clone()),
+            GraphEvent::NodeCreated(a.join("x")),
+        ]);
+
+        assert!(ctx.graph.unsubscribe(subscription));
+        assert!(!ctx.graph.unsubscribe(subscription), "Subscription should already be ended");
+        assert_eq!(events.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn commands_send_events_along_with_undo_and_redo() {
+        let func_name = "commands_send_events_along_with_undo_and_redo";
+        let mut ctx = TestCommandContext::new(func_name);
+        let (_, events) = ctx.graph.subscribe(None);
+
+        let path = NodePath::from("node");
+        ctx.graph.create_node_by_path(&path, None).unwrap();
+        ctx.graph.undo().unwrap();
+        ctx.graph.redo().unwrap();
+
+        let received: Vec<GraphEvent> = events.try_iter().collect();
+        assert_eq!(received, vec![
+            GraphEvent::NodeCreated(path.clone()),
+            GraphEvent::NodeDeleted(path.clone()),
+            GraphEvent::Undone("Create Node From Path".to_string()),
+            GraphEvent::NodeCreated(path.clone()),
+            GraphEvent::Redone("Create Node From Path".to_string()),
+        ]);
+    }
+}
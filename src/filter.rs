@@ -1,4 +1,509 @@
 // Main file of the Filter struct and its implementations.
 
 // Filter is a struct that can be passed to functions in the Graph struct
-// to limit the search to a certain part of the graph.
\ No newline at end of file
+// to limit the search to a certain part of the graph.
+
+use std::time::SystemTime;
+
+use agdb::{
+    Comparison, DbElement, DbValue, QueryCondition, QueryConditionData, QueryConditionLogic,
+    QueryConditionModifier,
+};
+
+use crate::elements::{
+    attribute::AttributeValue,
+    node_path::NodePath,
+    nodetype::{NodePhysicality, NodeType},
+};
+
+/// The kind of an edge. Contains edges are the parent-child relationships
+/// of the graph, links are all other edges.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EdgeKind {
+    Contains,
+    Link,
+}
+
+/// Comparison of an attribute value against a given value.
+/// Mirrors agdb's Comparison, but in terms of AttributeValues.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttrComparison {
+    Equal(AttributeValue),
+    NotEqual(AttributeValue),
+    GreaterThan(AttributeValue),
+    GreaterThanOrEqual(AttributeValue),
+    LessThan(AttributeValue),
+    LessThanOrEqual(AttributeValue),
+    /// Substring for strings, element for lists.
+    Contains(AttributeValue),
+}
+
+impl AttrComparison {
+    fn to_db_comparison(&self) -> Comparison {
+        match self.clone() {
+            AttrComparison::Equal(v) => Comparison::Equal(v.into()),
+            AttrComparison::NotEqual(v) => Comparison::NotEqual(v.into()),
+            AttrComparison::GreaterThan(v) => Comparison::GreaterThan(v.into()),
+            AttrComparison::GreaterThanOrEqual(v) => Comparison::GreaterThanOrEqual(v.into()),
+            AttrComparison::LessThan(v) => Comparison::LessThan(v.into()),
+            AttrComparison::LessThanOrEqual(v) => Comparison::LessThanOrEqual(v.into()),
+            AttrComparison::Contains(v) => Comparison::Contains(v.into()),
+        }
+    }
+}
+
+/// A time range. Both ends are optional, the start is inclusive and the end exclusive.
+/// Times are stored in the db with a precision of seconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeRange {
+    pub from: Option<SystemTime>,
+    pub to: Option<SystemTime>,
+}
+
+impl TimeRange {
+    fn secs(time: &Option<SystemTime>) -> Option<u64> {
+        time.map(|t| {
+            t.duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        })
+    }
+}
+
+/// A composable filter for searching nodes and edges.
+///
+/// Filters are compiled into agdb query conditions, so the filtering happens
+/// inside the database. Path globs can't be expressed exactly as agdb conditions,
+/// so they are narrowed down in the db by their literal parts and then matched
+/// exactly on the returned elements.
+///
+/// Node predicates (type, physicality, alive, path) never match edges and
+/// edge kind predicates never match nodes. Attribute and time predicates apply to both.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// Matches everything.
+    All,
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+
+    NodeType(NodeType),
+    Physicality(NodePhysicality),
+    Alive(bool),
+    /// Matches the node at the path and all of its descendants.
+    PathPrefix(NodePath),
+    /// Glob relative to the user_root, in the same format as NodePath::from.
+    /// `*` and `?` match within a single path segment, `**` matches any number of segments.
+    PathGlob(String),
+
+    HasAttribute(String),
+    Attribute(String, AttrComparison),
+    CreatedTime(TimeRange),
+    ModifiedTime(TimeRange),
+
+    EdgeKind(EdgeKind),
+}
+
+impl Filter {
+    pub fn all() -> Self {
+        Filter::All
+    }
+
+    pub fn ntype(ntype: NodeType) -> Self {
+        Filter::NodeType(ntype)
+    }
+
+    pub fn nphys(nphys: NodePhysicality) -> Self {
+        Filter::Physicality(nphys)
+    }
+
+    pub fn alive(alive: bool) -> Self {
+        Filter::Alive(alive)
+    }
+
+    pub fn path_prefix(path: &NodePath) -> Self {
+        Filter::PathPrefix(path.clone())
+    }
+
+    pub fn path_glob(pattern: &str) -> Self {
+        Filter::PathGlob(pattern.to_string())
+    }
+
+    pub fn has_attr(name: &str) -> Self {
+        Filter::HasAttribute(name.to_string())
+    }
+
+    pub fn attr(name: &str, comparison: AttrComparison) -> Self {
+        Filter::Attribute(name.to_string(), comparison)
+    }
+
+    pub fn created_between(from: Option<SystemTime>, to: Option<SystemTime>) -> Self {
+        Filter::CreatedTime(TimeRange { from, to })
+    }
+
+    pub fn modified_between(from: Option<SystemTime>, to: Option<SystemTime>) -> Self {
+        Filter::ModifiedTime(TimeRange { from, to })
+    }
+
+    pub fn edge_kind(kind: EdgeKind) -> Self {
+        Filter::EdgeKind(kind)
+    }
+
+    /// Combine two filters so that both must match.
+    pub fn and(self, other: Filter) -> Self {
+        match self {
+            Filter::And(mut filters) => {
+                filters.push(other);
+                Filter::And(filters)
+            }
+            filter => Filter::And(vec![filter, other]),
+        }
+    }
+
+    /// Combine two filters so that either must match.
+    pub fn or(self, other: Filter) -> Self {
+        match self {
+            Filter::Or(mut filters) => {
+                filters.push(other);
+                Filter::Or(filters)
+            }
+            filter => Filter::Or(vec![filter, other]),
+        }
+    }
+
+    /// Invert the filter.
+    pub fn negate(self) -> Self {
+        match self {
+            Filter::Not(filter) => *filter,
+            filter => Filter::Not(Box::new(filter)),
+        }
+    }
+
+    // -------------------------------------------------------------------
+    // Compilation into agdb conditions
+
+    /// Conditions for searching nodes with this filter.
+    pub(crate) fn node_conditions(&self) -> Vec<QueryCondition> {
+        vec![
+            condition(QueryConditionData::Node),
+            self.compile(false),
+        ]
+    }
+
+    /// Conditions for searching edges with this filter.
+    pub(crate) fn edge_conditions(&self) -> Vec<QueryCondition> {
+        vec![
+            condition(QueryConditionData::Edge),
+            self.compile(false),
+        ]
+    }
+
+    /// Whether the compiled conditions match exactly the same elements as
+    /// matches_element. If not, the results of the db search must be refined.
+    pub(crate) fn is_exact(&self) -> bool {
+        match self {
+            Filter::And(filters) | Filter::Or(filters) => filters.iter().all(|f| f.is_exact()),
+            Filter::Not(filter) => filter.is_exact(),
+            Filter::PathGlob(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Compile the filter into a single condition. Inexact predicates compile into a
+    /// condition that matches a superset of the exact result when not negated,
+    /// and a subset when negated, so that the db never drops a matching element.
+    fn compile(&self, negated: bool) -> QueryCondition {
+        match self {
+            Filter::All => always(true),
+            Filter::And(filters) => group(filters, QueryConditionLogic::And, negated),
+            Filter::Or(filters) => {
+                if filters.is_empty() {
+                    return always(false);
+                }
+                group(filters, QueryConditionLogic::Or, negated)
+            }
+            Filter::Not(filter) => QueryCondition {
+                logic: QueryConditionLogic::And,
+                modifier: QueryConditionModifier::Not,
+                data: QueryConditionData::Where(vec![filter.compile(!negated)]),
+            },
+
+            Filter::NodeType(ntype) => key_value("ntype", Comparison::Equal(ntype.clone().into())),
+            Filter::Physicality(nphys) => {
+                key_value("nphys", Comparison::Equal(nphys.clone().into()))
+            }
+            Filter::Alive(alive) => key_value("alive", Comparison::Equal((*alive).into())),
+            Filter::PathPrefix(path) => {
+                if *path == NodePath::root() {
+                    return condition(QueryConditionData::Node);
+                }
+                // Strings are compared lexicographically, and '0' is the character right
+                // after '/', so this range contains exactly the descendants of the path.
+                let alias = path.alias();
+                let exact = key_value("path", Comparison::Equal(alias.clone().into()));
+                let mut descendants = condition(QueryConditionData::Where(vec![
+                    key_value("path", Comparison::GreaterThanOrEqual(format!("{}/", alias).into())),
+                    key_value("path", Comparison::LessThan(format!("{}0", alias).into())),
+                ]));
+                descendants.logic = QueryConditionLogic::Or;
+                condition(QueryConditionData::Where(vec![exact, descendants]))
+            }
+            Filter::PathGlob(pattern) => {
+                if negated {
+                    return always(false);
+                }
+                let literals: Vec<String> = glob_alias(pattern)
+                    .split(|c| c == '*' || c == '?')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+                    .collect();
+                if literals.is_empty() {
+                    return condition(QueryConditionData::Keys(vec!["path".into()]));
+                }
+                key_value("path", Comparison::Contains(DbValue::VecString(literals)))
+            }
+
+            Filter::HasAttribute(name) => {
+                condition(QueryConditionData::Keys(vec![name.as_str().into()]))
+            }
+            Filter::Attribute(name, comparison) => {
+                key_value(name, comparison.to_db_comparison())
+            }
+            Filter::CreatedTime(range) => time_range("created_time", range),
+            Filter::ModifiedTime(range) => time_range("modified_time", range),
+
+            Filter::EdgeKind(kind) => {
+                let mut contains = condition(QueryConditionData::Keys(vec!["contains".into()]));
+                if *kind == EdgeKind::Link {
+                    contains.modifier = QueryConditionModifier::Not;
+                }
+                condition(QueryConditionData::Where(vec![
+                    condition(QueryConditionData::Edge),
+                    contains,
+                ]))
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------
+    // Exact matching
+
+    /// Check whether a db element with its values matches the filter exactly.
+    pub(crate) fn matches_element(&self, element: &DbElement) -> bool {
+        let value = |key: &str| {
+            element
+                .values
+                .iter()
+                .find(|kv| kv.key == key.into())
+                .map(|kv| &kv.value)
+        };
+        let compare = |key: &str, comparison: &Comparison| match value(key) {
+            Some(v) => compare_db_values(comparison, v),
+            None => false,
+        };
+        let is_node = element.id.0 > 0;
+        let is_edge = element.id.0 < 0;
+
+        match self {
+            Filter::All => true,
+            Filter::And(filters) => filters.iter().all(|f| f.matches_element(element)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches_element(element)),
+            Filter::Not(filter) => !filter.matches_element(element),
+
+            Filter::NodeType(ntype) => compare("ntype", &Comparison::Equal(ntype.clone().into())),
+            Filter::Physicality(nphys) => {
+                compare("nphys", &Comparison::Equal(nphys.clone().into()))
+            }
+            Filter::Alive(alive) => compare("alive", &Comparison::Equal((*alive).into())),
+            Filter::PathPrefix(path) => {
+                if *path == NodePath::root() {
+                    return is_node;
+                }
+                let alias = path.alias();
+                match value("path") {
+                    Some(DbValue::String(p)) => {
+                        *p == alias || p.starts_with(&format!("{}/", alias))
+                    }
+                    _ => false,
+                }
+            }
+            Filter::PathGlob(pattern) => match value("path") {
+                Some(DbValue::String(p)) => glob_match(&glob_alias(pattern), p),
+                _ => false,
+            },
+
+            Filter::HasAttribute(name) => value(name).is_some(),
+            Filter::Attribute(name, comparison) => {
+                compare(name, &comparison.to_db_comparison())
+            }
+            Filter::CreatedTime(range) => in_time_range(value("created_time"), range),
+            Filter::ModifiedTime(range) => in_time_range(value("modified_time"), range),
+
+            Filter::EdgeKind(kind) => {
+                let contains = value("contains").is_some();
+                match kind {
+                    EdgeKind::Contains => is_edge && contains,
+                    EdgeKind::Link => is_edge && !contains,
+                }
+            }
+        }
+    }
+}
+
+fn condition(data: QueryConditionData) -> QueryCondition {
+    QueryCondition {
+        logic: QueryConditionLogic::And,
+        modifier: QueryConditionModifier::None,
+        data,
+    }
+}
+
+/// A condition that is always true or always false.
+/// An empty key list is contained in every element.
+fn always(value: bool) -> QueryCondition {
+    let mut cond = condition(QueryConditionData::Keys(vec![]));
+    if !value {
+        cond.modifier = QueryConditionModifier::Not;
+    }
+    cond
+}
+
+fn key_value(key: &str, comparison: Comparison) -> QueryCondition {
+    condition(QueryConditionData::KeyValue {
+        key: key.into(),
+        value: comparison,
+    })
+}
+
+/// Group filters into a nested condition. Note that agdb folds the conditions
+/// from left to right starting from true, so the first condition must always be And.
+fn group(filters: &Vec<Filter>, logic: QueryConditionLogic, negated: bool) -> QueryCondition {
+    if filters.is_empty() {
+        return always(true);
+    }
+    let conditions = filters
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let mut cond = f.compile(negated);
+            if i > 0 {
+                cond.logic = logic;
+            }
+            cond
+        })
+        .collect();
+
+    condition(QueryConditionData::Where(conditions))
+}
+
+fn time_range(key: &str, range: &TimeRange) -> QueryCondition {
+    let mut conditions = vec![condition(QueryConditionData::Keys(vec![key.into()]))];
+    if let Some(from) = TimeRange::secs(&range.from) {
+        conditions.push(key_value(key, Comparison::GreaterThanOrEqual(from.into())));
+    }
+    if let Some(to) = TimeRange::secs(&range.to) {
+        conditions.push(key_value(key, Comparison::LessThan(to.into())));
+    }
+    condition(QueryConditionData::Where(conditions))
+}
+
+fn in_time_range(value: Option<&DbValue>, range: &TimeRange) -> bool {
+    let secs = match value.map(|v| v.to_u64()) {
+        Some(Ok(secs)) => secs,
+        _ => return false,
+    };
+    let after_from = TimeRange::secs(&range.from).map_or(true, |from| secs >= from);
+    let before_to = TimeRange::secs(&range.to).map_or(true, |to| secs < to);
+    after_from && before_to
+}
+
+/// Same semantics as agdb uses when evaluating a KeyValue condition.
+fn compare_db_values(comparison: &Comparison, left: &DbValue) -> bool {
+    match comparison {
+        Comparison::Equal(right) => left == right,
+        Comparison::NotEqual(right) => left != right,
+        Comparison::GreaterThan(right) => left > right,
+        Comparison::GreaterThanOrEqual(right) => left >= right,
+        Comparison::LessThan(right) => left < right,
+        Comparison::LessThanOrEqual(right) => left <= right,
+        Comparison::Contains(right) => match (left, right) {
+            (DbValue::String(l), DbValue::String(r)) => l.contains(r.as_str()),
+            (DbValue::String(l), DbValue::VecString(r)) => r.iter().all(|x| l.contains(x.as_str())),
+            (DbValue::VecI64(l), DbValue::I64(r)) => l.contains(r),
+            (DbValue::VecI64(l), DbValue::VecI64(r)) => r.iter().all(|x| l.contains(x)),
+            (DbValue::VecU64(l), DbValue::U64(r)) => l.contains(r),
+            (DbValue::VecU64(l), DbValue::VecU64(r)) => r.iter().all(|x| l.contains(x)),
+            (DbValue::VecF64(l), DbValue::F64(r)) => l.contains(r),
+            (DbValue::VecF64(l), DbValue::VecF64(r)) => r.iter().all(|x| l.contains(x)),
+            (DbValue::VecString(l), DbValue::String(r)) => l.contains(r),
+            (DbValue::VecString(l), DbValue::VecString(r)) => r.iter().all(|x| l.contains(x)),
+            _ => false,
+        },
+    }
+}
+
+/// Turn a glob relative to the user_root into a glob over aliases.
+fn glob_alias(pattern: &str) -> String {
+    NodePath::from(pattern).alias()
+}
+
+/// Match a path against a glob pattern. Both are split into segments by '/'.
+/// `**` matches any number of segments, `*` any number of characters within
+/// a segment and `?` a single character.
+pub(crate) fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| match_segments(rest, &path[i..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                match_segment(segment.as_bytes(), name.as_bytes())
+                    && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| match_segment(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_segment(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matching_respects_segments() {
+        assert!(glob_match("/user_root/*.png", "/user_root/a.png"));
+        assert!(!glob_match("/user_root/*.png", "/user_root/dir/a.png"));
+        assert!(glob_match("/user_root/**/*.png", "/user_root/a.png"));
+        assert!(glob_match("/user_root/**/*.png", "/user_root/dir/deeper/a.png"));
+        assert!(glob_match("/user_root/file?.txt", "/user_root/file1.txt"));
+        assert!(!glob_match("/user_root/file?.txt", "/user_root/file10.txt"));
+        assert!(glob_match("/user_root/**", "/user_root/anything/at/all"));
+    }
+
+    #[test]
+    fn combinators_flatten_and_double_negation_cancels() {
+        let filter = Filter::alive(true)
+            .and(Filter::ntype(NodeType::file()))
+            .and(Filter::has_attr("rating"));
+        match &filter {
+            Filter::And(filters) => assert_eq!(filters.len(), 3),
+            _ => panic!("Expected an And filter"),
+        }
+
+        assert_eq!(filter.clone().negate().negate(), filter);
+    }
+}
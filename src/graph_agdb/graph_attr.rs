@@ -0,0 +1,85 @@
+use agdb::{CountComparison, QueryBuilder};
+
+use crate::{
+    elements::{attribute::{AttributeDef, ATTRIBUTEDEF_TYPE, RESERVED_EDGE_ATTRS}, nodetype::NodeType},
+    error::GraphError,
+    graph_traits::{graph_attr::GraphAttr, graph_node::GraphNode},
+};
+
+use super::{attribute::{Attribute, RESERVED_NODE_ATTRS}, node::Node, node_path::NodePath, GraphAgdb};
+
+impl GraphAttr for GraphAgdb {
+    fn get_attribute_defs(&self) -> Result<Vec<AttributeDef>, GraphError> {
+        let search = QueryBuilder::search()
+            .from(NodePath::atype("attributes").alias())
+            .where_()
+            .node()
+            .and()
+            .distance(CountComparison::Equal(2))
+            .query();
+        let result = self.db.exec(&QueryBuilder::select().ids(search).query())?;
+
+        let mut defs: Vec<AttributeDef> = Vec::new();
+        for elem in result.elements {
+            let node = Node::try_from(elem)?;
+            if node.ntype_name().name() == ATTRIBUTEDEF_TYPE {
+                defs.push(AttributeDef::try_from(node)?);
+            }
+        }
+
+        Ok(defs)
+    }
+
+    fn open_attribute_def(&self, name: &str) -> Result<AttributeDef, GraphError> {
+        let node = self
+            .open_node(&AttributeDef::path(name))
+            .map_err(|_| GraphError::NodeNotFound(AttributeDef::path(name)))?;
+        Ok(AttributeDef::try_from(node)?)
+    }
+
+    fn create_attribute_def(&mut self, def: AttributeDef) -> Result<AttributeDef, GraphError> {
+        let node = self.attribute_def_node(&def)?;
+        self.insert_node(node)?;
+        Ok(def)
+    }
+
+    fn delete_attribute_def(&mut self, name: &str) -> Result<(), GraphError> {
+        self.open_attribute_def(name)?;
+        self.delete_nodes(&vec![AttributeDef::path(name)], false, false)
+    }
+}
+
+impl GraphAgdb {
+    /// Checks an attribute definition and turns it into the node that stores it.
+    pub(crate) fn attribute_def_node(&self, def: &AttributeDef) -> Result<Node, GraphError> {
+        let name = def.name();
+        if name.is_empty() || name.contains('/') {
+            return Err(GraphError::InvalidPath(format!("Invalid attribute name: {}", name)));
+        }
+        if RESERVED_NODE_ATTRS.contains(&name) || RESERVED_EDGE_ATTRS.contains(&name) {
+            return Err(GraphError::ReservedAttribute(name.to_string()));
+        }
+        if self.open_attribute_def(name).is_ok() {
+            return Err(GraphError::AlreadyExists(format!("Attribute definition {}", name)));
+        }
+        def.check()?;
+
+        Ok(def.to_node())
+    }
+
+    /// Checks the attributes against their definitions, if they have any. The
+    /// node type is given for node attributes and None for edge attributes.
+    pub(crate) fn validate_attrs(&self, ntype: Option<&NodeType>, attrs: &[Attribute]) -> Result<(), GraphError> {
+        for attr in attrs {
+            let def = match self.open_attribute_def(&attr.name) {
+                Ok(def) => def,
+                Err(_) => continue,
+            };
+            if let Some(ntype) = ntype {
+                def.check_ntype(ntype)?;
+            }
+            def.check_value(&attr.value)?;
+        }
+        Ok(())
+    }
+}
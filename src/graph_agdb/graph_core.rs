@@ -1,13 +1,18 @@
-use std::{error::Error, path::{self, PathBuf}};
+use std::{collections::HashMap, error::Error, path::{self, Path, PathBuf}};
 
-use agdb::QueryBuilder;
+use agdb::{DbKeyValue, DbValue, QueryBuilder, QueryError};
 
 use crate::{
-    elements::nodetype::NodeType,
-    graph_traits::{self, graph_core::GraphCore, graph_node::GraphNode},
+    config::GraphConfig,
+    elements::{attribute::{Attribute, AttributeValue}, edge::Edge, nodetype::NodeType},
+    error::GraphError,
+    events::{EventBus, GraphEvent},
+    filter::{EdgeKind, Filter},
+    indexing::{self, Fingerprint, IndexOptions, IndexSummary, MoveCandidates},
+    graph_traits::{self, graph_core::{CleanupReport, GraphCore, ImportReport}, graph_edge::GraphEdge, graph_node::GraphNode},
 };
 
-use super::{node::Node, node_path::NodePath, nodetype::ARCHETYPES, GraphAgdb, StoragePath};
+use super::{in_history, node::Node, node_path::NodePath, nodetype::ARCHETYPES, settings, sync, GraphAgdb, StoragePath};
 
 /// Implementation block for the Graph struct itself.
 /// Includes constructors and utility functions.
@@ -26,68 +31,25 @@ impl GraphCore for GraphAgdb {
         NodePath::root()
     }
 
-    /// Gets the name of the root directory without the full path
+    /// Gets the name of the root directory without the full path. Opening
+    /// the graph checks that the root has one.
     fn root_name(&self) -> String {
         self.root_path
             .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
     }
 
-    /// Constructor. Panics if the db cannot be created.
+    /// Constructor. Panics if the db cannot be created. Use GraphAgdb::open
+    /// to handle the failure instead.
     ///
-    /// Takes the desired root directory of the graph as a parameter and the name for the db.
-    /// The name of the root directory will become the user_root of the graph,
-    /// as first child of the root node.
+    /// The name of the root directory in the config will become the user_root
+    /// of the graph, as first child of the root node.
     ///
     /// Creates the db at the storage_path, or initialises the db if it already exists there.
-    ///
-    /// TODO: Add error handling.
-    fn new(name: &str, root_path: PathBuf, custom_storage_path: Option<PathBuf>) -> Self {
-        let storage_enum = match custom_storage_path {
-            Some(path) => graph_traits::StoragePath::Custom(path),
-            None => graph_traits::StoragePath::Default,
-        };
-        let storage_path = match storage_enum.clone() {
-            StoragePath::Custom(path) => path,
-            StoragePath::Default => {
-                directories::ProjectDirs::from("com", "teodosin_labs", "fs_graph")
-                    .unwrap()
-                    .data_dir()
-                    .to_path_buf()
-            }
-        };
-
-        // Create the path if it doesn't exist
-        if !storage_path.exists() {
-            std::fs::create_dir_all(&storage_path).expect("Failed to create storage path");
-        }
-
-        let db_path = storage_path.join(format!("{}.agdb", name));
-
-        // Check if the database already exists
-        let open_existing = db_path.exists();
-
-        let db = agdb::Db::new(db_path.to_str().unwrap()).expect("Failed to create new db");
-
-        let mut giraphe = GraphAgdb {
-            name: name.to_string(),
-            db,
-            root_path: root_path.into(),
-            storage_path: storage_enum,
-            maintain_readable_files: false,
-        };
-
-        println!("WE ARE ABOUT TO CREATE ARCHHHHH");
-
-        if !open_existing {
-            println!("WE HAVE ENTERED THE IF");
-            giraphe.init_archetype_nodes();
-        }
-
-        return giraphe;
+    /// Unlike open, doesn't check that the root exists or lock the db.
+    fn new(config: GraphConfig) -> Self {
+        GraphAgdb::open_with(config, false).unwrap_or_else(|e| panic!("Failed to open graph: {}", e))
     }
 
     /// Create the initial archetype nodes for the graph. Includes
@@ -95,67 +57,38 @@ impl GraphCore for GraphAgdb {
     /// attributes,
     /// settings,
     /// nodetypes
-    fn init_archetype_nodes(&mut self) {
-        let archetypes = ARCHETYPES;
-
-        println!("Length of archetypes {}", archetypes.len());
-
-        archetypes.iter().for_each(|at| {
-            println!("{}", at);
-        });
-
-        archetypes.iter().for_each(|atype| {
+    fn init_archetype_nodes(&mut self) -> Result<(), GraphError> {
+        for atype in ARCHETYPES.iter() {
             let atype_path = NodePath::atype(*atype);
-            println!("Atypepath {:?}", atype_path);
 
             println!("Creating archetype node: {}", atype_path.alias());
 
             let ntype = if atype_path == NodePath::root() {
-                println!("Root node in question");
                 NodeType::root_type()
             } else {
-                println!("Archetype node in question");
                 NodeType::archetype_type()
             };
 
             let node: Node = Node::new(&atype_path, ntype);
 
-            println!("alias is {}", atype_path.alias());
-
-            let query = self.db.exec_mut(
+            self.db.exec_mut(
                 &QueryBuilder::insert()
                     .nodes()
                     .aliases(atype_path.alias())
                     .values(&node)
                     .query(),
-            );
-
-            match query {
-                Ok(_) => {
-                    println!("Created archetype node: {}", atype_path.alias());
-                }
-                Err(ref err) => {
-                    panic!("Failed to create archetype node: {}", err);
-                    println!("Failed to create archetype node: {}", err);
-                }
-            }
+            )?;
 
             if atype_path != NodePath::root() {
-                println!(
-                    "autoparent: parent {:?} to child {:?}",
-                    &NodePath::root(),
-                    &atype_path
-                );
-                self.autoparent_nodes(&NodePath::root(), &atype_path);
-            } else {
-                println!("Root node, no autoparenting");
+                self.autoparent_nodes(&NodePath::root(), &atype_path)?;
             }
-        });
+        }
+        Ok(())
     }
 
     /// Syncs a node in the db with the file system. Errs on archetype nodes as
     /// well as other virtual nodes. 
-    fn index_single_node(&mut self, path: &NodePath) -> Result<Node, Box<dyn Error>>{
+    fn index_single_node(&mut self, path: &NodePath) -> Result<Node, GraphError>{
         
         let full_path = path.full(&self.root_path);
         let is_user_root = full_path == self.user_root_dirpath();
@@ -171,10 +104,12 @@ impl GraphCore for GraphAgdb {
             node_alias = NodePath::user_root().alias();
             is_phys = full_path.exists();
             is_dir = full_path.is_dir();
-            assert!(is_phys && is_dir, "User root directory must exist and be a directory");
+            if !(is_phys && is_dir) {
+                return Err(GraphError::RootNotFound(full_path))
+            }
         } else {
             if path.is_atype() {
-                return Err("Archetype nodes cannot be indexed".into())
+                return Err(GraphError::ArchetypeProtected(path.clone()))
             }
             node_alias = path.alias();
             is_phys = full_path.exists();
@@ -188,7 +123,7 @@ impl GraphCore for GraphAgdb {
         let node = self.db.exec(&QueryBuilder::select().ids(node_alias.clone()).query());
         if node.is_ok() {
             println!("Node already exists");
-            return Err("Node already exists".into())
+            return Err(GraphError::AlreadyExists(format!("Node {}", node_alias)))
         }
 
         if is_phys {
@@ -199,9 +134,8 @@ impl GraphCore for GraphAgdb {
                 return self.create_node_by_path(path, Some(NodeType::file()))
             } 
         } else {
-            return Err("Cannot index virtual node".into())
+            return Err(GraphError::NotPhysical(path.clone()))
         }
-        return Err("Indexing of path failed".into())
     }
 
     /// Syncs the node's and its relationships in the db with the file system.
@@ -219,7 +153,10 @@ impl GraphCore for GraphAgdb {
             node_alias = NodePath::user_root().alias();
             is_phys = full_path.exists();
             is_dir = full_path.is_dir();
-            assert!(is_phys && is_dir, "User root directory must exist and be a directory");
+            if !(is_phys && is_dir) {
+                println!("User root directory {:?} does not exist", full_path);
+                return
+            }
         } else {
             node_alias = path.alias();
             is_phys = full_path.exists();
@@ -238,7 +175,14 @@ impl GraphCore for GraphAgdb {
         // If the path is a directory, we must check for its contents in the 
         // file system and index them.
         if is_dir {
-            let children = full_path.read_dir().unwrap();
+            // The directory may have been removed or made unreadable since it was checked.
+            let children = match full_path.read_dir() {
+                Ok(children) => children,
+                Err(err) => {
+                    println!("Error reading directory {:?}: {}", full_path, err);
+                    return;
+                }
+            };
             children.into_iter().for_each(|child| {
                 match child {
                     Ok(child) => {
@@ -262,12 +206,204 @@ impl GraphCore for GraphAgdb {
         // Pls?
     }
 
-    fn cleanup_dead_nodes(&mut self) {
-        todo!()
+    fn index_tree(&mut self, path: &NodePath, options: &IndexOptions) -> Result<IndexSummary, GraphError> {
+        let full_path = path.full(&self.root_path);
+        if !full_path.exists() {
+            return Err(GraphError::NotPhysical(path.clone()));
+        }
+
+        let mut summary = IndexSummary::default();
+
+        // The user_root is an archetype and is always in the graph.
+        if *path != NodePath::user_root()
+            && !self.sync_tree_node(path, &full_path, options.hash_contents, &mut summary)
+        {
+            return Ok(summary);
+        }
+
+        if !full_path.is_dir() {
+            return Ok(summary);
+        }
+
+        let walk = indexing::walk(&self.root_path, &full_path, options, &self.db_files());
+
+        let mut moves: HashMap<PathBuf, NodePath> = HashMap::new();
+        if options.detect_moves {
+            let (found, reconciliation) = self.detect_moves(&walk.entries, options.hash_contents)?;
+            moves = found;
+            summary.reconciliation = reconciliation;
+        }
+
+        for entry in walk.entries {
+            let path = NodePath::from_dir_path(&self.root_path, &entry);
+
+            if let Some(from) = moves.get(&entry) {
+                match self.move_node(from, &path, options.hash_contents) {
+                    Ok(_) => summary.moved.push((from.clone(), path)),
+                    Err(err) => summary.errored.push((path, err.to_string())),
+                }
+                continue;
+            }
+
+            self.sync_tree_node(&path, &entry, options.hash_contents, &mut summary);
+        }
+
+        summary.skipped = walk
+            .skipped
+            .iter()
+            .map(|entry| NodePath::from_dir_path(&self.root_path, entry))
+            .collect();
+        summary.errored.extend(walk.errored.into_iter().map(|(entry, err)| {
+            (NodePath::from_dir_path(&self.root_path, &entry), err)
+        }));
+
+        Ok(summary)
+    }
+
+    fn reconcile_move(&mut self, from: &NodePath, to: &NodePath) -> Result<(), GraphError> {
+        if from.full(&self.root_path).exists() {
+            return Err(GraphError::AlreadyExists(format!("File of {}", from.alias())));
+        }
+        if !to.full(&self.root_path).exists() {
+            return Err(GraphError::NotPhysical(to.clone()));
+        }
+
+        // The node created for the new file is replaced, so it must not have
+        // any data of its own yet.
+        if let Ok(node) = self.open_node(to) {
+            let links = self
+                .search_edges(&Filter::edge_kind(EdgeKind::Link))?
+                .into_iter()
+                .any(|edge| edge.source() == to || edge.target() == to);
+            if !node.attributes().is_empty() || links {
+                return Err(GraphError::InvalidOperation(format!("Node {} already has attributes or links", to.alias())));
+            }
+        }
+
+        let root = self.root_path.clone();
+        transaction_mut!(&mut self.db, |t| -> Result<(), GraphError> {
+            if sync::node_id(t, to).is_some() {
+                t.exec_mut(&QueryBuilder::remove().ids(to.alias()).query())?;
+            }
+            sync::rekey_node(t, &root, from, to)?;
+            sync::touch_node(t, &root, to, false)?;
+            Ok(())
+        })?;
+
+        self.emit(GraphEvent::NodeMoved { from: from.clone(), to: to.clone() });
+        Ok(())
+    }
+
+    fn mark_dead_nodes(&mut self) -> Result<Vec<NodePath>, GraphError> {
+        let physical = Filter::ntype(NodeType::file()).or(Filter::ntype(NodeType::dir()));
+        let nodes = self.search_nodes(&physical)?;
+
+        let mut dead: Vec<NodePath> = Vec::new();
+
+        for node in nodes {
+            let exists = node.path().full(&self.root_path).exists();
+
+            if exists != node.alive() {
+                self.db.replace_values(node.path().alias(), vec![("alive", exists).into()])?;
+                self.emit(GraphEvent::AliveChanged { path: node.path(), alive: exists });
+            }
+
+            if !exists {
+                dead.push(node.path());
+            }
+        }
+
+        Ok(dead)
+    }
+
+    fn cleanup_dead_nodes(&mut self, dry_run: bool) -> Result<CleanupReport, GraphError> {
+        let dead_nodes = self.search_nodes(&Filter::alive(false))?;
+        let dead_paths: Vec<NodePath> = dead_nodes.iter().map(|node| node.path()).collect();
+
+        let lost_edges: Vec<Edge> = self
+            .search_edges(&Filter::edge_kind(EdgeKind::Link))?
+            .into_iter()
+            .filter(|edge| dead_paths.contains(edge.source()) || dead_paths.contains(edge.target()))
+            .collect();
+
+        let lost_attributes: Vec<(NodePath, Vec<Attribute>)> = dead_nodes
+            .iter()
+            .filter(|node| !node.attributes().is_empty())
+            .map(|node| (node.path(), node.attributes()))
+            .collect();
+
+        // Removing the nodes also removes all of their edges.
+        if !dry_run && !dead_paths.is_empty() {
+            self.delete_nodes(&dead_paths, false, false)?;
+        }
+
+        Ok(CleanupReport {
+            dry_run,
+            nodes: dead_paths,
+            lost_edges,
+            lost_attributes,
+        })
+    }
+
+    fn maintain_readable_files(&mut self, maintain: bool) -> Result<(), GraphError> {
+        self.set_setting(settings::MAINTAIN_READABLE_FILES, maintain.into())?;
+        if maintain {
+            self.rebuild_readable_files()?;
+        }
+        Ok(())
+    }
+
+    fn rebuild_readable_files(&mut self) -> Result<(), GraphError> {
+        self.write_all_readable_files()?;
+        Ok(())
+    }
+
+    fn import_readable_files(&mut self, dir: Option<PathBuf>) -> Result<ImportReport, GraphError> {
+        let dir = dir.unwrap_or_else(|| self.readable_dir());
+        let report = self.import_readable_dir(&dir)?;
+
+        // The settings may have been imported along with the nodes.
+        if let Ok(Some(AttributeValue::Bool(maintain))) = self.get_setting(settings::MAINTAIN_READABLE_FILES) {
+            self.maintain_readable_files = maintain;
+        }
+        Ok(report)
+    }
+
+    fn get_setting(&self, key: &str) -> Result<Option<AttributeValue>, GraphError> {
+        settings::get(self, key)
+    }
+
+    fn set_setting(&mut self, key: &str, value: AttributeValue) -> Result<(), GraphError> {
+        let stored = settings::setting_value(key, value.clone())?;
+        self.db.replace_values(settings::settings_path().alias(), vec![stored])?;
+
+        if let (settings::MAINTAIN_READABLE_FILES, AttributeValue::Bool(maintain)) = (key, value) {
+            self.maintain_readable_files = maintain;
+        }
+        self.update_readable_files(&[settings::settings_path()]);
+        Ok(())
+    }
+
+    fn list_settings(&self) -> Result<Vec<Attribute>, GraphError> {
+        settings::list(self)
+    }
+
+    fn index_options(&self) -> Result<IndexOptions, GraphError> {
+        settings::index_options(self)
     }
 
-    fn maintain_readable_files(&mut self, maintain: bool) {
-        self.maintain_readable_files = maintain;
+    fn set_index_options(&mut self, options: &IndexOptions) -> Result<(), GraphError> {
+        let keys: Vec<DbValue> = settings::INDEX_SETTINGS.iter().map(|key| (*key).into()).collect();
+        let values = settings::index_values(options);
+        let alias = settings::settings_path().alias();
+
+        transaction_mut!(&mut self.db, |t| -> Result<(), QueryError> {
+            t.exec_mut(&QueryBuilder::remove().values(keys.clone()).ids(alias.clone()).query())?;
+            t.exec_mut(&QueryBuilder::insert().values(vec![values.clone()]).ids(alias.clone()).query())?;
+            Ok(())
+        })?;
+        self.update_readable_files(&[settings::settings_path()]);
+        Ok(())
     }
 
     fn get_all_aliases(&self) -> Vec<String> {
@@ -276,7 +412,9 @@ impl GraphCore for GraphAgdb {
             Ok(aliases) => {
                 let all: Vec<String> = aliases.elements.iter().map(|alias| {
                     alias.values[0].value.to_string()
-                }).collect();
+                })
+                .filter(|alias| !in_history(&NodePath::from_alias(alias)))
+                .collect();
 
                 all
             },
@@ -287,3 +425,127 @@ impl GraphCore for GraphAgdb {
         }
     }
 }
+
+/// Helpers for index_tree.
+impl GraphAgdb {
+    /// Creates the node for a path, or brings an existing node in line with the
+    /// file system. Returns false if the path errored.
+    fn sync_tree_node(&mut self, path: &NodePath, full_path: &Path, hash: bool, summary: &mut IndexSummary) -> bool {
+        let existing = self
+            .db
+            .exec(&QueryBuilder::select().ids(path.alias()).query())
+            .ok()
+            .and_then(|result| result.elements.into_iter().next());
+
+        let elem = match existing {
+            Some(elem) => elem,
+            None => {
+                let created = self.create_node_by_path(path, None).and_then(|_| {
+                    if let Some(fingerprint) = Fingerprint::of(full_path, hash) {
+                        self.db.exec_mut(
+                            &QueryBuilder::insert()
+                                .values(vec![fingerprint.to_values()])
+                                .ids(path.alias())
+                                .query(),
+                        )?;
+                    }
+                    Ok(())
+                });
+                return match created {
+                    Ok(_) => {
+                        summary.created.push(path.clone());
+                        true
+                    }
+                    Err(err) => {
+                        summary.errored.push((path.clone(), err.to_string()));
+                        false
+                    }
+                };
+            }
+        };
+
+        let values = match sync::sync_values(&elem, full_path, hash) {
+            Ok(values) => values,
+            Err(err) => {
+                summary.errored.push((path.clone(), err.to_string()));
+                return false;
+            }
+        };
+
+        if values.is_empty() {
+            summary.unchanged += 1;
+            return true;
+        }
+
+        let update = self.db.replace_values(path.alias(), values);
+
+        match update {
+            Ok(_) => {
+                self.update_readable_files(&[path.clone()]);
+                summary.updated.push(path.clone());
+                true
+            }
+            Err(err) => {
+                summary.errored.push((path.clone(), err.to_string()));
+                false
+            }
+        }
+    }
+
+    /// Match the new files among the walked entries to the nodes of files that
+    /// have gone missing. See indexing::match_moves.
+    fn detect_moves(
+        &self,
+        entries: &[PathBuf],
+        hash: bool,
+    ) -> Result<(HashMap<PathBuf, NodePath>, Vec<MoveCandidates>), GraphError> {
+        let new_files: Vec<(PathBuf, NodePath)> = entries
+            .iter()
+            .filter(|entry| entry.is_file())
+            .map(|entry| (entry.clone(), NodePath::from_dir_path(&self.root_path, entry)))
+            .filter(|(_, path)| {
+                self.db
+                    .exec(&QueryBuilder::select().ids(path.alias()).query())
+                    .is_err()
+            })
+            .collect();
+        if new_files.is_empty() {
+            return Ok((HashMap::new(), Vec::new()));
+        }
+
+        // Nodes whose files are gone, whether or not they have been marked dead yet.
+        let mut search = QueryBuilder::search().elements().query();
+        search.conditions = Filter::ntype(NodeType::file()).node_conditions();
+        let missing: Vec<(NodePath, Fingerprint)> = self
+            .db
+            .exec(&QueryBuilder::select().ids(search).query())?
+            .elements
+            .iter()
+            .filter_map(|elem| {
+                let node = Node::try_from(elem.clone()).ok()?;
+                if node.path().full(&self.root_path).exists() {
+                    return None;
+                }
+                Some((node.path(), Fingerprint::from_element(elem)?))
+            })
+            .collect();
+        if missing.is_empty() {
+            return Ok((HashMap::new(), Vec::new()));
+        }
+
+        Ok(indexing::match_moves(&new_files, &missing, hash))
+    }
+
+    /// Move the node of a missing file to the path the file was found at.
+    fn move_node(&mut self, from: &NodePath, to: &NodePath, hash: bool) -> Result<(), GraphError> {
+        let root = self.root_path.clone();
+        transaction_mut!(&mut self.db, |t| -> Result<(), GraphError> {
+            sync::rekey_node(t, &root, from, to)?;
+            sync::touch_node(t, &root, to, hash)?;
+            Ok(())
+        })?;
+
+        self.emit(GraphEvent::NodeMoved { from: from.clone(), to: to.clone() });
+        Ok(())
+    }
+}
@@ -1,145 +1,435 @@
-use std::{error::Error, path::PathBuf};
+use std::{path::PathBuf, time::SystemTime};
 
-use agdb::{DbElement, QueryBuilder};
+use agdb::{CountComparison, DbElement, DbKeyValue, DbUserValue, DbValue, QueryBuilder, QueryError};
 
-use crate::{elements, graph_traits::graph_edge::GraphEdge};
+use crate::{elements, error::GraphError, events::GraphEvent, filter::Filter, graph_traits::graph_edge::GraphEdge};
 
-use super::{attribute::{Attribute, RESERVED_EDGE_ATTRS}, edge::Edge, node_path::NodePath, GraphAgdb, StoragePath};
+use super::{attribute::{Attribute, RESERVED_EDGE_ATTRS}, edge::Edge, in_history, node_path::NodePath, sync, GraphAgdb, StoragePath, SysTime};
 
 impl GraphEdge for GraphAgdb {
+    /// The edge from one node to another. If there is both a link and a contains
+    /// edge between them, the link is returned.
     fn get_edge_strict(
         &self,
         from: &NodePath,
         to: &NodePath,
-    ) -> Result<Edge, Box<dyn Error>> {
-        let from_al = from.alias();
-        let to_al = to.alias();
-
-        let edge_query = self.db.exec(
-            &QueryBuilder::search()
-            .from(from_al)
-            .to(to_al)
-            .query()
-        );
-
-        if edge_query.is_err() {
-            return Err("Failed to get edge".into());
-        }
-        let edge_query = edge_query.unwrap();
-
-        let edge_elems: Vec<&DbElement> = edge_query
-            .elements.iter().filter(|e| {
-                e.id.0 < 0
-            })
-            .collect::<Vec<_>>();
-
-        assert_eq!(edge_elems.len(), 1, "Expected only 1 edge, got {}", edge_elems.len());
-        let edge_id = edge_elems.first().unwrap().id;
-
-        // The search doesn't return the values, so we have to do a separate select
-        // on the edge id. 
-        let keys = Vec::new();
-        let data_query = self.db.exec(
-            &QueryBuilder::select().values(keys).ids(edge_id).query()
-        );
+    ) -> Result<Edge, GraphError> {
+        let elem = self
+            .find_edge(from, to, false)
+            .or_else(|| self.find_edge(from, to, true))
+            .ok_or_else(|| GraphError::EdgeNotFound { source: from.clone(), target: to.clone() })?;
+        Ok(Edge::try_from(elem)?)
+    }
 
-        if data_query.is_err() {
-            return Err("Failed to get edge data".into());
-        }
-        let data_query = data_query.unwrap();
-        let data_elem = data_query.elements.first().unwrap();
-        println!("Edge element: {:#?}", data_elem);
+    fn search_edges(&self, filter: &Filter) -> Result<Vec<Edge>, GraphError> {
+        let mut search = QueryBuilder::search().elements().query();
+        search.conditions = filter.edge_conditions();
 
-        let edge = Edge::try_from(data_elem.clone());
+        let result = self.db.exec(&QueryBuilder::select().ids(search).query())?;
 
-        println!("Edge: {:#?}", edge);
+        let edges = result
+            .elements
+            .into_iter()
+            .filter(|elem| filter.is_exact() || filter.matches_element(elem))
+            .map(|elem| Edge::try_from(elem))
+            .collect::<Result<Vec<Edge>, _>>()?;
 
-        match edge {
-            Ok(edge) => {
-                return Ok(edge);
-            }
-            Err(e) => {
-                return Err(e.into());
-            }
-        }
+        Ok(edges.into_iter().filter(|edge| !in_history(edge.source()) && !in_history(edge.target())).collect())
     }
 
+    /// Creates a link between two existing nodes. There can only be one link in
+    /// each direction between two nodes.
     fn create_edge(
         &mut self,
         source_path: &NodePath,
         target_path: &NodePath,
-    ) -> Result<(), Box<dyn Error>> {
-        let alias = source_path.alias();
-        let child_alias = target_path.alias();
+    ) -> Result<(), GraphError> {
+        self.check_endpoints(source_path, target_path)?;
+
+        if self.find_edge(source_path, target_path, false).is_some() {
+            return Err(GraphError::AlreadyExists(format!(
+                "Edge from {} to {}",
+                source_path.alias(),
+                target_path.alias()
+            )));
+        }
 
-        todo!()
+        let edge = Edge::new(source_path, target_path);
+        self.db.exec_mut(
+            &QueryBuilder::insert()
+                .edges()
+                .from(source_path.alias())
+                .to(target_path.alias())
+                .values_uniform(&edge)
+                .query(),
+        )?;
+
+        self.emit(GraphEvent::EdgeCreated { source: source_path.clone(), target: target_path.clone() });
+        Ok(())
     }
 
     /// Changes the parent directory of a node. If the node is physical, it will be moved in the file system.
     /// If the node is virtual, the parent will be changed in the db.
-    /// Note that due to the implementation, all children of the node will have to be reindexed, recursively.
+    /// The node keeps its name, and all of its descendants are moved along with it.
+    /// Either everything is moved or nothing is: the file system move is reverted
+    /// if the db can't be updated.
     fn reparent_node(
-        &self,
+        &mut self,
         node_path: &NodePath,
         new_parent_path: &NodePath,
-    ) -> Result<(), Box<dyn Error>> {
-        // Check if node is in database at all
+    ) -> Result<(), GraphError> {
         let alias = node_path.alias();
-        let existing = self.db.exec(&QueryBuilder::select().ids(alias).query());
-        
-        todo!()
+        let existing = self.db.exec(&QueryBuilder::select().ids(alias.clone()).query());
+        if existing.is_err() {
+            return Err(GraphError::NodeNotFound(node_path.clone()));
+        }
+        if node_path.is_atype() || *node_path == NodePath::root() || *node_path == NodePath::user_root() {
+            return Err(GraphError::ArchetypeProtected(node_path.clone()));
+        }
+
+        let parent_alias = new_parent_path.alias();
+        let parent = self.db.exec(&QueryBuilder::select().ids(parent_alias.clone()).query());
+        if parent.is_err() {
+            return Err(GraphError::NodeNotFound(new_parent_path.clone()));
+        }
+        if new_parent_path.buf().starts_with(node_path.buf()) {
+            return Err(GraphError::InvalidPath(format!("Cannot move {} inside itself", alias)));
+        }
+        if node_path.parent().as_ref() == Some(new_parent_path) {
+            return Ok(());
+        }
+
+        let new_path = new_parent_path.join(&node_path.name());
+        if self.db.exec(&QueryBuilder::select().ids(new_path.alias()).query()).is_ok() {
+            return Err(GraphError::AlreadyExists(format!("Node {}", new_path.alias())));
+        }
+
+        let full_path = node_path.full(&self.root_path);
+        let new_full_path = new_path.full(&self.root_path);
+        let is_physical = full_path.exists();
+
+        if is_physical {
+            if !new_parent_path.full(&self.root_path).is_dir() {
+                return Err(GraphError::NotPhysical(new_parent_path.clone()));
+            }
+            if new_full_path.exists() {
+                return Err(GraphError::AlreadyExists(format!("Path {} in the file system", new_full_path.display())));
+            }
+            std::fs::rename(&full_path, &new_full_path)?;
+        }
+
+        let root = self.root_path.clone();
+        let result = transaction_mut!(&mut self.db, |t| -> Result<(), GraphError> {
+            sync::rekey_node(t, &root, node_path, &new_path)
+        });
+
+        if let Err(err) = result {
+            if is_physical {
+                if let Err(revert) = std::fs::rename(&new_full_path, &full_path) {
+                    return Err(GraphError::Io(std::io::Error::new(revert.kind(), format!(
+                        "Failed to reparent {}: {}. Moving the file back also failed: {}",
+                        alias, err, revert
+                    ))));
+                }
+            }
+            return Err(err);
+        }
+
+        if is_physical {
+            self.record_moved_file(full_path, new_full_path);
+        }
+        self.emit(GraphEvent::NodeMoved { from: node_path.clone(), to: new_path });
+        Ok(())
     }
 
     /// Moves an edge and all its attributes to a new source and target. Parent edges can't be reconnected this way,
     /// use the reparent_node function instead.
     fn reconnect_edge(
-        &self,
+        &mut self,
         edge: Edge,
         from: &NodePath,
         to: &NodePath,
-    ) -> Result<(), Box<dyn Error>> {
-        todo!()
+    ) -> Result<(), GraphError> {
+        if edge.contains() {
+            return Err(GraphError::InvalidOperation(
+                "Contains edges can't be reconnected, use reparent_node instead".to_string(),
+            ));
+        }
+        self.check_endpoints(from, to)?;
+
+        let existing = self.find_edge(edge.source(), edge.target(), false).ok_or_else(|| {
+            GraphError::EdgeNotFound { source: edge.source().clone(), target: edge.target().clone() }
+        })?;
+
+        if from == edge.source() && to == edge.target() {
+            return Ok(());
+        }
+        if self.find_edge(from, to, false).is_some() {
+            return Err(GraphError::AlreadyExists(format!("Edge from {} to {}", from.alias(), to.alias())));
+        }
+
+        let values: Vec<DbKeyValue> = existing
+            .values
+            .iter()
+            .map(|kv| match kv.key.to_string().as_str() {
+                "source" => ("source", from.clone()).into(),
+                "target" => ("target", to.clone()).into(),
+                "modified_time" => ("modified_time", SysTime::from(SystemTime::now())).into(),
+                _ => kv.clone(),
+            })
+            .collect();
+
+        transaction_mut!(&mut self.db, |t| -> Result<(), QueryError> {
+            t.exec_mut(&QueryBuilder::remove().ids(existing.id).query())?;
+            t.exec_mut(
+                &QueryBuilder::insert()
+                    .edges()
+                    .from(from.alias())
+                    .to(to.alias())
+                    .values_uniform(values.clone())
+                    .query(),
+            )?;
+            Ok(())
+        })?;
+
+        self.emit(GraphEvent::EdgeDeleted { source: edge.source().clone(), target: edge.target().clone() });
+        self.emit(GraphEvent::EdgeCreated { source: from.clone(), target: to.clone() });
+        Ok(())
     }
 
-    fn insert_edge(&self, edge: Edge) -> Result<(), Box<dyn Error>> {
+    /// Inserts an edge with all of its values and attributes. If the edge already
+    /// exists, its values are updated instead. Contains edges can only be updated,
+    /// as they are created along with their child nodes.
+    fn insert_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+        self.check_endpoints(edge.source(), edge.target())?;
+        self.validate_attrs(None, edge.attributes())?;
+
+        match self.find_edge(edge.source(), edge.target(), edge.contains()) {
+            Some(existing) => {
+                self.db.replace_values(existing.id, edge.to_db_values())?;
+                self.emit_edge_attributes_changed(&edge);
+            }
+            None if edge.contains() => {
+                return Err(GraphError::InvalidOperation(
+                    "Contains edges are created along with their child nodes".to_string(),
+                ));
+            }
+            None => {
+                self.db.exec_mut(
+                    &QueryBuilder::insert()
+                        .edges()
+                        .from(edge.source().alias())
+                        .to(edge.target().alias())
+                        .values_uniform(&edge)
+                        .query(),
+                )?;
+                self.emit(GraphEvent::EdgeCreated { source: edge.source().clone(), target: edge.target().clone() });
+            }
+        }
+
         Ok(())
     }
 
     /// Delete an edge from the graph. Edges with the attribute "contains" refer to the parent-child relationship
     /// between nodes and will be ignored. All other attributes will be cleared from them instead.
-    fn delete_edge(&self, edge: Edge) -> Result<(), Box<dyn Error>> {
+    fn delete_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+        let existing = self.find_edge(edge.source(), edge.target(), edge.contains()).ok_or_else(|| {
+            GraphError::EdgeNotFound { source: edge.source().clone(), target: edge.target().clone() }
+        })?;
+
+        if !edge.contains() {
+            self.db.exec_mut(&QueryBuilder::remove().ids(existing.id).query())?;
+            self.emit(GraphEvent::EdgeDeleted { source: edge.source().clone(), target: edge.target().clone() });
+            return Ok(());
+        }
+
+        let kept: [&str; 5] = ["source", "target", "created_time", "modified_time", "contains"];
+        let stripped: Vec<DbValue> = existing
+            .values
+            .iter()
+            .filter(|kv| !kept.contains(&kv.key.to_string().as_str()))
+            .map(|kv| kv.key.clone())
+            .collect();
+
+        if !stripped.is_empty() {
+            self.db.exec_mut(&QueryBuilder::remove().values(stripped).ids(existing.id).query())?;
+            self.db.replace_values(existing.id, vec![("modified_time", SysTime::from(SystemTime::now())).into()])?;
+            self.emit_edge_attributes_changed(&edge);
+        }
+
         Ok(())
     }
 
     /// Insert attributes to an edge. Ignore reserved attribute names. Update attributes that already exist.
-    fn insert_edge_attr(&self, edge: Edge, attr: Attribute) -> Result<(), Box<dyn Error>> {
-        use RESERVED_EDGE_ATTRS;
-        let slice = attr.name.as_str();
-        let is_reserved = RESERVED_EDGE_ATTRS.contains(&slice);
+    fn insert_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        if attrs.is_empty() {
+            return Err(GraphError::InvalidOperation("Attributes cannot be empty".to_string()));
+        }
+        let first = attrs[0].name.clone();
+
+        let filtered: Vec<Attribute> = attrs
+            .into_iter()
+            .filter(|attr| !RESERVED_EDGE_ATTRS.contains(&attr.name.as_str()))
+            .collect();
 
-        if is_reserved {
-            return Err(format!(
-                "Cannot delete reserved attribute name: {}",
-                slice
-            ).into());
+        if filtered.is_empty() {
+            return Err(GraphError::ReservedAttribute(first));
         }
 
-        Ok(())
+        self.write_edge_attrs(&edge, filtered)
     }
 
     /// Delete attributes from an edge. Ignore reserved attribute names.
-    fn delete_edge_attr(&self, edge: Edge, attr: Attribute) -> Result<(), Box<dyn Error>> {
-        use RESERVED_EDGE_ATTRS;
-        let slice = attr.name.as_str();
-        let is_reserved = RESERVED_EDGE_ATTRS.contains(&slice);
+    fn delete_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        if attr_names.is_empty() {
+            return Err(GraphError::InvalidOperation("No attributes to delete".to_string()));
+        }
+        let first = attr_names[0].to_string();
+
+        let filtered: Vec<&str> = attr_names
+            .into_iter()
+            .filter(|name| !RESERVED_EDGE_ATTRS.contains(name))
+            .collect();
 
-        if is_reserved {
-            return Err(format!(
-                "Cannot insert reserved attribute name: {}",
-                slice
-            ).into());
+        if filtered.is_empty() {
+            return Err(GraphError::ReservedAttribute(first));
         }
 
+        self.remove_edge_attrs(&edge, filtered)
+    }
+
+    /// Insert reserved attributes, such as the relative positions of the nodes, to an edge.
+    fn insert_reserved_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        if let Some(attr) = attrs.iter().find(|attr| STRUCTURAL_EDGE_ATTRS.contains(&attr.name.as_str())) {
+            return Err(GraphError::ReservedAttribute(attr.name.clone()));
+        }
+        if attrs.is_empty() {
+            return Err(GraphError::InvalidOperation("Attributes cannot be empty".to_string()));
+        }
+
+        self.write_edge_attrs(&edge, attrs)
+    }
+
+    /// Delete reserved attributes from an edge.
+    fn delete_reserved_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        if let Some(name) = attr_names.iter().find(|name| STRUCTURAL_EDGE_ATTRS.contains(name)) {
+            return Err(GraphError::ReservedAttribute(name.to_string()));
+        }
+        if attr_names.is_empty() {
+            return Err(GraphError::InvalidOperation("No attributes to delete".to_string()));
+        }
+
+        self.remove_edge_attrs(&edge, attr_names)
+    }
+}
+
+/// Reserved edge attributes that describe the edge itself. They can't be
+/// written even through the privileged functions.
+pub(crate) const STRUCTURAL_EDGE_ATTRS: [&str; 5] = ["source", "target", "contains", "created_time", "modified_time"];
+
+impl GraphAgdb {
+    /// Opens the edge from source to target. Unlike get_edge_strict, the kind of
+    /// the edge must be given, as two nodes can have both a contains edge and a link.
+    pub(crate) fn open_edge(&self, source: &NodePath, target: &NodePath, contains: bool) -> Result<Edge, GraphError> {
+        let elem = self.find_edge(source, target, contains).ok_or_else(|| {
+            GraphError::EdgeNotFound { source: source.clone(), target: target.clone() }
+        })?;
+        Ok(Edge::try_from(elem)?)
+    }
+
+    /// All the edges going out from or coming in to the node, with their values.
+    pub(crate) fn node_edges(&self, path: &NodePath) -> Result<Vec<Edge>, GraphError> {
+        let outgoing = QueryBuilder::search()
+            .from(path.alias())
+            .where_()
+            .edge()
+            .and()
+            .distance(CountComparison::Equal(1))
+            .query();
+        let incoming = QueryBuilder::search()
+            .to(path.alias())
+            .where_()
+            .edge()
+            .and()
+            .distance(CountComparison::Equal(1))
+            .query();
+
+        let mut edges: Vec<Edge> = Vec::new();
+        for search in [outgoing, incoming] {
+            let result = self.db.exec(&QueryBuilder::select().ids(search).query())?;
+            for elem in result.elements {
+                edges.push(Edge::try_from(elem)?);
+            }
+        }
+
+        Ok(edges)
+    }
+
+    fn write_edge_attrs(&mut self, edge: &Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        self.validate_attrs(None, &attrs)?;
+
+        let existing = self.find_edge(edge.source(), edge.target(), edge.contains()).ok_or_else(|| {
+            GraphError::EdgeNotFound { source: edge.source().clone(), target: edge.target().clone() }
+        })?;
+
+        let mut values: Vec<DbKeyValue> = attrs.iter().map(|attr| attr.into()).collect();
+        values.push(("modified_time", SysTime::from(SystemTime::now())).into());
+
+        self.db.replace_values(existing.id, values)?;
+        self.emit_edge_attributes_changed(edge);
+        Ok(())
+    }
+
+    fn remove_edge_attrs(&mut self, edge: &Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        let existing = self.find_edge(edge.source(), edge.target(), edge.contains()).ok_or_else(|| {
+            GraphError::EdgeNotFound { source: edge.source().clone(), target: edge.target().clone() }
+        })?;
+
+        let keys: Vec<DbValue> = attr_names.into_iter().map(DbValue::from).collect();
+        let modified: DbKeyValue = ("modified_time", SysTime::from(SystemTime::now())).into();
+
+        transaction_mut!(&mut self.db, |t| -> Result<(), QueryError> {
+            t.exec_mut(&QueryBuilder::remove().values(keys.clone()).ids(existing.id).query())?;
+            sync::replace_values(t, existing.id, vec![modified.clone()])
+        })?;
+        self.emit_edge_attributes_changed(edge);
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn emit_edge_attributes_changed(&mut self, edge: &Edge) {
+        self.emit(GraphEvent::EdgeAttributesChanged {
+            source: edge.source().clone(),
+            target: edge.target().clone(),
+            contains: edge.contains(),
+        });
+    }
+
+    fn check_endpoints(&self, source: &NodePath, target: &NodePath) -> Result<(), GraphError> {
+        for path in [source, target] {
+            if self.db.exec(&QueryBuilder::select().ids(path.alias()).query()).is_err() {
+                return Err(GraphError::NodeNotFound(path.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the edge from source to target with its values. Two nodes can be
+    /// connected by both a contains edge and a link, so the kind must be given.
+    fn find_edge(&self, source: &NodePath, target: &NodePath, contains: bool) -> Option<DbElement> {
+        let search = QueryBuilder::search()
+            .from(source.alias())
+            .where_()
+            .edge()
+            .and()
+            .distance(CountComparison::Equal(1))
+            .query();
+        let result = self.db.exec(&QueryBuilder::select().ids(search).query()).ok()?;
+
+        let target: DbValue = target.clone().into();
+        result.elements.into_iter().find(|elem| {
+            let to = elem.values.iter().find(|kv| kv.key == "target".into());
+            let is_contains = elem.values.iter().any(|kv| kv.key == "contains".into());
+            to.map_or(false, |kv| kv.value == target) && is_contains == contains
+        })
+    }
+}
@@ -1,22 +1,26 @@
 use std::{error::Error, path::PathBuf, vec};
 
-use agdb::{DbElement, DbId, QueryBuilder};
+use agdb::{DbElement, DbId, DbUserValue, QueryBuilder};
 
 use crate::{
     elements::{self, edge::Edge, nodetype::NodeType},
+    error::GraphError,
+    events::GraphEvent,
+    filter::Filter,
     graph_traits::graph_node::GraphNode,
     prelude::GraphCore,
 };
 
 use super::{
     attribute::{Attribute, RESERVED_NODE_ATTRS},
+    in_history,
     node::Node,
     node_path::NodePath,
     GraphAgdb, StoragePath,
 };
 
 impl GraphNode for GraphAgdb {
-    fn open_node(&self, path: &NodePath) -> Result<Node, Box<dyn Error>> {
+    fn open_node(&self, path: &NodePath) -> Result<Node, GraphError> {
         let alias = path.alias();
 
         let node = self.db.exec(&QueryBuilder::select().ids(alias).query());
@@ -30,7 +34,7 @@ impl GraphNode for GraphAgdb {
                 Ok(node.unwrap())
             }
             Err(_err) => {
-                return Err("Could not open node".into());
+                return Err(GraphError::NodeNotFound(path.clone()));
             }
         }
     }
@@ -156,28 +160,38 @@ impl GraphNode for GraphAgdb {
         connections
     }
 
+    fn search_nodes(&self, filter: &Filter) -> Result<Vec<Node>, GraphError> {
+        let mut search = QueryBuilder::search().elements().query();
+        search.conditions = filter.node_conditions();
+
+        let result = self.db.exec(&QueryBuilder::select().ids(search).query())?;
+
+        let nodes = result
+            .elements
+            .into_iter()
+            .filter(|elem| filter.is_exact() || filter.matches_element(elem))
+            .map(|elem| Node::try_from(elem))
+            .collect::<Result<Vec<Node>, _>>()?;
+
+        Ok(nodes.into_iter().filter(|node| !in_history(&node.path())).collect())
+    }
+
     fn create_node_by_path(
         &mut self,
         path: &NodePath,
         ntype: Option<NodeType>,
-    ) -> Result<Node, Box<dyn Error>> {
+    ) -> Result<Node, GraphError> {
         let full_path = path.full(&self.root_path);
         let alias = path.alias();
 
         // Check if the node already exists in the db.
         // If it does, don't insert it, and return an error.
-        // Possibly redundant, unless used for updating an existing node.
         let existing = self
             .db
             .exec(&QueryBuilder::select().ids(alias.clone()).query());
 
-        match existing {
-            Ok(_) => {
-                // return Err("node already exists".into());
-            }
-            Err(_e) => {
-                // Node doesn't exist, proceed to insertion
-            }
+        if existing.is_ok() {
+            return Err(GraphError::AlreadyExists(format!("Node {}", alias)));
         }
 
         // Determine type of node. If not specified, it's an Other node.
@@ -218,26 +232,30 @@ impl GraphNode for GraphAgdb {
                 let parent_path = path.parent();
                 match parent_path {
                     Some(parent_path) => {
-                        if parent_path.parent().is_some() {
+                        let parent_exists = self
+                            .db
+                            .exec(&QueryBuilder::select().ids(parent_path.alias()).query())
+                            .is_ok();
+
+                        if !parent_exists {
                             println!("About to insert parent node: {:?}", parent_path);
 
                             let n = self.create_node_by_path(&parent_path, Some(NodeType::other()));
 
-                            match n {
-                                Ok(n) => {
-                                    let parent_path = n.path();
-                                    self.autoparent_nodes(&parent_path, &path);
-                                }
-                                Err(e) => {
-                                    println!("Failed to insert parent node: {}", e);
-                                }
+                            if let Err(e) = n {
+                                println!("Failed to insert parent node: {}", e);
+                                self.emit(GraphEvent::NodeCreated(path.clone()));
+                                return Ok(node);
                             }
                         }
+                        self.autoparent_nodes(&parent_path, &path)?;
+                        self.emit(GraphEvent::NodeCreated(path.clone()));
                         Ok(node)
                     }
                     None => {
                         // If the parent is root, parent them and move along.
                         self.autoparent_nodes(&NodePath::new(PathBuf::from("")), &path);
+                        self.emit(GraphEvent::NodeCreated(path.clone()));
                         Ok(node)
                     }
                 }
@@ -257,7 +275,7 @@ impl GraphNode for GraphAgdb {
         parent_path: Option<NodePath>,
         name: &str,
         ntype: Option<NodeType>,
-    ) -> Result<Node, Box<dyn Error>> {
+    ) -> Result<Node, GraphError> {
         let parent_path = parent_path.unwrap_or_else(|| NodePath::new("".into()));
 
         let rel_path = if parent_path.buf().as_os_str().is_empty() {
@@ -269,9 +287,55 @@ impl GraphNode for GraphAgdb {
         self.create_node_by_path(&rel_path, ntype)
     }
 
-    /// Inserts a Node.
-    fn insert_node(&mut self, node: Node) -> Result<(), Box<dyn Error>> {
-        todo!()
+    /// Inserts a Node with all of its values and attributes. If a node already exists
+    /// at the path, its values are updated instead. Missing ancestors are created the
+    /// same way as in create_node_by_path.
+    fn insert_node(&mut self, node: Node) -> Result<(), GraphError> {
+        let path = node.path();
+        let alias = path.alias();
+
+        // The nodes under the other archetypes hold the schema and settings,
+        // whose values aren't attributes of the user's nodes.
+        if path.buf().starts_with(NodePath::user_root().buf()) {
+            self.validate_attrs(Some(&node.ntype_name()), &node.attributes())?;
+        }
+
+        let existing = self
+            .db
+            .exec(&QueryBuilder::select().ids(alias.clone()).query())
+            .is_ok();
+
+        let parent = path.parent();
+        if !existing {
+            if let Some(parent) = &parent {
+                if self.open_node(parent).is_err() {
+                    self.create_node_by_path(parent, Some(NodeType::other()))?;
+                }
+            }
+        }
+
+        if existing {
+            self.db.replace_values(alias, node.to_db_values())?;
+        } else {
+            self.db.exec_mut(
+                &QueryBuilder::insert()
+                    .nodes()
+                    .aliases(alias)
+                    .values(&node)
+                    .query(),
+            )?;
+        }
+
+        if !existing {
+            if let Some(parent) = parent {
+                self.autoparent_nodes(&parent, &path)?;
+            }
+            self.emit(GraphEvent::NodeCreated(path));
+        } else {
+            self.emit(GraphEvent::AttributesChanged(path));
+        }
+
+        Ok(())
     }
 
     /// Deletes a node. Error if trying to delete root or archetype nodes.
@@ -286,24 +350,44 @@ impl GraphNode for GraphAgdb {
         paths: &Vec<NodePath>,
         files: bool,
         dirs: bool,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), GraphError> {
         let aliases = paths
             .iter()
             .map(|path| path.alias())
             .collect::<Vec<String>>();
 
+        // Nodes linking to the deleted ones lose those edges, so their readable
+        // files need to be rewritten as well.
+        let linking: Vec<NodePath> = match self.maintain_readable_files {
+            true => paths
+                .iter()
+                .flat_map(|path| self.node_edges(path).unwrap_or_default())
+                .map(|edge| edge.source().clone())
+                .filter(|source| !paths.contains(source))
+                .collect(),
+            false => Vec::new(),
+        };
+
         let query = self
             .db
             .exec_mut(&QueryBuilder::remove().ids(aliases).query());
 
         match query {
-            Ok(query) => Ok(()),
+            Ok(query) => {
+                for path in paths {
+                    self.emit(GraphEvent::NodeDeleted(path.clone()));
+                }
+                self.update_readable_files(&linking);
+                Ok(())
+            }
             Err(e) => Err(e.into()),
         }
     }
 
     /// Is this even needed? Does open node get all attributes?
-    fn get_node_attrs(&self, path: &NodePath) -> Result<Vec<Attribute>, Box<dyn Error>> {
+    /// Returns the same attributes as the opened node, so the fixed values
+    /// (path, ntype, etc.) are not included.
+    fn get_node_attrs(&self, path: &NodePath) -> Result<Vec<Attribute>, GraphError> {
         let alias = path.alias();
         let keys = Vec::new();
         let attrs = self
@@ -314,25 +398,13 @@ impl GraphNode for GraphAgdb {
             Ok(attrs) => {
                 let mut attrs = attrs.elements;
                 assert!(attrs.len() == 1);
-                let vec = attrs
-                    .first()
-                    .unwrap()
-                    .values
-                    .iter()
-                    .map(|attr| {
-                        let attr = attr.to_owned();
-                        Attribute {
-                            name: attr.key.to_string(),
-                            value: attr.value.to_f64().unwrap().to_f64() as f32,
-                        }
-                    })
-                    .collect();
+                let node = Node::try_from(attrs.remove(0))?;
 
-                return Ok(vec);
+                return Ok(node.attributes());
             }
             Err(e) => {
                 println!("Failed to get attributes: {}", e);
-                return Err(e.to_string().into());
+                return Err(GraphError::NodeNotFound(path.clone()));
             }
         }
     }
@@ -341,26 +413,22 @@ impl GraphNode for GraphAgdb {
         &mut self,
         path: &NodePath,
         attrs: Vec<Attribute>,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), GraphError> {
         use RESERVED_NODE_ATTRS;
 
         // Check if the node exists. If it doesn't, errrrrrrr
         let alias = path.alias();
-        let node = self
-            .db
-            .exec(&QueryBuilder::select().ids(alias.clone()).query());
-        match node {
-            Ok(node) => {}
-            Err(e) => {
-                return Err(e.into());
-            }
-        }
+        let node = self.open_node(path)?;
 
         // Error if attributes is empty
         if attrs.is_empty() {
-            return Err("Attributes cannot be empty".into());
+            return Err(GraphError::InvalidOperation("Attributes cannot be empty".to_string()));
         }
 
+        // Reserved names can't have definitions, so they pass through here
+        // and are filtered out below.
+        self.validate_attrs(Some(&node.ntype_name()), &attrs)?;
+
         let filtered_attrs = attrs
             .iter()
             .filter(|attr| {
@@ -373,32 +441,26 @@ impl GraphNode for GraphAgdb {
                 return true;
             })
             .map(|attr| {
-                let attr = (attr.name.clone(), attr.value).into();
+                let attr = attr.into();
                 return attr;
             })
             .collect::<Vec<agdb::DbKeyValue>>();
 
         // Error if filtered attrs is empty
         if filtered_attrs.is_empty() {
-            return Err("All insertion requests were for protected attributes".into());
+            return Err(GraphError::ReservedAttribute(attrs[0].name.clone()));
         }
 
-        let added = self.db.exec_mut(
-            &QueryBuilder::insert()
-                .values(vec![filtered_attrs])
-                .ids(alias)
-                .query(),
-        );
+        let added = self.db.replace_values(alias, filtered_attrs);
 
         println!("Added: {:?}", added);
 
         match added {
-            query_result => {
-                return Ok(());
-            }
-            query_error => {
-                return Err("Failed to insert attribute".into());
+            Ok(_) => {
+                self.emit(GraphEvent::AttributesChanged(path.clone()));
+                Ok(())
             }
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -406,11 +468,11 @@ impl GraphNode for GraphAgdb {
         &mut self,
         path: &NodePath,
         attr_names: Vec<&str>,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), GraphError> {
         use RESERVED_NODE_ATTRS;
 
         if attr_names.len() == 0 {
-            return Err("No attributes to delete".into());
+            return Err(GraphError::InvalidOperation("No attributes to delete".to_string()));
         }
 
         // Protect reserved attribute names
@@ -421,7 +483,7 @@ impl GraphNode for GraphAgdb {
             .collect();
 
         if filtered_attrs.len() == 0 {
-            return Err("All deletion requests were for protected attributes".into());
+            return Err(GraphError::ReservedAttribute(attr_names[0].to_string()));
         }
 
         let node = self.db.exec_mut(
@@ -432,13 +494,16 @@ impl GraphNode for GraphAgdb {
         );
 
         match node {
-            Ok(node) => Ok(()),
+            Ok(node) => {
+                self.emit(GraphEvent::AttributesChanged(path.clone()));
+                Ok(())
+            }
             Err(e) => Err(e.into()),
         }
     }
 
     /// Merges a vector of nodes into the last one.
-    fn merge_nodes(&mut self, nodes: Vec<NodePath>) -> Result<(), Box<dyn Error>> {
+    fn merge_nodes(&mut self, nodes: Vec<NodePath>) -> Result<(), GraphError> {
         Ok(())
     }
 
@@ -452,7 +517,7 @@ impl GraphNode for GraphAgdb {
         &mut self,
         parent: &NodePath,
         child: &NodePath,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), GraphError> {
         println!("Autoparenting nodes: {:?} and {:?}", parent, child);
         let edge = Edge::new_cont(parent, child);
 
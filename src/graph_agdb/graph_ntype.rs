@@ -1,21 +1,151 @@
-use std::{error::Error, path::PathBuf};
+use std::path::PathBuf;
 
-use agdb::QueryBuilder;
+use agdb::{CountComparison, QueryBuilder};
 
-use crate::{graph_traits::graph_ntype::GraphNtype, elements::nodetype::NodeType};
+use crate::{
+    elements::nodetype::{NodeType, NodeTypeDef, NODETYPE_TYPE, PARENT_TYPE_KEY},
+    error::GraphError,
+    graph_traits::{graph_core::GraphCore, graph_node::GraphNode, graph_ntype::GraphNtype},
+};
 
-use super::{GraphAgdb, StoragePath};
+use super::{attribute::{Attribute, RESERVED_NODE_ATTRS}, node::Node, node_path::NodePath, GraphAgdb, StoragePath};
+
+/// Types that the graph assigns by itself. They can't be defined by users.
+const BUILTIN_NODETYPES: [&str; 7] = ["Root", "Archetype", "Directory", "File", "Other", NODETYPE_TYPE, "HistoryEntry"];
 
 impl GraphNtype for GraphAgdb {
-    fn get_node_types(&self) -> Result<Vec<NodeType>, Box<dyn Error>> {
-        todo!()
+    fn get_node_types(&self) -> Result<Vec<NodeTypeDef>, GraphError> {
+        let search = QueryBuilder::search()
+            .from(NodePath::atype("nodetypes").alias())
+            .where_()
+            .node()
+            .and()
+            .distance(CountComparison::Equal(2))
+            .query();
+        let result = self.db.exec(&QueryBuilder::select().ids(search).query())?;
+
+        let mut types: Vec<NodeTypeDef> = Vec::new();
+        for elem in result.elements {
+            let node = Node::try_from(elem)?;
+            if node.ntype_name().name() == NODETYPE_TYPE {
+                types.push(NodeTypeDef::try_from(node)?);
+            }
+        }
+
+        Ok(types)
+    }
+
+    fn open_nodetype(&self, ntype: &NodeType) -> Result<NodeTypeDef, GraphError> {
+        let node = self
+            .open_node(&NodeTypeDef::path(ntype))
+            .map_err(|_| GraphError::NodeNotFound(NodeTypeDef::path(ntype)))?;
+        Ok(NodeTypeDef::try_from(node)?)
+    }
+
+    fn create_nodetype(&mut self, nodetype: NodeTypeDef) -> Result<NodeTypeDef, GraphError> {
+        let node = self.nodetype_node(&nodetype)?;
+        self.insert_node(node)?;
+        Ok(nodetype)
     }
 
-    fn create_nodetype(&mut self, nodetype: NodeType) -> Result<NodeType, Box<dyn Error>> {
-        todo!()
+    fn get_nodetype_attrs(&self, ntype: &NodeType) -> Result<Vec<Attribute>, GraphError> {
+        nodetype_attrs(self, ntype)
     }
 
-    fn instance_nodetype(&self) {
-        todo!()
+    fn instance_nodetype(&mut self, path: &NodePath, ntype: &NodeType) -> Result<Node, GraphError> {
+        let node = self.nodetype_instance(path, ntype)?;
+        self.insert_node(node)?;
+        self.open_node(path)
     }
-}
\ No newline at end of file
+}
+
+impl GraphAgdb {
+    /// Checks a node type definition and turns it into the node that stores it.
+    pub(crate) fn nodetype_node(&self, nodetype: &NodeTypeDef) -> Result<Node, GraphError> {
+        nodetype_node(self, nodetype)
+    }
+
+    /// Builds a node of the given type with the defaults of the type, without
+    /// inserting it. Errors if the path is already taken.
+    pub(crate) fn nodetype_instance(&self, path: &NodePath, ntype: &NodeType) -> Result<Node, GraphError> {
+        nodetype_instance(self, path, ntype)
+    }
+}
+
+// The rules for node types don't depend on the backend, so they are shared with GraphFiles.
+
+/// The attributes of a type and its parent types, with the more specific
+/// types overriding the defaults of their parents.
+pub(crate) fn nodetype_attrs<G: GraphNtype>(graph: &G, ntype: &NodeType) -> Result<Vec<Attribute>, GraphError> {
+    // Walk up to the topmost parent first, so that the more specific types
+    // override the defaults of their parents.
+    let mut chain: Vec<NodeTypeDef> = Vec::new();
+    let mut next = Some(ntype.clone());
+    while let Some(ntype) = next {
+        if chain.iter().any(|def| *def.ntype() == ntype) {
+            return Err(GraphError::SchemaViolation(format!("Node type {} inherits from itself", ntype.name())));
+        }
+        let def = graph.open_nodetype(&ntype)?;
+        next = def.parent().cloned();
+        chain.push(def);
+    }
+
+    let mut attrs: Vec<Attribute> = Vec::new();
+    for def in chain.iter().rev() {
+        for attr in def.attributes() {
+            match attrs.iter_mut().find(|existing| existing.name == attr.name) {
+                Some(existing) => *existing = attr.clone(),
+                None => attrs.push(attr.clone()),
+            }
+        }
+    }
+
+    Ok(attrs)
+}
+
+/// Checks a node type definition and turns it into the node that stores it.
+pub(crate) fn nodetype_node<G: GraphNtype>(graph: &G, nodetype: &NodeTypeDef) -> Result<Node, GraphError> {
+    let name = nodetype.ntype().name();
+    if name.is_empty() || name.contains('/') {
+        return Err(GraphError::InvalidPath(format!("Invalid node type name: {}", name)));
+    }
+    if BUILTIN_NODETYPES.contains(&name) {
+        return Err(GraphError::SchemaViolation(format!("{} is a built-in node type", name)));
+    }
+    if graph.open_nodetype(nodetype.ntype()).is_ok() {
+        return Err(GraphError::AlreadyExists(format!("Node type {}", name)));
+    }
+    if let Some(parent) = nodetype.parent() {
+        graph.open_nodetype(parent)?;
+    }
+    if let Some(attr) = nodetype
+        .attributes()
+        .iter()
+        .find(|attr| RESERVED_NODE_ATTRS.contains(&attr.name.as_str()) || attr.name == PARENT_TYPE_KEY)
+    {
+        return Err(GraphError::ReservedAttribute(attr.name.clone()));
+    }
+
+    Ok(nodetype.to_node())
+}
+
+/// Builds a node of the given type with the defaults of the type, without
+/// inserting it. Errors if the path is already taken.
+pub(crate) fn nodetype_instance<G: GraphCore + GraphNode + GraphNtype>(
+    graph: &G,
+    path: &NodePath,
+    ntype: &NodeType,
+) -> Result<Node, GraphError> {
+    let attrs = graph.get_nodetype_attrs(ntype)?;
+
+    if graph.open_node(path).is_ok() {
+        return Err(GraphError::AlreadyExists(format!("Node {}", path.alias())));
+    }
+    if path.full(&graph.user_root_dirpath()).exists() {
+        return Err(GraphError::AlreadyExists(format!("Path {} in the file system", path.alias())));
+    }
+
+    let mut node = Node::new(path, ntype.clone());
+    node.insert_attributes(attrs);
+    Ok(node)
+}
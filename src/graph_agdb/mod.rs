@@ -1,15 +1,52 @@
-use std::{error::Error, path::PathBuf};
+use std::{error::Error, fs::{File, TryLockError}, panic::{self, AssertUnwindSafe}, path::{Path, PathBuf}, sync::mpsc::Receiver};
 
-use agdb::{CountComparison, DbElement, DbError, DbId, DbUserValue, QueryBuilder, QueryError};
-use crate::graph_traits::{Graph, StoragePath};
+use agdb::{CountComparison, DbElement, DbError, DbId, DbKeyValue, DbUserValue, Query, QueryBuilder, QueryError, QueryMut, QueryResult};
+use crate::graph_traits::{graph_core::GraphCore, Graph, StoragePath};
 
-use crate::{elements, elements::nodetype::NodeType};
-use elements::*;
+use crate::{config::{DbBackend, GraphConfig}, elements, elements::nodetype::NodeType, error::GraphError, events::{EventBus, EventFilter, GraphEvent, Subscription}};
+use elements::{*, node_path::NodePath};
+use transaction::TransactionPtr;
 
+/// Runs the closure in a mutable transaction on whichever backend the db uses.
+/// This is a macro rather than a method of GraphDb, because the type of the
+/// transaction differs between the backends and a closure can't be generic over it.
+/// The closure is expanded once per backend, so the helpers it calls must be
+/// generic over StorageData, like the ones in sync.
+///
+/// Inside GraphAgdb::transaction, the closure runs in the transaction that is
+/// already open, and is only rolled back along with all of it.
+macro_rules! transaction_mut {
+    ($db:expr, |$t:ident| -> $ret:ty { $($body:tt)* }) => {
+        match $db {
+            $crate::graph_agdb::GraphDb::Mem(db) => db.transaction_mut(|$t| -> $ret { $($body)* }),
+            $crate::graph_agdb::GraphDb::Mapped(db) => db.transaction_mut(|$t| -> $ret { $($body)* }),
+            $crate::graph_agdb::GraphDb::File(db) => db.transaction_mut(|$t| -> $ret { $($body)* }),
+            $crate::graph_agdb::GraphDb::Transaction(open) => match open.ptr {
+                // The pointer is valid while it is set, see transaction.rs.
+                Some($crate::graph_agdb::transaction::TransactionPtr::Mem(ptr)) => {
+                    (|$t: &mut agdb::DbMemoryTransactionMut| -> $ret { $($body)* })(unsafe { &mut *ptr })
+                }
+                Some($crate::graph_agdb::transaction::TransactionPtr::Mapped(ptr)) => {
+                    (|$t: &mut agdb::DbTransactionMut| -> $ret { $($body)* })(unsafe { &mut *ptr })
+                }
+                Some($crate::graph_agdb::transaction::TransactionPtr::File(ptr)) => {
+                    (|$t: &mut agdb::DbFileTransactionMut| -> $ret { $($body)* })(unsafe { &mut *ptr })
+                }
+                None => Err(agdb::QueryError::from("The db is in use by a transaction").into()),
+            },
+        }
+    };
+}
+
+pub (crate) mod graph_attr;
 pub (crate) mod graph_core;
 pub (crate) mod graph_ntype;
 pub (crate) mod graph_node;
 pub (crate) mod graph_edge;
+pub (crate) mod readable;
+pub (crate) mod settings;
+pub (crate) mod sync;
+pub (crate) mod transaction;
 
 /// The main graph structure to be interacted with.
 ///
@@ -20,7 +57,7 @@ pub struct GraphAgdb {
 
     /// AGDB database.
     /// Set to public, though direct access to the db is discouraged.
-    db: agdb::Db,
+    db: GraphDb,
 
     /// Path to the root directory of the graph.
     /// All paths are relative to this root.
@@ -31,6 +68,9 @@ pub struct GraphAgdb {
     /// Includes the name of the directory.  
     storage_path: StoragePath,
 
+    /// The directory the storage path resolved to when the graph was opened.
+    storage_dir: PathBuf,
+
     /// Whether the library should maintain readable files for the nodes
     /// in the graph.
     ///
@@ -38,33 +78,287 @@ pub struct GraphAgdb {
     /// mirrors the directory structure starting from the root path.
     /// TODO: Should this be behind a feature flag?
     maintain_readable_files: bool,
+
+    /// Subscribers to the changes made to the graph.
+    events: EventBus,
+
+    /// Lock file held while the graph is open, so that the db isn't opened
+    /// twice. Only taken by GraphAgdb::open.
+    lock: Option<File>,
+
+    /// Files moved during the current transaction, to move back if it is
+    /// rolled back. None outside of transactions.
+    moved_files: Option<Vec<(PathBuf, PathBuf)>>,
 }
 
 
 /// Agdb has multiple implementations. If the size of the database is small enough, it can be stored in memory.
-/// If the database is too large, it can be stored in a file.
-/// TODO: Not in use currently.
-enum GraphDb {
-    Mem(agdb::Db),
+/// If the database is too large, it can be stored in a file. The backend is chosen
+/// in the GraphConfig, and the graph behaves the same with all of them.
+pub enum GraphDb {
+    /// Kept only in memory and lost when the graph is dropped.
+    Mem(agdb::DbMemory),
+    /// Stored in a file and mapped to memory. The default.
+    Mapped(agdb::Db),
+    /// Read from and written to the file directly, without keeping the db in memory.
     File(agdb::DbFile),
+    /// Takes the place of the db while the graph runs several changes in one
+    /// transaction of it. Never seen outside of the graph functions.
+    #[doc(hidden)]
+    Transaction(transaction::OpenTransaction),
+}
+
+impl GraphDb {
+    /// Opens the db at the path with the backend, or creates it. A memory db
+    /// doesn't touch the file system.
+    fn new(backend: DbBackend, path: &str) -> Result<Self, DbError> {
+        Ok(match backend {
+            DbBackend::Memory => GraphDb::Mem(agdb::DbMemory::new(path)?),
+            DbBackend::MemoryMapped => GraphDb::Mapped(agdb::Db::new(path)?),
+            DbBackend::File => GraphDb::File(agdb::DbFile::new(path)?),
+        })
+    }
+
+    pub fn backend(&self) -> DbBackend {
+        match self {
+            GraphDb::Mem(_) => DbBackend::Memory,
+            GraphDb::Mapped(_) => DbBackend::MemoryMapped,
+            GraphDb::File(_) => DbBackend::File,
+            GraphDb::Transaction(open) => open.backend,
+        }
+    }
+
+    /// Executes an immutable query. See agdb::Db::exec.
+    pub fn exec<T: Query>(&self, query: &T) -> Result<QueryResult, QueryError> {
+        match self {
+            GraphDb::Mem(db) => db.exec(query),
+            GraphDb::Mapped(db) => db.exec(query),
+            GraphDb::File(db) => db.exec(query),
+            GraphDb::Transaction(open) => match open.ptr {
+                // The pointer is valid while it is set, see transaction.rs.
+                Some(TransactionPtr::Mem(ptr)) => unsafe { (*ptr).exec(query) },
+                Some(TransactionPtr::Mapped(ptr)) => unsafe { (*ptr).exec(query) },
+                Some(TransactionPtr::File(ptr)) => unsafe { (*ptr).exec(query) },
+                None => Err(QueryError::from("The db is in use by a transaction")),
+            },
+        }
+    }
+
+    /// Executes a mutable query. See agdb::Db::exec_mut.
+    pub fn exec_mut<T: QueryMut>(&mut self, query: &T) -> Result<QueryResult, QueryError> {
+        match self {
+            GraphDb::Mem(db) => db.exec_mut(query),
+            GraphDb::Mapped(db) => db.exec_mut(query),
+            GraphDb::File(db) => db.exec_mut(query),
+            GraphDb::Transaction(open) => match open.ptr {
+                Some(TransactionPtr::Mem(ptr)) => unsafe { (*ptr).exec_mut(query) },
+                Some(TransactionPtr::Mapped(ptr)) => unsafe { (*ptr).exec_mut(query) },
+                Some(TransactionPtr::File(ptr)) => unsafe { (*ptr).exec_mut(query) },
+                None => Err(QueryError::from("The db is in use by a transaction")),
+            },
+        }
+    }
+
+    /// Inserts values into an existing element, replacing the ones with the
+    /// same keys. See sync::replace_values for why this isn't a plain insert.
+    pub(crate) fn replace_values(&mut self, id: impl Into<agdb::QueryId>, values: Vec<DbKeyValue>) -> Result<(), QueryError> {
+        let id: agdb::QueryId = id.into();
+        transaction_mut!(self, |t| -> Result<(), QueryError> {
+            sync::replace_values(t, id.clone(), values.clone())
+        })
+    }
+
+    /// The size of the db in bytes. During a transaction, the size from
+    /// before it started.
+    pub fn size(&self) -> u64 {
+        match self {
+            GraphDb::Mem(db) => db.size(),
+            GraphDb::Mapped(db) => db.size(),
+            GraphDb::File(db) => db.size(),
+            GraphDb::Transaction(open) => open.size,
+        }
+    }
 }
 
 impl Graph for GraphAgdb {}
 
 impl GraphAgdb {
+    /// Opens the graph described by the config, creating the db if it doesn't
+    /// exist yet. Unlike GraphCore::new, reports why the graph couldn't be opened:
+    /// the root directory is missing, a path isn't UTF-8, the db is already open
+    /// elsewhere or the db file is corrupted.
+    pub fn open(config: GraphConfig) -> Result<Self, GraphError> {
+        GraphAgdb::open_with(config, true)
+    }
+
+    /// Checked opens also validate the root and lock the db.
+    pub(crate) fn open_with(config: GraphConfig, checked: bool) -> Result<Self, GraphError> {
+        let GraphConfig { name, root_path, storage_path: storage_enum, maintain_readable_files, backend } = config;
+
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(GraphError::InvalidPath(format!("Invalid graph name: {:?}", name)));
+        }
+        if root_path.to_str().is_none() {
+            return Err(GraphError::NonUtf8Path(root_path));
+        }
+        // The name of the root directory becomes the name of the user_root
+        if root_path.file_name().is_none() {
+            return Err(GraphError::InvalidPath(format!("Root {} has no directory name", root_path.display())));
+        }
+        // The storage may be inside the root, so check the root before creating it
+        if checked && !root_path.is_dir() {
+            return Err(GraphError::RootNotFound(root_path));
+        }
+
+        let storage_path = resolve_storage_dir(&storage_enum)?;
+        let db_path = storage_path.join(format!("{}.agdb", name));
+        let Some(db_str) = db_path.to_str() else {
+            return Err(GraphError::NonUtf8Path(db_path));
+        };
+
+        // A memory db has no files to create or protect
+        let in_memory = backend == DbBackend::Memory;
+        if !in_memory {
+            std::fs::create_dir_all(&storage_path)?;
+        }
+
+        let lock = if checked && !in_memory {
+            Some(lock_db(&storage_path.join(format!(".{}.lock", name)), &db_path)?)
+        } else {
+            None
+        };
+
+        // Check if the database already exists
+        let open_existing = !in_memory && db_path.exists();
+
+        // Agdb panics on some malformed files instead of returning an error
+        let db = match panic::catch_unwind(AssertUnwindSafe(|| GraphDb::new(backend, db_str))) {
+            Ok(Ok(db)) => db,
+            Ok(Err(err)) if open_existing => {
+                return Err(GraphError::DbCorrupted { path: db_path, reason: err.to_string() })
+            }
+            Ok(Err(err)) => return Err(GraphError::Db(err)),
+            Err(_) => {
+                return Err(GraphError::DbCorrupted { path: db_path, reason: "the file is not a valid db".to_string() })
+            }
+        };
+
+        let mut giraphe = GraphAgdb {
+            name,
+            db,
+            root_path,
+            storage_path: storage_enum,
+            storage_dir: storage_path,
+            maintain_readable_files: false,
+            events: EventBus::default(),
+            lock,
+            moved_files: None,
+        };
+
+        let has_root = giraphe.db.exec(&QueryBuilder::select().ids(NodePath::root().alias()).query()).is_ok();
+        if !has_root {
+            // A new or empty db is initialised, but one with other nodes is broken
+            let aliases = giraphe.db.exec(&QueryBuilder::select().aliases().query())?;
+            if aliases.result > 0 {
+                return Err(GraphError::DbCorrupted { path: db_path, reason: "the root node is missing".to_string() });
+            }
+            giraphe.init_archetype_nodes()?;
+        }
+
+        match maintain_readable_files {
+            // The db was lost but its readable files weren't, so the graph is
+            // recovered from them instead of overwriting them with an empty one.
+            Some(true) if !has_root && giraphe.readable_dir().is_dir() => {
+                giraphe.import_readable_files(None)?;
+                giraphe.maintain_readable_files(true)?;
+            }
+            Some(maintain) => giraphe.maintain_readable_files(maintain)?,
+            None => {
+                if let Ok(Some(attribute::AttributeValue::Bool(maintain))) = giraphe.get_setting(settings::MAINTAIN_READABLE_FILES) {
+                    giraphe.maintain_readable_files = maintain;
+                }
+            }
+        }
+
+        Ok(giraphe)
+    }
+
     /// Direct getter for the db. Not recommended to use. If possible, 
     /// use the other implemented functions. They are the intended way
     /// of interacting with the db.
-    pub fn db(&self) -> &agdb::Db {
+    pub fn db(&self) -> &GraphDb {
         &self.db
     }
 
     /// Direct mutable getter for the db. Not recommended to use. If possible,
     /// use the other implemented functions. They are the intended way
     /// of interacting with the db.
-    pub fn db_mut(&mut self) -> &mut agdb::Db {
+    pub fn db_mut(&mut self) -> &mut GraphDb {
         &mut self.db
     }
+
+    /// Subscribes to the changes made to the graph, optionally only to the ones
+    /// matching the filter. The events arrive on the returned receiver.
+    pub fn subscribe(&mut self, filter: Option<EventFilter>) -> (Subscription, Receiver<GraphEvent>) {
+        self.events.subscribe(filter)
+    }
+
+    /// Ends a subscription. Returns false if it had already ended.
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        self.events.unsubscribe(subscription)
+    }
+
+    /// Keeps the readable files up to date and sends the event to the subscribers.
+    pub(crate) fn emit(&mut self, event: GraphEvent) {
+        self.update_readable_files_for(&event);
+        self.events.emit(event);
+    }
+
+    /// The directory the db is stored in.
+    pub(crate) fn storage_dir(&self) -> PathBuf {
+        self.storage_dir.clone()
+    }
+
+    /// The db file, its write-ahead log and the readable files. The db may be
+    /// stored inside the vault, but these files are never part of the graph.
+    pub(crate) fn db_files(&self) -> Vec<PathBuf> {
+        let storage_dir = self.storage_dir();
+        vec![
+            storage_dir.join(format!("{}.agdb", self.name)),
+            storage_dir.join(format!(".{}.agdb", self.name)),
+            storage_dir.join(format!(".{}.lock", self.name)),
+            self.readable_dir(),
+        ]
+    }
+}
+
+/// Whether the path is in the command history. The history is stored under
+/// its own archetype, but it isn't part of the graph the user sees, so it is
+/// left out of searches.
+pub(crate) fn in_history(path: &NodePath) -> bool {
+    path.buf().starts_with(NodePath::atype("history").buf())
+}
+
+/// The directory a db is stored in. The default is the data directory of the
+/// operating system, which might not be available.
+pub(crate) fn resolve_storage_dir(storage_path: &StoragePath) -> Result<PathBuf, GraphError> {
+    match storage_path {
+        StoragePath::Custom(path) => Ok(path.clone()),
+        StoragePath::Default => directories::ProjectDirs::from("com", "teodosin_labs", "fs_graph")
+            .map(|dirs| dirs.data_dir().to_path_buf())
+            .ok_or_else(|| GraphError::InvalidPath("No default storage directory on this system".to_string())),
+    }
+}
+
+/// Takes an exclusive lock on the lock file of a db. The lock is released when
+/// the file is dropped, including when the process exits.
+pub(crate) fn lock_db(lock_path: &Path, db_path: &Path) -> Result<File, GraphError> {
+    let file = File::options().create(true).truncate(false).write(true).open(lock_path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(GraphError::DbLocked(db_path.to_path_buf())),
+        Err(TryLockError::Error(err)) => Err(GraphError::Io(err)),
+    }
 }
 
 // ------------------------------------------------------------------
@@ -77,6 +371,9 @@ impl GraphAgdb {
 // Update: the conversion has happened, and the trait impls are now in submodules. 
 // The rest of the basic impl will be commented out until it's safe to remove. 
 
+// Update: GraphFiles in graph_files stores the graph as text files, and
+// implements the same traits.
+
 
 
 
@@ -0,0 +1,306 @@
+/*
+Readable files that mirror the metadata of the graph.
+
+When maintain_readable_files is on, every node gets a RON file in a directory
+next to the db, at the same relative path as the node. The file holds the node
+with its type, attributes and times, and its outgoing edges. This keeps the
+metadata inspectable and diffable without agdb, and allows it to be recovered.
+
+The files are updated whenever a change to the graph is emitted as an event,
+and when the file system sync touches a node. The command history is not
+mirrored, as it lives only in the db.
+
+The files can also be read back into a graph, to rebuild a lost db. This is
+done on open when readable files are maintained and the db is missing. Nodes of
+files and directories are compared against the file system as they are read,
+and any differences are reported rather than fixed.
+
+GraphFiles stores its nodes in the same format, so the two backends can read
+each other's files.
+*/
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    elements::{edge::Edge, node::Node, node_path::NodePath, nodetype::NodeType},
+    error::GraphError,
+    events::GraphEvent,
+    filter::Filter,
+    indexing::Fingerprint,
+    graph_traits::{
+        graph_core::{ImportReport, ReadableConflict},
+        graph_edge::GraphEdge,
+        graph_node::GraphNode,
+    },
+};
+
+use super::{in_history, GraphAgdb};
+
+/// Contents of the readable file of a node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ReadableNode {
+    pub node: Node,
+    /// Edges going out from the node, including the contains edges to its children.
+    pub edges: Vec<Edge>,
+    /// Fingerprint of the file of the node. Only stored by GraphFiles, the
+    /// readable files of GraphAgdb leave it out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl GraphAgdb {
+    /// The directory of the readable files, next to the db.
+    pub(crate) fn readable_dir(&self) -> PathBuf {
+        self.storage_dir().join(format!("{}_readable", self.name))
+    }
+
+    /// Path of the readable file of a node.
+    pub(crate) fn readable_file(&self, path: &NodePath) -> PathBuf {
+        readable_path(&self.readable_dir(), path)
+    }
+
+    /// Rewrites the readable files of the nodes, or removes them if the nodes
+    /// no longer exist. Does nothing unless readable files are maintained.
+    ///
+    /// The change that led here has already gone through, so a file that can't
+    /// be written doesn't fail it. A ReadableFileFailed event is sent instead.
+    pub(crate) fn update_readable_files(&mut self, paths: &[NodePath]) {
+        if !self.maintain_readable_files {
+            return;
+        }
+        for path in paths {
+            if let Err(e) = self.write_readable_file(path) {
+                self.events.emit(GraphEvent::ReadableFileFailed { path: path.clone(), reason: e.to_string() });
+            }
+        }
+    }
+
+    /// Updates the readable files of the nodes affected by the change.
+    pub(crate) fn update_readable_files_for(&mut self, event: &GraphEvent) {
+        if !self.maintain_readable_files {
+            return;
+        }
+
+        let parent = |path: &NodePath| path.parent().into_iter();
+        let paths: Vec<NodePath> = match event {
+            GraphEvent::NodeCreated(path) | GraphEvent::NodeDeleted(path) => {
+                std::iter::once(path.clone()).chain(parent(path)).collect()
+            }
+            GraphEvent::NodeMoved { from, to } => {
+                let old = self.readable_file(from);
+                let _ = std::fs::remove_file(&old);
+                let _ = std::fs::remove_dir_all(old.with_extension(""));
+
+                // The moved nodes, their old and new parents, and the nodes that
+                // link to them all have paths in their files that changed.
+                let moved = self.search_nodes(&Filter::path_prefix(to)).unwrap_or_default();
+                let mut paths: Vec<NodePath> = parent(from).chain(parent(to)).collect();
+                for node in moved {
+                    let edges = self.node_edges(&node.path()).unwrap_or_default();
+                    paths.extend(edges.iter().map(|edge| edge.source().clone()));
+                    paths.push(node.path());
+                }
+                paths
+            }
+            GraphEvent::AttributesChanged(path) | GraphEvent::AliveChanged { path, .. } => vec![path.clone()],
+            GraphEvent::EdgeCreated { source, .. }
+            | GraphEvent::EdgeDeleted { source, .. }
+            | GraphEvent::EdgeAttributesChanged { source, .. } => vec![source.clone()],
+            GraphEvent::Undone(_) | GraphEvent::Redone(_) | GraphEvent::ReadableFileFailed { .. } => vec![],
+        };
+
+        let mut unique: Vec<NodePath> = Vec::new();
+        for path in paths {
+            if !unique.contains(&path) {
+                unique.push(path);
+            }
+        }
+        self.update_readable_files(&unique);
+    }
+
+    /// Writes the readable files of all nodes, and then removes the files of
+    /// nodes that are no longer in the graph. The directory itself is kept.
+    pub(crate) fn write_all_readable_files(&mut self) -> Result<usize, GraphError> {
+        let dir = self.readable_dir();
+        std::fs::create_dir_all(&dir)?;
+
+        let nodes = self.search_nodes(&Filter::path_prefix(&NodePath::root()))?;
+        let mut written: Vec<PathBuf> = Vec::new();
+        for node in nodes {
+            if self.write_readable_file(&node.path())? {
+                written.push(self.readable_file(&node.path()));
+            }
+        }
+
+        let mut files: Vec<PathBuf> = Vec::new();
+        collect_readable_files(&dir, &mut files)?;
+        for file in files.into_iter().filter(|file| !written.contains(file)) {
+            std::fs::remove_file(&file)?;
+        }
+        Ok(written.len())
+    }
+
+    /// Returns whether a file was written. Nodes that don't exist have their
+    /// file removed, and the history isn't mirrored.
+    fn write_readable_file(&self, path: &NodePath) -> Result<bool, GraphError> {
+        let file = self.readable_file(path);
+        if in_history(path) {
+            return Ok(false);
+        }
+
+        let node = match self.open_node(path) {
+            Ok(node) => node,
+            Err(_) => {
+                if file.exists() {
+                    std::fs::remove_file(&file)?;
+                }
+                return Ok(false);
+            }
+        };
+
+        let edges: Vec<Edge> = self
+            .node_edges(path)?
+            .into_iter()
+            .filter(|edge| edge.source() == path)
+            .collect();
+
+        let readable = ReadableNode { node, edges, fingerprint: None };
+        let contents = ron::ser::to_string_pretty(&readable, ron::ser::PrettyConfig::default())?;
+
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&file, contents)?;
+        Ok(true)
+    }
+}
+
+/// Reading the files back.
+impl GraphAgdb {
+    /// Inserts the nodes of all readable files in the directory, parents
+    /// first, and then their edges.
+    pub(crate) fn import_readable_dir(&mut self, dir: &Path) -> Result<ImportReport, GraphError> {
+        let root_path = self.root_path.clone();
+        import_readable_dir(self, &root_path, dir)
+    }
+}
+
+/// Path of the file of a node in a directory of readable files.
+pub(crate) fn readable_path(dir: &Path, path: &NodePath) -> PathBuf {
+    if *path == NodePath::root() {
+        return dir.join("root.ron");
+    }
+    let mut file = dir.join(path.buf()).into_os_string();
+    file.push(".ron");
+    PathBuf::from(file)
+}
+
+/// Inserts the nodes of all readable files in the directory into any graph,
+/// parents first, and then their edges.
+pub(crate) fn import_readable_dir<G: GraphNode + GraphEdge>(
+    graph: &mut G,
+    root_path: &Path,
+    dir: &Path,
+) -> Result<ImportReport, GraphError> {
+    if !dir.is_dir() {
+        return Err(GraphError::InvalidPath(format!("Readable files directory {} does not exist", dir.display())));
+    }
+
+    let mut report = ImportReport::default();
+
+    let mut readables: Vec<(PathBuf, ReadableNode)> = Vec::new();
+    for (file, readable) in read_readable_dir(dir)? {
+        match readable {
+            Ok(readable) => readables.push((file, readable)),
+            Err(e) => report.errored.push((file, e.to_string())),
+        }
+    }
+
+    for (file, readable) in &readables {
+        let node = readable.node.clone();
+        let path = node.path();
+        if let Some(conflict) = readable_conflict(root_path, &node) {
+            report.conflicts.push(conflict);
+        }
+        match graph.insert_node(node) {
+            Ok(_) => report.nodes.push(path),
+            Err(e) => report.errored.push((file.clone(), e.to_string())),
+        }
+    }
+
+    // Contains edges were created along with the nodes, so for them this
+    // only restores their attributes and times.
+    for (file, readable) in readables {
+        for edge in readable.edges {
+            match graph.insert_edge(edge) {
+                Ok(_) => report.edges += 1,
+                Err(e) => report.errored.push((file.clone(), e.to_string())),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Reads all readable files in the directory, parents first. Files that can't
+/// be read are returned with the error.
+pub(crate) fn read_readable_dir(dir: &Path) -> Result<Vec<(PathBuf, Result<ReadableNode, GraphError>)>, GraphError> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    collect_readable_files(dir, &mut files)?;
+
+    let mut readables: Vec<(PathBuf, Result<ReadableNode, GraphError>)> = files
+        .into_iter()
+        .map(|file| {
+            let readable = read_readable_file(&file);
+            (file, readable)
+        })
+        .collect();
+    readables.sort_by_key(|(file, readable)| match readable {
+        Ok(readable) => readable.node.path().buf().components().count(),
+        Err(_) => 0,
+    });
+    Ok(readables)
+}
+
+/// Compares a recorded file or directory node to the file system.
+fn readable_conflict(root_path: &Path, node: &Node) -> Option<ReadableConflict> {
+    let recorded = node.ntype_name();
+    if recorded != NodeType::file() && recorded != NodeType::dir() {
+        return None;
+    }
+
+    let path = node.path();
+    let full_path = path.full(&root_path.to_path_buf());
+    if !full_path.exists() {
+        return node.alive().then(|| ReadableConflict::Missing(path));
+    }
+
+    let found = if full_path.is_dir() { NodeType::dir() } else { NodeType::file() };
+    if found != recorded {
+        return Some(ReadableConflict::TypeMismatch { path, recorded, found });
+    }
+    if !node.alive() {
+        return Some(ReadableConflict::Reappeared(path));
+    }
+    None
+}
+
+fn collect_readable_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), GraphError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_readable_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "ron") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a readable file back.
+pub(crate) fn read_readable_file(file: &Path) -> Result<ReadableNode, GraphError> {
+    let contents = std::fs::read_to_string(file)?;
+    Ok(ron::from_str(&contents)?)
+}
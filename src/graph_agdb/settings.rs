@@ -0,0 +1,194 @@
+/*
+Graph-level settings, stored as values on the settings archetype node so that
+they persist along with the graph.
+
+The settings used by the library itself are listed below along with their
+types, and are type checked when set. Any other setting is stored as it is and
+left untouched, so that settings written by newer versions or by other tools
+survive.
+
+Reading the settings and preparing the values to store is shared by the
+backends. Only writing the values to the settings node is up to each backend.
+*/
+
+use agdb::DbKeyValue;
+
+use crate::{
+    error::GraphError,
+    elements::{
+        attribute::{Attribute, AttributeValue, FINGERPRINT_KEYS, RESERVED_NODE_ATTRS},
+        node_path::NodePath,
+    },
+    graph_traits::graph_node::GraphNode,
+    indexing::{IndexOptions, SymlinkPolicy},
+};
+
+pub(crate) const MAINTAIN_READABLE_FILES: &str = "maintain_readable_files";
+
+const INDEX_MAX_DEPTH: &str = "index_max_depth";
+const INDEX_SYMLINKS: &str = "index_symlinks";
+const INDEX_INCLUDE_HIDDEN: &str = "index_include_hidden";
+const INDEX_USE_GITIGNORE: &str = "index_use_gitignore";
+const INDEX_IGNORE_PATTERNS: &str = "index_ignore_patterns";
+const INDEX_DETECT_MOVES: &str = "index_detect_moves";
+const INDEX_HASH_CONTENTS: &str = "index_hash_contents";
+
+/// The settings of the indexing options. They are removed rather than set when
+/// the option is None.
+pub(crate) const INDEX_SETTINGS: [&str; 7] = [
+    INDEX_MAX_DEPTH,
+    INDEX_SYMLINKS,
+    INDEX_INCLUDE_HIDDEN,
+    INDEX_USE_GITIGNORE,
+    INDEX_IGNORE_PATTERNS,
+    INDEX_DETECT_MOVES,
+    INDEX_HASH_CONTENTS,
+];
+
+/// Settings known to the library and the types of their values.
+const KNOWN_SETTINGS: [(&str, &str); 8] = [
+    (MAINTAIN_READABLE_FILES, "Bool"),
+    (INDEX_MAX_DEPTH, "Int"),
+    (INDEX_SYMLINKS, "String"),
+    (INDEX_INCLUDE_HIDDEN, "Bool"),
+    (INDEX_USE_GITIGNORE, "Bool"),
+    (INDEX_IGNORE_PATTERNS, "List"),
+    (INDEX_DETECT_MOVES, "Bool"),
+    (INDEX_HASH_CONTENTS, "Bool"),
+];
+
+/// The node the settings are stored on.
+pub(crate) fn settings_path() -> NodePath {
+    NodePath::atype("settings")
+}
+
+/// All settings of the graph.
+pub(crate) fn list<G: GraphNode>(graph: &G) -> Result<Vec<Attribute>, GraphError> {
+    Ok(graph.open_node(&settings_path())?.attributes())
+}
+
+/// The value of a setting, or None if it isn't set.
+pub(crate) fn get<G: GraphNode>(graph: &G, key: &str) -> Result<Option<AttributeValue>, GraphError> {
+    Ok(list(graph)?.into_iter().find(|attr| attr.name == key).map(|attr| attr.value))
+}
+
+/// The indexing options stored in the settings of the graph.
+pub(crate) fn index_options<G: GraphNode>(graph: &G) -> Result<IndexOptions, GraphError> {
+    Ok(read_index_options(&list(graph)?))
+}
+
+/// Checks the setting and turns it into the value stored on the settings node.
+pub(crate) fn setting_value(key: &str, value: AttributeValue) -> Result<DbKeyValue, GraphError> {
+    check(key, &value)?;
+    Ok(Attribute::new(key, value).into())
+}
+
+/// The values stored on the settings node for the indexing options. The
+/// settings in INDEX_SETTINGS are removed before these are stored.
+pub(crate) fn index_values(options: &IndexOptions) -> Vec<DbKeyValue> {
+    index_settings(options).into_iter().map(|attr| attr.into()).collect()
+}
+
+/// Checks the key and, for known settings, the type of the value.
+pub(crate) fn check(key: &str, value: &AttributeValue) -> Result<(), GraphError> {
+    if RESERVED_NODE_ATTRS.contains(&key) || FINGERPRINT_KEYS.contains(&key) {
+        return Err(GraphError::ReservedAttribute(key.to_string()));
+    }
+    if key.is_empty() {
+        return Err(GraphError::SchemaViolation("Invalid setting name: setting names can't be empty".to_string()));
+    }
+
+    if let Some((_, expected)) = KNOWN_SETTINGS.iter().find(|(known, _)| *known == key) {
+        if value.type_name() != *expected {
+            return Err(GraphError::SchemaViolation(format!(
+                "Invalid value for setting {}: expected {}, got {}",
+                key, expected, value.type_name()
+            )));
+        }
+    }
+
+    let invalid = match (key, value) {
+        (INDEX_MAX_DEPTH, AttributeValue::Int(depth)) if *depth < 0 => {
+            Some("depth can't be negative".to_string())
+        }
+        (INDEX_SYMLINKS, AttributeValue::String(policy)) if symlink_policy(policy).is_none() => {
+            Some(format!("unknown symlink policy {}", policy))
+        }
+        (INDEX_IGNORE_PATTERNS, AttributeValue::List(patterns)) if patterns.iter().any(|p| p.as_str().is_none()) => {
+            Some("patterns must be strings".to_string())
+        }
+        _ => None,
+    };
+
+    match invalid {
+        Some(reason) => Err(GraphError::SchemaViolation(format!("Invalid value for setting {}: {}", key, reason))),
+        None => Ok(()),
+    }
+}
+
+fn symlink_policy(name: &str) -> Option<SymlinkPolicy> {
+    match name {
+        "Skip" => Some(SymlinkPolicy::Skip),
+        "IndexOnly" => Some(SymlinkPolicy::IndexOnly),
+        "Follow" => Some(SymlinkPolicy::Follow),
+        _ => None,
+    }
+}
+
+fn symlink_policy_name(policy: SymlinkPolicy) -> &'static str {
+    match policy {
+        SymlinkPolicy::Skip => "Skip",
+        SymlinkPolicy::IndexOnly => "IndexOnly",
+        SymlinkPolicy::Follow => "Follow",
+    }
+}
+
+/// Reads the indexing options from the settings. Options that are missing or
+/// can't be read keep their defaults.
+fn read_index_options(settings: &[Attribute]) -> IndexOptions {
+    let value = |key: &str| settings.iter().find(|attr| attr.name == key).map(|attr| &attr.value);
+    let flag = |key: &str, default: bool| match value(key) {
+        Some(AttributeValue::Bool(b)) => *b,
+        _ => default,
+    };
+
+    let defaults = IndexOptions::default();
+    IndexOptions {
+        max_depth: value(INDEX_MAX_DEPTH)
+            .and_then(|v| v.as_int())
+            .map(|depth| depth.max(0) as usize),
+        symlinks: value(INDEX_SYMLINKS)
+            .and_then(|v| v.as_str())
+            .and_then(symlink_policy)
+            .unwrap_or(defaults.symlinks),
+        include_hidden: flag(INDEX_INCLUDE_HIDDEN, defaults.include_hidden),
+        use_gitignore: flag(INDEX_USE_GITIGNORE, defaults.use_gitignore),
+        ignore_patterns: match value(INDEX_IGNORE_PATTERNS) {
+            Some(AttributeValue::List(patterns)) => patterns
+                .iter()
+                .filter_map(|p| p.as_str().map(|s| s.to_string()))
+                .collect(),
+            _ => defaults.ignore_patterns,
+        },
+        detect_moves: flag(INDEX_DETECT_MOVES, defaults.detect_moves),
+        hash_contents: flag(INDEX_HASH_CONTENTS, defaults.hash_contents),
+    }
+}
+
+/// The settings for the indexing options. A max_depth of None is left out.
+fn index_settings(options: &IndexOptions) -> Vec<Attribute> {
+    let patterns: Vec<AttributeValue> = options.ignore_patterns.iter().map(|p| p.as_str().into()).collect();
+
+    let mut settings = vec![
+        Attribute::new(INDEX_SYMLINKS, symlink_policy_name(options.symlinks)),
+        Attribute::new(INDEX_INCLUDE_HIDDEN, options.include_hidden),
+        Attribute::new(INDEX_USE_GITIGNORE, options.use_gitignore),
+        Attribute::new(INDEX_IGNORE_PATTERNS, patterns),
+        Attribute::new(INDEX_DETECT_MOVES, options.detect_moves),
+        Attribute::new(INDEX_HASH_CONTENTS, options.hash_contents),
+    ];
+    if let Some(depth) = options.max_depth {
+        settings.push(Attribute::new(INDEX_MAX_DEPTH, depth as i64));
+    }
+    settings
+}
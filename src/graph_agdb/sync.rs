@@ -0,0 +1,481 @@
+/*
+Lower level operations for keeping the db in line with the file system. They run
+on a mutable transaction, so that a whole batch of them can be applied atomically.
+
+None of these functions touch the file system itself. They only read it to
+determine node types, modification times and fingerprints.
+*/
+
+use std::path::{Path, PathBuf};
+
+use agdb::{
+    CountComparison, DbElement, DbId, DbKeyValue, DbUserValue, DbValue, QueryBuilder, QueryError, QueryId,
+    StorageData, TransactionMut,
+};
+
+use crate::{
+    elements::{edge::Edge, node::Node, node_path::NodePath, nodetype::NodeType, SysTime},
+    error::GraphError,
+    events::GraphEvent,
+    filter::Filter,
+    indexing::{self, Fingerprint, IndexOptions},
+    watcher::{FsEvent, GraphChange},
+};
+
+use super::GraphAgdb;
+
+impl GraphAgdb {
+    /// Apply a batch of file system events to the graph in a single transaction.
+    /// Renames are applied first, so that the moved nodes keep their edges and
+    /// attributes. Every other path is then checked against the file system:
+    /// new paths are created, existing ones revived or touched and missing ones
+    /// marked dead. If any part of the batch fails, none of it is applied.
+    pub(crate) fn apply_fs_events(
+        &mut self,
+        events: &[FsEvent],
+        options: &IndexOptions,
+    ) -> Result<Vec<GraphChange>, GraphError> {
+        let root = self.root_path.clone();
+        let exclude = self.db_files();
+        let keep = |path: &Path| *path != root && !indexing::is_excluded(&root, path, options, &exclude);
+
+        let mut renames: Vec<(NodePath, NodePath)> = Vec::new();
+        let mut changed: Vec<PathBuf> = Vec::new();
+
+        for event in events {
+            match event {
+                FsEvent::Renamed(from, to) => {
+                    if keep(from) && keep(to) {
+                        renames.push((
+                            NodePath::from_dir_path(&root, from),
+                            NodePath::from_dir_path(&root, to),
+                        ));
+                    }
+                    // Both ends are checked afterwards as well, in case the
+                    // rename couldn't be matched to a node.
+                    changed.push(from.clone());
+                    changed.push(to.clone());
+                }
+                FsEvent::Changed(path) => changed.push(path.clone()),
+            }
+        }
+
+        changed.retain(|path| keep(path));
+        changed.sort();
+        changed.dedup();
+
+        // New directories may already have contents, for example when they are
+        // moved in from outside the vault.
+        let mut contents: Vec<PathBuf> = Vec::new();
+        for path in changed.iter().filter(|path| path.is_dir()) {
+            let alias = NodePath::from_dir_path(&root, path).alias();
+            if self.db.exec(&QueryBuilder::select().ids(alias).query()).is_err() {
+                contents.extend(indexing::walk(&root, path, options, &exclude).entries);
+            }
+        }
+
+        let changes = transaction_mut!(&mut self.db, |t| -> Result<Vec<GraphChange>, GraphError> {
+            let mut changes: Vec<GraphChange> = Vec::new();
+
+            for (from, to) in &renames {
+                let movable = node_id(t, from).is_some()
+                    && node_id(t, to).is_none()
+                    && to.full(&root).exists();
+                if movable {
+                    rekey_node(t, &root, from, to)?;
+                    touch_node(t, &root, to, options.hash_contents)?;
+                    changes.push(GraphChange::Moved { from: from.clone(), to: to.clone() });
+                }
+            }
+
+            for path in changed.iter().chain(contents.iter()) {
+                let node_path = NodePath::from_dir_path(&root, path);
+                let exists = node_id(t, &node_path).is_some();
+
+                if path.exists() {
+                    if !exists {
+                        let created = insert_node_with_parents(t, &root, &node_path, options.hash_contents)?;
+                        changes.extend(created.into_iter().map(GraphChange::Created));
+                    } else if touch_node(t, &root, &node_path, options.hash_contents)? {
+                        changes.push(GraphChange::Modified(node_path));
+                    }
+                } else if exists {
+                    let dead = mark_dead(t, &node_path)?;
+                    changes.extend(dead.into_iter().map(GraphChange::Removed));
+                }
+            }
+
+            Ok(changes)
+        })?;
+
+        for change in &changes {
+            match change {
+                GraphChange::Created(path) => self.emit(GraphEvent::NodeCreated(path.clone())),
+                GraphChange::Moved { from, to } => {
+                    self.emit(GraphEvent::NodeMoved { from: from.clone(), to: to.clone() })
+                }
+                GraphChange::Removed(path) => {
+                    self.emit(GraphEvent::AliveChanged { path: path.clone(), alive: false })
+                }
+                GraphChange::Modified(path) => self.update_readable_files(&[path.clone()]),
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+/// The id of the node at a path, if it is in the db.
+pub(crate) fn node_id<S: StorageData>(t: &TransactionMut<S>, path: &NodePath) -> Option<DbId> {
+    t.exec(&QueryBuilder::select().ids(path.alias()).query())
+        .ok()
+        .and_then(|result| result.elements.first().map(|elem| elem.id))
+}
+
+pub(crate) fn search_nodes<S: StorageData>(
+    t: &TransactionMut<S>,
+    filter: &Filter,
+) -> Result<Vec<Node>, GraphError> {
+    let mut search = QueryBuilder::search().elements().query();
+    search.conditions = filter.node_conditions();
+
+    let result = t.exec(&QueryBuilder::select().ids(search).query())?;
+
+    let nodes = result
+        .elements
+        .into_iter()
+        .filter(|elem| filter.is_exact() || filter.matches_element(elem))
+        .map(Node::try_from)
+        .collect::<Result<Vec<Node>, _>>()?;
+
+    Ok(nodes)
+}
+
+/// The values that bring an existing node in line with the file system: reviving
+/// it, correcting a generic type, bumping modified_time if the file is newer and
+/// refreshing the fingerprint of a file. Nodes that have been given a more specific
+/// type keep it. The contents are only hashed if the file has changed or has no
+/// hash yet.
+pub(crate) fn sync_values(elem: &DbElement, full_path: &Path, hash: bool) -> Result<Vec<DbKeyValue>, GraphError> {
+    let node = Node::try_from(elem.clone())?;
+    let mut values: Vec<DbKeyValue> = Vec::new();
+
+    if !node.alive() {
+        values.push(("alive", true).into());
+    }
+
+    let ntype = if full_path.is_dir() { NodeType::dir() } else { NodeType::file() };
+    let generic = [NodeType::dir(), NodeType::file(), NodeType::other()];
+    if node.ntype_name() != ntype && generic.contains(&node.ntype_name()) {
+        values.push(("ntype", ntype).into());
+    }
+
+    if let Ok(modified) = std::fs::metadata(full_path).and_then(|meta| meta.modified()) {
+        let modified = SysTime::from(modified);
+        if modified.as_secs() > node.modified_time().as_secs() {
+            values.push(("modified_time", modified).into());
+        }
+    }
+
+    let stored = Fingerprint::from_element(elem);
+    if let Some(current) = Fingerprint::of(full_path, false) {
+        let stale = match &stored {
+            Some(stored) => {
+                stored.size != current.size
+                    || stored.mtime != current.mtime
+                    || (hash && stored.hash.is_none())
+            }
+            None => true,
+        };
+        if stale {
+            // A stored hash is kept up to date even when hashing is off, so that
+            // it never goes stale.
+            let hash = hash || stored.map_or(false, |stored| stored.hash.is_some());
+            let current = if hash { Fingerprint::of(full_path, true).unwrap_or(current) } else { current };
+            values.extend(current.to_values());
+        }
+    }
+
+    Ok(values)
+}
+
+/// Insert the node for a path along with any missing ancestors, each under a
+/// contains edge from its parent. Types are taken from the file system. Returns
+/// the paths of the inserted nodes, parents first.
+pub(crate) fn insert_node_with_parents<S: StorageData>(
+    t: &mut TransactionMut<S>,
+    root_path: &PathBuf,
+    path: &NodePath,
+    hash: bool,
+) -> Result<Vec<NodePath>, GraphError> {
+    if node_id(t, path).is_some() {
+        return Ok(Vec::new());
+    }
+
+    let mut inserted: Vec<NodePath> = Vec::new();
+    let parent = path.parent();
+    if let Some(parent) = &parent {
+        inserted.extend(insert_node_with_parents(t, root_path, parent, hash)?);
+    }
+
+    let full_path = path.full(root_path);
+    let ntype = if full_path.is_dir() {
+        NodeType::dir()
+    } else if full_path.exists() {
+        NodeType::file()
+    } else {
+        NodeType::other()
+    };
+
+    let node = Node::new(path, ntype);
+    let mut values = node.to_db_values();
+    if let Some(fingerprint) = Fingerprint::of(&full_path, hash) {
+        values.extend(fingerprint.to_values());
+    }
+    t.exec_mut(
+        &QueryBuilder::insert()
+            .nodes()
+            .aliases(path.alias())
+            .values(vec![values])
+            .query(),
+    )?;
+
+    if let Some(parent) = &parent {
+        let edge = Edge::new_cont(parent, path);
+        t.exec_mut(
+            &QueryBuilder::insert()
+                .edges()
+                .from(parent.alias())
+                .to(path.alias())
+                .values_uniform(&edge)
+                .query(),
+        )?;
+    }
+
+    inserted.push(path.clone());
+    Ok(inserted)
+}
+
+/// Sync an existing node with the file system. Returns whether anything changed.
+pub(crate) fn touch_node<S: StorageData>(
+    t: &mut TransactionMut<S>,
+    root_path: &PathBuf,
+    path: &NodePath,
+    hash: bool,
+) -> Result<bool, GraphError> {
+    let result = t
+        .exec(&QueryBuilder::select().ids(path.alias()).query())
+        .map_err(|_| GraphError::NodeNotFound(path.clone()))?;
+    let elem = result
+        .elements
+        .into_iter()
+        .next()
+        .ok_or_else(|| GraphError::NodeNotFound(path.clone()))?;
+    let values = sync_values(&elem, &path.full(root_path), hash)?;
+    if values.is_empty() {
+        return Ok(false);
+    }
+
+    replace_values(t, path.alias(), values)?;
+    Ok(true)
+}
+
+/// Mark the File and Directory nodes at and under a path dead. Returns the paths
+/// of the nodes that were alive before.
+pub(crate) fn mark_dead<S: StorageData>(
+    t: &mut TransactionMut<S>,
+    path: &NodePath,
+) -> Result<Vec<NodePath>, GraphError> {
+    let filter = Filter::path_prefix(path)
+        .and(Filter::ntype(NodeType::file()).or(Filter::ntype(NodeType::dir())))
+        .and(Filter::alive(true));
+
+    let mut dead: Vec<NodePath> = Vec::new();
+    for node in search_nodes(t, &filter)? {
+        replace_values(t, node.path().alias(), vec![("alive", false).into()])?;
+        dead.push(node.path());
+    }
+
+    Ok(dead)
+}
+
+/// Move a node and all of its descendants to a new path. The nodes keep their
+/// ids, so their edges and attributes come along, but the paths stored in the
+/// nodes and their edges are rewritten. The contains edge from the old parent is
+/// moved to the new parent, which is created if it's missing.
+pub(crate) fn rekey_node<S: StorageData>(
+    t: &mut TransactionMut<S>,
+    root_path: &PathBuf,
+    from: &NodePath,
+    to: &NodePath,
+) -> Result<(), GraphError> {
+    if node_id(t, from).is_none() {
+        return Err(GraphError::NodeNotFound(from.clone()));
+    }
+    if node_id(t, to).is_some() {
+        return Err(GraphError::AlreadyExists(format!("Node {}", to.alias())));
+    }
+    if to.buf().starts_with(from.buf()) {
+        return Err(GraphError::InvalidPath(format!("Cannot move {} inside itself", from.alias())));
+    }
+
+    for node in search_nodes(t, &Filter::path_prefix(from))? {
+        let old = node.path();
+        let new = rebase(&old, from, to);
+        let id = node.id().ok_or_else(|| GraphError::NodeNotFound(old.clone()))?;
+
+        t.exec_mut(&QueryBuilder::insert().aliases(new.alias()).ids(id).query())?;
+        replace_values(t, id, vec![("path", new.clone()).into()])?;
+
+        for edge in edges_of(t, id)? {
+            let mut values: Vec<DbKeyValue> = Vec::new();
+            if value_of(&edge, "source") == Some(&old.clone().into()) {
+                values.push(("source", new.clone()).into());
+            }
+            if value_of(&edge, "target") == Some(&old.clone().into()) {
+                values.push(("target", new.clone()).into());
+            }
+            if !values.is_empty() {
+                replace_values(t, edge.id, values)?;
+            }
+        }
+    }
+
+    let old_parent = from.parent();
+    let new_parent = to.parent();
+    if old_parent == new_parent {
+        return Ok(());
+    }
+
+    if let Some(new_parent) = &new_parent {
+        insert_node_with_parents(t, root_path, new_parent, false)?;
+    }
+
+    let id = node_id(t, to).ok_or_else(|| GraphError::NodeNotFound(to.clone()))?;
+    let old_source: Option<DbValue> = old_parent.map(|parent| parent.into());
+
+    for edge in edges_of(t, id)? {
+        let is_parent_edge = edge.id.0 < 0
+            && value_of(&edge, "contains").is_some()
+            && value_of(&edge, "target") == Some(&to.clone().into())
+            && value_of(&edge, "source") == old_source.as_ref();
+        if !is_parent_edge {
+            continue;
+        }
+
+        t.exec_mut(&QueryBuilder::remove().ids(edge.id).query())?;
+
+        if let Some(new_parent) = &new_parent {
+            let values: Vec<DbKeyValue> = edge
+                .values
+                .iter()
+                .map(|kv| match kv.key.to_string().as_str() {
+                    "source" => ("source", new_parent.clone()).into(),
+                    _ => kv.clone(),
+                })
+                .collect();
+            t.exec_mut(
+                &QueryBuilder::insert()
+                    .edges()
+                    .from(new_parent.alias())
+                    .to(to.alias())
+                    .values_uniform(values)
+                    .query(),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Swap the from prefix of a path for to.
+pub(crate) fn rebase(path: &NodePath, from: &NodePath, to: &NodePath) -> NodePath {
+    match path.buf().strip_prefix(from.buf()) {
+        Ok(rest) if rest.as_os_str().is_empty() => to.clone(),
+        Ok(rest) => to.join(&rest.to_string_lossy()),
+        Err(_) => path.clone(),
+    }
+}
+
+/// Insert values into an existing element, replacing the ones with the same keys.
+/// Values that change are removed first instead of being overwritten, as agdb
+/// stops rolling back a transaction at the last value it overwrote, which would
+/// leave the earlier changes of the transaction in place. Values that stay the
+/// same aren't touched.
+pub(crate) fn replace_values<S: StorageData>(
+    t: &mut TransactionMut<S>,
+    id: impl Into<QueryId>,
+    values: Vec<DbKeyValue>,
+) -> Result<(), QueryError> {
+    let id: QueryId = id.into();
+    let current: Vec<DbKeyValue> = t
+        .exec(&QueryBuilder::select().ids(vec![id.clone()]).query())?
+        .elements
+        .into_iter()
+        .next()
+        .map(|elem| elem.values)
+        .unwrap_or_default();
+
+    let changed: Vec<DbKeyValue> = values.into_iter().filter(|kv| !current.contains(kv)).collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+    let keys: Vec<DbValue> = changed
+        .iter()
+        .filter(|kv| current.iter().any(|old| old.key == kv.key))
+        .map(|kv| kv.key.clone())
+        .collect();
+
+    if !keys.is_empty() {
+        t.exec_mut(&QueryBuilder::remove().values(keys).ids(vec![id.clone()]).query())?;
+    }
+    t.exec_mut(&QueryBuilder::insert().values(vec![changed]).ids(vec![id]).query())?;
+    Ok(())
+}
+
+/// All edges going in and out of an element, with their values.
+fn edges_of<S: StorageData>(t: &TransactionMut<S>, id: DbId) -> Result<Vec<DbElement>, GraphError> {
+    let outgoing = QueryBuilder::search()
+        .from(id)
+        .where_()
+        .edge()
+        .and()
+        .distance(CountComparison::Equal(1))
+        .query();
+    let incoming = QueryBuilder::search()
+        .to(id)
+        .where_()
+        .edge()
+        .and()
+        .distance(CountComparison::Equal(1))
+        .query();
+
+    let mut edges = t.exec(&QueryBuilder::select().ids(outgoing).query())?.elements;
+    edges.extend(t.exec(&QueryBuilder::select().ids(incoming).query())?.elements);
+    Ok(edges)
+}
+
+fn value_of<'a>(elem: &'a DbElement, key: &str) -> Option<&'a DbValue> {
+    elem.values
+        .iter()
+        .find(|kv| kv.key == key.into())
+        .map(|kv| &kv.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{elements::node_path::NodePath, error::GraphError, graph_traits::graph_core::GraphCore, utils::utils::TestContext};
+
+    #[test]
+    fn missing_nodes_are_reported_as_not_found() {
+        let func_name = "missing_nodes_are_reported_as_not_found";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        let missing = NodePath::from("missing.txt");
+        let result = transaction_mut!(ctx.graph.db_mut(), |t| -> Result<bool, GraphError> {
+            super::touch_node(t, &root, &missing, false)
+        });
+        assert!(matches!(result, Err(GraphError::NodeNotFound(path)) if path == missing));
+    }
+}
@@ -0,0 +1,152 @@
+/*
+Running several graph functions in one db transaction.
+
+Agdb only rolls back the changes made inside the closure given to
+transaction_mut, and every query run through the db outside of it is committed
+on its own. To run whole graph functions in one transaction, the db is taken out
+of the graph for the duration, and the graph is given a GraphDb::Transaction in
+its place. That one runs all queries, including those of transaction_mut!, in the
+open agdb transaction. If anything fails, agdb rolls all of them back.
+
+The file system can't be rolled back, so the files moved by reparent_node are
+recorded and moved back instead. The readable files are rewritten, as they were
+updated along with the changes that were rolled back.
+*/
+
+use std::{any::Any, panic::{self, AssertUnwindSafe}, path::PathBuf};
+
+use agdb::{DbFileTransactionMut, DbMemoryTransactionMut, DbTransactionMut};
+
+use crate::{config::DbBackend, error::GraphError};
+
+use super::{GraphAgdb, GraphDb};
+
+/// The db of a graph while it runs in a transaction, see GraphAgdb::transaction.
+/// Can't be created outside of this crate.
+pub struct OpenTransaction {
+    pub(crate) backend: DbBackend,
+    /// The size of the db when the transaction started.
+    pub(crate) size: u64,
+    pub(crate) ptr: Option<TransactionPtr>,
+}
+
+/// The agdb transaction. The pointer is only set while the closure given to
+/// transaction_mut runs, and is cleared before it returns, so it never outlives
+/// the transaction.
+#[derive(Clone, Copy)]
+pub(crate) enum TransactionPtr {
+    Mem(*mut DbMemoryTransactionMut<'static>),
+    Mapped(*mut DbTransactionMut<'static>),
+    File(*mut DbFileTransactionMut<'static>),
+}
+
+// The pointer is only used by the thread running the transaction, which holds
+// the graph mutably for the whole time.
+unsafe impl Send for TransactionPtr {}
+
+impl GraphAgdb {
+    /// Runs the function with all of its db changes in one transaction. If the
+    /// function fails or panics, the changes are rolled back, the files moved
+    /// by reparent_node are moved back and no events are sent. A transaction
+    /// started inside another one is part of it.
+    pub(crate) fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut GraphAgdb) -> Result<T, GraphError>,
+    ) -> Result<T, GraphError> {
+        if matches!(self.db, GraphDb::Transaction(_)) {
+            return f(self);
+        }
+
+        let open = OpenTransaction { backend: self.db.backend(), size: self.db.size(), ptr: None };
+        let mut db = std::mem::replace(&mut self.db, GraphDb::Transaction(open));
+        let mark = self.events.hold();
+        self.moved_files = Some(Vec::new());
+
+        let mut f = Some(f);
+        let mut panicked = None;
+        let result = match &mut db {
+            GraphDb::Mem(inner) => inner.transaction_mut(|t| {
+                let ptr = t as *mut DbMemoryTransactionMut<'_> as *mut DbMemoryTransactionMut<'static>;
+                self.run_in_transaction(TransactionPtr::Mem(ptr), &mut f, &mut panicked)
+            }),
+            GraphDb::Mapped(inner) => inner.transaction_mut(|t| {
+                let ptr = t as *mut DbTransactionMut<'_> as *mut DbTransactionMut<'static>;
+                self.run_in_transaction(TransactionPtr::Mapped(ptr), &mut f, &mut panicked)
+            }),
+            GraphDb::File(inner) => inner.transaction_mut(|t| {
+                let ptr = t as *mut DbFileTransactionMut<'_> as *mut DbFileTransactionMut<'static>;
+                self.run_in_transaction(TransactionPtr::File(ptr), &mut f, &mut panicked)
+            }),
+            GraphDb::Transaction(_) => Err(GraphError::InvalidOperation("Transaction is already open".to_string())),
+        };
+
+        self.db = db;
+        let moved = self.moved_files.take().unwrap_or_default();
+        self.events.release(mark, result.is_ok());
+
+        let result = match result {
+            Ok(value) => Ok(value),
+            Err(err) => Err(self.restore_after_rollback(err, moved)),
+        };
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+        result
+    }
+
+    /// Records a file moved by the graph, so that it is moved back if the
+    /// transaction it is part of is rolled back.
+    pub(crate) fn record_moved_file(&mut self, from: PathBuf, to: PathBuf) {
+        if let Some(moved) = &mut self.moved_files {
+            moved.push((from, to));
+        }
+    }
+
+    fn run_in_transaction<T, F: FnOnce(&mut GraphAgdb) -> Result<T, GraphError>>(
+        &mut self,
+        ptr: TransactionPtr,
+        f: &mut Option<F>,
+        panicked: &mut Option<Box<dyn Any + Send>>,
+    ) -> Result<T, GraphError> {
+        let Some(f) = f.take() else {
+            return Err(GraphError::InvalidOperation("Transaction can only run once".to_string()));
+        };
+
+        self.set_transaction_ptr(Some(ptr));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+        self.set_transaction_ptr(None);
+
+        result.unwrap_or_else(|payload| {
+            *panicked = Some(payload);
+            Err(GraphError::InvalidOperation("Transaction panicked".to_string()))
+        })
+    }
+
+    fn set_transaction_ptr(&mut self, ptr: Option<TransactionPtr>) {
+        if let GraphDb::Transaction(open) = &mut self.db {
+            open.ptr = ptr;
+        }
+    }
+
+    /// Moves the files back, last one first, and rewrites the readable files.
+    /// Returns the error of the transaction, along with anything that couldn't
+    /// be restored.
+    fn restore_after_rollback(&mut self, err: GraphError, moved: Vec<(PathBuf, PathBuf)>) -> GraphError {
+        let mut failed: Vec<String> = Vec::new();
+        for (from, to) in moved.into_iter().rev() {
+            if let Err(e) = std::fs::rename(&to, &from) {
+                failed.push(format!("moving {} back: {}", to.display(), e));
+            }
+        }
+        if self.maintain_readable_files {
+            if let Err(e) = self.write_all_readable_files() {
+                failed.push(format!("rewriting the readable files: {}", e));
+            }
+        }
+
+        if failed.is_empty() {
+            return err;
+        }
+        GraphError::InvalidOperation(format!("{}. The db was rolled back, but {}", err, failed.join(", ")))
+    }
+}
@@ -1,21 +1,538 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{elements::attribute::RESERVED_EDGE_ATTRS, prelude::*};
+
+/// Command for creating links between nodes. If any of the links can't be
+/// created, the ones that were already created are removed again.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CreateEdgesCommand {
+    edges: Vec<(NodePath, NodePath)>,
+}
+
+impl CreateEdgesCommand {
+    pub fn new(edges: Vec<(NodePath, NodePath)>) -> Self {
+        CreateEdgesCommand { edges }
+    }
+
+    fn remove(graph: &mut GraphAgdb, edges: &[(NodePath, NodePath)]) -> Result<(), GraphError> {
+        for (source, target) in edges.iter().rev() {
+            graph.delete_edge(Edge::new(source, target))?;
+        }
+        Ok(())
+    }
+}
+
+impl CommandAgdb for CreateEdgesCommand {
+    fn command_name(&self) -> String {
+        "Create Edges".to_string()
+    }
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::CreateEdges(self.clone())
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        for (i, (source, target)) in self.edges.iter().enumerate() {
+            if let Err(e) = graph.create_edge(source, target) {
+                CreateEdgesCommand::remove(graph, &self.edges[..i])?;
+                return Err(e);
+            }
+        }
+
+        Ok(CommandResult {
+            msg: format!("Edges created: {}", self.edges.len()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: self.edges.iter().map(|(source, target)| Edge::new(source, target)).collect(),
+            attributes: vec![],
+        })
+    }
+
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        CreateEdgesCommand::remove(graph, &self.edges)?;
+
+        Ok(CommandResult {
+            msg: format!("Edges removed: {}", self.edges.len()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: vec![],
+            attributes: vec![],
+        })
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
+    }
+}
+
+/// Command for inserting an edge with all its values. If the edge already existed,
+/// the previous version is kept so that it can be restored.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InsertEdgeCommand {
+    edge: Edge,
+    previous: Option<Edge>,
+}
+
+impl InsertEdgeCommand {
+    pub fn new(edge: Edge) -> Self {
+        InsertEdgeCommand { edge, previous: None }
+    }
+}
+
+impl CommandAgdb for InsertEdgeCommand {
+    fn command_name(&self) -> String {
+        "Insert Edge".to_string()
+    }
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::InsertEdge(self.clone())
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.previous = graph.open_edge(self.edge.source(), self.edge.target(), self.edge.contains()).ok();
+        graph.insert_edge(self.edge.clone())?;
+
+        Ok(CommandResult {
+            msg: format!("Edge inserted from {:?} to {:?}", self.edge.source(), self.edge.target()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: vec![self.edge.clone()],
+            attributes: vec![],
+        })
+    }
 
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        match &self.previous {
+            Some(previous) => {
+                // Inserting only overwrites values, so the attributes that were
+                // added have to be removed separately.
+                let added = added_attr_names(self.edge.attributes(), previous.attributes());
+                if !added.is_empty() {
+                    graph.delete_reserved_edge_attrs(previous.clone(), added)?;
+                }
+                graph.insert_edge(previous.clone())?;
+            }
+            None => graph.delete_edge(self.edge.clone())?,
+        }
+
+        Ok(CommandResult {
+            msg: format!("Edge insertion reverted from {:?} to {:?}", self.edge.source(), self.edge.target()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: self.previous.iter().cloned().collect(),
+            attributes: vec![],
+        })
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
+    }
 }
 
+/// Command for deleting edges. The deleted edges are kept with all their values,
+/// so that they can be restored. Contains edges only lose their attributes.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DeleteEdgesCommand {
+    edges: Vec<Edge>,
+    deleted: Vec<Edge>,
+}
+
+impl DeleteEdgesCommand {
+    pub fn new(edges: Vec<Edge>) -> Self {
+        DeleteEdgesCommand { edges, deleted: Vec::new() }
+    }
+}
+
+impl CommandAgdb for DeleteEdgesCommand {
+    fn command_name(&self) -> String {
+        "Delete Edges".to_string()
+    }
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::DeleteEdges(self.clone())
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let mut deleted: Vec<Edge> = Vec::new();
+
+        for edge in &self.edges {
+            let current = graph.open_edge(edge.source(), edge.target(), edge.contains());
+            let result = current.and_then(|current| {
+                graph.delete_edge(current.clone())?;
+                Ok(current)
+            });
+            match result {
+                Ok(current) => deleted.push(current),
+                Err(e) => {
+                    for edge in deleted.iter().rev() {
+                        graph.insert_edge(edge.clone())?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        self.deleted = deleted;
+
+        Ok(CommandResult {
+            msg: format!("Edges deleted: {}", self.deleted.len()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: vec![],
+            attributes: vec![],
+        })
+    }
 
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        for edge in self.deleted.iter().rev() {
+            graph.insert_edge(edge.clone())?;
+        }
+
+        Ok(CommandResult {
+            msg: format!("Edges restored: {}", self.deleted.len()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: self.deleted.clone(),
+            attributes: vec![],
+        })
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
+    }
+}
+
+/// Command for moving a link to new endpoints. Remembers the old endpoints so
+/// that the link can be moved back.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReconnectEdgeCommand {
+    edge: Edge,
+    from: NodePath,
+    to: NodePath,
+}
+
+impl ReconnectEdgeCommand {
+    pub fn new(edge: Edge, from: NodePath, to: NodePath) -> Self {
+        ReconnectEdgeCommand { edge, from, to }
+    }
+}
+
+impl CommandAgdb for ReconnectEdgeCommand {
+    fn command_name(&self) -> String {
+        "Reconnect Edge".to_string()
+    }
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::ReconnectEdge(self.clone())
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        graph.reconnect_edge(self.edge.clone(), &self.from, &self.to)?;
+
+        Ok(CommandResult {
+            msg: format!("Edge reconnected from {:?} to {:?}", self.from, self.to),
+            nodepaths: vec![self.from.clone(), self.to.clone()],
+            nodes: vec![],
+            edges: vec![],
+            attributes: vec![],
+        })
+    }
+
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let moved = Edge::new(&self.from, &self.to);
+        graph.reconnect_edge(moved, self.edge.source(), self.edge.target())?;
+
+        Ok(CommandResult {
+            msg: format!("Edge reconnected back from {:?} to {:?}", self.edge.source(), self.edge.target()),
+            nodepaths: vec![self.edge.source().clone(), self.edge.target().clone()],
+            nodes: vec![],
+            edges: vec![],
+            attributes: vec![],
+        })
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
+    }
 }
 
 /// Command for moving nodes to different paths. Triggers the move of all children,
 /// so their paths have to be changed as well. 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ReparentNodesCommand {
+    node_paths: Vec<NodePath>,
+    new_parent: NodePath,
+    /// Paths of the nodes that were moved, before and after.
+    moved: Vec<(NodePath, NodePath)>,
+}
+
+impl ReparentNodesCommand {
+    pub fn new(node_paths: Vec<NodePath>, new_parent: NodePath) -> Self {
+        ReparentNodesCommand {
+            node_paths,
+            new_parent,
+            moved: Vec::new(),
+        }
+    }
+
+    /// Move nodes back to their original parents, in reverse order.
+    fn move_back(graph: &mut GraphAgdb, moved: &[(NodePath, NodePath)]) -> Result<(), GraphError> {
+        for (old, new) in moved.iter().rev() {
+            let old_parent = old
+                .parent()
+                .ok_or_else(|| GraphError::InvalidPath(format!("Moved node {} has no parent", old.alias())))?;
+            graph.reparent_node(new, &old_parent)?;
+        }
+        Ok(())
+    }
+}
+
+impl CommandAgdb for ReparentNodesCommand {
+    fn command_name(&self) -> String {
+        "Reparent Nodes".to_string()
+    }
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::ReparentNodes(self.clone())
+    }
+
+    /// If any of the nodes fails to move, the ones that were already moved are
+    /// moved back before returning the error.
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let mut moved: Vec<(NodePath, NodePath)> = Vec::new();
+
+        for path in &self.node_paths {
+            let new_path = self.new_parent.join(&path.name());
+            if let Err(e) = graph.reparent_node(path, &self.new_parent) {
+                ReparentNodesCommand::move_back(graph, &moved)?;
+                return Err(e);
+            }
+            if *path != new_path {
+                moved.push((path.clone(), new_path));
+            }
+        }
 
+        self.moved = moved;
+
+        Ok(CommandResult {
+            msg: format!("Nodes moved under {:?}", self.new_parent),
+            nodepaths: self.moved.iter().map(|(_, new)| new.clone()).collect(),
+            nodes: vec![],
+            edges: vec![],
+            attributes: vec![],
+        })
+    }
+
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        ReparentNodesCommand::move_back(graph, &self.moved)?;
+
+        Ok(CommandResult {
+            msg: "Nodes moved back to their original parents".to_string(),
+            nodepaths: self.moved.iter().map(|(old, _)| old.clone()).collect(),
+            nodes: vec![],
+            edges: vec![],
+            attributes: vec![],
+        })
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
+    }
 }
 
+/// Command for inserting attributes to an edge. The previous values of the
+/// overwritten attributes are kept for undo. Reserved attributes can only be
+/// inserted by commands created with new_reserved.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct InsertEdgeAttributesCommand {
+    edge: Edge,
+    reserved: bool,
+    old_values: Vec<Attribute>,
+    new_values: Vec<Attribute>,
+}
+
+impl InsertEdgeAttributesCommand {
+    pub fn new(edge: Edge, attrs: Vec<Attribute>) -> Self {
+        InsertEdgeAttributesCommand { edge, reserved: false, old_values: Vec::new(), new_values: attrs }
+    }
 
+    pub fn new_reserved(edge: Edge, attrs: Vec<Attribute>) -> Self {
+        InsertEdgeAttributesCommand { edge, reserved: true, old_values: Vec::new(), new_values: attrs }
+    }
 }
 
+impl CommandAgdb for InsertEdgeAttributesCommand {
+    fn command_name(&self) -> String {
+        "Insert Edge Attributes".to_string()
+    }
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::InsertEdgeAttributes(self.clone())
+    }
+
+    /// Repeated inserts on the same edge are merged, keeping the values from
+    /// before the first one.
+    fn coalesce(&mut self, next: &dyn CommandAgdb) -> bool {
+        let next = match next.stored() {
+            StoredCommand::InsertEdgeAttributes(next)
+                if next.reserved == self.reserved
+                    && next.edge.source() == self.edge.source()
+                    && next.edge.target() == self.edge.target()
+                    && next.edge.contains() == self.edge.contains() => next,
+            _ => return false,
+        };
+
+        for old in next.old_values {
+            let known = self.new_values.iter().chain(self.old_values.iter()).any(|a| a.name == old.name);
+            if !known {
+                self.old_values.push(old);
+            }
+        }
+        for new in next.new_values {
+            self.new_values.retain(|a| a.name != new.name);
+            self.new_values.push(new);
+        }
+        true
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let current = graph.open_edge(self.edge.source(), self.edge.target(), self.edge.contains())?;
+        self.old_values = current
+            .attributes()
+            .iter()
+            .filter(|attr| self.new_values.iter().any(|a| a.name == attr.name))
+            .cloned()
+            .collect();
+
+        if self.reserved {
+            graph.insert_reserved_edge_attrs(current, self.new_values.clone())?;
+        } else {
+            graph.insert_edge_attrs(current, self.new_values.clone())?;
+        }
+
+        Ok(CommandResult {
+            msg: format!("Attributes inserted to edge from {:?} to {:?}", self.edge.source(), self.edge.target()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: vec![],
+            attributes: self.new_values.clone(),
+        })
+    }
+
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let mut added = added_attr_names(&self.new_values, &self.old_values);
+        if !self.reserved {
+            added.retain(|name| !RESERVED_EDGE_ATTRS.contains(name));
+        }
+
+        // The values were already checked on apply, so they can be put back as they were.
+        if !added.is_empty() {
+            graph.delete_reserved_edge_attrs(self.edge.clone(), added)?;
+        }
+        if !self.old_values.is_empty() {
+            graph.insert_reserved_edge_attrs(self.edge.clone(), self.old_values.clone())?;
+        }
+
+        Ok(CommandResult {
+            msg: format!("Attributes restored on edge from {:?} to {:?}", self.edge.source(), self.edge.target()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: vec![],
+            attributes: self.old_values.clone(),
+        })
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
+    }
+}
+
+/// Command for deleting attributes from an edge. The deleted values are kept for undo.
+/// Reserved attributes can only be deleted by commands created with new_reserved.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DeleteEdgeAttributesCommand {
+    edge: Edge,
+    reserved: bool,
+    attr_names: Vec<String>,
+    old_values: Vec<Attribute>,
+}
+
+impl DeleteEdgeAttributesCommand {
+    pub fn new(edge: Edge, attr_names: Vec<&str>) -> Self {
+        DeleteEdgeAttributesCommand {
+            edge,
+            reserved: false,
+            attr_names: attr_names.into_iter().map(String::from).collect(),
+            old_values: Vec::new(),
+        }
+    }
+
+    pub fn new_reserved(edge: Edge, attr_names: Vec<&str>) -> Self {
+        DeleteEdgeAttributesCommand {
+            reserved: true,
+            ..DeleteEdgeAttributesCommand::new(edge, attr_names)
+        }
+    }
+}
 
-}
\ No newline at end of file
+impl CommandAgdb for DeleteEdgeAttributesCommand {
+    fn command_name(&self) -> String {
+        "Delete Edge Attributes".to_string()
+    }
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::DeleteEdgeAttributes(self.clone())
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let current = graph.open_edge(self.edge.source(), self.edge.target(), self.edge.contains())?;
+        self.old_values = current
+            .attributes()
+            .iter()
+            .filter(|attr| self.attr_names.contains(&attr.name))
+            .filter(|attr| self.reserved || !RESERVED_EDGE_ATTRS.contains(&attr.name.as_str()))
+            .cloned()
+            .collect();
+
+        let names: Vec<&str> = self.attr_names.iter().map(|s| s.as_str()).collect();
+        if self.reserved {
+            graph.delete_reserved_edge_attrs(current, names)?;
+        } else {
+            graph.delete_edge_attrs(current, names)?;
+        }
+
+        Ok(CommandResult {
+            msg: format!("Attributes deleted from edge from {:?} to {:?}", self.edge.source(), self.edge.target()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: vec![],
+            attributes: self.old_values.clone(),
+        })
+    }
+
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        if !self.old_values.is_empty() {
+            graph.insert_reserved_edge_attrs(self.edge.clone(), self.old_values.clone())?;
+        }
+
+        Ok(CommandResult {
+            msg: format!("Attributes restored on edge from {:?} to {:?}", self.edge.source(), self.edge.target()),
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: vec![],
+            attributes: self.old_values.clone(),
+        })
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
+    }
+}
+
+/// Names of the attributes in new that weren't in old.
+fn added_attr_names<'a>(new: &'a [Attribute], old: &[Attribute]) -> Vec<&'a str> {
+    new.iter()
+        .map(|attr| attr.name.as_str())
+        .filter(|name| *name != "contains" && !old.iter().any(|a| a.name == *name))
+        .collect()
+}
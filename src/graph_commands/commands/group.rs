@@ -0,0 +1,256 @@
+use crate::prelude::*;
+
+use super::StoredCommand;
+
+/// A list of commands that are applied, undone and redone as one. Appears as a
+/// single entry on the undo stack.
+///
+/// The commands run in one transaction of the db. If any of them fails, all of
+/// their db changes are rolled back, the files they moved are moved back, and
+/// none of their events are sent. Either all of the commands are applied or
+/// none are.
+pub struct CommandGroup {
+    name: String,
+    commands: Vec<Box<dyn CommandAgdb>>,
+}
+
+impl CommandGroup {
+    pub fn new(name: &str) -> Self {
+        CommandGroup {
+            name: name.to_string(),
+            commands: Vec::new(),
+        }
+    }
+
+    /// Adds a command to the end of the group.
+    pub fn with(mut self, command: Box<dyn CommandAgdb>) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn push(&mut self, command: Box<dyn CommandAgdb>) {
+        self.commands.push(command);
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Runs the step on every command in order, in one transaction. If one
+    /// fails, the transaction is rolled back.
+    fn run_all(
+        &mut self,
+        graph: &mut GraphAgdb,
+        step: fn(&mut dyn CommandAgdb, &mut GraphAgdb) -> Result<CommandResult, GraphError>,
+    ) -> Result<Vec<CommandResult>, GraphError> {
+        let commands = &mut self.commands;
+        graph.transaction(|graph| commands.iter_mut().map(|command| step(command.as_mut(), graph)).collect())
+    }
+
+    fn merge(&self, msg: String, results: Vec<CommandResult>) -> CommandResult {
+        let mut merged = CommandResult {
+            msg,
+            nodepaths: vec![],
+            nodes: vec![],
+            edges: vec![],
+            attributes: vec![],
+        };
+        for result in results {
+            merged.nodepaths.extend(result.nodepaths);
+            merged.nodes.extend(result.nodes);
+            merged.edges.extend(result.edges);
+            merged.attributes.extend(result.attributes);
+        }
+        merged
+    }
+}
+
+impl CommandAgdb for CommandGroup {
+    fn command_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::Group {
+            name: self.name.clone(),
+            commands: self.commands.iter().map(|command| command.stored()).collect(),
+        }
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let results = self.run_all(graph, |c, g| c.apply(g))?;
+        Ok(self.merge(format!("{} applied", self.name), results))
+    }
+
+    /// The commands are undone in reverse order.
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.commands.reverse();
+        let results = self.run_all(graph, |c, g| c.undo(g));
+        self.commands.reverse();
+        Ok(self.merge(format!("{} undone", self.name), results?))
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let results = self.run_all(graph, |c, g| c.redo(g))?;
+        Ok(self.merge(format!("{} redone", self.name), results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph_commands::TestCommandContext, prelude::*};
+
+    use super::CommandGroup;
+    use crate::graph_commands::commands::{
+        edge::{CreateEdgesCommand, ReparentNodesCommand},
+        node::{CreateNodeByPathCommand, InsertNodeAttributesCommand},
+        CommandAgdb, CommandResult, StoredCommand,
+    };
+
+    #[test]
+    fn group_is_one_undo_step() {
+        let func_name = "group_is_one_undo_step";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        let before = ctx.graph.command_manager.get_undo_stack().len();
+
+        let group = CommandGroup::new("Add Linked Node")
+            .with(Box::new(CreateNodeByPathCommand::new(b.clone(), None)))
+            .with(Box::new(CreateEdgesCommand::new(vec![(a.clone(), b.clone())])))
+            .with(Box::new(InsertNodeAttributesCommand::new(b.clone(), vec![Attribute::new("rating", 4)])));
+        let (_, events) = ctx.graph.subscribe(None);
+        ctx.graph.apply_group(group).unwrap();
+
+        let received: Vec<GraphEvent> = events.try_iter().collect();
+        assert_eq!(received, vec![
+            GraphEvent::NodeCreated(b.clone()),
+            GraphEvent::EdgeCreated { source: a.clone(), target: b.clone() },
+            GraphEvent::AttributesChanged(b.clone()),
+        ], "The events of a group are sent once it has gone through");
+        assert_eq!(ctx.graph.command_manager.get_undo_stack().len(), before + 1);
+        assert_eq!(ctx.graph.get_node_attrs(&b).unwrap(), vec![Attribute::new("rating", 4)]);
+
+        // The group is stored in the history as a whole.
+        ctx.reopen();
+
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.open_node(&b).is_err());
+        assert!(ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap().is_empty());
+        assert!(ctx.graph.open_node(&a).is_ok(), "Commands outside the group stay");
+
+        ctx.graph.redo().unwrap();
+        assert_eq!(ctx.graph.get_node_attrs(&b).unwrap(), vec![Attribute::new("rating", 4)]);
+        assert_eq!(ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failing_group_rolls_back() {
+        let func_name = "failing_group_rolls_back";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        let before = ctx.graph.command_manager.get_undo_stack().len();
+
+        let group = CommandGroup::new("Add Linked Node")
+            .with(Box::new(CreateNodeByPathCommand::new(b.clone(), None)))
+            .with(Box::new(CreateEdgesCommand::new(vec![(a.clone(), b.clone())])))
+            .with(Box::new(CreateEdgesCommand::new(vec![(a.clone(), NodePath::from("missing"))])));
+        let (_, events) = ctx.graph.subscribe(None);
+        assert!(ctx.graph.apply_group(group).is_err());
+        assert_eq!(events.try_iter().count(), 0, "A failed group sends no events");
+
+        assert!(ctx.graph.open_node(&b).is_err(), "Created node should be removed again");
+        assert!(ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap().is_empty());
+        assert_eq!(ctx.graph.command_manager.get_undo_stack().len(), before, "Failed group shouldn't be undoable");
+    }
+
+    /// Adds an attribute to a node, but fails to be undone.
+    struct UnrevertableCommand(NodePath);
+
+    impl CommandAgdb for UnrevertableCommand {
+        fn command_name(&self) -> String {
+            "Unrevertable".to_string()
+        }
+
+        fn stored(&self) -> StoredCommand {
+            StoredCommand::Group { name: self.command_name(), commands: vec![] }
+        }
+
+        fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+            graph.insert_node_attrs(&self.0, vec![Attribute::new("rating", 1)])?;
+            Ok(CommandResult { msg: self.command_name(), nodepaths: vec![], nodes: vec![], edges: vec![], attributes: vec![] })
+        }
+
+        fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+            Err(GraphError::InvalidOperation("Can't be undone".to_string()))
+        }
+
+        fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+            self.apply(graph)
+        }
+    }
+
+    #[test]
+    fn failing_group_leaves_db_and_files_unchanged() {
+        let func_name = "failing_group_leaves_db_and_files_unchanged";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir(root.join("archive")).unwrap();
+        std::fs::write(root.join("docs/plan.md"), "plan").unwrap();
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+        ctx.graph.maintain_readable_files(true).unwrap();
+
+        let a = NodePath::from("a");
+        let docs = NodePath::from("docs");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+
+        // Nothing in the group could be reverted command by command.
+        let group = CommandGroup::new("Archive")
+            .with(Box::new(ReparentNodesCommand::new(vec![docs.clone()], NodePath::from("archive"))))
+            .with(Box::new(UnrevertableCommand(a.clone())))
+            .with(Box::new(CreateNodeByPathCommand::new(NodePath::from("b"), None)))
+            .with(Box::new(CreateEdgesCommand::new(vec![(a.clone(), NodePath::from("missing"))])));
+        assert!(ctx.graph.apply_group(group).is_err());
+
+        assert!(ctx.graph.get_node_attrs(&a).unwrap().is_empty());
+        assert!(ctx.graph.open_node(&NodePath::from("b")).is_err());
+        assert!(ctx.graph.open_node(&NodePath::from("docs/plan.md")).is_ok());
+        assert!(ctx.graph.open_node(&NodePath::from("archive/docs")).is_err());
+        assert!(root.join("docs/plan.md").exists(), "Moved files should be moved back");
+        assert!(!root.join("archive/docs").exists());
+        assert!(ctx.graph.graph.readable_file(&docs).exists(), "Readable files should match the db again");
+        assert!(!ctx.graph.graph.readable_file(&NodePath::from("b")).exists());
+    }
+
+    #[test]
+    fn failing_undo_leaves_db_unchanged() {
+        let func_name = "failing_undo_leaves_db_unchanged";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+
+        // Undone last one first, so b loses its rating before the undo fails.
+        let group = CommandGroup::new("Rate")
+            .with(Box::new(UnrevertableCommand(a.clone())))
+            .with(Box::new(InsertNodeAttributesCommand::new(b.clone(), vec![Attribute::new("rating", 4)])));
+        ctx.graph.apply_group(group).unwrap();
+
+        assert!(ctx.graph.undo().is_err());
+        assert_eq!(ctx.graph.get_node_attrs(&a).unwrap(), vec![Attribute::new("rating", 1)]);
+        assert_eq!(ctx.graph.get_node_attrs(&b).unwrap(), vec![Attribute::new("rating", 4)]);
+    }
+}
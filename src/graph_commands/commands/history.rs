@@ -0,0 +1,318 @@
+/*
+Command history stored in the graph db.
+
+Every command applied through a persistent CommandManager is stored as a node
+under the /history archetype, so that the history survives restarts and can be
+read back as an audit trail. The command itself is serialized to RON, along with
+its name, whether it is on the undo or the redo stack, and a sequence number.
+
+The sequence number is renewed every time a command moves between the stacks.
+The order of both stacks is therefore given by it: the top of a stack is the
+entry with the highest sequence number.
+*/
+
+
+use agdb::{CountComparison, DbElement, DbKeyValue, DbUserValue, DbValue, QueryBuilder};
+
+use crate::{graph_traits::graph_node::GraphNode, prelude::*};
+
+use super::{CommandAgdb, StoredCommand};
+
+/// A command in the stored history, for displaying or auditing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub name: String,
+    /// When the command was first applied.
+    pub time: SysTime,
+    /// When the command was last applied, undone or redone.
+    pub modified_time: SysTime,
+    /// Whether the command is currently undone, i.e. on the redo stack.
+    pub undone: bool,
+}
+
+/// The stacks read back from the db, bottom first, along with the ids of
+/// their entries.
+pub(crate) struct LoadedHistory {
+    pub undo: Vec<(u64, Box<dyn CommandAgdb>)>,
+    pub redo: Vec<(u64, Box<dyn CommandAgdb>)>,
+    /// Next free id or sequence number.
+    pub next: u64,
+}
+
+fn history_path() -> NodePath {
+    NodePath::atype("history")
+}
+
+fn entry_path(id: u64) -> NodePath {
+    history_path().join(&id.to_string())
+}
+
+/// Graphs created before the history was stored don't have the archetype node.
+fn ensure_history_node(graph: &mut GraphAgdb) -> Result<(), GraphError> {
+    let path = history_path();
+    if graph.db().exec(&QueryBuilder::select().ids(path.alias()).query()).is_ok() {
+        return Ok(());
+    }
+
+    let node = Node::new(&path, NodeType::archetype_type());
+    graph.db_mut().exec_mut(
+        &QueryBuilder::insert()
+            .nodes()
+            .aliases(path.alias())
+            .values(&node)
+            .query(),
+    )?;
+    graph.autoparent_nodes(&NodePath::root(), &path)
+}
+
+/// Writes the command into its history entry, creating the entry if needed.
+pub(crate) fn write(
+    graph: &mut GraphAgdb,
+    id: u64,
+    seq: u64,
+    undone: bool,
+    command: &dyn CommandAgdb,
+) -> Result<(), GraphError> {
+    let serialized = ron::to_string(&command.stored())?;
+    let path = entry_path(id);
+
+    let values: Vec<DbKeyValue> = vec![
+        ("command_name", command.command_name()).into(),
+        ("command", serialized).into(),
+        ("undone", undone).into(),
+        ("seq", seq).into(),
+        ("modified_time", SysTime::from(std::time::SystemTime::now())).into(),
+    ];
+
+    let existing = graph.db().exec(&QueryBuilder::select().ids(path.alias()).query());
+    if existing.is_ok() {
+        graph.db_mut().exec_mut(&QueryBuilder::insert().values(vec![values]).ids(path.alias()).query())?;
+        return Ok(());
+    }
+
+    ensure_history_node(graph)?;
+
+    let mut node_values = Node::new(&path, NodeType::new("HistoryEntry".to_string())).to_db_values();
+    node_values.retain(|kv| kv.key != "modified_time".into());
+    node_values.extend(values);
+
+    graph.db_mut().exec_mut(
+        &QueryBuilder::insert()
+            .nodes()
+            .aliases(path.alias())
+            .values(vec![node_values])
+            .query(),
+    )?;
+    graph.autoparent_nodes(&history_path(), &path)
+}
+
+/// Removes the history entries of commands that were dropped from the stacks.
+pub(crate) fn remove(graph: &mut GraphAgdb, ids: &[u64]) -> Result<(), GraphError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let aliases: Vec<String> = ids.iter().map(|id| entry_path(*id).alias()).collect();
+    graph.db_mut().exec_mut(&QueryBuilder::remove().ids(aliases).query())?;
+    Ok(())
+}
+
+fn entry_elements(graph: &GraphAgdb) -> Result<Vec<DbElement>, GraphError> {
+    if graph.db().exec(&QueryBuilder::select().ids(history_path().alias()).query()).is_err() {
+        return Ok(vec![]);
+    }
+
+    let search = QueryBuilder::search()
+        .from(history_path().alias())
+        .where_()
+        .node()
+        .and()
+        .distance(CountComparison::Equal(2))
+        .query();
+    let result = graph.db().exec(&QueryBuilder::select().ids(search).query())?;
+
+    let mut elements = result.elements;
+    elements.sort_by_key(|elem| value_of(elem, "seq").and_then(|v| v.to_u64().ok()).unwrap_or(0));
+    Ok(elements)
+}
+
+fn value_of<'a>(elem: &'a DbElement, key: &str) -> Option<&'a DbValue> {
+    elem.values.iter().find(|kv| kv.key == key.into()).map(|kv| &kv.value)
+}
+
+/// Entries are named after their ids.
+fn entry_id(elem: &DbElement) -> Option<u64> {
+    value_of(elem, "path")
+        .and_then(|v| NodePath::try_from(v.clone()).ok())
+        .and_then(|path| path.name().parse::<u64>().ok())
+}
+
+/// Reads the stored commands back. Fails if an entry can't be read.
+pub(crate) fn load(graph: &GraphAgdb) -> Result<LoadedHistory, GraphError> {
+    let mut history = LoadedHistory { undo: vec![], redo: vec![], next: 0 };
+
+    for elem in entry_elements(graph)? {
+        let id = entry_id(&elem);
+        let seq = value_of(&elem, "seq").and_then(|v| v.to_u64().ok());
+        let undone = value_of(&elem, "undone").and_then(|v| v.to_bool().ok());
+        let command = value_of(&elem, "command")
+            .and_then(|v| v.string().ok())
+            .and_then(|s| ron::from_str::<StoredCommand>(s).ok());
+
+        let (id, seq, undone, command) = match (id, seq, undone, command) {
+            (Some(id), Some(seq), Some(undone), Some(command)) => (id, seq, undone, command),
+            _ => return Err(GraphError::Serialization(format!("History entry {:?} can't be read", elem.id))),
+        };
+
+        history.next = history.next.max(id + 1).max(seq + 1);
+        if undone {
+            history.redo.push((id, command.into_command()));
+        } else {
+            history.undo.push((id, command.into_command()));
+        }
+    }
+
+    Ok(history)
+}
+
+/// The stored history, oldest first. Includes the undone commands.
+pub(crate) fn entries(graph: &GraphAgdb) -> Result<Vec<HistoryEntry>, GraphError> {
+    let mut entries: Vec<(u64, HistoryEntry)> = Vec::new();
+
+    for elem in entry_elements(graph)? {
+        let id = entry_id(&elem);
+        let name = value_of(&elem, "command_name").and_then(|v| v.string().ok()).cloned();
+        let time = value_of(&elem, "created_time").and_then(|v| SysTime::try_from(v.clone()).ok());
+        let modified = value_of(&elem, "modified_time").and_then(|v| SysTime::try_from(v.clone()).ok());
+        let undone = value_of(&elem, "undone").and_then(|v| v.to_bool().ok());
+
+        if let (Some(id), Some(name), Some(time), Some(modified_time), Some(undone)) = (id, name, time, modified, undone) {
+            entries.push((id, HistoryEntry { name, time, modified_time, undone }));
+        }
+    }
+
+    entries.sort_by_key(|(id, _)| *id);
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use agdb::QueryBuilder;
+
+    use crate::{graph_commands::TestCommandContext, prelude::*};
+
+    #[test]
+    fn history_survives_reopening_the_graph() {
+        let func_name = "history_survives_reopening_the_graph";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let path = NodePath::from("node");
+        ctx.graph.create_node_by_path(&path, None).unwrap();
+        ctx.graph.insert_node_attrs(&path, vec![Attribute::new("rating", 3)]).unwrap();
+        ctx.graph.insert_node_attrs(&path, vec![Attribute::new("rating", 5)]).unwrap();
+        ctx.graph.undo().unwrap();
+
+        ctx.reopen();
+
+        let history = ctx.graph.history().unwrap();
+        let names: Vec<&str> = history.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["Create Node From Path", "Insert Node Attribute", "Insert Node Attribute"]);
+        assert_eq!(history.iter().map(|entry| entry.undone).collect::<Vec<_>>(), vec![false, false, true]);
+
+        ctx.graph.redo().unwrap();
+        assert_eq!(ctx.graph.get_node_attrs(&path).unwrap(), vec![Attribute::new("rating", 5)]);
+
+        ctx.graph.undo().unwrap();
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.get_node_attrs(&path).unwrap().is_empty(), "Undo should reach commands from before the restart");
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.open_node(&path).is_err());
+        assert!(ctx.graph.undo().is_err(), "Nothing should be left to undo");
+    }
+
+    #[test]
+    fn stack_order_is_kept_when_commands_move_between_stacks() {
+        let func_name = "stack_order_is_kept_when_commands_move_between_stacks";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+        ctx.graph.undo().unwrap();
+        ctx.graph.undo().unwrap();
+        ctx.graph.redo().unwrap();
+
+        ctx.reopen();
+
+        // The creation of b was undone last, so it is redone first.
+        ctx.graph.redo().unwrap();
+        assert!(ctx.graph.open_node(&b).is_ok());
+        ctx.graph.undo().unwrap();
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.open_node(&b).is_err());
+        assert!(ctx.graph.open_node(&a).is_err());
+    }
+
+    #[test]
+    fn history_is_hidden_from_searches() {
+        let func_name = "history_is_hidden_from_searches";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+        ctx.graph.create_edge(&a, &b).unwrap();
+        assert_eq!(ctx.graph.history().unwrap().len(), 3);
+
+        let history = NodePath::atype("history");
+        let in_history = |path: &NodePath| path.buf().starts_with(history.buf());
+        let nodes = ctx.graph.search_nodes(&Filter::all()).unwrap();
+        assert!(nodes.iter().any(|node| node.path() == a));
+        assert!(!nodes.iter().any(|node| in_history(&node.path())), "History entries aren't nodes of the graph");
+        let edges = ctx.graph.search_edges(&Filter::all()).unwrap();
+        assert!(!edges.iter().any(|edge| in_history(edge.source()) || in_history(edge.target())));
+        let aliases = ctx.graph.get_all_aliases();
+        assert!(aliases.contains(&a.alias()));
+        assert!(!aliases.iter().any(|alias| in_history(&NodePath::from_alias(alias))));
+    }
+
+    #[test]
+    fn failed_undo_stays_on_the_stack() {
+        let func_name = "failed_undo_stays_on_the_stack";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+        ctx.graph.create_edge(&a, &b).unwrap();
+
+        // The link is removed behind the back of the history, so it can't be undone.
+        ctx.graph.graph.delete_edge(Edge::new(&a, &b)).unwrap();
+        let (_, events) = ctx.graph.subscribe(None);
+        assert!(ctx.graph.undo().is_err());
+        assert!(ctx.graph.undo().is_err(), "The failed command should still be on top");
+        assert_eq!(events.try_iter().count(), 0);
+        assert!(ctx.graph.redo().is_err(), "Nothing was undone");
+
+        ctx.reopen();
+        let history = ctx.graph.history().unwrap();
+        assert!(history.iter().all(|entry| !entry.undone), "The failed undo isn't stored");
+        assert!(ctx.graph.undo().is_err());
+    }
+
+    #[test]
+    fn unreadable_history_fails_to_load() {
+        let func_name = "unreadable_history_fails_to_load";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        ctx.graph.create_node_by_path(&NodePath::from("a"), None).unwrap();
+        let entry = super::entry_path(ctx.graph.command_manager.undo_ids[0]);
+        ctx.graph.graph.db_mut().exec_mut(
+            &QueryBuilder::insert().values(vec![vec![("command", "not a command").into()]]).ids(entry.alias()).query(),
+        ).unwrap();
+
+        assert!(matches!(super::load(&ctx.graph.graph), Err(GraphError::Serialization(_))));
+    }
+}
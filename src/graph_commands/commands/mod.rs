@@ -1,26 +1,82 @@
-use std::{error::Error, path::PathBuf};
+use std::{path::PathBuf, time::{Duration, Instant}};
 use agdb::QueryBuilder;
+use serde::{Deserialize, Serialize};
 
 use crate::{graph_traits::Graph, prelude::*};
 
 pub mod node;
 pub mod edge;
+pub mod group;
+pub mod history;
+
+use edge::*;
+use group::CommandGroup;
+use history::HistoryEntry;
+use node::*;
 
 pub struct CommandManager {
     queue: Vec<Box<dyn CommandAgdb>>,
     undo_stack: Vec<Box<dyn CommandAgdb>>,
     redo_stack: Vec<Box<dyn CommandAgdb>>,
+
+    /// Whether the commands are stored in the history of the graph.
+    persistent: bool,
+    /// History entry ids of the commands on the stacks. Only used if persistent.
+    undo_ids: Vec<u64>,
+    redo_ids: Vec<u64>,
+    /// Next free history entry id or sequence number.
+    next_id: u64,
+
+    /// Maximum number of commands on the undo stack. The oldest ones are dropped.
+    max_history: Option<usize>,
+    /// Commands applied within this time of the previous one are merged into
+    /// it, if they are of the same kind and target.
+    coalesce_window: Option<Duration>,
+    last_applied: Option<Instant>,
 }
 
+/// Default maximum number of commands on the undo stack.
+pub const DEFAULT_MAX_HISTORY: usize = 1000;
+
 impl CommandManager {
+    /// Manager that keeps the history in memory only.
     pub fn new() -> Self {
         CommandManager {
             queue: Vec::new(),
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
+            persistent: false,
+            undo_ids: Vec::new(),
+            redo_ids: Vec::new(),
+            next_id: 0,
+            max_history: Some(DEFAULT_MAX_HISTORY),
+            coalesce_window: None,
+            last_applied: None,
         }
     }
 
+    /// Manager that stores its history in the graph, continuing from the
+    /// history that is already there.
+    pub fn load(graph: &GraphAgdb) -> Result<Self, GraphError> {
+        let loaded = history::load(graph)?;
+
+        let (undo_ids, undo_stack) = loaded.undo.into_iter().unzip();
+        let (redo_ids, redo_stack) = loaded.redo.into_iter().unzip();
+
+        Ok(CommandManager {
+            queue: Vec::new(),
+            undo_stack,
+            redo_stack,
+            persistent: true,
+            undo_ids,
+            redo_ids,
+            next_id: loaded.next,
+            max_history: Some(DEFAULT_MAX_HISTORY),
+            coalesce_window: None,
+            last_applied: None,
+        })
+    }
+
     pub fn get_undo_stack(&self) -> &Vec<Box<dyn CommandAgdb>> {
         &self.undo_stack
     }
@@ -29,30 +85,247 @@ impl CommandManager {
         &self.redo_stack
     }
 
-    pub fn apply(&mut self, graph: &mut GraphAgdb, mut command: Box<dyn CommandAgdb>) -> Result<CommandResult, Box<dyn Error>> {
-        let result = command.apply(graph);
-        self.undo_stack.push(command);
-        result
+    /// The stored history of the graph, oldest first. Empty if the manager
+    /// isn't persistent.
+    pub fn history(&self, graph: &GraphAgdb) -> Result<Vec<HistoryEntry>, GraphError> {
+        if !self.persistent {
+            return Ok(vec![]);
+        }
+        history::entries(graph)
+    }
+
+    /// Sets the maximum number of commands on the undo stack. None for no limit.
+    /// If there are more commands than that already, the oldest are dropped.
+    pub fn set_max_history(&mut self, graph: &mut GraphAgdb, max: Option<usize>) -> Result<(), GraphError> {
+        self.max_history = max;
+        let excess = self.excess(0);
+        let dropped: Vec<u64> = self.undo_ids[..excess].to_vec();
+        self.forget(graph, &dropped)?;
+        self.undo_stack.drain(..excess);
+        self.undo_ids.drain(..excess);
+        Ok(())
+    }
+
+    /// Drops every command from the stacks and the stored history.
+    pub fn clear(&mut self, graph: &mut GraphAgdb) -> Result<(), GraphError> {
+        let ids: Vec<u64> = self.undo_ids.iter().chain(&self.redo_ids).copied().collect();
+        self.forget(graph, &ids)?;
+        self.undo_stack.clear();
+        self.undo_ids.clear();
+        self.redo_stack.clear();
+        self.redo_ids.clear();
+        self.last_applied = None;
+        Ok(())
+    }
+
+    /// Sets the time window for merging consecutive commands, such as repeated
+    /// attribute edits on the same node during a drag. None to never merge.
+    pub fn set_coalesce_window(&mut self, window: Option<Duration>) {
+        self.coalesce_window = window;
+    }
+
+    /// The number of the oldest commands that don't fit in the history once
+    /// the given number of commands is added.
+    fn excess(&self, added: usize) -> usize {
+        match self.max_history {
+            Some(max) => (self.undo_stack.len() + added).saturating_sub(max).min(self.undo_stack.len()),
+            None => 0,
+        }
+    }
+
+    /// Removes the commands from the stored history.
+    fn forget(&self, graph: &mut GraphAgdb, ids: &[u64]) -> Result<(), GraphError> {
+        if !self.persistent || ids.is_empty() {
+            return Ok(());
+        }
+        history::remove(graph, ids)
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Stores the current state of the command.
+    fn persist(&mut self, graph: &mut GraphAgdb, id: u64, undone: bool, command: &dyn CommandAgdb) -> Result<(), GraphError> {
+        if !self.persistent {
+            return Ok(());
+        }
+        let seq = self.next_id();
+        history::write(graph, id, seq, undone, command)
+    }
+
+    /// The command on top of the undo stack with the new one merged into it,
+    /// if they can be merged. The command on the stack is left as it is.
+    fn merged_top(&self, command: &dyn CommandAgdb) -> Option<(u64, Box<dyn CommandAgdb>)> {
+        let (top, &id) = (self.undo_stack.last()?, self.undo_ids.last()?);
+        let mut merged = top.stored().into_command();
+        merged.coalesce(command).then_some((id, merged))
+    }
+
+    /// Failed commands are not added to the history. Applying a command clears
+    /// the redo stack, and drops the oldest commands that don't fit in the history.
+    ///
+    /// The command and the changes to the stored history are made in one
+    /// transaction, so if the history can't be stored, the command is rolled
+    /// back and the error is returned.
+    pub fn apply(&mut self, graph: &mut GraphAgdb, mut command: Box<dyn CommandAgdb>) -> Result<CommandResult, GraphError> {
+        let now = Instant::now();
+        let recent = match (self.coalesce_window, self.last_applied) {
+            (Some(window), Some(last)) => now.duration_since(last) <= window,
+            _ => false,
+        };
+
+        let redo_ids = self.redo_ids.clone();
+        let (result, merged, id) = graph.transaction(|graph| {
+            let result = command.apply(graph)?;
+            self.forget(graph, &redo_ids)?;
+
+            if let Some((id, merged)) = recent.then(|| self.merged_top(command.as_ref())).flatten() {
+                self.persist(graph, id, false, merged.as_ref())?;
+                return Ok((result, Some(merged), id));
+            }
+
+            let dropped: Vec<u64> = self.undo_ids[..self.excess(1)].to_vec();
+            self.forget(graph, &dropped)?;
+            let id = self.next_id();
+            self.persist(graph, id, false, command.as_ref())?;
+            Ok((result, None, id))
+        })?;
+
+        self.redo_stack.clear();
+        self.redo_ids.clear();
+        self.last_applied = Some(now);
+
+        match merged {
+            Some(merged) => {
+                self.undo_stack.pop();
+                self.undo_stack.push(merged);
+            }
+            None => {
+                let excess = self.excess(1);
+                self.undo_stack.drain(..excess);
+                self.undo_ids.drain(..excess);
+                self.undo_stack.push(command);
+                self.undo_ids.push(id);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Applies the commands of the group as one undo step. If any of them fails,
+    /// all of them are rolled back.
+    pub fn apply_group(&mut self, graph: &mut GraphAgdb, group: CommandGroup) -> Result<CommandResult, GraphError> {
+        if group.is_empty() {
+            return Err(GraphError::InvalidOperation("Command group is empty".to_string()));
+        }
+        self.apply(graph, Box::new(group))
     }
 
-    pub fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        if let Some(mut command) = self.undo_stack.pop() {
-            let result = command.undo(graph);
-            self.redo_stack.push(command);
-            result
-        } else {
-            Err("Unable to undo".into())
+    /// A command that fails to undo stays on the undo stack, and its history
+    /// entry is left as it was. As with apply, the history is stored in the
+    /// same transaction as the changes, and a failure to store it fails the undo.
+    pub fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let Some(mut command) = self.undo_stack.pop() else {
+            return Err(GraphError::InvalidOperation("Unable to undo".to_string()));
+        };
+        let id = self.undo_ids.pop().unwrap_or_default();
+
+        let undone = graph.transaction(|graph| {
+            let result = command.undo(graph)?;
+            graph.emit(GraphEvent::Undone(command.command_name()));
+            self.persist(graph, id, true, command.as_ref())?;
+            Ok(result)
+        });
+
+        match undone {
+            Ok(result) => {
+                self.redo_stack.push(command);
+                self.redo_ids.push(id);
+                Ok(result)
+            }
+            Err(err) => {
+                self.undo_stack.push(command);
+                self.undo_ids.push(id);
+                Err(err)
+            }
         }
+    }
+
+    /// A command that fails to redo stays on the redo stack, and its history
+    /// entry is left as it was. Like undo, fails if the history can't be stored.
+    pub fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let Some(mut command) = self.redo_stack.pop() else {
+            return Err(GraphError::InvalidOperation("Unable to redo".to_string()));
+        };
+        let id = self.redo_ids.pop().unwrap_or_default();
 
+        let redone = graph.transaction(|graph| {
+            let result = command.redo(graph)?;
+            graph.emit(GraphEvent::Redone(command.command_name()));
+            self.persist(graph, id, false, command.as_ref())?;
+            Ok(result)
+        });
+
+        match redone {
+            Ok(result) => {
+                self.undo_stack.push(command);
+                self.undo_ids.push(id);
+                Ok(result)
+            }
+            Err(err) => {
+                self.redo_stack.push(command);
+                self.redo_ids.push(id);
+                Err(err)
+            }
+        }
     }
+}
+
+/// Serializable form of the commands, used for storing the history.
+#[derive(Serialize, Deserialize)]
+pub enum StoredCommand {
+    CreateNodeByPath(CreateNodeByPathCommand),
+    CreateNodeByName(CreateNodeByNameCommand),
+    InsertNode(InsertNodeCommand),
+    DeleteNodes(DeleteNodesCommand),
+    InsertNodeAttributes(InsertNodeAttributesCommand),
+    DeleteNodeAttributes(DeleteNodeAttributesCommand),
+    CreateEdges(CreateEdgesCommand),
+    InsertEdge(InsertEdgeCommand),
+    DeleteEdges(DeleteEdgesCommand),
+    ReconnectEdge(ReconnectEdgeCommand),
+    ReparentNodes(ReparentNodesCommand),
+    InsertEdgeAttributes(InsertEdgeAttributesCommand),
+    DeleteEdgeAttributes(DeleteEdgeAttributesCommand),
+    Group { name: String, commands: Vec<StoredCommand> },
+}
 
-    pub fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        if let Some(mut command) = self.redo_stack.pop() {
-            let result = command.redo(graph);
-            self.undo_stack.push(command);
-            result
-        } else {
-            Err("Unable to redo".into())
+impl StoredCommand {
+    pub fn into_command(self) -> Box<dyn CommandAgdb> {
+        match self {
+            StoredCommand::CreateNodeByPath(cmd) => Box::new(cmd),
+            StoredCommand::CreateNodeByName(cmd) => Box::new(cmd),
+            StoredCommand::InsertNode(cmd) => Box::new(cmd),
+            StoredCommand::DeleteNodes(cmd) => Box::new(cmd),
+            StoredCommand::InsertNodeAttributes(cmd) => Box::new(cmd),
+            StoredCommand::DeleteNodeAttributes(cmd) => Box::new(cmd),
+            StoredCommand::CreateEdges(cmd) => Box::new(cmd),
+            StoredCommand::InsertEdge(cmd) => Box::new(cmd),
+            StoredCommand::DeleteEdges(cmd) => Box::new(cmd),
+            StoredCommand::ReconnectEdge(cmd) => Box::new(cmd),
+            StoredCommand::ReparentNodes(cmd) => Box::new(cmd),
+            StoredCommand::InsertEdgeAttributes(cmd) => Box::new(cmd),
+            StoredCommand::DeleteEdgeAttributes(cmd) => Box::new(cmd),
+            StoredCommand::Group { name, commands } => {
+                let mut group = CommandGroup::new(&name);
+                for command in commands {
+                    group.push(command.into_command());
+                }
+                Box::new(group)
+            }
         }
     }
 }
@@ -60,11 +333,21 @@ impl CommandManager {
 pub trait CommandAgdb: Send + Sync {
     fn command_name(&self) -> String;
 
-    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>>;
+    /// The command in its serializable form, with the state it has captured.
+    fn stored(&self) -> StoredCommand;
+
+    /// Merges the next command into this one, so that they are undone together.
+    /// Both have already been applied. Returns false if the commands can't be
+    /// merged, which is the default.
+    fn coalesce(&mut self, next: &dyn CommandAgdb) -> bool {
+        false
+    }
 
-    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>>;
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError>;
 
-    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>>;
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError>;
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError>;
 }
 
 pub struct CommandResult {
@@ -84,3 +367,80 @@ impl Into<Vec<Node>> for CommandResult {
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{graph_commands::TestCommandContext, prelude::*};
+
+    #[test]
+    fn applying_a_command_clears_redo() {
+        let func_name = "applying_a_command_clears_redo";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.undo().unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+
+        assert!(ctx.graph.redo().is_err(), "Undone command shouldn't be redone after a new one");
+        assert!(ctx.graph.open_node(&a).is_err());
+
+        ctx.reopen();
+        let history = ctx.graph.history().unwrap();
+        assert_eq!(history.len(), 1, "Dropped commands should be removed from the stored history");
+        assert!(!history[0].undone);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let func_name = "history_is_bounded";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        ctx.graph.set_max_history(Some(2)).unwrap();
+        for name in ["a", "b", "c"] {
+            ctx.graph.create_node_by_path(&NodePath::from(name), None).unwrap();
+        }
+
+        ctx.graph.undo().unwrap();
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.undo().is_err(), "Oldest command should be dropped");
+        assert!(ctx.graph.open_node(&NodePath::from("a")).is_ok());
+        assert_eq!(ctx.graph.history().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rapid_attribute_edits_are_coalesced() {
+        let func_name = "rapid_attribute_edits_are_coalesced";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.insert_node_attrs(&a, vec![Attribute::new("x", 0)]).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+
+        ctx.graph.set_coalesce_window(Some(Duration::from_secs(60)));
+        for x in 1..=3 {
+            ctx.graph.insert_node_attrs(&a, vec![Attribute::new("x", x), Attribute::new("y", x)]).unwrap();
+        }
+        ctx.graph.insert_node_attrs(&b, vec![Attribute::new("x", 9)]).unwrap();
+
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.get_node_attrs(&b).unwrap().is_empty(), "Edits of another node are separate");
+
+        ctx.graph.undo().unwrap();
+        assert_eq!(
+            ctx.graph.get_node_attrs(&a).unwrap(),
+            vec![Attribute::new("x", 0)],
+            "The whole drag should be undone at once"
+        );
+
+        ctx.graph.redo().unwrap();
+        let mut attrs = ctx.graph.get_node_attrs(&a).unwrap();
+        attrs.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(attrs, vec![Attribute::new("x", 3), Attribute::new("y", 3)]);
+    }
+}
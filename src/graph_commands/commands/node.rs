@@ -1,11 +1,14 @@
-use std::{error::Error, path::MAIN_SEPARATOR};
+use std::path::MAIN_SEPARATOR;
 
-use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{elements::attribute::RESERVED_NODE_ATTRS, prelude::*};
 
 /// Action for creating a new node. 
 /// Note that this action may create multiple nodes if its
 /// ancestor nodes are missing. Therefore the undo of this action 
 /// must also undo the creation of all the ancestor nodes.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CreateNodeByPathCommand {
     created_ancestors: Vec<NodePath>,
     node_path: NodePath,
@@ -27,7 +30,11 @@ impl CommandAgdb for CreateNodeByPathCommand {
         "Create Node From Path".to_string()
     }
 
-    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::CreateNodeByPath(self.clone())
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
         // Find all the ancestor nodes that are missing.
         let mut missing_ancestors: Vec<NodePath> = Vec::new();
         let mut ancestor_found: bool = false;
@@ -72,9 +79,13 @@ impl CommandAgdb for CreateNodeByPathCommand {
         }
     }
 
-    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        // The missing ancestors are searched for starting from the node itself,
+        // so the node is usually among them already.
         let mut all_created_nodes: Vec<NodePath> = self.created_ancestors.clone();
-        all_created_nodes.push(self.node_path.clone());
+        if !all_created_nodes.contains(&self.node_path) {
+            all_created_nodes.push(self.node_path.clone());
+        }
         let result = graph.delete_nodes(&all_created_nodes, false, false);
 
         match result {
@@ -93,93 +104,401 @@ impl CommandAgdb for CreateNodeByPathCommand {
         }
     }
 
-    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
         self.apply(graph)
     }
 }
 
+/// Action for creating a new node under a parent. Works the same way as
+/// CreateNodeByPathCommand, missing ancestors included.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CreateNodeByNameCommand {
     parent_path: NodePath,
     node_name: String,
-    node_type: Option<NodeType>,
+    create: CreateNodeByPathCommand,
+}
+
+impl CreateNodeByNameCommand {
+    pub fn new(parent_path: Option<NodePath>, node_name: &str, node_type: Option<NodeType>) -> Self {
+        let parent_path = parent_path.unwrap_or_else(NodePath::root);
+        let node_path = parent_path.join(node_name);
+
+        CreateNodeByNameCommand {
+            parent_path,
+            node_name: node_name.to_string(),
+            create: CreateNodeByPathCommand::new(node_path, node_type),
+        }
+    }
 }
 
 impl CommandAgdb for CreateNodeByNameCommand {
     fn command_name(&self) -> String {
         "Create Node From Name".to_string()
     }
-    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::CreateNodeByName(self.clone())
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.create.apply(graph)
+    }
+
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.create.undo(graph)
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.create.redo(graph)
+    }
+}
+
+/// Action for inserting a node with all its values. If the node already existed,
+/// the previous version is kept so that it can be restored.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InsertNodeCommand {
+    node: Node,
+    previous: Option<Node>,
+    created_ancestors: Vec<NodePath>,
+}
+
+impl InsertNodeCommand {
+    pub fn new(node: Node) -> Self {
+        InsertNodeCommand {
+            node,
+            previous: None,
+            created_ancestors: Vec::new(),
+        }
+    }
+}
+
+impl CommandAgdb for InsertNodeCommand {
+    fn command_name(&self) -> String {
+        "Insert Node".to_string()
+    }
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::InsertNode(self.clone())
+    }
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let path = self.node.path();
+        self.previous = graph.open_node(&path).ok();
+
+        self.created_ancestors = Vec::new();
+        if self.previous.is_none() {
+            let mut ancestor = path.parent();
+            while let Some(path) = ancestor {
+                if graph.open_node(&path).is_ok() {
+                    break;
+                }
+                ancestor = path.parent();
+                self.created_ancestors.push(path);
+            }
+        }
+
+        graph.insert_node(self.node.clone())?;
+
+        Ok(CommandResult {
+            msg: format!("Node inserted: {:?}", path),
+            nodepaths: self.created_ancestors.clone(),
+            nodes: vec![self.node.clone()],
+            edges: vec![],
+            attributes: vec![],
+        })
     }
-    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let path = self.node.path();
+
+        match &self.previous {
+            Some(previous) => {
+                // Inserting only overwrites values, so the attributes that were
+                // added have to be removed separately.
+                let attrs = self.node.attributes();
+                let previous_attrs = previous.attributes();
+                let added: Vec<&str> = attrs.iter()
+                    .map(|attr| attr.name.as_str())
+                    .filter(|name| !RESERVED_NODE_ATTRS.contains(name))
+                    .filter(|name| !previous_attrs.iter().any(|a| a.name == *name))
+                    .collect();
+                if !added.is_empty() {
+                    graph.delete_node_attrs(&path, added)?;
+                }
+                graph.insert_node(previous.clone())?;
+            }
+            None => {
+                let mut inserted = vec![path.clone()];
+                inserted.extend(self.created_ancestors.iter().cloned());
+                graph.delete_nodes(&inserted, false, false)?;
+            }
+        }
+
+        Ok(CommandResult {
+            msg: format!("Node insertion reverted: {:?}", path),
+            nodepaths: vec![path],
+            nodes: self.previous.iter().cloned().collect(),
+            edges: vec![],
+            attributes: vec![],
+        })
     }
-    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
     }
 }
 
+/// Action for deleting nodes. The deleted nodes are kept along with all of their
+/// edges, so that they can be restored exactly.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DeleteNodesCommand {
     node_paths: Vec<NodePath>,
     deleted_nodes: Vec<Node>,
+    deleted_edges: Vec<Edge>,
     files: bool,
     dirs: bool,
 }
 
+impl DeleteNodesCommand {
+    pub fn new(node_paths: Vec<NodePath>, files: bool, dirs: bool) -> Self {
+        DeleteNodesCommand {
+            node_paths,
+            deleted_nodes: Vec::new(),
+            deleted_edges: Vec::new(),
+            files,
+            dirs,
+        }
+    }
+}
+
 impl CommandAgdb for DeleteNodesCommand {
     fn command_name(&self) -> String {
         "Delete Nodes".to_string()
     }
-    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::DeleteNodes(self.clone())
     }
-    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut edges: Vec<Edge> = Vec::new();
+
+        for path in &self.node_paths {
+            nodes.push(graph.open_node(path)?);
+            for edge in graph.node_edges(path)? {
+                // Edges between two deleted nodes are found twice.
+                let duplicate = edges.iter().any(|e| {
+                    e.source() == edge.source() && e.target() == edge.target() && e.contains() == edge.contains()
+                });
+                if !duplicate {
+                    edges.push(edge);
+                }
+            }
+        }
+
+        graph.delete_nodes(&self.node_paths, self.files, self.dirs)?;
+
+        self.deleted_nodes = nodes;
+        self.deleted_edges = edges;
+
+        Ok(CommandResult {
+            msg: format!("Nodes deleted: {:?}", self.node_paths),
+            nodepaths: self.node_paths.clone(),
+            nodes: vec![],
+            edges: vec![],
+            attributes: vec![],
+        })
+    }
+
+    /// Parents are restored before their children, and the edges only after all
+    /// the nodes are back.
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let mut nodes = self.deleted_nodes.clone();
+        nodes.sort_by_key(|node| node.path().buf().components().count());
+        for node in nodes {
+            graph.insert_node(node)?;
+        }
+
+        for edge in &self.deleted_edges {
+            if edge.contains() && graph.open_edge(edge.source(), edge.target(), true).is_err() {
+                // The contains edge of a child that wasn't deleted.
+                graph.autoparent_nodes(edge.source(), edge.target())?;
+            }
+            graph.insert_edge(edge.clone())?;
+        }
+
+        Ok(CommandResult {
+            msg: format!("Nodes restored: {:?}", self.node_paths),
+            nodepaths: self.node_paths.clone(),
+            nodes: self.deleted_nodes.clone(),
+            edges: self.deleted_edges.clone(),
+            attributes: vec![],
+        })
     }
-    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
     }
 }
 
+/// Action for inserting attributes to a node. The previous values of the
+/// overwritten attributes are kept for undo.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct InsertNodeAttributesCommand {
     node_path: NodePath,
     old_values: Vec<Attribute>,
     new_values: Vec<Attribute>,
 }
 
+impl InsertNodeAttributesCommand {
+    pub fn new(node_path: NodePath, attrs: Vec<Attribute>) -> Self {
+        InsertNodeAttributesCommand {
+            node_path,
+            old_values: Vec::new(),
+            new_values: attrs,
+        }
+    }
+}
+
 impl CommandAgdb for InsertNodeAttributesCommand {
     fn command_name(&self) -> String {
         "Insert Node Attribute".to_string()
     }
-    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::InsertNodeAttributes(self.clone())
     }
-    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    /// Repeated inserts on the same node are merged, keeping the values from
+    /// before the first one.
+    fn coalesce(&mut self, next: &dyn CommandAgdb) -> bool {
+        let next = match next.stored() {
+            StoredCommand::InsertNodeAttributes(next) if next.node_path == self.node_path => next,
+            _ => return false,
+        };
+
+        for old in next.old_values {
+            let known = self.new_values.iter().chain(self.old_values.iter()).any(|a| a.name == old.name);
+            if !known {
+                self.old_values.push(old);
+            }
+        }
+        for new in next.new_values {
+            self.new_values.retain(|a| a.name != new.name);
+            self.new_values.push(new);
+        }
+        true
     }
-    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let current = graph.get_node_attrs(&self.node_path)?;
+        self.old_values = current
+            .into_iter()
+            .filter(|attr| self.new_values.iter().any(|a| a.name == attr.name))
+            .collect();
+
+        graph.insert_node_attrs(&self.node_path, self.new_values.clone())?;
+
+        Ok(CommandResult {
+            msg: format!("Attributes inserted to {:?}", self.node_path),
+            nodepaths: vec![self.node_path.clone()],
+            nodes: vec![],
+            edges: vec![],
+            attributes: self.new_values.clone(),
+        })
+    }
+
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let added: Vec<&str> = self
+            .new_values
+            .iter()
+            .map(|attr| attr.name.as_str())
+            .filter(|name| !RESERVED_NODE_ATTRS.contains(name))
+            .filter(|name| !self.old_values.iter().any(|a| a.name == *name))
+            .collect();
+
+        if !added.is_empty() {
+            graph.delete_node_attrs(&self.node_path, added)?;
+        }
+        if !self.old_values.is_empty() {
+            graph.insert_node_attrs(&self.node_path, self.old_values.clone())?;
+        }
+
+        Ok(CommandResult {
+            msg: format!("Attributes restored on {:?}", self.node_path),
+            nodepaths: vec![self.node_path.clone()],
+            nodes: vec![],
+            edges: vec![],
+            attributes: self.old_values.clone(),
+        })
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
     }
 }
 
+/// Action for deleting attributes from a node. The deleted values are kept for undo.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DeleteNodeAttributesCommand {
     node_path: NodePath,
     attr_names: Vec<String>,
     old_values: Vec<Attribute>,
-    new_values: Vec<Attribute>,
+}
+
+impl DeleteNodeAttributesCommand {
+    pub fn new(node_path: NodePath, attr_names: Vec<&str>) -> Self {
+        DeleteNodeAttributesCommand {
+            node_path,
+            attr_names: attr_names.into_iter().map(String::from).collect(),
+            old_values: Vec::new(),
+        }
+    }
 }
 
 impl CommandAgdb for DeleteNodeAttributesCommand {
     fn command_name(&self) -> String {
         "Delete Node Attributes".to_string()
     }
-    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn stored(&self) -> StoredCommand {
+        StoredCommand::DeleteNodeAttributes(self.clone())
     }
-    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn apply(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        let current = graph.get_node_attrs(&self.node_path)?;
+        self.old_values = current
+            .into_iter()
+            .filter(|attr| self.attr_names.contains(&attr.name))
+            .collect();
+
+        graph.delete_node_attrs(&self.node_path, self.attr_names.iter().map(|s| s.as_str()).collect())?;
+
+        Ok(CommandResult {
+            msg: format!("Attributes deleted from {:?}", self.node_path),
+            nodepaths: vec![self.node_path.clone()],
+            nodes: vec![],
+            edges: vec![],
+            attributes: self.old_values.clone(),
+        })
     }
-    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, Box<dyn Error>> {
-        todo!();
+
+    fn undo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        if !self.old_values.is_empty() {
+            graph.insert_node_attrs(&self.node_path, self.old_values.clone())?;
+        }
+
+        Ok(CommandResult {
+            msg: format!("Attributes restored on {:?}", self.node_path),
+            nodepaths: vec![self.node_path.clone()],
+            nodes: vec![],
+            edges: vec![],
+            attributes: self.old_values.clone(),
+        })
+    }
+
+    fn redo(&mut self, graph: &mut GraphAgdb) -> Result<CommandResult, GraphError> {
+        self.apply(graph)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,50 @@
+use node::{DeleteNodesCommand, InsertNodeCommand};
+
+use crate::prelude::*;
+
+/// Attribute definitions are nodes under the attributes archetype, so they are
+/// created and deleted through the node commands and can be undone.
+impl GraphAttr for GraphCommands {
+    fn get_attribute_defs(&self) -> Result<Vec<AttributeDef>, GraphError> {
+        self.graph.get_attribute_defs()
+    }
+
+    fn open_attribute_def(&self, name: &str) -> Result<AttributeDef, GraphError> {
+        self.graph.open_attribute_def(name)
+    }
+
+    fn create_attribute_def(&mut self, def: AttributeDef) -> Result<AttributeDef, GraphError> {
+        let node = self.graph.attribute_def_node(&def)?;
+        self.apply(Box::new(InsertNodeCommand::new(node)))?;
+        Ok(def)
+    }
+
+    fn delete_attribute_def(&mut self, name: &str) -> Result<(), GraphError> {
+        self.graph.open_attribute_def(name)?;
+        self.apply(Box::new(DeleteNodesCommand::new(vec![AttributeDef::path(name)], false, false)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph_commands::TestCommandContext, prelude::*};
+
+    #[test]
+    fn attribute_def_commands_can_be_reverted() {
+        let func_name = "attribute_def_commands_can_be_reverted";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let rating = AttributeDef::new("rating", "Int").with_range(Some(1.0), Some(5.0));
+        ctx.graph.create_attribute_def(rating.clone()).unwrap();
+        ctx.graph.delete_attribute_def("rating").unwrap();
+        assert!(ctx.graph.open_attribute_def("rating").is_err());
+
+        ctx.graph.undo().unwrap();
+        assert_eq!(ctx.graph.open_attribute_def("rating").unwrap(), rating);
+
+        let path = NodePath::from("note");
+        ctx.graph.create_node_by_path(&path, None).unwrap();
+        assert!(ctx.graph.insert_node_attrs(&path, vec![Attribute::new("rating", 9)]).is_err(), "Commands should be validated too");
+    }
+}
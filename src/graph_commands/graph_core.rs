@@ -1,47 +1,140 @@
-use super::{GraphCommands, GraphCore};
+use crate::{config::GraphConfig, error::GraphError, graph_traits::graph_core::{CleanupReport, ImportReport}, indexing::{IndexOptions, IndexSummary}};
+
+use super::{Attribute, AttributeValue, GraphCommands, GraphCore};
 
 impl GraphCore for GraphCommands {
     fn storage_path(&self) -> super::StoragePath {
-        todo!()
+        self.graph.storage_path()
     }
 
     fn user_root_dirpath(&self) -> std::path::PathBuf {
-        todo!()
+        self.graph.user_root_dirpath()
     }
 
     fn root_nodepath(&self) -> super::NodePath {
-        todo!()
+        self.graph.root_nodepath()
     }
 
     fn root_name(&self) -> String {
-        todo!()
+        self.graph.root_name()
     }
 
-    fn new(name: &str, root_path: std::path::PathBuf, custom_storage_path: Option<std::path::PathBuf>) -> Self {
-        todo!()
+    fn new(config: GraphConfig) -> Self {
+        GraphCommands::new(config)
     }
 
-    fn init_archetype_nodes(&mut self) {
-        todo!()
+    fn init_archetype_nodes(&mut self) -> Result<(), GraphError> {
+        self.graph.init_archetype_nodes()
     }
 
-    fn index_single_node(&mut self, path: &super::NodePath) -> Result<super::Node, Box<dyn std::error::Error>> {
-        todo!()
+    fn index_single_node(&mut self, path: &super::NodePath) -> Result<super::Node, GraphError> {
+        self.graph.index_single_node(path)
     }
 
     fn index_node_context(&mut self, path: &super::NodePath) {
         self.graph.index_node_context(path);
     }
 
-    fn cleanup_dead_nodes(&mut self) {
-        todo!()
+    /// Clears the history if nodes were moved.
+    fn index_tree(&mut self, path: &super::NodePath, options: &IndexOptions) -> Result<IndexSummary, GraphError> {
+        let summary = self.graph.index_tree(path, options)?;
+        if !summary.moved.is_empty() {
+            self.clear_history()?;
+        }
+        Ok(summary)
+    }
+
+    /// Clears the history.
+    fn reconcile_move(&mut self, from: &super::NodePath, to: &super::NodePath) -> Result<(), GraphError> {
+        self.graph.reconcile_move(from, to)?;
+        self.clear_history()
+    }
+
+    fn mark_dead_nodes(&mut self) -> Result<Vec<super::NodePath>, GraphError> {
+        self.graph.mark_dead_nodes()
+    }
+
+    /// Clears the history if nodes were deleted.
+    fn cleanup_dead_nodes(&mut self, dry_run: bool) -> Result<CleanupReport, GraphError> {
+        let report = self.graph.cleanup_dead_nodes(dry_run)?;
+        if !dry_run && !report.nodes.is_empty() {
+            self.clear_history()?;
+        }
+        Ok(report)
+    }
+
+    fn maintain_readable_files(&mut self, maintain: bool) -> Result<(), GraphError> {
+        self.graph.maintain_readable_files(maintain)
+    }
+
+    fn rebuild_readable_files(&mut self) -> Result<(), GraphError> {
+        self.graph.rebuild_readable_files()
+    }
+
+    fn import_readable_files(&mut self, dir: Option<std::path::PathBuf>) -> Result<ImportReport, GraphError> {
+        self.graph.import_readable_files(dir)
+    }
+
+    fn get_setting(&self, key: &str) -> Result<Option<AttributeValue>, GraphError> {
+        self.graph.get_setting(key)
     }
 
-    fn maintain_readable_files(&mut self, maintain: bool) {
-        todo!()
+    /// Settings aren't part of the undo history.
+    fn set_setting(&mut self, key: &str, value: AttributeValue) -> Result<(), GraphError> {
+        self.graph.set_setting(key, value)
+    }
+
+    fn list_settings(&self) -> Result<Vec<Attribute>, GraphError> {
+        self.graph.list_settings()
+    }
+
+    fn index_options(&self) -> Result<IndexOptions, GraphError> {
+        self.graph.index_options()
+    }
+
+    fn set_index_options(&mut self, options: &IndexOptions) -> Result<(), GraphError> {
+        self.graph.set_index_options(options)
     }
 
     fn get_all_aliases(&self) -> Vec<String> {
-        todo!()
+        self.graph.get_all_aliases()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::{graph_commands::TestCommandContext, prelude::*};
+
+    #[test]
+    fn core_calls_go_through_to_the_graph() {
+        let func_name = "core_calls_go_through_to_the_graph";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let path = NodePath::from("single.txt");
+        std::fs::write(path.full(&ctx.graph.user_root_dirpath()), "single").unwrap();
+
+        let node = ctx.graph.index_single_node(&path).unwrap();
+        assert_eq!(node.path(), path);
+        assert!(ctx.graph.get_all_aliases().contains(&path.alias()));
+    }
+
+    #[test]
+    fn deleting_dead_nodes_clears_the_history() {
+        let func_name = "deleting_dead_nodes_clears_the_history";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let file = NodePath::from("gone.txt");
+        std::fs::write(file.full(&ctx.graph.user_root_dirpath()), "gone").unwrap();
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+        ctx.graph.create_node_by_path(&NodePath::from("a"), None).unwrap();
+        std::fs::remove_file(file.full(&ctx.graph.user_root_dirpath())).unwrap();
+        ctx.graph.mark_dead_nodes().unwrap();
+
+        ctx.graph.cleanup_dead_nodes(true).unwrap();
+        assert_eq!(ctx.graph.history().unwrap().len(), 1, "A dry run changes nothing");
+
+        ctx.graph.cleanup_dead_nodes(false).unwrap();
+        assert!(ctx.graph.history().unwrap().is_empty());
+        assert!(ctx.graph.undo().is_err(), "Nothing should be left to undo");
+        assert!(ctx.graph.open_node(&NodePath::from("a")).is_ok());
     }
-}
\ No newline at end of file
+}
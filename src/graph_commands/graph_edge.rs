@@ -0,0 +1,169 @@
+use edge::{
+    CreateEdgesCommand, DeleteEdgeAttributesCommand, DeleteEdgesCommand, InsertEdgeAttributesCommand,
+    InsertEdgeCommand, ReconnectEdgeCommand, ReparentNodesCommand,
+};
+
+use crate::prelude::*;
+
+impl GraphEdge for GraphCommands {
+    fn get_edge_strict(&self, from: &NodePath, to: &NodePath) -> Result<Edge, GraphError> {
+        self.graph.get_edge_strict(from, to)
+    }
+
+    fn search_edges(&self, filter: &Filter) -> Result<Vec<Edge>, GraphError> {
+        self.graph.search_edges(filter)
+    }
+
+    fn create_edge(
+        &mut self,
+        source_path: &NodePath,
+        target_path: &NodePath,
+    ) -> Result<(), GraphError> {
+        let cmd = CreateEdgesCommand::new(vec![(source_path.clone(), target_path.clone())]);
+        self.apply(Box::new(cmd))?;
+        Ok(())
+    }
+
+    fn reparent_node(
+        &mut self,
+        node_path: &NodePath,
+        new_parent_path: &NodePath,
+    ) -> Result<(), GraphError> {
+        let cmd = ReparentNodesCommand::new(vec![node_path.clone()], new_parent_path.clone());
+        self.apply(Box::new(cmd))?;
+        Ok(())
+    }
+
+    fn reconnect_edge(
+        &mut self,
+        edge: Edge,
+        from: &NodePath,
+        to: &NodePath,
+    ) -> Result<(), GraphError> {
+        self.apply(Box::new(ReconnectEdgeCommand::new(edge, from.clone(), to.clone())))?;
+        Ok(())
+    }
+
+    fn insert_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+        self.apply(Box::new(InsertEdgeCommand::new(edge)))?;
+        Ok(())
+    }
+
+    fn delete_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+        self.apply(Box::new(DeleteEdgesCommand::new(vec![edge])))?;
+        Ok(())
+    }
+
+    fn insert_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        self.apply(Box::new(InsertEdgeAttributesCommand::new(edge, attrs)))?;
+        Ok(())
+    }
+
+    fn delete_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        self.apply(Box::new(DeleteEdgeAttributesCommand::new(edge, attr_names)))?;
+        Ok(())
+    }
+
+    fn insert_reserved_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        self.apply(Box::new(InsertEdgeAttributesCommand::new_reserved(edge, attrs)))?;
+        Ok(())
+    }
+
+    fn delete_reserved_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        self.apply(Box::new(DeleteEdgeAttributesCommand::new_reserved(edge, attr_names)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph_commands::TestCommandContext;
+
+    use super::*;
+
+    #[test]
+    fn reparent_command_can_be_reverted() {
+        let func_name = "reparent_command_can_be_reverted";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        std::fs::create_dir_all(root.join("from/inner")).unwrap();
+        std::fs::create_dir(root.join("to")).unwrap();
+        std::fs::write(root.join("from/inner/file.txt"), "content").unwrap();
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+        let node = NodePath::from("from/inner");
+        let moved = NodePath::from("to/inner");
+        ctx.graph.reparent_node(&node, &NodePath::from("to")).unwrap();
+
+        assert!(root.join("to/inner/file.txt").exists(), "Files should be moved on disk");
+        assert!(ctx.graph.open_node(&NodePath::from("to/inner/file.txt")).is_ok());
+        assert!(ctx.graph.open_node(&node).is_err());
+
+        ctx.graph.undo().unwrap();
+
+        assert!(root.join("from/inner/file.txt").exists(), "Files should be moved back on disk");
+        assert!(!root.join("to/inner").exists());
+        assert!(ctx.graph.open_node(&NodePath::from("from/inner/file.txt")).is_ok());
+        assert!(ctx.graph.open_node(&moved).is_err());
+
+        ctx.graph.redo().unwrap();
+
+        assert!(root.join("to/inner/file.txt").exists());
+        assert!(ctx.graph.open_node(&moved).is_ok());
+    }
+
+    #[test]
+    fn edge_commands_can_be_reverted() {
+        let func_name = "edge_commands_can_be_reverted";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        let c = NodePath::from("c");
+        for path in [&a, &b, &c] {
+            ctx.graph.create_node_by_path(path, None).unwrap();
+        }
+        let links = |ctx: &TestCommandContext| ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+
+        ctx.graph.create_edge(&a, &b).unwrap();
+        let edge = ctx.graph.graph.open_edge(&a, &b, false).unwrap();
+        ctx.graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("label", "cites")]).unwrap();
+        ctx.graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("label", "quotes")]).unwrap();
+        ctx.graph.insert_reserved_edge_attrs(edge.clone(), vec![Attribute::new("to_position", [1.0, 1.0])]).unwrap();
+        ctx.graph.reconnect_edge(edge.clone(), &c, &b).unwrap();
+        let moved = ctx.graph.graph.open_edge(&c, &b, false).unwrap();
+        ctx.graph.delete_edge(moved).unwrap();
+        assert!(links(&ctx).is_empty());
+
+        ctx.graph.undo().unwrap();
+        let restored = ctx.graph.graph.open_edge(&c, &b, false).unwrap();
+        assert!(restored.attributes().contains(&Attribute::new("label", "quotes")));
+        assert!(restored.attributes().contains(&Attribute::new("to_position", [1.0, 1.0])));
+
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.graph.open_edge(&c, &b, false).is_err(), "Link should be moved back");
+        ctx.graph.undo().unwrap();
+        let edge = ctx.graph.graph.open_edge(&a, &b, false).unwrap();
+        assert_eq!(*edge.attributes(), vec![Attribute::new("label", "quotes")]);
+
+        ctx.graph.undo().unwrap();
+        let edge = ctx.graph.graph.open_edge(&a, &b, false).unwrap();
+        assert_eq!(*edge.attributes(), vec![Attribute::new("label", "cites")], "Old value should be restored");
+
+        ctx.graph.undo().unwrap();
+        let edge = ctx.graph.graph.open_edge(&a, &b, false).unwrap();
+        assert!(edge.attributes().is_empty());
+
+        ctx.graph.undo().unwrap();
+        assert!(links(&ctx).is_empty(), "Link creation should be undone");
+
+        for _ in 0..6 {
+            ctx.graph.redo().unwrap();
+        }
+        assert!(links(&ctx).is_empty(), "Link should be deleted again");
+        ctx.graph.undo().unwrap();
+        let restored = ctx.graph.graph.open_edge(&c, &b, false).unwrap();
+        assert!(restored.attributes().contains(&Attribute::new("label", "quotes")));
+    }
+}
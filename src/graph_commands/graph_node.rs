@@ -1,12 +1,15 @@
-use std::{error::Error, path::PathBuf};
+use std::path::PathBuf;
 
-use node::CreateNodeByPathCommand;
+use node::{
+    CreateNodeByNameCommand, CreateNodeByPathCommand, DeleteNodeAttributesCommand, DeleteNodesCommand,
+    InsertNodeAttributesCommand, InsertNodeCommand,
+};
 
 use crate::prelude::*;
 
 
 impl GraphNode for GraphCommands {
-    fn open_node(&self, path: &NodePath) -> Result<Node, Box<dyn std::error::Error>> {
+    fn open_node(&self, path: &NodePath) -> Result<Node, GraphError> {
         self.graph.open_node(path)
     }
 
@@ -14,11 +17,15 @@ impl GraphNode for GraphCommands {
         self.graph.open_node_connections(path)
     }
 
+    fn search_nodes(&self, filter: &Filter) -> Result<Vec<Node>, GraphError> {
+        self.graph.search_nodes(filter)
+    }
+
     fn create_node_by_path(
         &mut self,
         path: &NodePath,
         ntype: Option<NodeType>,
-    ) -> Result<Node, Box<dyn std::error::Error>> {
+    ) -> Result<Node, GraphError> {
         let cmd = CreateNodeByPathCommand::new(path.clone(), ntype);
 
         let result = match self.apply(Box::new(cmd)) {
@@ -39,51 +46,66 @@ impl GraphNode for GraphCommands {
         parent_path: Option<NodePath>,
         name: &str,
         ntype: Option<NodeType>,
-    ) -> Result<Node, Box<dyn std::error::Error>> {
-        todo!()
+    ) -> Result<Node, GraphError> {
+        let cmd = CreateNodeByNameCommand::new(parent_path, name, ntype);
+
+        let nodes: Vec<Node> = self.apply(Box::new(cmd))?.into();
+        let node = nodes
+            .first()
+            .ok_or_else(|| GraphError::InvalidOperation("No node was created".to_string()))?
+            .clone();
+        Ok(node)
     }
 
-    fn insert_node(&mut self, node: Node) -> Result<(), Box<dyn std::error::Error>> {
-        todo!()
+    fn insert_node(&mut self, node: Node) -> Result<(), GraphError> {
+        self.apply(Box::new(InsertNodeCommand::new(node)))?;
+        Ok(())
     }
 
-    fn delete_nodes(&mut self, paths: &Vec<NodePath>, files: bool, dirs: bool) -> Result<(), Box<dyn Error>> {
-        todo!()
+    fn delete_nodes(&mut self, paths: &Vec<NodePath>, files: bool, dirs: bool) -> Result<(), GraphError> {
+        self.apply(Box::new(DeleteNodesCommand::new(paths.clone(), files, dirs)))?;
+        Ok(())
     }
 
-    fn get_node_attrs(&self, path: &NodePath) -> Result<Vec<Attribute>, Box<dyn std::error::Error>> {
-        todo!()
+    fn get_node_attrs(&self, path: &NodePath) -> Result<Vec<Attribute>, GraphError> {
+        self.graph.get_node_attrs(path)
     }
 
     fn insert_node_attrs(
         &mut self,
         path: &NodePath,
         attrs: Vec<Attribute>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        todo!()
+    ) -> Result<(), GraphError> {
+        self.apply(Box::new(InsertNodeAttributesCommand::new(path.clone(), attrs)))?;
+        Ok(())
     }
 
     fn delete_node_attrs(
         &mut self,
         path: &NodePath,
         attr_name: Vec<&str>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        todo!()
+    ) -> Result<(), GraphError> {
+        self.apply(Box::new(DeleteNodeAttributesCommand::new(path.clone(), attr_name)))?;
+        Ok(())
     }
 
-    fn merge_nodes(&mut self, nodes: Vec<NodePath>) -> Result<(), Box<dyn Error>> {
-        todo!()
+    /// Not a command yet, as merging isn't implemented in the graph either.
+    fn merge_nodes(&mut self, nodes: Vec<NodePath>) -> Result<(), GraphError> {
+        self.graph.merge_nodes(nodes)
     }
 
+    /// Clears the history.
     fn autoparent_nodes(
         &mut self,
         parent: &NodePath,
         child: &NodePath,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        self.graph.autoparent_nodes(parent, child)
+    ) -> Result<(), GraphError> {
+        self.graph.autoparent_nodes(parent, child)?;
+        self.clear_history()
     }
 }
 
+#[cfg(test)]
 mod tests {
     use crate::graph_commands::TestCommandContext;
 
@@ -179,4 +201,64 @@ mod tests {
         let found = ctx.graph.open_node(&npath);
         assert_eq!(found.is_ok(), true, "Node should be found");
     }
+
+    #[test]
+    fn delete_nodes_command_restores_nodes_and_edges() {
+        let func_name = "delete_nodes_command_restores_nodes_and_edges";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let dir = NodePath::from("dir");
+        let child = NodePath::from("dir/child");
+        let other = NodePath::from("other");
+        ctx.graph.create_node_by_path(&child, None).unwrap();
+        ctx.graph.create_node_by_path(&other, None).unwrap();
+        ctx.graph.insert_node_attrs(&dir, vec![Attribute::new("rating", 3)]).unwrap();
+        ctx.graph.create_edge(&other, &dir).unwrap();
+        let contains = ctx.graph.graph.open_edge(&dir, &child, true).unwrap();
+        ctx.graph.insert_edge_attrs(contains, vec![Attribute::new("order", 1)]).unwrap();
+
+        ctx.graph.delete_nodes(&vec![dir.clone()], false, false).unwrap();
+        assert!(ctx.graph.open_node(&dir).is_err());
+
+        ctx.graph.undo().unwrap();
+
+        let node = ctx.graph.open_node(&dir).unwrap();
+        assert_eq!(node.attributes(), vec![Attribute::new("rating", 3)]);
+        assert!(ctx.graph.graph.open_edge(&other, &dir, false).is_ok(), "Link should be restored");
+        let contains = ctx.graph.graph.open_edge(&dir, &child, true).unwrap();
+        assert!(contains.attributes().contains(&Attribute::new("order", 1)), "Child should be reattached");
+        assert!(ctx.graph.graph.open_edge(&NodePath::user_root(), &dir, true).is_ok());
+
+        ctx.graph.redo().unwrap();
+        assert!(ctx.graph.open_node(&dir).is_err());
+    }
+
+    #[test]
+    fn node_attribute_commands_can_be_reverted() {
+        let func_name = "node_attribute_commands_can_be_reverted";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let path = NodePath::from("node");
+        ctx.graph.create_node_by_name(Some(NodePath::user_root()), "node", None).unwrap();
+        ctx.graph.insert_node_attrs(&path, vec![Attribute::new("rating", 3)]).unwrap();
+        ctx.graph.insert_node_attrs(&path, vec![Attribute::new("rating", 5), Attribute::new("tag", "x")]).unwrap();
+        ctx.graph.delete_node_attrs(&path, vec!["rating"]).unwrap();
+        assert_eq!(ctx.graph.get_node_attrs(&path).unwrap(), vec![Attribute::new("tag", "x")]);
+
+        ctx.graph.undo().unwrap();
+        let mut attrs = ctx.graph.get_node_attrs(&path).unwrap();
+        attrs.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(attrs, vec![Attribute::new("rating", 5), Attribute::new("tag", "x")]);
+
+        ctx.graph.undo().unwrap();
+        assert_eq!(ctx.graph.get_node_attrs(&path).unwrap(), vec![Attribute::new("rating", 3)]);
+
+        ctx.graph.undo().unwrap();
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.open_node(&path).is_err(), "Node creation should be undone");
+
+        ctx.graph.redo().unwrap();
+        ctx.graph.redo().unwrap();
+        assert_eq!(ctx.graph.get_node_attrs(&path).unwrap(), vec![Attribute::new("rating", 3)]);
+    }
 }
@@ -0,0 +1,58 @@
+use node::InsertNodeCommand;
+
+use crate::prelude::*;
+
+/// Node types and their instances are created through InsertNodeCommand, so
+/// both can be undone.
+impl GraphNtype for GraphCommands {
+    fn get_node_types(&self) -> Result<Vec<NodeTypeDef>, GraphError> {
+        self.graph.get_node_types()
+    }
+
+    fn open_nodetype(&self, ntype: &NodeType) -> Result<NodeTypeDef, GraphError> {
+        self.graph.open_nodetype(ntype)
+    }
+
+    fn create_nodetype(&mut self, nodetype: NodeTypeDef) -> Result<NodeTypeDef, GraphError> {
+        let node = self.graph.nodetype_node(&nodetype)?;
+        self.apply(Box::new(InsertNodeCommand::new(node)))?;
+        Ok(nodetype)
+    }
+
+    fn get_nodetype_attrs(&self, ntype: &NodeType) -> Result<Vec<Attribute>, GraphError> {
+        self.graph.get_nodetype_attrs(ntype)
+    }
+
+    fn instance_nodetype(&mut self, path: &NodePath, ntype: &NodeType) -> Result<Node, GraphError> {
+        let node = self.graph.nodetype_instance(path, ntype)?;
+        self.apply(Box::new(InsertNodeCommand::new(node)))?;
+        self.graph.open_node(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph_commands::TestCommandContext, prelude::*};
+
+    #[test]
+    fn nodetype_commands_can_be_reverted() {
+        let func_name = "nodetype_commands_can_be_reverted";
+        let mut ctx = TestCommandContext::new(func_name);
+
+        let task = NodeType::new("Task".to_string());
+        ctx.graph
+            .create_nodetype(NodeTypeDef::new(task.clone()).with_attributes(vec![Attribute::new("done", false)]))
+            .unwrap();
+        let path = NodePath::from("todo");
+        ctx.graph.instance_nodetype(&path, &task).unwrap();
+        assert_eq!(ctx.graph.get_node_attrs(&path).unwrap(), vec![Attribute::new("done", false)]);
+
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.open_node(&path).is_err());
+        ctx.graph.undo().unwrap();
+        assert!(ctx.graph.get_node_types().unwrap().is_empty());
+
+        ctx.graph.redo().unwrap();
+        assert_eq!(ctx.graph.get_node_types().unwrap().len(), 1);
+    }
+}
@@ -1,41 +1,99 @@
-use std::{error::Error, path::PathBuf};
+use std::{path::PathBuf, sync::mpsc::Receiver};
 
 use directories::ProjectDirs;
 
 use crate::prelude::*;
+use commands::{group::CommandGroup, history::HistoryEntry};
 
 pub mod commands;
+pub mod graph_attr;
 pub mod graph_node;
+pub mod graph_edge;
 pub mod graph_core;
-
+pub mod graph_ntype;
+
+/// The graph with its changes made through commands, so that they can be undone.
+///
+/// index_tree, reconcile_move, cleanup_dead_nodes and autoparent_nodes aren't
+/// commands, as they sync the graph with the file system rather than edit it.
+/// When they move, delete or reparent nodes, the undo history is cleared, since
+/// the stored commands could otherwise be undone onto the wrong nodes.
 pub struct GraphCommands {
     command_manager: CommandManager,
     graph: GraphAgdb,
 }
 
 impl GraphCommands {    
-    pub fn new(name: &str, root_path: PathBuf, custom_storage_path: Option<PathBuf>) -> Self {
-        let graph = GraphAgdb::new(name, root_path, custom_storage_path);
-        let action_manager = CommandManager::new();
-        GraphCommands {
-            command_manager: action_manager,
-            graph,
-        }
+    /// Opens the graph along with its stored command history, so that commands
+    /// from earlier sessions can still be undone. Panics if the graph or its history can't be opened.
+    pub fn new(config: GraphConfig) -> Self {
+        let graph = GraphAgdb::new(config);
+        GraphCommands::with_graph(graph).unwrap_or_else(|e| panic!("Failed to load command history: {}", e))
+    }
+
+    /// Like new, but reports why the graph or its history couldn't be opened
+    /// instead of panicking. See GraphAgdb::open.
+    pub fn open(config: GraphConfig) -> Result<Self, GraphError> {
+        let graph = GraphAgdb::open(config)?;
+        GraphCommands::with_graph(graph)
     }
 
-    pub fn apply(&mut self, command: Box<dyn CommandAgdb>) -> Result<CommandResult, Box<dyn Error>> {
+    fn with_graph(graph: GraphAgdb) -> Result<Self, GraphError> {
+        let command_manager = CommandManager::load(&graph)?;
+        Ok(GraphCommands { command_manager, graph })
+    }
+
+    pub fn apply(&mut self, command: Box<dyn CommandAgdb>) -> Result<CommandResult, GraphError> {
         self.command_manager.apply(&mut self.graph, command)
     }
 
-    pub fn undo(&mut self) -> Result<CommandResult, Box<dyn Error>> {
+    /// Applies a group of commands as a single undoable action. Either all of
+    /// the commands are applied or none are.
+    pub fn apply_group(&mut self, group: CommandGroup) -> Result<CommandResult, GraphError> {
+        self.command_manager.apply_group(&mut self.graph, group)
+    }
+
+    pub fn undo(&mut self) -> Result<CommandResult, GraphError> {
         let action = self.command_manager.undo(&mut self.graph);
         action
     }
 
-    pub fn redo(&mut self) -> Result<CommandResult, Box<dyn Error>> {
+    pub fn redo(&mut self) -> Result<CommandResult, GraphError> {
         let action = self.command_manager.redo(&mut self.graph);
         action
     }
+
+    /// Subscribes to the changes made to the graph, whether directly or through
+    /// commands. Undo and redo send an event of their own after the changes they made.
+    pub fn subscribe(&mut self, filter: Option<EventFilter>) -> (Subscription, Receiver<GraphEvent>) {
+        self.graph.subscribe(filter)
+    }
+
+    /// Ends a subscription. Returns false if it had already ended.
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        self.graph.unsubscribe(subscription)
+    }
+
+    /// Sets the maximum number of undoable commands. None for no limit.
+    pub fn set_max_history(&mut self, max: Option<usize>) -> Result<(), GraphError> {
+        self.command_manager.set_max_history(&mut self.graph, max)
+    }
+
+    /// Sets the time window within which consecutive edits of the same target are
+    /// merged into one undo step. None to keep every command separate.
+    pub fn set_coalesce_window(&mut self, window: Option<std::time::Duration>) {
+        self.command_manager.set_coalesce_window(window);
+    }
+
+    /// Drops every command from the undo and redo stacks and the stored history.
+    pub fn clear_history(&mut self) -> Result<(), GraphError> {
+        self.command_manager.clear(&mut self.graph)
+    }
+
+    /// The stored command history, oldest first, including the undone commands.
+    pub fn history(&self) -> Result<Vec<HistoryEntry>, GraphError> {
+        self.command_manager.history(&self.graph)
+    }
 }
 
 pub struct TestCommandContext {
@@ -62,6 +120,21 @@ impl TestCommandContext {
         }
     }
 
+    /// Closes the graph and opens it again from the same storage, as if the
+    /// app was restarted.
+    pub fn reopen(&mut self) {
+        let root = self.graph.user_root_dirpath();
+        let mut config = GraphConfig::new(&self.test_name, root.clone());
+        config.storage_path = self.graph.storage_path();
+
+        let placeholder = GraphCommands::new(
+            GraphConfig::new(&format!("{}_placeholder", self.test_name), root.clone()).with_storage(root),
+        );
+        drop(std::mem::replace(&mut self.graph, placeholder));
+
+        self.graph = GraphCommands::new(config);
+    }
+
     /// Graph setup function for tests. Always stores the db in the data_dir.
     fn setup(test_name: &str, storage: Option<PathBuf>) -> GraphCommands {
         // let test_name = self.test_name.clone();
@@ -80,7 +153,7 @@ impl TestCommandContext {
 
         println!("Trying to create test directory: {:#?}", full_path);
 
-        let graph = GraphCommands::new(&test_name, full_path.clone(), Some(strg_dir));
+        let graph = GraphCommands::new(GraphConfig::new(test_name, full_path.clone()).with_storage(strg_dir));
 
         assert_eq!(
             full_path.exists(),
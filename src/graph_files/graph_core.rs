@@ -0,0 +1,387 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use agdb::DbKeyValue;
+
+use crate::{
+    config::GraphConfig,
+    elements::{
+        attribute::{Attribute, AttributeValue},
+        edge::Edge,
+        node::Node,
+        node_path::NodePath,
+        nodetype::{NodeType, ARCHETYPES},
+    },
+    error::GraphError,
+    filter::{EdgeKind, Filter},
+    graph_agdb::{in_history, readable::import_readable_dir, settings, sync::sync_values},
+    graph_traits::{
+        graph_core::{CleanupReport, GraphCore, ImportReport},
+        graph_edge::GraphEdge,
+        graph_node::GraphNode,
+        StoragePath,
+    },
+    indexing::{self, Fingerprint, IndexOptions, IndexSummary, MoveCandidates},
+};
+
+use super::GraphFiles;
+
+impl GraphCore for GraphFiles {
+    fn storage_path(&self) -> StoragePath {
+        self.storage_path.clone()
+    }
+
+    fn user_root_dirpath(&self) -> PathBuf {
+        self.root_path.clone()
+    }
+
+    fn root_nodepath(&self) -> NodePath {
+        NodePath::root()
+    }
+
+    /// Gets the name of the root directory without the full path. Opening
+    /// the graph checks that the root has one.
+    fn root_name(&self) -> String {
+        self.root_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Constructor. Panics if the files cannot be read or written. Use
+    /// GraphFiles::open to handle the failure instead.
+    ///
+    /// Unlike open, doesn't check that the root exists or lock the files.
+    fn new(config: GraphConfig) -> Self {
+        GraphFiles::open_with(config, false).unwrap_or_else(|e| panic!("Failed to open graph: {}", e))
+    }
+
+    fn init_archetype_nodes(&mut self) -> Result<(), GraphError> {
+        for atype in ARCHETYPES.iter() {
+            let atype_path = NodePath::atype(*atype);
+
+            let ntype = if atype_path == NodePath::root() {
+                NodeType::root_type()
+            } else {
+                NodeType::archetype_type()
+            };
+
+            self.insert_with_parent(&Node::new(&atype_path, ntype))?;
+        }
+        self.flush()
+    }
+
+    /// Creates the node of a file or directory that isn't in the graph yet.
+    /// Errs on archetype nodes as well as other virtual nodes.
+    fn index_single_node(&mut self, path: &NodePath) -> Result<Node, GraphError> {
+        let full_path = path.full(&self.root_path);
+
+        // The user_root is an archetype, so it is always in the graph.
+        if full_path == self.root_path && !full_path.is_dir() {
+            return Err(GraphError::RootNotFound(full_path));
+        }
+        if path.is_atype() && *path != NodePath::user_root() {
+            return Err(GraphError::ArchetypeProtected(path.clone()));
+        }
+        if self.has_node(path) {
+            return Err(GraphError::AlreadyExists(format!("Node {}", path.alias())));
+        }
+        if !full_path.exists() {
+            return Err(GraphError::NotPhysical(path.clone()));
+        }
+
+        self.create_node_by_path(path, None)
+    }
+
+    /// Indexes the parent of the node and, for directories, their contents.
+    fn index_node_context(&mut self, path: &NodePath) {
+        let full_path = path.full(&self.root_path);
+        if !full_path.exists() {
+            return;
+        }
+
+        if *path != NodePath::user_root() {
+            if let Some(parent) = path.parent() {
+                let _ = self.index_single_node(&parent);
+            }
+        }
+
+        if let Ok(children) = full_path.read_dir() {
+            for child in children.flatten() {
+                let child_path = NodePath::from_dir_path(&self.root_path, &child.path());
+                let _ = self.index_single_node(&child_path);
+            }
+        }
+    }
+
+    fn index_tree(&mut self, path: &NodePath, options: &IndexOptions) -> Result<IndexSummary, GraphError> {
+        let full_path = path.full(&self.root_path);
+        if !full_path.exists() {
+            return Err(GraphError::NotPhysical(path.clone()));
+        }
+
+        let mut summary = IndexSummary::default();
+
+        // The user_root is an archetype and is always in the graph.
+        if *path != NodePath::user_root()
+            && !self.sync_tree_node(path, &full_path, options.hash_contents, &mut summary)
+        {
+            self.flush()?;
+            return Ok(summary);
+        }
+
+        if !full_path.is_dir() {
+            self.flush()?;
+            return Ok(summary);
+        }
+
+        let walk = indexing::walk(&self.root_path, &full_path, options, &self.db_files());
+
+        let mut moves: HashMap<PathBuf, NodePath> = HashMap::new();
+        if options.detect_moves {
+            let (found, reconciliation) = self.detect_moves(&walk.entries, options.hash_contents);
+            moves = found;
+            summary.reconciliation = reconciliation;
+        }
+
+        for entry in walk.entries {
+            let path = NodePath::from_dir_path(&self.root_path, &entry);
+
+            if let Some(from) = moves.get(&entry) {
+                match self.move_node(from, &path, options.hash_contents) {
+                    Ok(_) => summary.moved.push((from.clone(), path)),
+                    Err(err) => summary.errored.push((path, err.to_string())),
+                }
+                continue;
+            }
+
+            self.sync_tree_node(&path, &entry, options.hash_contents, &mut summary);
+        }
+
+        summary.skipped = walk
+            .skipped
+            .iter()
+            .map(|entry| NodePath::from_dir_path(&self.root_path, entry))
+            .collect();
+        summary.errored.extend(walk.errored.into_iter().map(|(entry, err)| {
+            (NodePath::from_dir_path(&self.root_path, &entry), err)
+        }));
+
+        self.flush()?;
+        Ok(summary)
+    }
+
+    fn reconcile_move(&mut self, from: &NodePath, to: &NodePath) -> Result<(), GraphError> {
+        if from.full(&self.root_path).exists() {
+            return Err(GraphError::AlreadyExists(format!("File of {}", from.alias())));
+        }
+        if !to.full(&self.root_path).exists() {
+            return Err(GraphError::NotPhysical(to.clone()));
+        }
+        if !self.has_node(from) {
+            return Err(GraphError::NodeNotFound(from.clone()));
+        }
+
+        // The node created for the new file is replaced, so it must not have
+        // any data of its own yet.
+        if let Ok(node) = self.open_node(to) {
+            let links = self
+                .node_edges(to)?
+                .iter()
+                .any(|edge| !edge.contains());
+            if !node.attributes().is_empty() || links {
+                return Err(GraphError::InvalidOperation(format!("Node {} already has attributes or links", to.alias())));
+            }
+            self.remove_node(to);
+        }
+
+        self.move_node(from, to, false)?;
+        self.flush()
+    }
+
+    fn mark_dead_nodes(&mut self) -> Result<Vec<NodePath>, GraphError> {
+        let physical = Filter::ntype(NodeType::file()).or(Filter::ntype(NodeType::dir()));
+        let nodes = self.search_nodes(&physical)?;
+
+        let mut dead: Vec<NodePath> = Vec::new();
+
+        for node in nodes {
+            let exists = node.path().full(&self.root_path).exists();
+
+            if exists != node.alive() {
+                self.set_node_values(&node.path(), vec![("alive", exists).into()]);
+            }
+
+            if !exists {
+                dead.push(node.path());
+            }
+        }
+
+        self.flush()?;
+        Ok(dead)
+    }
+
+    fn cleanup_dead_nodes(&mut self, dry_run: bool) -> Result<CleanupReport, GraphError> {
+        let dead_nodes = self.search_nodes(&Filter::alive(false))?;
+        let dead_paths: Vec<NodePath> = dead_nodes.iter().map(|node| node.path()).collect();
+
+        let lost_edges: Vec<Edge> = self
+            .search_edges(&Filter::edge_kind(EdgeKind::Link))?
+            .into_iter()
+            .filter(|edge| dead_paths.contains(edge.source()) || dead_paths.contains(edge.target()))
+            .collect();
+
+        let lost_attributes: Vec<(NodePath, Vec<Attribute>)> = dead_nodes
+            .iter()
+            .filter(|node| !node.attributes().is_empty())
+            .map(|node| (node.path(), node.attributes()))
+            .collect();
+
+        if !dry_run && !dead_paths.is_empty() {
+            self.delete_nodes(&dead_paths, false, false)?;
+        }
+
+        Ok(CleanupReport {
+            dry_run,
+            nodes: dead_paths,
+            lost_edges,
+            lost_attributes,
+        })
+    }
+
+    /// The graph is already stored as readable files, so this only stores the setting.
+    fn maintain_readable_files(&mut self, maintain: bool) -> Result<(), GraphError> {
+        self.set_setting(settings::MAINTAIN_READABLE_FILES, maintain.into())
+    }
+
+    /// Rewrites the files of all nodes, which are the readable files.
+    fn rebuild_readable_files(&mut self) -> Result<(), GraphError> {
+        self.write_all_files()
+    }
+
+    /// Imports a directory of readable files, such as the ones maintained by
+    /// GraphAgdb. Defaults to the graph's own files, which are already loaded.
+    fn import_readable_files(&mut self, dir: Option<PathBuf>) -> Result<ImportReport, GraphError> {
+        let dir = dir.unwrap_or_else(|| self.files_dir());
+        let root_path = self.root_path.clone();
+        import_readable_dir(self, &root_path, &dir)
+    }
+
+    fn get_setting(&self, key: &str) -> Result<Option<AttributeValue>, GraphError> {
+        settings::get(self, key)
+    }
+
+    fn set_setting(&mut self, key: &str, value: AttributeValue) -> Result<(), GraphError> {
+        let value = settings::setting_value(key, value)?;
+        self.set_node_values(&settings::settings_path(), vec![value]);
+        self.flush()
+    }
+
+    fn list_settings(&self) -> Result<Vec<Attribute>, GraphError> {
+        settings::list(self)
+    }
+
+    fn index_options(&self) -> Result<IndexOptions, GraphError> {
+        settings::index_options(self)
+    }
+
+    fn set_index_options(&mut self, options: &IndexOptions) -> Result<(), GraphError> {
+        let path = settings::settings_path();
+        self.remove_node_values(&path, &settings::INDEX_SETTINGS);
+        self.set_node_values(&path, settings::index_values(options));
+        self.flush()
+    }
+
+    fn get_all_aliases(&self) -> Vec<String> {
+        self.nodes.keys().filter(|alias| !in_history(&NodePath::from_alias(alias))).cloned().collect()
+    }
+}
+
+/// Helpers for index_tree.
+impl GraphFiles {
+    /// Creates the node for a path, or brings an existing node in line with the
+    /// file system. Returns false if the path errored. Doesn't flush.
+    fn sync_tree_node(&mut self, path: &NodePath, full_path: &Path, hash: bool, summary: &mut IndexSummary) -> bool {
+        let Some(elem) = self.node_element(path).cloned() else {
+            let ntype = if full_path.is_dir() { NodeType::dir() } else { NodeType::file() };
+            return match self.insert_with_parent(&Node::new(path, ntype)) {
+                Ok(_) => {
+                    if let Some(fingerprint) = Fingerprint::of(full_path, hash) {
+                        self.set_node_values(path, fingerprint.to_values());
+                    }
+                    summary.created.push(path.clone());
+                    true
+                }
+                Err(err) => {
+                    summary.errored.push((path.clone(), err.to_string()));
+                    false
+                }
+            };
+        };
+
+        match sync_values(&elem, full_path, hash) {
+            Ok(values) if values.is_empty() => {
+                summary.unchanged += 1;
+                true
+            }
+            Ok(values) => {
+                self.set_node_values(path, values);
+                summary.updated.push(path.clone());
+                true
+            }
+            Err(err) => {
+                summary.errored.push((path.clone(), err.to_string()));
+                false
+            }
+        }
+    }
+
+    /// Match the new files among the walked entries to the nodes of files that
+    /// have gone missing. See indexing::match_moves.
+    fn detect_moves(&self, entries: &[PathBuf], hash: bool) -> (HashMap<PathBuf, NodePath>, Vec<MoveCandidates>) {
+        let new_files: Vec<(PathBuf, NodePath)> = entries
+            .iter()
+            .filter(|entry| entry.is_file())
+            .map(|entry| (entry.clone(), NodePath::from_dir_path(&self.root_path, entry)))
+            .filter(|(_, path)| !self.has_node(path))
+            .collect();
+        if new_files.is_empty() {
+            return (HashMap::new(), Vec::new());
+        }
+
+        // Nodes whose files are gone, whether or not they have been marked dead yet.
+        let filter = Filter::ntype(NodeType::file());
+        let missing: Vec<(NodePath, Fingerprint)> = self
+            .nodes
+            .values()
+            .filter(|elem| filter.matches_element(elem))
+            .filter_map(|elem| {
+                let node = Node::try_from(elem.clone()).ok()?;
+                if node.path().full(&self.root_path).exists() {
+                    return None;
+                }
+                Some((node.path(), Fingerprint::from_element(elem)?))
+            })
+            .collect();
+        if missing.is_empty() {
+            return (HashMap::new(), Vec::new());
+        }
+
+        indexing::match_moves(&new_files, &missing, hash)
+    }
+
+    /// Move the node of a missing file to the path the file was found at.
+    /// Doesn't flush.
+    fn move_node(&mut self, from: &NodePath, to: &NodePath, hash: bool) -> Result<(), GraphError> {
+        self.rekey(from, to)?;
+
+        let elem = self.node_element(to).cloned().ok_or_else(|| GraphError::NodeNotFound(to.clone()))?;
+        let values = sync_values(&elem, &to.full(&self.root_path), hash)?;
+        if !values.is_empty() {
+            self.set_node_values(to, values);
+        }
+        Ok(())
+    }
+}
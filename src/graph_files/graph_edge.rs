@@ -0,0 +1,371 @@
+use std::time::SystemTime;
+
+use agdb::{DbId, DbKeyValue, DbUserValue};
+
+use crate::{
+    elements::{
+        attribute::{Attribute, RESERVED_EDGE_ATTRS},
+        edge::Edge,
+        node::Node,
+        node_path::NodePath,
+        nodetype::NodeType,
+        SysTime,
+    },
+    error::GraphError,
+    filter::Filter,
+    graph_agdb::{graph_edge::STRUCTURAL_EDGE_ATTRS, in_history, sync::rebase},
+    graph_traits::{graph_edge::GraphEdge, graph_node::GraphNode},
+};
+
+use super::{element, merge_values, GraphFiles};
+
+impl GraphEdge for GraphFiles {
+    /// The link from one node to the other, or the contains edge if there is no link.
+    fn get_edge_strict(
+        &self,
+        from: &NodePath,
+        to: &NodePath,
+    ) -> Result<Edge, GraphError> {
+        self.open_edge(from, to, false).or_else(|_| self.open_edge(from, to, true))
+    }
+
+    fn search_edges(&self, filter: &Filter) -> Result<Vec<Edge>, GraphError> {
+        let edges = self.filter_edges(filter)?;
+        Ok(edges.into_iter().filter(|edge| !in_history(edge.source()) && !in_history(edge.target())).collect())
+    }
+
+    /// Creates a link between two existing nodes. There can only be one link in
+    /// each direction between two nodes.
+    fn create_edge(
+        &mut self,
+        source_path: &NodePath,
+        target_path: &NodePath,
+    ) -> Result<(), GraphError> {
+        self.check_endpoints(source_path, target_path)?;
+
+        if self.edge_element(source_path, target_path, false).is_some() {
+            return Err(GraphError::AlreadyExists(format!(
+                "Edge from {} to {}",
+                source_path.alias(),
+                target_path.alias()
+            )));
+        }
+
+        let edge = Edge::new(source_path, target_path);
+        self.set_edge_values(source_path, target_path, false, edge.to_db_values());
+        self.flush()
+    }
+
+    /// Changes the parent directory of a node. If the node is physical, it will be moved in the file system.
+    /// If the node is virtual, the parent will be changed in the graph.
+    /// The node keeps its name, and all of its descendants are moved along with it.
+    fn reparent_node(
+        &mut self,
+        node_path: &NodePath,
+        new_parent_path: &NodePath,
+    ) -> Result<(), GraphError> {
+        let alias = node_path.alias();
+        if !self.has_node(node_path) {
+            return Err(GraphError::NodeNotFound(node_path.clone()));
+        }
+        if node_path.is_atype() || *node_path == NodePath::root() || *node_path == NodePath::user_root() {
+            return Err(GraphError::ArchetypeProtected(node_path.clone()));
+        }
+        if !self.has_node(new_parent_path) {
+            return Err(GraphError::NodeNotFound(new_parent_path.clone()));
+        }
+        if new_parent_path.buf().starts_with(node_path.buf()) {
+            return Err(GraphError::InvalidPath(format!("Cannot move {} inside itself", alias)));
+        }
+        if node_path.parent().as_ref() == Some(new_parent_path) {
+            return Ok(());
+        }
+
+        let new_path = new_parent_path.join(&node_path.name());
+        if self.has_node(&new_path) {
+            return Err(GraphError::AlreadyExists(format!("Node {}", new_path.alias())));
+        }
+
+        let full_path = node_path.full(&self.root_path);
+        let new_full_path = new_path.full(&self.root_path);
+
+        let is_physical = full_path.exists();
+
+        if is_physical {
+            if !new_parent_path.full(&self.root_path).is_dir() {
+                return Err(GraphError::NotPhysical(new_parent_path.clone()));
+            }
+            if new_full_path.exists() {
+                return Err(GraphError::AlreadyExists(format!("Path {} in the file system", new_full_path.display())));
+            }
+            std::fs::rename(&full_path, &new_full_path)?;
+        }
+
+        if let Err(err) = self.rekey(node_path, &new_path) {
+            if is_physical {
+                if let Err(revert) = std::fs::rename(&new_full_path, &full_path) {
+                    return Err(GraphError::Io(std::io::Error::new(revert.kind(), format!(
+                        "Failed to reparent {}: {}. Moving the file back also failed: {}",
+                        alias, err, revert
+                    ))));
+                }
+            }
+            return Err(err);
+        }
+        self.flush()
+    }
+
+    /// Moves an edge and all its attributes to a new source and target. Parent edges can't be reconnected this way,
+    /// use the reparent_node function instead.
+    fn reconnect_edge(
+        &mut self,
+        edge: Edge,
+        from: &NodePath,
+        to: &NodePath,
+    ) -> Result<(), GraphError> {
+        if edge.contains() {
+            return Err(GraphError::InvalidOperation(
+                "Contains edges can't be reconnected, use reparent_node instead".to_string(),
+            ));
+        }
+        self.check_endpoints(from, to)?;
+
+        if self.edge_element(edge.source(), edge.target(), false).is_none() {
+            return Err(GraphError::EdgeNotFound { source: edge.source().clone(), target: edge.target().clone() });
+        }
+        if from == edge.source() && to == edge.target() {
+            return Ok(());
+        }
+        if self.edge_element(from, to, false).is_some() {
+            return Err(GraphError::AlreadyExists(format!("Edge from {} to {}", from.alias(), to.alias())));
+        }
+
+        let mut existing = self.remove_edge(edge.source(), edge.target(), false).unwrap();
+        merge_values(&mut existing, vec![
+            ("source", from.clone()).into(),
+            ("target", to.clone()).into(),
+            ("modified_time", SysTime::from(SystemTime::now())).into(),
+        ]);
+        self.set_edge_values(from, to, false, existing.values);
+        self.flush()
+    }
+
+    /// Inserts an edge with all of its values and attributes. If the edge already
+    /// exists, its values are updated instead. Contains edges can only be updated,
+    /// as they are created along with their child nodes.
+    fn insert_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+        self.check_endpoints(edge.source(), edge.target())?;
+
+        if edge.contains() && self.edge_element(edge.source(), edge.target(), true).is_none() {
+            return Err(GraphError::InvalidOperation(
+                "Contains edges are created along with their child nodes".to_string(),
+            ));
+        }
+
+        self.set_edge_values(edge.source(), edge.target(), edge.contains(), edge.to_db_values());
+        self.flush()
+    }
+
+    /// Delete an edge from the graph. Edges with the attribute "contains" refer to the parent-child relationship
+    /// between nodes and will be ignored. All other attributes will be cleared from them instead.
+    fn delete_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+        let existing = self.edge_element(edge.source(), edge.target(), edge.contains()).ok_or_else(|| {
+            GraphError::EdgeNotFound { source: edge.source().clone(), target: edge.target().clone() }
+        })?;
+
+        if !edge.contains() {
+            self.remove_edge(edge.source(), edge.target(), false);
+            return self.flush();
+        }
+
+        let kept: [&str; 5] = ["source", "target", "created_time", "modified_time", "contains"];
+        let stripped: Vec<String> = existing
+            .values
+            .iter()
+            .map(|kv| kv.key.to_string())
+            .filter(|key| !kept.contains(&key.as_str()))
+            .collect();
+
+        if !stripped.is_empty() {
+            let stripped: Vec<&str> = stripped.iter().map(|key| key.as_str()).collect();
+            self.remove_edge_values(edge.source(), edge.target(), true, &stripped);
+            self.set_edge_values(
+                edge.source(),
+                edge.target(),
+                true,
+                vec![("modified_time", SysTime::from(SystemTime::now())).into()],
+            );
+        }
+
+        self.flush()
+    }
+
+    /// Insert attributes to an edge. Ignore reserved attribute names. Update attributes that already exist.
+    fn insert_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        if attrs.is_empty() {
+            return Err(GraphError::InvalidOperation("Attributes cannot be empty".to_string()));
+        }
+        let first = attrs[0].name.clone();
+
+        let filtered: Vec<Attribute> = attrs
+            .into_iter()
+            .filter(|attr| !RESERVED_EDGE_ATTRS.contains(&attr.name.as_str()))
+            .collect();
+
+        if filtered.is_empty() {
+            return Err(GraphError::ReservedAttribute(first));
+        }
+
+        self.write_edge_attrs(&edge, filtered)
+    }
+
+    /// Delete attributes from an edge. Ignore reserved attribute names.
+    fn delete_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        if attr_names.is_empty() {
+            return Err(GraphError::InvalidOperation("No attributes to delete".to_string()));
+        }
+        let first = attr_names[0].to_string();
+
+        let filtered: Vec<&str> = attr_names
+            .into_iter()
+            .filter(|name| !RESERVED_EDGE_ATTRS.contains(name))
+            .collect();
+
+        if filtered.is_empty() {
+            return Err(GraphError::ReservedAttribute(first));
+        }
+
+        self.remove_edge_attrs(&edge, filtered)
+    }
+
+    /// Insert reserved attributes, such as the relative positions of the nodes, to an edge.
+    fn insert_reserved_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        if let Some(attr) = attrs.iter().find(|attr| STRUCTURAL_EDGE_ATTRS.contains(&attr.name.as_str())) {
+            return Err(GraphError::ReservedAttribute(attr.name.clone()));
+        }
+        if attrs.is_empty() {
+            return Err(GraphError::InvalidOperation("Attributes cannot be empty".to_string()));
+        }
+
+        self.write_edge_attrs(&edge, attrs)
+    }
+
+    /// Delete reserved attributes from an edge.
+    fn delete_reserved_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        if let Some(name) = attr_names.iter().find(|name| STRUCTURAL_EDGE_ATTRS.contains(name)) {
+            return Err(GraphError::ReservedAttribute(name.to_string()));
+        }
+        if attr_names.is_empty() {
+            return Err(GraphError::InvalidOperation("No attributes to delete".to_string()));
+        }
+
+        self.remove_edge_attrs(&edge, attr_names)
+    }
+}
+
+impl GraphFiles {
+    /// Opens the edge from source to target. The kind of the edge must be given,
+    /// as two nodes can have both a contains edge and a link.
+    pub(crate) fn open_edge(&self, source: &NodePath, target: &NodePath, contains: bool) -> Result<Edge, GraphError> {
+        let elem = self.edge_element(source, target, contains).ok_or_else(|| {
+            GraphError::EdgeNotFound { source: source.clone(), target: target.clone() }
+        })?;
+        Ok(Edge::try_from(elem.clone())?)
+    }
+
+    /// Moves a node and all of its descendants to a new path, keeping their edges
+    /// and attributes. The contains edge from the old parent is moved to the new
+    /// parent, which is created if it's missing. Doesn't flush.
+    pub(crate) fn rekey(&mut self, from: &NodePath, to: &NodePath) -> Result<(), GraphError> {
+        if !self.has_node(from) {
+            return Err(GraphError::NodeNotFound(from.clone()));
+        }
+        if self.has_node(to) {
+            return Err(GraphError::AlreadyExists(format!("Node {}", to.alias())));
+        }
+        if to.buf().starts_with(from.buf()) {
+            return Err(GraphError::InvalidPath(format!("Cannot move {} inside itself", from.alias())));
+        }
+
+        let moved: Vec<NodePath> = self.filter_nodes(&Filter::path_prefix(from))?.iter().map(Node::path).collect();
+
+        // The edges are taken out before their nodes, which would remove them.
+        let mut edges: Vec<Edge> = Vec::new();
+        for path in &moved {
+            for edge in self.node_edges(path)? {
+                if self.remove_edge(edge.source(), edge.target(), edge.contains()).is_some() {
+                    edges.push(edge);
+                }
+            }
+        }
+
+        for old in &moved {
+            let new = rebase(old, from, to);
+            let mut elem = self.remove_node(old).unwrap();
+            merge_values(&mut elem, vec![("path", new.clone()).into()]);
+            self.set_node_values(&new, elem.values);
+        }
+
+        let old_parent = from.parent();
+        let new_parent = to.parent();
+        if old_parent != new_parent {
+            if let Some(new_parent) = &new_parent {
+                if !self.has_node(new_parent) {
+                    self.insert_with_parent(&Node::new(new_parent, NodeType::other()))?;
+                }
+            }
+        }
+
+        for edge in edges {
+            let is_parent_edge = edge.contains() && edge.target() == from && edge.source() != from;
+            let source = match (&new_parent, is_parent_edge) {
+                (Some(new_parent), true) => new_parent.clone(),
+                (None, true) => continue,
+                (_, false) => rebase(edge.source(), from, to),
+            };
+            let target = rebase(edge.target(), from, to);
+
+            let mut elem = element(DbId(0), edge.to_db_values());
+            merge_values(&mut elem, vec![("source", source.clone()).into(), ("target", target.clone()).into()]);
+            self.set_edge_values(&source, &target, edge.contains(), elem.values);
+        }
+
+        Ok(())
+    }
+
+    fn write_edge_attrs(&mut self, edge: &Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        if self.edge_element(edge.source(), edge.target(), edge.contains()).is_none() {
+            return Err(GraphError::EdgeNotFound { source: edge.source().clone(), target: edge.target().clone() });
+        }
+
+        let mut values: Vec<DbKeyValue> = attrs.iter().map(|attr| attr.into()).collect();
+        values.push(("modified_time", SysTime::from(SystemTime::now())).into());
+
+        self.set_edge_values(edge.source(), edge.target(), edge.contains(), values);
+        self.flush()
+    }
+
+    fn remove_edge_attrs(&mut self, edge: &Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        if self.edge_element(edge.source(), edge.target(), edge.contains()).is_none() {
+            return Err(GraphError::EdgeNotFound { source: edge.source().clone(), target: edge.target().clone() });
+        }
+
+        self.remove_edge_values(edge.source(), edge.target(), edge.contains(), &attr_names);
+        self.set_edge_values(
+            edge.source(),
+            edge.target(),
+            edge.contains(),
+            vec![("modified_time", SysTime::from(SystemTime::now())).into()],
+        );
+        self.flush()
+    }
+
+    fn check_endpoints(&self, source: &NodePath, target: &NodePath) -> Result<(), GraphError> {
+        for path in [source, target] {
+            if !self.has_node(path) {
+                return Err(GraphError::NodeNotFound(path.clone()));
+            }
+        }
+        Ok(())
+    }
+}
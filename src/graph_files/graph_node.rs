@@ -0,0 +1,208 @@
+use std::path::PathBuf;
+
+use agdb::{DbKeyValue, DbUserValue};
+
+use crate::{
+    elements::{
+        attribute::{Attribute, RESERVED_NODE_ATTRS},
+        edge::Edge,
+        node::Node,
+        node_path::NodePath,
+        nodetype::NodeType,
+    },
+    error::GraphError,
+    filter::Filter,
+    graph_agdb::in_history,
+    graph_traits::graph_node::GraphNode,
+};
+
+use super::GraphFiles;
+
+impl GraphNode for GraphFiles {
+    fn open_node(&self, path: &NodePath) -> Result<Node, GraphError> {
+        let elem = self.node_element(path).ok_or_else(|| GraphError::NodeNotFound(path.clone()))?;
+        Ok(Node::try_from(elem.clone())?)
+    }
+
+    /// The nodes linked to the node in either direction, with the edge between them.
+    fn open_node_connections(&self, path: &NodePath) -> Vec<(Node, Edge)> {
+        self.node_edges(path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|edge| {
+                let other = if edge.source() == path { edge.target() } else { edge.source() };
+                let node = self.open_node(other).ok()?;
+                Some((node, edge))
+            })
+            .collect()
+    }
+
+    fn search_nodes(&self, filter: &Filter) -> Result<Vec<Node>, GraphError> {
+        let nodes = self.filter_nodes(filter)?;
+        Ok(nodes.into_iter().filter(|node| !in_history(&node.path())).collect())
+    }
+
+    fn create_node_by_path(
+        &mut self,
+        path: &NodePath,
+        ntype: Option<NodeType>,
+    ) -> Result<Node, GraphError> {
+        if self.has_node(path) {
+            return Err(GraphError::AlreadyExists(format!("Node {}", path.alias())));
+        }
+
+        // Physical nodes are typed by the file system.
+        let full_path = path.full(&self.root_path);
+        let ntype = if full_path.is_dir() {
+            NodeType::dir()
+        } else if full_path.exists() {
+            NodeType::file()
+        } else {
+            ntype.unwrap_or_else(NodeType::other)
+        };
+
+        let node = Node::new(path, ntype);
+        self.insert_with_parent(&node)?;
+        self.flush()?;
+        Ok(node)
+    }
+
+    fn create_node_by_name(
+        &mut self,
+        parent_path: Option<NodePath>,
+        name: &str,
+        ntype: Option<NodeType>,
+    ) -> Result<Node, GraphError> {
+        let parent_path = parent_path.unwrap_or_else(|| NodePath::new("".into()));
+
+        let rel_path = if parent_path.buf().as_os_str().is_empty() {
+            NodePath::new(PathBuf::from(name))
+        } else {
+            NodePath::new(parent_path.buf().join(name))
+        };
+
+        self.create_node_by_path(&rel_path, ntype)
+    }
+
+    /// Inserts a Node with all of its values and attributes. If a node already exists
+    /// at the path, its values are updated instead. Missing ancestors are created the
+    /// same way as in create_node_by_path.
+    fn insert_node(&mut self, node: Node) -> Result<(), GraphError> {
+        if self.has_node(&node.path()) {
+            self.set_node_values(&node.path(), node.to_db_values());
+        } else {
+            self.insert_with_parent(&node)?;
+        }
+        self.flush()
+    }
+
+    /// Deletes nodes along with their edges. Children of the nodes are kept, and
+    /// nodes that don't exist are skipped.
+    fn delete_nodes(
+        &mut self,
+        paths: &Vec<NodePath>,
+        files: bool,
+        dirs: bool,
+    ) -> Result<(), GraphError> {
+        for path in paths {
+            self.remove_node(path);
+        }
+        self.flush()
+    }
+
+    /// Returns the same attributes as the opened node, so the fixed values
+    /// (path, ntype, etc.) are not included.
+    fn get_node_attrs(&self, path: &NodePath) -> Result<Vec<Attribute>, GraphError> {
+        Ok(self.open_node(path)?.attributes())
+    }
+
+    fn insert_node_attrs(
+        &mut self,
+        path: &NodePath,
+        attrs: Vec<Attribute>,
+    ) -> Result<(), GraphError> {
+        self.open_node(path)?;
+
+        if attrs.is_empty() {
+            return Err(GraphError::InvalidOperation("Attributes cannot be empty".to_string()));
+        }
+
+        let filtered: Vec<DbKeyValue> = attrs
+            .iter()
+            .filter(|attr| !RESERVED_NODE_ATTRS.contains(&attr.name.as_str()))
+            .map(|attr| attr.into())
+            .collect();
+
+        if filtered.is_empty() {
+            return Err(GraphError::ReservedAttribute(attrs[0].name.clone()));
+        }
+
+        self.set_node_values(path, filtered);
+        self.flush()
+    }
+
+    fn delete_node_attrs(
+        &mut self,
+        path: &NodePath,
+        attr_names: Vec<&str>,
+    ) -> Result<(), GraphError> {
+        if attr_names.is_empty() {
+            return Err(GraphError::InvalidOperation("No attributes to delete".to_string()));
+        }
+
+        let filtered: Vec<&str> = attr_names
+            .iter()
+            .filter(|name| !RESERVED_NODE_ATTRS.contains(name))
+            .copied()
+            .collect();
+
+        if filtered.is_empty() {
+            return Err(GraphError::ReservedAttribute(attr_names[0].to_string()));
+        }
+        self.open_node(path)?;
+
+        self.remove_node_values(path, &filtered);
+        self.flush()
+    }
+
+    /// Merges a vector of nodes into the last one.
+    fn merge_nodes(&mut self, nodes: Vec<NodePath>) -> Result<(), GraphError> {
+        Ok(())
+    }
+
+    fn autoparent_nodes(
+        &mut self,
+        parent: &NodePath,
+        child: &NodePath,
+    ) -> Result<(), GraphError> {
+        for path in [parent, child] {
+            if !self.has_node(path) {
+                return Err(GraphError::NodeNotFound(path.clone()));
+            }
+        }
+        if self.edge_element(parent, child, true).is_none() {
+            self.set_edge_values(parent, child, true, Edge::new_cont(parent, child).to_db_values());
+        }
+        self.flush()
+    }
+}
+
+impl GraphFiles {
+    /// Inserts a new node under a contains edge from its parent, creating missing
+    /// ancestors as Other nodes or typed by the file system. Doesn't flush.
+    pub(crate) fn insert_with_parent(&mut self, node: &Node) -> Result<(), GraphError> {
+        let path = node.path();
+        if let Some(parent) = path.parent() {
+            if !self.has_node(&parent) {
+                let full_path = parent.full(&self.root_path);
+                let ntype = if full_path.is_dir() { NodeType::dir() } else { NodeType::other() };
+                self.insert_with_parent(&Node::new(&parent, ntype))?;
+            }
+            self.set_node_values(&path, node.to_db_values());
+            self.set_edge_values(&parent, &path, true, Edge::new_cont(&parent, &path).to_db_values());
+        } else {
+            self.set_node_values(&path, node.to_db_values());
+        }
+        Ok(())
+    }
+}
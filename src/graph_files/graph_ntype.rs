@@ -0,0 +1,52 @@
+use crate::{
+    elements::{
+        attribute::Attribute,
+        node::Node,
+        node_path::NodePath,
+        nodetype::{NodeType, NodeTypeDef, NODETYPE_TYPE},
+    },
+    error::GraphError,
+    filter::Filter,
+    graph_agdb::graph_ntype::{nodetype_attrs, nodetype_instance, nodetype_node},
+    graph_traits::{graph_node::GraphNode, graph_ntype::GraphNtype},
+};
+
+use super::GraphFiles;
+
+impl GraphNtype for GraphFiles {
+    fn get_node_types(&self) -> Result<Vec<NodeTypeDef>, GraphError> {
+        let nodetypes = NodePath::atype("nodetypes");
+
+        let mut types: Vec<NodeTypeDef> = Vec::new();
+        for node in self.filter_nodes(&Filter::ntype(NodeType::new(NODETYPE_TYPE.to_string())))? {
+            if node.path().parent() == Some(nodetypes.clone()) {
+                types.push(NodeTypeDef::try_from(node)?);
+            }
+        }
+
+        Ok(types)
+    }
+
+    fn open_nodetype(&self, ntype: &NodeType) -> Result<NodeTypeDef, GraphError> {
+        let node = self
+            .open_node(&NodeTypeDef::path(ntype))
+            .map_err(|_| GraphError::NodeNotFound(NodeTypeDef::path(ntype)))?;
+        Ok(NodeTypeDef::try_from(node)?)
+    }
+
+    fn create_nodetype(&mut self, nodetype: NodeTypeDef) -> Result<NodeTypeDef, GraphError> {
+        let node = nodetype_node(self, &nodetype)?;
+        self.insert_node(node)?;
+        Ok(nodetype)
+    }
+
+    fn get_nodetype_attrs(&self, ntype: &NodeType) -> Result<Vec<Attribute>, GraphError> {
+        nodetype_attrs(self, ntype)
+    }
+
+    fn instance_nodetype(&mut self, path: &NodePath, ntype: &NodeType) -> Result<Node, GraphError> {
+        let node = nodetype_instance(self, path, ntype)?;
+        self.insert_node(node)?;
+        self.open_node(path)
+    }
+}
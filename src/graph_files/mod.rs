@@ -0,0 +1,392 @@
+/*
+A graph stored as plain files instead of an agdb db.
+
+Every node is a RON file in a directory next to where the db would be, at the
+same relative path as the node, holding the node, its outgoing edges and the
+fingerprint of its file. This is the format of the readable files of GraphAgdb,
+so either backend can import the files of the other. Unlike agdb's binary
+format, the files can be versioned with git and edited by hand.
+
+The whole graph is read into memory when it is opened. Nodes and edges are kept
+as agdb elements, so that filters, fingerprints and the conversions to Node and
+Edge work the same as with GraphAgdb. Every change rewrites the files of the
+nodes it touched before returning.
+
+GraphFiles implements GraphCore, GraphNode, GraphEdge and GraphNtype. Attribute
+definitions aren't validated, there are no events or command history, and
+changes are not atomic: if writing a file fails, the files written before it
+stay changed.
+*/
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use agdb::{DbElement, DbId, DbKeyValue, DbUserValue, DbValue};
+
+use crate::{
+    config::GraphConfig,
+    elements::{attribute::AttributeValue, edge::Edge, node::Node, node_path::NodePath},
+    error::GraphError,
+    filter::Filter,
+    graph_agdb::{
+        lock_db,
+        readable::{read_readable_dir, readable_path, ReadableNode},
+        resolve_storage_dir, settings,
+    },
+    graph_traits::{graph_core::GraphCore, StoragePath},
+    indexing::Fingerprint,
+};
+
+pub(crate) mod graph_core;
+pub(crate) mod graph_edge;
+pub(crate) mod graph_node;
+pub(crate) mod graph_ntype;
+
+/// Key of an edge: the aliases of its source and target, and whether it is a
+/// contains edge. Two nodes can have both a contains edge and a link.
+type EdgeKey = (String, String, bool);
+
+/// A graph stored in a directory of per-node RON files.
+pub struct GraphFiles {
+    /// The name of the application using this library.
+    name: String,
+
+    /// Path to the root directory of the graph.
+    root_path: PathBuf,
+
+    /// Where the files are stored. The files are in a directory named after
+    /// the graph inside it.
+    storage_path: StoragePath,
+
+    /// The directory the storage path resolved to when the graph was opened.
+    storage_dir: PathBuf,
+
+    /// The nodes by alias. Node ids are positive and edge ids negative, like in
+    /// agdb, but they are only valid until the graph is closed.
+    nodes: BTreeMap<String, DbElement>,
+    edges: BTreeMap<EdgeKey, DbElement>,
+    next_id: i64,
+
+    /// Nodes whose files are out of date. Missing nodes have their files removed.
+    dirty: Vec<NodePath>,
+
+    /// Lock file held while the graph is open. Only taken by GraphFiles::open.
+    lock: Option<File>,
+}
+
+impl GraphFiles {
+    /// Opens the graph described by the config, creating its directory if it
+    /// doesn't exist yet. Reports the same errors as GraphAgdb::open. A file
+    /// that can't be read makes the graph corrupted. The backend in the config
+    /// is ignored.
+    pub fn open(config: GraphConfig) -> Result<Self, GraphError> {
+        GraphFiles::open_with(config, true)
+    }
+
+    /// Checked opens also validate the root and lock the files.
+    pub(crate) fn open_with(config: GraphConfig, checked: bool) -> Result<Self, GraphError> {
+        let GraphConfig { name, root_path, storage_path: storage_enum, maintain_readable_files, .. } = config;
+
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(GraphError::InvalidPath(format!("Invalid graph name: {:?}", name)));
+        }
+        if root_path.to_str().is_none() {
+            return Err(GraphError::NonUtf8Path(root_path));
+        }
+        // The name of the root directory becomes the name of the user_root
+        if root_path.file_name().is_none() {
+            return Err(GraphError::InvalidPath(format!("Root {} has no directory name", root_path.display())));
+        }
+        if checked && !root_path.is_dir() {
+            return Err(GraphError::RootNotFound(root_path));
+        }
+
+        let storage_path = resolve_storage_dir(&storage_enum)?;
+        let dir = storage_path.join(format!("{}_files", name));
+        std::fs::create_dir_all(&dir)?;
+
+        let lock = match checked {
+            true => Some(lock_db(&storage_path.join(format!(".{}_files.lock", name)), &dir)?),
+            false => None,
+        };
+
+        let mut graph = GraphFiles {
+            name,
+            root_path,
+            storage_path: storage_enum,
+            storage_dir: storage_path,
+            nodes: BTreeMap::new(),
+            edges: BTreeMap::new(),
+            next_id: 1,
+            dirty: Vec::new(),
+            lock,
+        };
+
+        for (file, readable) in read_readable_dir(&dir)? {
+            let readable = readable.map_err(|e| GraphError::DbCorrupted { path: file, reason: e.to_string() })?;
+            graph.load(readable);
+        }
+
+        if !graph.nodes.contains_key(&NodePath::root().alias()) {
+            // An empty directory is initialised, but one with other nodes is broken
+            if !graph.nodes.is_empty() {
+                return Err(GraphError::DbCorrupted { path: dir, reason: "the root node is missing".to_string() });
+            }
+            graph.init_archetype_nodes()?;
+        }
+
+        if let Some(maintain) = maintain_readable_files {
+            graph.set_setting(settings::MAINTAIN_READABLE_FILES, maintain.into())?;
+        }
+
+        Ok(graph)
+    }
+
+    /// The directory of the node files.
+    pub fn files_dir(&self) -> PathBuf {
+        self.storage_dir().join(format!("{}_files", self.name))
+    }
+
+    /// The directory the files are stored in.
+    pub(crate) fn storage_dir(&self) -> PathBuf {
+        self.storage_dir.clone()
+    }
+
+    /// The node files and the lock. They may be stored inside the vault, but are
+    /// never part of the graph.
+    pub(crate) fn db_files(&self) -> Vec<PathBuf> {
+        vec![
+            self.files_dir(),
+            self.storage_dir().join(format!(".{}_files.lock", self.name)),
+        ]
+    }
+
+    fn load(&mut self, readable: ReadableNode) {
+        let mut values = readable.node.to_db_values();
+        if let Some(fingerprint) = readable.fingerprint {
+            values.extend(fingerprint.to_values());
+        }
+        let id = self.new_id();
+        self.nodes.insert(readable.node.path().alias(), element(id, values));
+
+        for edge in readable.edges {
+            let id = DbId(-self.new_id().0);
+            self.edges.insert(edge_key(edge.source(), edge.target(), edge.contains()), element(id, edge.to_db_values()));
+        }
+    }
+
+    fn new_id(&mut self) -> DbId {
+        let id = DbId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+}
+
+/// The element store. Changes mark the files that need to be rewritten.
+impl GraphFiles {
+    pub(crate) fn has_node(&self, path: &NodePath) -> bool {
+        self.nodes.contains_key(&path.alias())
+    }
+
+    pub(crate) fn node_element(&self, path: &NodePath) -> Option<&DbElement> {
+        self.nodes.get(&path.alias())
+    }
+
+    /// Inserts a node, or merges the values into an existing one.
+    pub(crate) fn set_node_values(&mut self, path: &NodePath, values: Vec<DbKeyValue>) {
+        match self.nodes.get_mut(&path.alias()) {
+            Some(elem) => merge_values(elem, values),
+            None => {
+                let id = self.new_id();
+                self.nodes.insert(path.alias(), element(id, values));
+            }
+        }
+        self.mark(path);
+    }
+
+    pub(crate) fn remove_node_values(&mut self, path: &NodePath, keys: &[&str]) {
+        if let Some(elem) = self.nodes.get_mut(&path.alias()) {
+            elem.values.retain(|kv| !keys.iter().any(|key| kv.key == (*key).into()));
+        }
+        self.mark(path);
+    }
+
+    /// Removes a node along with all of its edges.
+    pub(crate) fn remove_node(&mut self, path: &NodePath) -> Option<DbElement> {
+        let removed = self.nodes.remove(&path.alias())?;
+        let alias = path.alias();
+        let keys: Vec<EdgeKey> = self
+            .edges
+            .keys()
+            .filter(|(source, target, _)| *source == alias || *target == alias)
+            .cloned()
+            .collect();
+        for key in keys {
+            self.remove_edge_element(&key);
+        }
+        self.mark(path);
+        Some(removed)
+    }
+
+    pub(crate) fn edge_element(&self, source: &NodePath, target: &NodePath, contains: bool) -> Option<&DbElement> {
+        self.edges.get(&edge_key(source, target, contains))
+    }
+
+    /// Inserts an edge, or merges the values into an existing one.
+    pub(crate) fn set_edge_values(&mut self, source: &NodePath, target: &NodePath, contains: bool, values: Vec<DbKeyValue>) {
+        let key = edge_key(source, target, contains);
+        match self.edges.get_mut(&key) {
+            Some(elem) => merge_values(elem, values),
+            None => {
+                let id = DbId(-self.new_id().0);
+                self.edges.insert(key, element(id, values));
+            }
+        }
+        self.mark(source);
+    }
+
+    pub(crate) fn remove_edge_values(&mut self, source: &NodePath, target: &NodePath, contains: bool, keys: &[&str]) {
+        if let Some(elem) = self.edges.get_mut(&edge_key(source, target, contains)) {
+            elem.values.retain(|kv| !keys.iter().any(|key| kv.key == (*key).into()));
+        }
+        self.mark(source);
+    }
+
+    pub(crate) fn remove_edge(&mut self, source: &NodePath, target: &NodePath, contains: bool) -> Option<DbElement> {
+        self.remove_edge_element(&edge_key(source, target, contains))
+    }
+
+    fn remove_edge_element(&mut self, key: &EdgeKey) -> Option<DbElement> {
+        let removed = self.edges.remove(key)?;
+        if let Ok(edge) = Edge::try_from(removed.clone()) {
+            self.mark(edge.source());
+        }
+        Some(removed)
+    }
+
+    /// All the nodes matching the filter, ordered by alias.
+    pub(crate) fn filter_nodes(&self, filter: &Filter) -> Result<Vec<Node>, GraphError> {
+        self.nodes
+            .values()
+            .filter(|elem| filter.matches_element(elem))
+            .map(|elem| Ok(Node::try_from(elem.clone())?))
+            .collect()
+    }
+
+    pub(crate) fn filter_edges(&self, filter: &Filter) -> Result<Vec<Edge>, GraphError> {
+        self.edges
+            .values()
+            .filter(|elem| filter.matches_element(elem))
+            .map(|elem| Ok(Edge::try_from(elem.clone())?))
+            .collect()
+    }
+
+    /// All the edges going out from or coming in to the node.
+    pub(crate) fn node_edges(&self, path: &NodePath) -> Result<Vec<Edge>, GraphError> {
+        let alias = path.alias();
+        self.edges
+            .iter()
+            .filter(|((source, target, _), _)| *source == alias || *target == alias)
+            .map(|(_, elem)| Ok(Edge::try_from(elem.clone())?))
+            .collect()
+    }
+
+    fn mark(&mut self, path: &NodePath) {
+        if !self.dirty.contains(path) {
+            self.dirty.push(path.clone());
+        }
+    }
+}
+
+/// Writing the files.
+impl GraphFiles {
+    /// Writes the files of the nodes changed since the last flush.
+    pub(crate) fn flush(&mut self) -> Result<(), GraphError> {
+        let dirty = std::mem::take(&mut self.dirty);
+        let dir = self.files_dir();
+        for path in dirty {
+            self.write_file(&dir, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the files of all nodes from scratch.
+    pub(crate) fn write_all_files(&mut self) -> Result<(), GraphError> {
+        let dir = self.files_dir();
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        self.dirty.clear();
+        let paths = self.filter_nodes(&Filter::path_prefix(&NodePath::root()))?;
+        for node in paths {
+            self.write_file(&dir, &node.path())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the file of a node, or removes it along with any directories
+    /// left empty if the node no longer exists.
+    fn write_file(&self, dir: &Path, path: &NodePath) -> Result<(), GraphError> {
+        let file = readable_path(dir, path);
+
+        let Some(elem) = self.node_element(path) else {
+            if file.exists() {
+                std::fs::remove_file(&file)?;
+            }
+            let mut parent = file.parent();
+            while let Some(empty) = parent.filter(|parent| *parent != dir) {
+                if std::fs::remove_dir(empty).is_err() {
+                    break;
+                }
+                parent = empty.parent();
+            }
+            return Ok(());
+        };
+
+        let node = Node::try_from(elem.clone())?;
+        let alias = path.alias();
+        let edges = self
+            .edges
+            .iter()
+            .filter(|((source, _, _), _)| *source == alias)
+            .map(|(_, elem)| Edge::try_from(elem.clone()))
+            .collect::<Result<Vec<Edge>, _>>()?;
+
+        let readable = ReadableNode { node, edges, fingerprint: Fingerprint::from_element(elem) };
+        let contents = ron::ser::to_string_pretty(&readable, ron::ser::PrettyConfig::default())?;
+
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&file, contents)?;
+        Ok(())
+    }
+}
+
+fn element(id: DbId, values: Vec<DbKeyValue>) -> DbElement {
+    DbElement { id, from: None, to: None, values }
+}
+
+fn edge_key(source: &NodePath, target: &NodePath, contains: bool) -> EdgeKey {
+    (source.alias(), target.alias(), contains)
+}
+
+/// Replaces the values with the same keys and adds the rest, like an agdb insert.
+fn merge_values(elem: &mut DbElement, values: Vec<DbKeyValue>) {
+    for kv in values {
+        match elem.values.iter_mut().find(|existing| existing.key == kv.key) {
+            Some(existing) => existing.value = kv.value,
+            None => elem.values.push(kv),
+        }
+    }
+}
+
+/// The value of a key of an element.
+pub(crate) fn value_of<'a>(elem: &'a DbElement, key: &str) -> Option<&'a DbValue> {
+    elem.values.iter().find(|kv| kv.key == key.into()).map(|kv| &kv.value)
+}
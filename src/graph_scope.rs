@@ -20,4 +20,508 @@
 // aforementioned reliance on data that exists in the large graph but not in the subgraph.
 
 // For this feature it would also be important to allow exporting a subgraph and package it into
-// WASM as well. Imagine packaging up a collection of knowledge for and publishing it as a webapp. 
\ No newline at end of file
+// WASM as well. Imagine packaging up a collection of knowledge for and publishing it as a webapp. 
+use std::path::{Path, PathBuf};
+
+use agdb::QueryBuilder;
+
+use crate::{
+    config::GraphConfig,
+    elements::{
+        attribute::Attribute,
+        edge::Edge,
+        node::Node,
+        node_path::NodePath,
+        nodetype::NodeType,
+    },
+    error::GraphError,
+    filter::{glob_match, Filter},
+    graph_agdb::GraphAgdb,
+    graph_traits::{graph_core::GraphCore, graph_edge::GraphEdge, graph_node::GraphNode},
+};
+
+/// A graph handle that is restricted to the nodes matching a set of path patterns.
+/// Any read or write that touches a node outside of the scope is rejected.
+///
+/// The patterns are globs relative to the user_root, same as in Filter::path_glob.
+/// Note that a pattern like "game" only matches the game node itself, use "game/**"
+/// to include its descendants.
+pub struct GraphScope {
+    graph: GraphAgdb,
+    patterns: Vec<String>,
+}
+
+/// The result of exporting a scope into a standalone graph.
+pub struct ScopeExport {
+    /// The newly created graph.
+    pub graph: GraphAgdb,
+    /// Paths of the nodes that were copied.
+    pub nodes: Vec<NodePath>,
+    /// Edges that were copied, besides the contains edges of the copied nodes.
+    pub edges: Vec<Edge>,
+    /// Files and directories that were copied to the new root, as absolute paths.
+    pub files: Vec<PathBuf>,
+    /// Links between a node in the scope and a node outside of it. These were not exported.
+    pub dangling_edges: Vec<Edge>,
+}
+
+impl GraphScope {
+    pub fn new(graph: GraphAgdb, patterns: Vec<&str>) -> Self {
+        GraphScope {
+            graph,
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// Direct getter for the wrapped graph. Bypasses the scope entirely.
+    pub fn graph(&self) -> &GraphAgdb {
+        &self.graph
+    }
+
+    pub fn into_inner(self) -> GraphAgdb {
+        self.graph
+    }
+
+    pub fn patterns(&self) -> &Vec<String> {
+        &self.patterns
+    }
+
+    /// Whether the path matches any of the patterns of the scope.
+    pub fn in_scope(&self, path: &NodePath) -> bool {
+        let alias = path.alias();
+        self.patterns
+            .iter()
+            .any(|pattern| glob_match(&NodePath::from(pattern.as_str()).alias(), &alias))
+    }
+
+    /// The scope as a Filter, for searching.
+    pub fn filter(&self) -> Filter {
+        Filter::Or(
+            self.patterns
+                .iter()
+                .map(|pattern| Filter::path_glob(pattern))
+                .collect(),
+        )
+    }
+
+    fn check(&self, path: &NodePath) -> Result<(), GraphError> {
+        if self.in_scope(path) {
+            Ok(())
+        } else {
+            Err(GraphError::InvalidPath(format!("Path {} is outside of the scope", path.alias())))
+        }
+    }
+
+    /// Checks a node that is about to be created, along with the ancestors
+    /// that would be created for it because they don't exist yet.
+    fn check_new(&self, path: &NodePath) -> Result<(), GraphError> {
+        self.check(path)?;
+
+        let mut ancestor = path.parent();
+        while let Some(parent) = ancestor {
+            if parent == NodePath::user_root() || parent == NodePath::root() || self.graph.open_node(&parent).is_ok() {
+                break;
+            }
+            if !self.in_scope(&parent) {
+                return Err(GraphError::InvalidPath(format!(
+                    "Creating {} would create {} outside of the scope",
+                    path.alias(),
+                    parent.alias()
+                )));
+            }
+            ancestor = parent.parent();
+        }
+        Ok(())
+    }
+
+    /// Copy the scoped nodes, their edges and the files they refer to into a new
+    /// graph with its own root directory. Links that cross the boundary of the scope
+    /// are not copied, but they are reported in the result.
+    ///
+    /// The new graph is opened with the config, and its root directory is created
+    /// if it doesn't exist.
+    pub fn export_scope(&self, config: GraphConfig) -> Result<ScopeExport, GraphError> {
+        let old_root = self.graph.user_root_dirpath();
+        let new_root = config.root_path.clone();
+        std::fs::create_dir_all(&new_root)?;
+
+        let mut new_graph = GraphAgdb::open(config)?;
+
+        // Parents must be inserted before their children, so that the
+        // ancestors don't get created as placeholders.
+        let mut nodes = self.graph.search_nodes(&self.filter())?;
+        nodes.sort_by_key(|node| node.path().buf().components().count());
+
+        let mut exported: Vec<NodePath> = Vec::new();
+
+        for node in nodes {
+            let path = node.path();
+            if path.is_atype() {
+                continue;
+            }
+            new_graph.insert_node(node)?;
+            exported.push(path);
+        }
+
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut dangling_edges: Vec<Edge> = Vec::new();
+
+        for edge in self.graph.search_edges(&Filter::all())? {
+            let source_in = self.in_scope(edge.source());
+            let target_in = self.in_scope(edge.target());
+
+            if source_in && target_in {
+                // Contains edges were created when their children were inserted,
+                // only their attributes are carried over.
+                new_graph.insert_edge(edge.clone())?;
+                if !edge.contains() {
+                    edges.push(edge);
+                }
+            } else if (source_in || target_in) && !edge.contains() {
+                dangling_edges.push(edge);
+            }
+        }
+
+        // The files are copied last, so that a failure in the db leaves none
+        // behind. If a copy fails, the ones made so far are removed again.
+        let mut files: Vec<PathBuf> = Vec::new();
+        let mut created: Vec<PathBuf> = Vec::new();
+        for path in &exported {
+            let (old_path, new_path) = (path.full(&old_root), path.full(&new_root));
+            if !old_path.exists() {
+                continue;
+            }
+            if !new_path.exists() {
+                created.push(new_path.clone());
+            }
+            if let Err(err) = copy_path(&old_path, &new_path) {
+                created.iter().rev().for_each(|path| remove_path(path));
+                return Err(err.into());
+            }
+            files.push(new_path);
+        }
+
+        Ok(ScopeExport {
+            graph: new_graph,
+            nodes: exported,
+            edges,
+            files,
+            dangling_edges,
+        })
+    }
+}
+
+/// Copies a file, or creates the directory if the path is one.
+fn copy_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    if from.is_dir() {
+        return std::fs::create_dir_all(to);
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(from, to).map(|_| ())
+}
+
+/// Removes a copied file or directory. Directories are only removed if empty.
+fn remove_path(path: &Path) {
+    let _ = if path.is_dir() { std::fs::remove_dir(path) } else { std::fs::remove_file(path) };
+}
+
+impl GraphNode for GraphScope {
+    fn open_node(&self, path: &NodePath) -> Result<Node, GraphError> {
+        self.check(path)?;
+        self.graph.open_node(path)
+    }
+
+    /// Only the connections that are within the scope are returned.
+    fn open_node_connections(&self, path: &NodePath) -> Vec<(Node, Edge)> {
+        if !self.in_scope(path) {
+            return vec![];
+        }
+        self.graph
+            .open_node_connections(path)
+            .into_iter()
+            .filter(|(node, _)| self.in_scope(&node.path()))
+            .collect()
+    }
+
+    fn search_nodes(&self, filter: &Filter) -> Result<Vec<Node>, GraphError> {
+        self.graph.search_nodes(&filter.clone().and(self.filter()))
+    }
+
+    fn create_node_by_path(
+        &mut self,
+        path: &NodePath,
+        ntype: Option<NodeType>,
+    ) -> Result<Node, GraphError> {
+        self.check_new(path)?;
+        self.graph.create_node_by_path(path, ntype)
+    }
+
+    fn create_node_by_name(
+        &mut self,
+        parent_path: Option<NodePath>,
+        name: &str,
+        ntype: Option<NodeType>,
+    ) -> Result<Node, GraphError> {
+        let parent = parent_path.clone().unwrap_or_else(|| NodePath::new("".into()));
+        self.check_new(&parent.join(name))?;
+        self.graph.create_node_by_name(parent_path, name, ntype)
+    }
+
+    fn insert_node(&mut self, node: Node) -> Result<(), GraphError> {
+        self.check_new(&node.path())?;
+        self.graph.insert_node(node)
+    }
+
+    fn delete_nodes(&mut self, paths: &Vec<NodePath>, files: bool, dirs: bool) -> Result<(), GraphError> {
+        paths.iter().try_for_each(|path| self.check(path))?;
+        self.graph.delete_nodes(paths, files, dirs)
+    }
+
+    fn get_node_attrs(&self, path: &NodePath) -> Result<Vec<Attribute>, GraphError> {
+        self.check(path)?;
+        self.graph.get_node_attrs(path)
+    }
+
+    fn insert_node_attrs(
+        &mut self,
+        path: &NodePath,
+        attrs: Vec<Attribute>,
+    ) -> Result<(), GraphError> {
+        self.check(path)?;
+        self.graph.insert_node_attrs(path, attrs)
+    }
+
+    fn delete_node_attrs(
+        &mut self,
+        path: &NodePath,
+        attr_name: Vec<&str>,
+    ) -> Result<(), GraphError> {
+        self.check(path)?;
+        self.graph.delete_node_attrs(path, attr_name)
+    }
+
+    fn merge_nodes(&mut self, nodes: Vec<NodePath>) -> Result<(), GraphError> {
+        nodes.iter().try_for_each(|path| self.check(path))?;
+        self.graph.merge_nodes(nodes)
+    }
+
+    fn autoparent_nodes(
+        &mut self,
+        parent: &NodePath,
+        child: &NodePath,
+    ) -> Result<(), GraphError> {
+        self.check(parent)?;
+        self.check(child)?;
+        self.graph.autoparent_nodes(parent, child)
+    }
+}
+
+impl GraphEdge for GraphScope {
+    fn get_edge_strict(&self, from: &NodePath, to: &NodePath) -> Result<Edge, GraphError> {
+        self.check(from)?;
+        self.check(to)?;
+        self.graph.get_edge_strict(from, to)
+    }
+
+    /// Only the edges with both ends within the scope are returned.
+    fn search_edges(&self, filter: &Filter) -> Result<Vec<Edge>, GraphError> {
+        let edges = self.graph.search_edges(filter)?;
+        Ok(edges
+            .into_iter()
+            .filter(|edge| self.in_scope(edge.source()) && self.in_scope(edge.target()))
+            .collect())
+    }
+
+    fn create_edge(
+        &mut self,
+        source_path: &NodePath,
+        target_path: &NodePath,
+    ) -> Result<(), GraphError> {
+        self.check(source_path)?;
+        self.check(target_path)?;
+        self.graph.create_edge(source_path, target_path)
+    }
+
+    fn reparent_node(
+        &mut self,
+        node_path: &NodePath,
+        new_parent_path: &NodePath,
+    ) -> Result<(), GraphError> {
+        self.check(node_path)?;
+        self.check(new_parent_path)?;
+        self.check(&new_parent_path.join(&node_path.name()))?;
+        self.graph.reparent_node(node_path, new_parent_path)
+    }
+
+    fn reconnect_edge(
+        &mut self,
+        edge: Edge,
+        from: &NodePath,
+        to: &NodePath,
+    ) -> Result<(), GraphError> {
+        self.check(edge.source())?;
+        self.check(edge.target())?;
+        self.check(from)?;
+        self.check(to)?;
+        self.graph.reconnect_edge(edge, from, to)
+    }
+
+    fn insert_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+        self.check(edge.source())?;
+        self.check(edge.target())?;
+        self.graph.insert_edge(edge)
+    }
+
+    fn delete_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+        self.check(edge.source())?;
+        self.check(edge.target())?;
+        self.graph.delete_edge(edge)
+    }
+
+    fn insert_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        self.check(edge.source())?;
+        self.check(edge.target())?;
+        self.graph.insert_edge_attrs(edge, attrs)
+    }
+
+    fn delete_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        self.check(edge.source())?;
+        self.check(edge.target())?;
+        self.graph.delete_edge_attrs(edge, attr_names)
+    }
+
+    fn insert_reserved_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError> {
+        self.check(edge.source())?;
+        self.check(edge.target())?;
+        self.graph.insert_reserved_edge_attrs(edge, attrs)
+    }
+
+    fn delete_reserved_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError> {
+        self.check(edge.source())?;
+        self.check(edge.target())?;
+        self.graph.delete_reserved_edge_attrs(edge, attr_names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, File};
+
+    use agdb::QueryBuilder;
+
+    use crate::{
+        config::GraphConfig,
+        elements::{attribute::Attribute, edge::Edge, node_path::NodePath},
+        error::GraphError,
+        graph_traits::{graph_core::GraphCore, graph_edge::GraphEdge, graph_node::GraphNode},
+        utils::utils::TestContext,
+    };
+
+    use super::GraphScope;
+
+    #[test]
+    fn scope_rejects_paths_outside_of_it() {
+        let func_name = "scope_rejects_paths_outside_of_it";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_node_by_path(&NodePath::from("game/level"), None).unwrap();
+        ctx.graph.create_node_by_path(&NodePath::from("notes/todo"), None).unwrap();
+
+        let mut scope = GraphScope::new(ctx.take_graph(), vec!["game", "game/**"]);
+
+        assert!(scope.open_node(&NodePath::from("game/level")).is_ok(), "In scope node should open");
+        assert!(scope.open_node(&NodePath::from("notes/todo")).is_err(), "Out of scope node should not open");
+        assert!(scope.create_node_by_path(&NodePath::from("notes/new"), None).is_err());
+        assert!(scope.create_node_by_path(&NodePath::from("game/new"), None).is_ok());
+
+        let all = scope.search_nodes(&crate::filter::Filter::all()).unwrap();
+        assert_eq!(all.len(), 3, "Only game, game/level and game/new should be visible");
+    }
+
+    #[test]
+    fn exported_scope_contains_nodes_files_and_reports_dangling_edges() {
+        let func_name = "exported_scope_contains_nodes_files_and_reports_dangling_edges";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        create_dir(NodePath::from("game").full(&root)).unwrap();
+        File::create(NodePath::from("game/sprite.png").full(&root)).unwrap();
+        ctx.graph.index_node_context(&NodePath::from("game"));
+        ctx.graph.create_node_by_path(&NodePath::from("notes"), None).unwrap();
+        ctx.graph
+            .insert_node_attrs(&NodePath::from("game/sprite.png"), vec![Attribute::new("frames", 4)])
+            .unwrap();
+
+        // One link inside the scope and one crossing it.
+        for (from, to) in [("game", "game/sprite.png"), ("game/sprite.png", "notes")] {
+            let edge = Edge::new(&NodePath::from(from), &NodePath::from(to));
+            ctx.graph
+                .db_mut()
+                .exec_mut(
+                    &QueryBuilder::insert()
+                        .edges()
+                        .from(NodePath::from(from).alias())
+                        .to(NodePath::from(to).alias())
+                        .values_uniform(&edge)
+                        .query(),
+                )
+                .unwrap();
+        }
+
+        let scope = GraphScope::new(ctx.take_graph(), vec!["game", "game/**"]);
+
+        let export_root = root.join("export");
+        let export = scope
+            .export_scope(GraphConfig::new("exported", export_root.clone()).with_storage(export_root.clone()))
+            .unwrap();
+
+        assert_eq!(export.nodes.len(), 2, "Both game and the sprite should be exported");
+        assert!(NodePath::from("game/sprite.png").full(&export_root).is_file(), "File should be copied");
+        assert_eq!(export.edges.len(), 1, "The link inside the scope should be exported");
+        assert_eq!(export.dangling_edges.len(), 1, "The link to notes should be reported");
+        assert_eq!(*export.dangling_edges[0].target(), NodePath::from("notes"));
+
+        let sprite = export.graph.open_node(&NodePath::from("game/sprite.png")).unwrap();
+        assert!(sprite.attributes().contains(&Attribute::new("frames", 4)), "Attributes should be copied");
+        assert!(export.graph.open_node(&NodePath::from("notes")).is_err(), "Notes should not be exported");
+    }
+
+    #[test]
+    fn scope_rejects_creating_ancestors_outside_of_it() {
+        let func_name = "scope_rejects_creating_ancestors_outside_of_it";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_node_by_path(&NodePath::from("game"), None).unwrap();
+        let mut scope = GraphScope::new(ctx.take_graph(), vec!["game/**", "notes/todo"]);
+
+        assert!(scope.create_node_by_path(&NodePath::from("game/levels/first"), None).is_ok(), "Ancestors in the scope can be created");
+        assert!(scope.create_node_by_path(&NodePath::from("notes/todo"), None).is_err(), "notes would be created outside of the scope");
+        assert!(scope.graph().open_node(&NodePath::from("notes")).is_err());
+    }
+
+    #[test]
+    fn failed_export_removes_the_copied_files() {
+        let func_name = "failed_export_removes_the_copied_files";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        create_dir(NodePath::from("game").full(&root)).unwrap();
+        File::create(NodePath::from("top.png").full(&root)).unwrap();
+        File::create(NodePath::from("game/sprite.png").full(&root)).unwrap();
+        ctx.graph.index_node_context(&NodePath::from("game"));
+        ctx.graph.index_single_node(&NodePath::from("top.png")).unwrap();
+
+        // A directory in the way of the sprite makes its copy fail.
+        let export_root = root.join("export");
+        std::fs::create_dir_all(NodePath::from("game/sprite.png").full(&export_root)).unwrap();
+
+        let scope = GraphScope::new(ctx.take_graph(), vec!["top.png", "game", "game/**"]);
+        let export = scope.export_scope(GraphConfig::new("exported", export_root.clone()).with_storage(export_root.clone()));
+
+        assert!(matches!(export, Err(GraphError::Io(_))), "The copy of the sprite should fail");
+        assert!(!NodePath::from("top.png").full(&export_root).exists(), "Copied files should be removed");
+        assert!(NodePath::from("game/sprite.png").full(&export_root).is_dir(), "What was there before is kept");
+    }
+}
@@ -0,0 +1,440 @@
+//! Scenarios that every backend must pass. Each one runs against GraphAgdb and
+//! GraphFiles, and the graphs must end up with the same contents.
+
+use std::path::PathBuf;
+
+use crate::{prelude::*, utils::utils::TestContext};
+
+/// The traits a backend implements.
+trait Backend: GraphCore + GraphNode + GraphEdge + GraphNtype {}
+impl<G: GraphCore + GraphNode + GraphEdge + GraphNtype> Backend for G {}
+
+/// Nodes with their attributes, contains edges and links with their attributes.
+type Contents = (Vec<(String, Vec<Attribute>)>, Vec<(String, String)>, Vec<(String, String, Vec<Attribute>)>);
+
+fn contents<G: Backend>(graph: &G) -> Contents {
+    let mut nodes: Vec<_> = graph
+        .search_nodes(&Filter::path_prefix(&NodePath::root()))
+        .unwrap()
+        .into_iter()
+        .map(|node| {
+            let mut attrs = node.attributes();
+            attrs.sort_by(|a, b| a.name.cmp(&b.name));
+            (node.path().alias(), attrs)
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut contains: Vec<_> = graph
+        .search_edges(&Filter::edge_kind(EdgeKind::Contains))
+        .unwrap()
+        .into_iter()
+        .map(|edge| (edge.source().alias(), edge.target().alias()))
+        .collect();
+    contains.sort();
+
+    let mut links: Vec<_> = graph
+        .search_edges(&Filter::edge_kind(EdgeKind::Link))
+        .unwrap()
+        .into_iter()
+        .map(|edge| {
+            let mut attrs = edge.attributes().clone();
+            attrs.sort_by(|a, b| a.name.cmp(&b.name));
+            (edge.source().alias(), edge.target().alias(), attrs)
+        })
+        .collect();
+    links.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    (nodes, contains, links)
+}
+
+/// A config for a backend with its own root directory inside the test directory.
+fn config(ctx: &TestContext, backend: &str) -> GraphConfig {
+    let base = ctx.graph.user_root_dirpath();
+    let root = base.join("roots").join(backend);
+    std::fs::create_dir_all(&root).unwrap();
+    GraphConfig::new(backend, root).with_storage(base.join("storage"))
+}
+
+/// Runs the scenario on both backends and checks that they agree.
+macro_rules! conformance {
+    ($name:ident, $scenario:ident) => {
+        #[test]
+        fn $name() {
+            let ctx = TestContext::new(stringify!($name));
+
+            let mut agdb = GraphAgdb::open(config(&ctx, "agdb")).unwrap();
+            let mut files = GraphFiles::open(config(&ctx, "files")).unwrap();
+
+            let expected = $scenario(&mut agdb);
+            let found = $scenario(&mut files);
+
+            assert_eq!(expected, found, "GraphFiles should behave the same as GraphAgdb");
+            assert_eq!(contents(&agdb), contents(&files), "The graphs should have the same contents");
+        }
+    };
+}
+
+conformance!(conformance_nodes_and_attributes, nodes_and_attributes);
+conformance!(conformance_edges, edges);
+conformance!(conformance_parent_links, parent_links);
+conformance!(conformance_reparent, reparent);
+conformance!(conformance_nodetypes, nodetypes);
+conformance!(conformance_indexing, indexing);
+conformance!(conformance_settings_and_search, settings_and_search);
+
+/// Results of operations, reduced to whether they succeeded and how they failed,
+/// so that they can be compared between backends.
+fn outcome<T>(result: Result<T, GraphError>) -> String {
+    match result {
+        Ok(_) => "ok".to_string(),
+        Err(err) => format!("{:?}", std::mem::discriminant(&err)),
+    }
+}
+
+fn nodes_and_attributes<G: Backend>(graph: &mut G) -> Vec<String> {
+    let note = NodePath::from("notes/note");
+    let other = NodePath::from("notes/other");
+    let mut outcomes = Vec::new();
+
+    outcomes.push(outcome(graph.create_node_by_path(&note, None)));
+    outcomes.push(outcome(graph.create_node_by_path(&note, None)));
+    outcomes.push(outcome(graph.create_node_by_name(Some(NodePath::from("notes")), "other", None)));
+
+    let parent = graph.open_node(&NodePath::from("notes")).unwrap();
+    assert_eq!(parent.ntype_name(), NodeType::other(), "Missing parents are created as Other");
+    assert!(graph.get_edge_strict(&NodePath::from("notes"), &note).unwrap().contains());
+
+    outcomes.push(outcome(graph.insert_node_attrs(&note, vec![Attribute::new("status", "draft"), Attribute::new("rank", 3)])));
+    outcomes.push(outcome(graph.insert_node_attrs(&note, vec![Attribute::new("ntype", "Fake")])));
+    outcomes.push(outcome(graph.insert_node_attrs(&note, vec![])));
+    outcomes.push(outcome(graph.insert_node_attrs(&NodePath::from("missing"), vec![Attribute::new("a", 1)])));
+    outcomes.push(outcome(graph.insert_node_attrs(&note, vec![Attribute::new("status", "done")])));
+    outcomes.push(outcome(graph.delete_node_attrs(&note, vec!["rank"])));
+    outcomes.push(outcome(graph.delete_node_attrs(&note, vec!["path"])));
+
+    let attrs = graph.get_node_attrs(&note).unwrap();
+    assert_eq!(attrs, vec![Attribute::new("status", "done")]);
+
+    // Inserting a node merges its values into an existing one.
+    let mut node = Node::new(&other, NodeType::other());
+    node.insert_attributes(vec![Attribute::new("color", "red")]);
+    outcomes.push(outcome(graph.insert_node(node)));
+    outcomes.push(outcome(graph.insert_node(Node::new(&NodePath::from("deep/down/node"), NodeType::other()))));
+
+    graph.create_edge(&note, &other).unwrap();
+    outcomes.push(outcome(graph.delete_nodes(&vec![other.clone()], false, false)));
+    assert!(graph.open_node(&other).is_err());
+    assert!(graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap().is_empty(), "Edges go with their nodes");
+    outcomes.push(outcome(graph.delete_nodes(&vec![other.clone()], false, false)));
+
+    let connections = graph.open_node_connections(&note);
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].0.path(), NodePath::from("notes"));
+
+    outcomes
+}
+
+fn edges<G: Backend>(graph: &mut G) -> Vec<String> {
+    let a = NodePath::from("a");
+    let b = NodePath::from("b");
+    let c = NodePath::from("c");
+    for path in [&a, &b, &c] {
+        graph.create_node_by_path(path, None).unwrap();
+    }
+    let mut outcomes = Vec::new();
+
+    outcomes.push(outcome(graph.create_edge(&a, &b)));
+    outcomes.push(outcome(graph.create_edge(&a, &b)));
+    outcomes.push(outcome(graph.create_edge(&a, &NodePath::from("missing"))));
+    outcomes.push(outcome(graph.insert_edge(Edge::new(&b, &a).with_attributes(vec![Attribute::new("weight", 2)]))));
+    outcomes.push(outcome(graph.insert_edge(Edge::new_cont(&a, &c))));
+
+    let edge = graph.get_edge_strict(&a, &b).unwrap();
+    outcomes.push(outcome(graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("label", "next")])));
+    outcomes.push(outcome(graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("source", "x")])));
+    outcomes.push(outcome(graph.insert_reserved_edge_attrs(edge.clone(), vec![Attribute::new("relative_position", "1,1")])));
+    outcomes.push(outcome(graph.insert_reserved_edge_attrs(edge.clone(), vec![Attribute::new("contains", true)])));
+    outcomes.push(outcome(graph.delete_reserved_edge_attrs(edge.clone(), vec!["relative_position"])));
+    outcomes.push(outcome(graph.delete_edge_attrs(edge.clone(), vec!["label"])));
+    outcomes.push(outcome(graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("label", "again")])));
+
+    outcomes.push(outcome(graph.reconnect_edge(edge.clone(), &a, &c)));
+    let links = graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+    assert!(!links.iter().any(|edge| edge.source() == &a && edge.target() == &b));
+    let moved = graph.get_edge_strict(&a, &c).unwrap();
+    assert!(moved.attributes().contains(&Attribute::new("label", "again")), "Reconnected edges keep their attributes");
+    outcomes.push(outcome(graph.reconnect_edge(graph.get_edge_strict(&NodePath::user_root(), &a).unwrap(), &b, &c)));
+
+    // Contains edges lose their attributes instead of being deleted.
+    let parent = graph.get_edge_strict(&NodePath::user_root(), &b).unwrap();
+    outcomes.push(outcome(graph.insert_reserved_edge_attrs(parent.clone(), vec![Attribute::new("pin", true)])));
+    outcomes.push(outcome(graph.delete_edge(parent)));
+    let parent = graph.get_edge_strict(&NodePath::user_root(), &b).unwrap();
+    assert!(parent.contains());
+    assert!(!parent.attributes().contains(&Attribute::new("pin", true)));
+
+    outcomes.push(outcome(graph.delete_edge(moved)));
+    outcomes.push(outcome(graph.delete_edge(Edge::new(&c, &a))));
+
+    outcomes
+}
+
+/// A link alongside the contains edge between the same nodes, and lookups of
+/// edges between nodes that aren't adjacent.
+fn parent_links<G: Backend>(graph: &mut G) -> Vec<String> {
+    let parent = NodePath::from("a");
+    let child = NodePath::from("a/b");
+    let grandchild = NodePath::from("a/b/c");
+    graph.create_node_by_path(&grandchild, None).unwrap();
+    let mut outcomes = Vec::new();
+
+    outcomes.push(outcome(graph.create_edge(&parent, &child)));
+    let link = graph.get_edge_strict(&parent, &child).unwrap();
+    assert!(!link.contains(), "The link should be returned before the contains edge");
+    outcomes.push(outcome(graph.insert_edge_attrs(link.clone(), vec![Attribute::new("label", "favourite")])));
+    let contains = graph.search_edges(&Filter::edge_kind(EdgeKind::Contains)).unwrap();
+    let contains = contains.iter().find(|edge| edge.source() == &parent && edge.target() == &child).unwrap();
+    assert!(!contains.attributes().contains(&Attribute::new("label", "favourite")), "Only the link gets the attributes");
+
+    for (from, to) in [(&NodePath::user_root(), &child), (&parent, &grandchild), (&child, &parent)] {
+        let result = graph.get_edge_strict(from, to);
+        assert!(matches!(result, Err(GraphError::EdgeNotFound { .. })), "No edge from {} to {}", from.alias(), to.alias());
+        outcomes.push(outcome(result));
+    }
+
+    outcomes.push(outcome(graph.delete_edge(link)));
+    assert!(graph.get_edge_strict(&parent, &child).unwrap().contains(), "The contains edge is left after the link");
+
+    outcomes
+}
+
+fn reparent<G: Backend>(graph: &mut G) -> Vec<String> {
+    let root = graph.user_root_dirpath();
+    std::fs::create_dir_all(root.join("docs/sub")).unwrap();
+    std::fs::create_dir(root.join("archive")).unwrap();
+    std::fs::write(root.join("docs/sub/plan.md"), "plan").unwrap();
+    graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+    let idea = NodePath::from("idea");
+    let plan = NodePath::from("docs/sub/plan.md");
+    graph.create_node_by_path(&idea, None).unwrap();
+    graph.create_edge(&idea, &plan).unwrap();
+    graph.create_edge(&plan, &idea).unwrap();
+    graph.insert_node_attrs(&plan, vec![Attribute::new("status", "draft")]).unwrap();
+    let mut outcomes = Vec::new();
+
+    outcomes.push(outcome(graph.reparent_node(&NodePath::from("docs"), &NodePath::from("archive"))));
+    outcomes.push(outcome(graph.reparent_node(&NodePath::from("archive"), &NodePath::from("archive/docs"))));
+    outcomes.push(outcome(graph.reparent_node(&NodePath::user_root(), &NodePath::from("archive"))));
+    outcomes.push(outcome(graph.reparent_node(&NodePath::from("missing"), &NodePath::from("archive"))));
+
+    let moved = NodePath::from("archive/docs/sub/plan.md");
+    assert!(root.join("archive/docs/sub/plan.md").exists());
+    assert!(graph.open_node(&plan).is_err());
+    assert_eq!(graph.get_node_attrs(&moved).unwrap(), vec![Attribute::new("status", "draft")]);
+    assert!(graph.get_edge_strict(&idea, &moved).is_ok());
+    assert!(graph.get_edge_strict(&moved, &idea).is_ok());
+    assert!(graph.get_edge_strict(&NodePath::from("archive"), &NodePath::from("archive/docs")).unwrap().contains());
+
+    // Virtual nodes are moved only in the graph.
+    outcomes.push(outcome(graph.reparent_node(&idea, &NodePath::from("archive"))));
+    assert!(graph.open_node(&NodePath::from("archive/idea")).is_ok());
+
+    outcomes
+}
+
+fn nodetypes<G: Backend>(graph: &mut G) -> Vec<String> {
+    let task = NodeTypeDef::new(NodeType::new("Task".to_string()))
+        .with_attributes(vec![Attribute::new("done", false), Attribute::new("priority", 1)]);
+    let bug = NodeTypeDef::new(NodeType::new("Bug".to_string()))
+        .with_parent(NodeType::new("Task".to_string()))
+        .with_attributes(vec![Attribute::new("priority", 5), Attribute::new("severity", "low")]);
+    let mut outcomes = Vec::new();
+
+    outcomes.push(outcome(graph.create_nodetype(bug.clone())));
+    outcomes.push(outcome(graph.create_nodetype(task.clone())));
+    outcomes.push(outcome(graph.create_nodetype(bug.clone())));
+    outcomes.push(outcome(graph.create_nodetype(task.clone())));
+    outcomes.push(outcome(graph.create_nodetype(NodeTypeDef::new(NodeType::file()))));
+
+    let mut names: Vec<String> = graph.get_node_types().unwrap().iter().map(|def| def.ntype().name().to_string()).collect();
+    names.sort();
+    assert_eq!(names, vec!["Bug", "Task"]);
+
+    let mut attrs = graph.get_nodetype_attrs(bug.ntype()).unwrap();
+    attrs.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(attrs, vec![
+        Attribute::new("done", false),
+        Attribute::new("priority", 5),
+        Attribute::new("severity", "low"),
+    ]);
+
+    let crash = NodePath::from("bugs/crash");
+    outcomes.push(outcome(graph.instance_nodetype(&crash, bug.ntype())));
+    outcomes.push(outcome(graph.instance_nodetype(&crash, bug.ntype())));
+    outcomes.push(outcome(graph.instance_nodetype(&NodePath::from("x"), &NodeType::new("Missing".to_string()))));
+    assert_eq!(graph.open_node(&crash).unwrap().ntype_name(), *bug.ntype());
+
+    outcomes
+}
+
+fn indexing<G: Backend>(graph: &mut G) -> Vec<String> {
+    let root = graph.user_root_dirpath();
+    std::fs::create_dir_all(root.join("docs")).unwrap();
+    std::fs::write(root.join("docs/plan.md"), "the plan").unwrap();
+    std::fs::write(root.join("docs/gone.md"), "going away").unwrap();
+    std::fs::write(root.join("docs/.hidden"), "hidden").unwrap();
+    let mut outcomes = Vec::new();
+
+    let summary = graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+    assert_eq!(summary.created.len(), 3);
+    assert!(graph.open_node(&NodePath::from("docs/.hidden")).is_err());
+    assert_eq!(graph.open_node(&NodePath::from("docs")).unwrap().ntype_name(), NodeType::dir());
+
+    let plan = NodePath::from("docs/plan.md");
+    let other = NodePath::from("other");
+    graph.create_node_by_path(&other, None).unwrap();
+    graph.create_edge(&other, &plan).unwrap();
+    graph.insert_node_attrs(&plan, vec![Attribute::new("status", "draft")]).unwrap();
+
+    // Moved outside of the app, the node follows the file.
+    std::fs::create_dir(root.join("archive")).unwrap();
+    std::fs::rename(root.join("docs/plan.md"), root.join("archive/plan.md")).unwrap();
+    let summary = graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+    let moved = NodePath::from("archive/plan.md");
+    assert_eq!(summary.moved, vec![(plan.clone(), moved.clone())]);
+    assert_eq!(graph.get_node_attrs(&moved).unwrap(), vec![Attribute::new("status", "draft")]);
+    assert!(graph.get_edge_strict(&other, &moved).is_ok());
+
+    outcomes.push(outcome(graph.index_single_node(&NodePath::from("archive"))));
+    std::fs::write(root.join("late.md"), "late").unwrap();
+    outcomes.push(outcome(graph.index_single_node(&NodePath::from("late.md"))));
+    outcomes.push(outcome(graph.index_single_node(&NodePath::from("nowhere"))));
+
+    std::fs::remove_file(root.join("docs/gone.md")).unwrap();
+    let dead = graph.mark_dead_nodes().unwrap();
+    assert_eq!(dead, vec![NodePath::from("docs/gone.md")]);
+
+    let report = graph.cleanup_dead_nodes(true).unwrap();
+    assert_eq!(report.nodes, dead);
+    assert!(graph.open_node(&NodePath::from("docs/gone.md")).is_ok());
+    graph.cleanup_dead_nodes(false).unwrap();
+    assert!(graph.open_node(&NodePath::from("docs/gone.md")).is_err());
+
+    // An empty file that matches nothing for certain is left for reconciliation.
+    std::fs::write(root.join("docs/a.md"), "").unwrap();
+    graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+    std::fs::rename(root.join("docs/a.md"), root.join("docs/b.md")).unwrap();
+    let summary = graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+    assert_eq!(summary.reconciliation.len(), 1);
+    outcomes.push(outcome(graph.reconcile_move(&NodePath::from("docs/a.md"), &NodePath::from("docs/b.md"))));
+    outcomes.push(outcome(graph.reconcile_move(&NodePath::from("docs/a.md"), &NodePath::from("docs/b.md"))));
+
+    outcomes
+}
+
+fn settings_and_search<G: Backend>(graph: &mut G) -> Vec<String> {
+    let mut outcomes = Vec::new();
+
+    outcomes.push(outcome(graph.set_setting("theme", "dark".into())));
+    outcomes.push(outcome(graph.set_setting("maintain_readable_files", 3.into())));
+    outcomes.push(outcome(graph.set_setting("path", "x".into())));
+    assert_eq!(graph.get_setting("theme").unwrap(), Some(AttributeValue::from("dark")));
+    assert_eq!(graph.get_setting("unset").unwrap(), None);
+
+    let options = IndexOptions::default().max_depth(2).ignore("*.tmp").hash_contents(true);
+    graph.set_index_options(&options).unwrap();
+    graph.set_index_options(&IndexOptions::default().ignore("*.log")).unwrap();
+    let stored = graph.index_options().unwrap();
+    assert_eq!(stored.max_depth, None);
+    assert_eq!(stored.ignore_patterns, vec!["*.log".to_string()]);
+    assert!(graph.list_settings().unwrap().iter().any(|attr| attr.name == "theme"));
+
+    for (name, rank) in [("x/one", 1), ("x/two", 2), ("y/three", 3)] {
+        let path = NodePath::from(name);
+        graph.create_node_by_path(&path, None).unwrap();
+        graph.insert_node_attrs(&path, vec![Attribute::new("rank", rank)]).unwrap();
+    }
+
+    let names = |nodes: Vec<Node>| {
+        let mut names: Vec<String> = nodes.iter().map(|node| node.path().alias()).collect();
+        names.sort();
+        names
+    };
+    let ranked = Filter::attr("rank", AttrComparison::GreaterThan(1.into()));
+    assert_eq!(names(graph.search_nodes(&ranked).unwrap()), vec![
+        NodePath::from("x/two").alias(),
+        NodePath::from("y/three").alias(),
+    ]);
+    let under_x = Filter::path_prefix(&NodePath::from("x")).and(Filter::has_attr("rank"));
+    assert_eq!(names(graph.search_nodes(&under_x).unwrap()).len(), 2);
+    assert_eq!(names(graph.search_nodes(&Filter::path_glob("y/*")).unwrap()), vec![NodePath::from("y/three").alias()]);
+    assert_eq!(graph.search_edges(&Filter::edge_kind(EdgeKind::Contains).and(Filter::path_prefix(&NodePath::from("x")))).unwrap().len(), 0);
+
+    let mut aliases = graph.get_all_aliases();
+    aliases.sort();
+    assert!(aliases.contains(&NodePath::from("x/one").alias()));
+
+    outcomes
+}
+
+#[test]
+fn files_are_persisted_and_readable_by_both_backends() {
+    let func_name = "files_are_persisted_and_readable_by_both_backends";
+    let ctx = TestContext::new(func_name);
+
+    let mut files = GraphFiles::open(config(&ctx, "files")).unwrap();
+    reparent(&mut files);
+    let mut idea = Node::new(&NodePath::from("ideas/idea"), NodeType::other());
+    idea.insert_attributes(vec![Attribute::new("tags", vec![AttributeValue::from("a"), AttributeValue::from("b")])]);
+    files.insert_node(idea).unwrap();
+    let expected = contents(&files);
+
+    let file = files.files_dir().join("user_root/archive/docs/sub/plan.md.ron");
+    assert!(file.exists(), "Every node is stored in its own file");
+    assert!(!files.files_dir().join("user_root/docs").exists(), "Files of moved nodes are removed");
+    drop(files);
+
+    let reopened = GraphFiles::open(config(&ctx, "files")).unwrap();
+    assert_eq!(contents(&reopened), expected, "The graph should be read back from the files");
+    let dir = reopened.files_dir();
+    drop(reopened);
+
+    // GraphAgdb imports the files as readable files.
+    let mut agdb = GraphAgdb::open(config(&ctx, "files").with_backend(DbBackend::Memory)).unwrap();
+    let report = agdb.import_readable_files(Some(dir)).unwrap();
+    assert!(report.errored.is_empty(), "{:?}", report.errored);
+    assert_eq!(contents(&agdb), expected);
+
+    // And GraphFiles imports the readable files of GraphAgdb.
+    agdb.maintain_readable_files(true).unwrap();
+    let mut imported = GraphFiles::open(config(&ctx, "imported")).unwrap();
+    let report = imported.import_readable_files(Some(agdb.readable_dir())).unwrap();
+    assert!(report.errored.is_empty(), "{:?}", report.errored);
+    let (nodes, _, links) = contents(&imported);
+    assert!(nodes.iter().any(|(path, attrs)| path == &NodePath::from("ideas/idea").alias() && !attrs.is_empty()));
+    assert_eq!(links, expected.2);
+}
+
+#[test]
+fn files_report_why_the_graph_cant_be_opened() {
+    let func_name = "files_report_why_the_graph_cant_be_opened";
+    let ctx = TestContext::new(func_name);
+    let base = ctx.graph.user_root_dirpath();
+
+    let missing = GraphConfig::new("missing", base.join("nowhere")).with_storage(base.join("storage"));
+    assert!(matches!(GraphFiles::open(missing), Err(GraphError::RootNotFound(_))));
+
+    let graph = GraphFiles::open(config(&ctx, "locked")).unwrap();
+    assert!(matches!(GraphFiles::open(config(&ctx, "locked")), Err(GraphError::DbLocked(_))));
+    let dir = graph.files_dir();
+    drop(graph);
+
+    std::fs::write(dir.join("broken.ron"), "not ron").unwrap();
+    assert!(matches!(GraphFiles::open(config(&ctx, "locked")), Err(GraphError::DbCorrupted { .. })));
+
+    std::fs::remove_file(dir.join("root.ron")).unwrap();
+    std::fs::remove_file(dir.join("broken.ron")).unwrap();
+    assert!(matches!(GraphFiles::open(config(&ctx, "locked")), Err(GraphError::DbCorrupted { .. })));
+}
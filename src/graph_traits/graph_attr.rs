@@ -0,0 +1,152 @@
+use crate::{elements::attribute::AttributeDef, error::GraphError};
+
+pub trait GraphAttr {
+    // -------------------------------------------------------------------
+    // Attribute definitions
+
+    /// All the attribute definitions, stored under the attributes archetype.
+    fn get_attribute_defs(&self) -> Result<Vec<AttributeDef>, GraphError>;
+
+    fn open_attribute_def(&self, name: &str) -> Result<AttributeDef, GraphError>;
+
+    /// Creates an attribute definition. From then on, node and edge attributes with
+    /// that name are validated against it when inserted. Values that are already
+    /// in the graph are not checked. Errors if the definition already exists, is
+    /// for a reserved attribute name or contradicts itself.
+    fn create_attribute_def(&mut self, def: AttributeDef) -> Result<AttributeDef, GraphError>;
+
+    /// Deletes an attribute definition. The attributes themselves are kept.
+    fn delete_attribute_def(&mut self, name: &str) -> Result<(), GraphError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{prelude::*, utils::utils::TestContext};
+
+    fn rating() -> AttributeDef {
+        AttributeDef::new("rating", "Int").with_default(3).with_range(Some(1.0), Some(5.0))
+    }
+
+    fn status() -> AttributeDef {
+        AttributeDef::new("status", "String")
+            .with_allowed(vec!["todo".into(), "done".into()])
+            .with_ntypes(vec![NodeType::other()])
+    }
+
+    #[test]
+    fn attribute_defs_are_stored_under_the_archetype() {
+        let func_name = "attribute_defs_are_stored_under_the_archetype";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_attribute_def(rating()).unwrap();
+        ctx.graph.create_attribute_def(status()).unwrap();
+
+        assert!(ctx.graph.open_node(&NodePath::atype("attributes").join("rating")).is_ok());
+        assert_eq!(ctx.graph.open_attribute_def("status").unwrap(), status());
+
+        let mut defs = ctx.graph.get_attribute_defs().unwrap();
+        defs.sort_by(|a, b| a.name().cmp(b.name()));
+        assert_eq!(defs, vec![rating(), status()]);
+
+        ctx.graph.delete_attribute_def("rating").unwrap();
+        assert!(ctx.graph.open_attribute_def("rating").is_err());
+    }
+
+    #[test]
+    fn invalid_attribute_defs_are_refused() {
+        let func_name = "invalid_attribute_defs_are_refused";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_attribute_def(rating()).unwrap();
+        assert!(ctx.graph.create_attribute_def(rating()).is_err(), "Definitions can't be created twice");
+        assert!(ctx.graph.create_attribute_def(AttributeDef::new("ntype", "String")).is_err(), "Reserved names can't be defined");
+        assert!(ctx.graph.create_attribute_def(AttributeDef::new("mood", "Feeling")).is_err(), "Unknown type");
+        assert!(ctx.graph.create_attribute_def(AttributeDef::new("title", "String").with_range(Some(0.0), None)).is_err());
+        assert!(ctx.graph.create_attribute_def(AttributeDef::new("stars", "Int").with_default(9).with_range(None, Some(5.0))).is_err());
+        assert!(ctx.graph.create_attribute_def(AttributeDef::new("phase", "String").with_allowed(vec![1.into()])).is_err());
+    }
+
+    #[test]
+    fn node_attributes_are_validated_against_definitions() {
+        let func_name = "node_attributes_are_validated_against_definitions";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_attribute_def(rating()).unwrap();
+        ctx.graph.create_attribute_def(status()).unwrap();
+
+        let path = NodePath::new("note".into());
+        ctx.graph.create_node_by_path(&path, None).unwrap();
+
+        ctx.graph.insert_node_attrs(&path, vec![Attribute::new("rating", 4)]).unwrap();
+        ctx.graph.insert_node_attrs(&path, vec![Attribute::new("status", "done")]).unwrap();
+        ctx.graph.insert_node_attrs(&path, vec![Attribute::new("undefined", 1.5)]).unwrap();
+
+        let err = ctx.graph.insert_node_attrs(&path, vec![Attribute::new("rating", "high")]).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid value for rating: expected Int, got String");
+        let err = ctx.graph.insert_node_attrs(&path, vec![Attribute::new("rating", 7)]).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid value for rating: 7 is outside of the range 1 to 5");
+        assert!(ctx.graph.insert_node_attrs(&path, vec![Attribute::new("status", "maybe")]).is_err());
+
+        // Nothing is written if any of the attributes is invalid.
+        let mixed = vec![Attribute::new("undefined", 2.5), Attribute::new("rating", 0)];
+        assert!(ctx.graph.insert_node_attrs(&path, mixed).is_err());
+        let attrs = ctx.graph.get_node_attrs(&path).unwrap();
+        assert!(attrs.contains(&Attribute::new("rating", 4)));
+        assert!(attrs.contains(&Attribute::new("undefined", 1.5)));
+
+        let dir = NodePath::new("dir".into());
+        std::fs::create_dir(dir.full(&ctx.graph.user_root_dirpath())).unwrap();
+        ctx.graph.create_node_by_path(&dir, None).unwrap();
+        let err = ctx.graph.insert_node_attrs(&dir, vec![Attribute::new("status", "todo")]).unwrap_err();
+        assert_eq!(err.to_string(), "Attribute status does not apply to nodes of type Directory");
+    }
+
+    #[test]
+    fn edge_attributes_are_validated_against_definitions() {
+        let func_name = "edge_attributes_are_validated_against_definitions";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_attribute_def(rating()).unwrap();
+        ctx.graph.create_attribute_def(status()).unwrap();
+
+        let a = NodePath::new("a".into());
+        let b = NodePath::new("b".into());
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+        ctx.graph.create_edge(&a, &b).unwrap();
+        let edge = Edge::new(&a, &b);
+
+        ctx.graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("rating", 2)]).unwrap();
+        ctx.graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("status", "todo")]).unwrap();
+        assert!(ctx.graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("rating", 6)]).is_err());
+        assert!(ctx.graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("status", 1)]).is_err());
+    }
+
+    #[test]
+    fn inserted_nodes_and_edges_are_validated() {
+        let func_name = "inserted_nodes_and_edges_are_validated";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_attribute_def(rating()).unwrap();
+        ctx.graph.create_attribute_def(status()).unwrap();
+
+        let mut node = Node::new(&NodePath::from("note"), NodeType::other());
+        node.insert_attributes(vec![Attribute::new("rating", 9)]);
+        assert!(ctx.graph.insert_node(node).is_err());
+        assert!(ctx.graph.open_node(&NodePath::from("note")).is_err(), "Invalid nodes aren't inserted");
+
+        // The defaults of a node type are checked when it is instanced.
+        let task = NodeType::new("Task".to_string());
+        ctx.graph.create_nodetype(NodeTypeDef::new(task.clone()).with_attributes(vec![Attribute::new("rating", 0)])).unwrap();
+        assert!(ctx.graph.instance_nodetype(&NodePath::from("task"), &task).is_err());
+        let note = NodeType::new("Note".to_string());
+        ctx.graph.create_nodetype(NodeTypeDef::new(note.clone()).with_attributes(vec![Attribute::new("rating", 4)])).unwrap();
+        ctx.graph.instance_nodetype(&NodePath::from("other"), &note).unwrap();
+
+        let a = NodePath::from("a");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        let edge = Edge::new(&a, &NodePath::from("other")).with_attributes(vec![Attribute::new("status", "maybe")]);
+        assert!(ctx.graph.insert_edge(edge).is_err());
+        assert!(ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap().is_empty(), "Invalid edges aren't inserted");
+    }
+}
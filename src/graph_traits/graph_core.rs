@@ -1,5 +1,50 @@
-use super::{node::Node, node_path::NodePath, StoragePath};
-use std::{error::Error, path::PathBuf};
+use crate::config::GraphConfig;
+
+use super::{attribute::{Attribute, AttributeValue}, edge::Edge, node::Node, node_path::NodePath, StoragePath};
+use crate::{elements::nodetype::NodeType, error::GraphError, indexing::{IndexOptions, IndexSummary}};
+use std::path::PathBuf;
+
+/// Report of the nodes that were deleted by cleanup_dead_nodes, or would be
+/// deleted in a dry run, along with the user data that goes with them.
+#[derive(Clone, Debug, Default)]
+pub struct CleanupReport {
+    /// Whether the report is from a dry run, in which case nothing was deleted.
+    pub dry_run: bool,
+    /// The dead nodes.
+    pub nodes: Vec<NodePath>,
+    /// Links (non-contains edges) connected to the dead nodes. These are lost
+    /// along with the nodes.
+    pub lost_edges: Vec<Edge>,
+    /// User attributes of the dead nodes.
+    pub lost_attributes: Vec<(NodePath, Vec<Attribute>)>,
+}
+
+/// A difference between a readable file and the file system, found by
+/// import_readable_files. The node is imported as it was recorded either way.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReadableConflict {
+    /// The node was recorded as alive, but its file or directory is gone.
+    Missing(NodePath),
+    /// The node was recorded as dead, but its file or directory exists.
+    Reappeared(NodePath),
+    /// The node was recorded as a file but is a directory, or the other way around.
+    TypeMismatch { path: NodePath, recorded: NodeType, found: NodeType },
+}
+
+/// Report of a call to GraphCore::import_readable_files.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// Nodes that were inserted or updated from their readable files.
+    pub nodes: Vec<NodePath>,
+    /// Number of edges that were inserted or updated.
+    pub edges: usize,
+    /// Nodes whose readable files disagree with the file system. Nothing is
+    /// done about them, so that the user can decide.
+    pub conflicts: Vec<ReadableConflict>,
+    /// Readable files that couldn't be read, and nodes or edges that couldn't
+    /// be inserted, with the reason.
+    pub errored: Vec<(PathBuf, String)>,
+}
 
 pub trait GraphCore {
     fn storage_path(&self) -> StoragePath;
@@ -12,44 +57,105 @@ pub trait GraphCore {
 
     /// Constructor. Panics if the db cannot be created.
     ///
-    /// Takes the desired root of the graph as a parameter and the name for the db.
+    /// Takes the name for the db, the desired root of the graph and the other
+    /// options in a GraphConfig.
     ///
     /// Creates the db at the storage_path, or initialises the db if it already exists there.
     ///
     /// Note that it uses PathBuf instead of NodePath, because of course
     /// it's not dealing with nodes yet.
     ///
-    /// TODO: Add error handling.
-
-    fn new(name: &str, root_path: PathBuf, custom_storage_path: Option<PathBuf>) -> Self;
+    /// To report a missing root, a locked or a corrupted db instead of panicking,
+    /// use GraphAgdb::open.
+    fn new(config: GraphConfig) -> Self;
 
     /// Create the initial archetype nodes for the graph. Includes
     /// the root,
     /// attributes,
     /// settings,
     /// nodetypes,
-    /// history?
-    fn init_archetype_nodes(&mut self);
+    /// history
+    fn init_archetype_nodes(&mut self) -> Result<(), GraphError>;
 
     /// Syncs a node in the db with the file system
-    fn index_single_node(&mut self, path: &NodePath) -> Result<Node, Box<dyn Error>>;
+    fn index_single_node(&mut self, path: &NodePath) -> Result<Node, GraphError>;
 
     /// Syncs the node's relationships in the db with the file system.
     fn index_node_context(&mut self, path: &NodePath);
 
-    /// Delete all dead nodes from the graph.
-    fn cleanup_dead_nodes(&mut self);
+    /// Recursively syncs the tree under a path with the file system. Missing nodes
+    /// are created and existing ones are revived, retyped or have their modified_time
+    /// bumped as needed. New files that match the fingerprint of a missing file are
+    /// treated as moved, and take over its node. The options control the depth of the walk, symlinks,
+    /// hidden files and ignore patterns. Errors on individual paths don't stop
+    /// the walk; they are collected in the summary.
+    fn index_tree(&mut self, path: &NodePath, options: &IndexOptions) -> Result<IndexSummary, GraphError>;
+
+    /// Resolves an entry of the reconciliation list of index_tree. The node of the
+    /// missing file at from is moved to to, keeping its edges and attributes. The
+    /// node that was created for the new file is replaced, so it must not have any
+    /// attributes or links of its own.
+    fn reconcile_move(&mut self, from: &NodePath, to: &NodePath) -> Result<(), GraphError>;
+
+    /// Checks every File and Directory node against the file system. Nodes whose
+    /// file is missing are marked dead, and dead nodes whose file has reappeared are
+    /// revived. Returns the paths of all the nodes that are dead after the sweep.
+    fn mark_dead_nodes(&mut self) -> Result<Vec<NodePath>, GraphError>;
+
+    /// Delete all dead nodes from the graph, along with their contains edges.
+    /// The report lists the links and attributes that are lost with them.
+    /// With dry_run set, nothing is deleted, so the report can be confirmed first.
+    fn cleanup_dead_nodes(&mut self, dry_run: bool) -> Result<CleanupReport, GraphError>;
 
     // Open all nodes and edges in the graph.
     // fn open_all(&self) -> (Vec<Node>, Vec<Edge>);
 
     /// Set whether the library should maintain readable files for the nodes in the graph.
-    fn maintain_readable_files(&mut self, maintain: bool);
+    /// The choice is stored in the settings, so it persists across sessions.
+    ///
+    /// Every node gets a RON file with its type, attributes and outgoing edges,
+    /// in a directory next to the db. Switching this on writes the files of all
+    /// nodes, and from then on they are updated on every change. Switching it
+    /// off leaves the existing files in place. Fails if the files can't be
+    /// written.
+    fn maintain_readable_files(&mut self, maintain: bool) -> Result<(), GraphError>;
+
+    /// Writes the readable files of all nodes from scratch, removing any stale
+    /// ones. Works whether or not the files are being maintained.
+    fn rebuild_readable_files(&mut self) -> Result<(), GraphError>;
+
+    /// Reads the nodes and edges back from a directory of readable files, such
+    /// as the one maintained by maintain_readable_files. Reads the graph's own
+    /// directory if none is given. Together with new, this rebuilds a db that
+    /// has been lost, or builds one from readable files kept in version control.
+    ///
+    /// Nodes and edges that are already in the graph are updated. Fingerprints
+    /// aren't stored in the readable files of GraphAgdb, so they are filled in
+    /// by the next index_tree.
+    fn import_readable_files(&mut self, dir: Option<PathBuf>) -> Result<ImportReport, GraphError>;
+
+    /// Gets a setting of the graph, or None if it hasn't been set.
+    fn get_setting(&self, key: &str) -> Result<Option<AttributeValue>, GraphError>;
+
+    /// Sets a setting of the graph. Settings are stored on the settings archetype node.
+    /// The ones used by the library are type checked, all others are stored as they are.
+    fn set_setting(&mut self, key: &str, value: AttributeValue) -> Result<(), GraphError>;
+
+    /// All the settings of the graph, including the ones unknown to the library.
+    fn list_settings(&self) -> Result<Vec<Attribute>, GraphError>;
+
+    /// The indexing options stored in the settings. Options that haven't been
+    /// set have their default values.
+    fn index_options(&self) -> Result<IndexOptions, GraphError>;
+
+    /// Stores the indexing options in the settings.
+    fn set_index_options(&mut self, options: &IndexOptions) -> Result<(), GraphError>;
 
     // fn undo(&mut self, num: usize);
     // fn redo(&mut self, num: usize);
 
-    /// For debugging purposes, print all aliases.
+    /// For debugging purposes, print all aliases. The aliases of the command
+    /// history are left out.
     fn get_all_aliases(&self) -> Vec<String>;
 }
 
@@ -57,14 +163,22 @@ pub trait GraphCore {
 mod tests {
     #![allow(warnings)]
 
-    use std::path::PathBuf;
+    use std::{path::PathBuf, time::{Duration, SystemTime}};
 
     use directories::ProjectDirs;
 
+    use agdb::QueryBuilder;
+
     use crate::{
-        elements::{node, node_path::NodePath},
-        graph_agdb::GraphAgdb,
-        graph_traits::{graph_core::GraphCore, graph_edge::GraphEdge, graph_node::GraphNode, StoragePath},
+        config::{DbBackend, GraphConfig},
+        elements::{attribute::{Attribute, AttributeValue}, edge::Edge, node, node_path::NodePath, nodetype::NodeType},
+        error::GraphError,
+        events::{EventFilter, GraphEvent},
+        filter::{EdgeKind, Filter},
+        graph_agdb::{readable::read_readable_file, GraphAgdb},
+        graph_commands::GraphCommands,
+        graph_traits::{graph_core::{GraphCore, ReadableConflict}, graph_edge::GraphEdge, graph_node::GraphNode, StoragePath},
+        indexing::{IndexOptions, SymlinkPolicy},
         utils::utils::TestContext,
     };
 
@@ -157,6 +271,405 @@ mod tests {
         });
     }
 
+    #[test]
+    fn missing_files_are_marked_dead_and_purged_after_dry_run() {
+        let func_name = "missing_files_are_marked_dead_and_purged_after_dry_run";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        let kept = NodePath::from("kept.txt");
+        let gone = NodePath::from("gone.txt");
+        std::fs::File::create(kept.full(&root)).unwrap();
+        std::fs::File::create(gone.full(&root)).unwrap();
+        ctx.graph.index_node_context(&NodePath::user_root());
+
+        ctx.graph.insert_node_attrs(&gone, vec![Attribute::new("rating", 3)]).unwrap();
+        let link = Edge::new(&kept, &gone);
+        ctx.graph.db_mut().exec_mut(
+            &QueryBuilder::insert()
+                .edges()
+                .from(kept.alias())
+                .to(gone.alias())
+                .values_uniform(&link)
+                .query(),
+        ).unwrap();
+
+        std::fs::remove_file(gone.full(&root)).unwrap();
+
+        let (_, events) = ctx.graph.subscribe(Some(EventFilter::new().under(&gone)));
+        let dead = ctx.graph.mark_dead_nodes().unwrap();
+        assert_eq!(dead, vec![gone.clone()], "Only the removed file should be dead");
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![GraphEvent::AliveChanged { path: gone.clone(), alive: false }]);
+        assert_eq!(ctx.graph.open_node(&gone).unwrap().alive(), false);
+        assert_eq!(ctx.graph.open_node(&kept).unwrap().alive(), true);
+
+        let report = ctx.graph.cleanup_dead_nodes(true).unwrap();
+        assert_eq!(report.nodes, vec![gone.clone()]);
+        assert_eq!(report.lost_edges.len(), 1, "The link to the dead node should be reported");
+        assert_eq!(report.lost_attributes.len(), 1, "The rating should be reported");
+        assert!(ctx.graph.open_node(&gone).is_ok(), "Dry run should not delete anything");
+
+        let report = ctx.graph.cleanup_dead_nodes(false).unwrap();
+        assert_eq!(report.nodes, vec![gone.clone()]);
+        assert!(ctx.graph.open_node(&gone).is_err(), "Dead node should be deleted");
+        assert!(ctx.graph.open_node(&kept).is_ok(), "Living node should remain");
+    }
+
+    #[test]
+    fn index_tree_walks_recursively_and_respects_ignore_rules() {
+        let func_name = "index_tree_walks_recursively_and_respects_ignore_rules";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        for dir in ["notes/deep/deeper", "build", ".hidden"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "notes/a.md",
+            "notes/debug.log",
+            "notes/deep/b.md",
+            "notes/deep/deeper/c.md",
+            "notes/deep/scratch.tmp",
+            "build/out.bin",
+            ".hidden/secret.txt",
+        ] {
+            std::fs::File::create(root.join(file)).unwrap();
+        }
+        std::fs::write(root.join(".gitignore"), "*.log\nbuild/\n").unwrap();
+
+        let options = IndexOptions::default().ignore("**/*.tmp");
+        let summary = ctx.graph.index_tree(&NodePath::user_root(), &options).unwrap();
+
+        assert!(summary.errored.is_empty(), "Errors: {:?}", summary.errored);
+        assert_eq!(summary.created.len(), 6, "Created: {:?}", summary.created);
+        assert!(ctx.graph.open_node(&NodePath::from("notes/deep/deeper/c.md")).is_ok());
+        assert_eq!(
+            ctx.graph.open_node(&NodePath::from("notes/deep")).unwrap().ntype_name(),
+            NodeType::dir(),
+            "Indexing children must not change the type of their parent"
+        );
+
+        for skipped in ["notes/debug.log", "build", ".hidden", ".gitignore", "notes/deep/scratch.tmp"] {
+            let path = NodePath::from(skipped);
+            assert!(summary.skipped.contains(&path), "{} should be skipped", skipped);
+            assert!(ctx.graph.open_node(&path).is_err(), "{} should not be indexed", skipped);
+        }
+        assert!(ctx.graph.open_node(&NodePath::from("build/out.bin")).is_err());
+
+        let again = ctx.graph.index_tree(&NodePath::user_root(), &options).unwrap();
+        assert!(again.created.is_empty(), "Second run should not create anything");
+        assert_eq!(again.unchanged, 6);
+
+        let children = ctx.graph.open_node_connections(&NodePath::from("notes"));
+        assert_eq!(children.len(), 3, "The parent and two children of notes, without duplicates");
+    }
+
+    #[test]
+    fn index_tree_stops_at_max_depth() {
+        let func_name = "index_tree_stops_at_max_depth";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        std::fs::create_dir_all(root.join("one/two/three")).unwrap();
+
+        let options = IndexOptions::default().max_depth(1);
+        let summary = ctx.graph.index_tree(&NodePath::from("one"), &options).unwrap();
+
+        assert_eq!(summary.created, vec![NodePath::from("one"), NodePath::from("one/two")]);
+        assert!(ctx.graph.open_node(&NodePath::from("one/two/three")).is_err());
+    }
+
+    #[test]
+    fn files_moved_outside_the_app_keep_their_node() {
+        let func_name = "files_moved_outside_the_app_keep_their_node";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        let photo = NodePath::from("photo.png");
+        let other = NodePath::from("other.txt");
+        std::fs::write(photo.full(&root), "not really a png").unwrap();
+        std::fs::write(other.full(&root), "something else").unwrap();
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+        ctx.graph.insert_node_attrs(&photo, vec![Attribute::new("rating", 4)]).unwrap();
+        let link = Edge::new(&other, &photo);
+        ctx.graph.db_mut().exec_mut(
+            &QueryBuilder::insert()
+                .edges()
+                .from(other.alias())
+                .to(photo.alias())
+                .values_uniform(&link)
+                .query(),
+        ).unwrap();
+
+        let moved = NodePath::from("archive/renamed.png");
+        std::fs::create_dir(root.join("archive")).unwrap();
+        std::fs::rename(photo.full(&root), moved.full(&root)).unwrap();
+
+        let summary = ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+        assert_eq!(summary.moved, vec![(photo.clone(), moved.clone())]);
+        assert!(summary.reconciliation.is_empty());
+        assert!(ctx.graph.open_node(&photo).is_err(), "Old path should be gone");
+
+        let node = ctx.graph.open_node(&moved).unwrap();
+        assert_eq!(node.attributes(), vec![Attribute::new("rating", 4)], "Attributes should move along");
+
+        let links = ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].target(), &moved, "Link should follow the moved node");
+    }
+
+    #[test]
+    fn ambiguous_moves_go_to_reconciliation() {
+        let func_name = "ambiguous_moves_go_to_reconciliation";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        let first = NodePath::from("first.txt");
+        let second = NodePath::from("second.txt");
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for path in [&first, &second] {
+            std::fs::write(path.full(&root), "same").unwrap();
+            std::fs::File::options().write(true).open(path.full(&root)).unwrap().set_modified(mtime).unwrap();
+        }
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+        ctx.graph.insert_node_attrs(&first, vec![Attribute::new("note", "first")]).unwrap();
+
+        let first_moved = NodePath::from("first_moved.txt");
+        let second_moved = NodePath::from("second_moved.txt");
+        std::fs::rename(first.full(&root), first_moved.full(&root)).unwrap();
+        std::fs::rename(second.full(&root), second_moved.full(&root)).unwrap();
+
+        let summary = ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+        assert!(summary.moved.is_empty(), "Identical files can't be told apart");
+        assert_eq!(summary.reconciliation.len(), 2);
+        assert!(summary.reconciliation.iter().all(|entry| entry.candidates.len() == 2));
+        assert!(summary.created.contains(&first_moved), "New files are indexed as new nodes meanwhile");
+
+        ctx.graph.reconcile_move(&first, &first_moved).unwrap();
+
+        assert!(ctx.graph.open_node(&first).is_err());
+        let node = ctx.graph.open_node(&first_moved).unwrap();
+        assert_eq!(node.attributes(), vec![Attribute::new("note", "first")]);
+        assert_eq!(node.alive(), true);
+    }
+
+    #[test]
+    fn settings_persist_across_sessions() {
+        let func_name = "settings_persist_across_sessions";
+        let mut first = TestContext::new(func_name);
+
+        first.graph.maintain_readable_files(true).unwrap();
+        first.graph.set_setting("ui_theme", "dark".into()).unwrap();
+
+        assert!(first.graph.set_setting("maintain_readable_files", 1.into()).is_err(), "Known settings are type checked");
+        assert!(first.graph.set_setting("ntype", "x".into()).is_err(), "Reserved names can't be settings");
+
+        let second = TestContext::new(func_name);
+        assert_eq!(second.graph.get_setting("maintain_readable_files").unwrap(), Some(AttributeValue::Bool(true)));
+        assert_eq!(second.graph.get_setting("ui_theme").unwrap(), Some("dark".into()), "Unknown settings pass through");
+        assert_eq!(second.graph.get_setting("missing").unwrap(), None);
+        assert_eq!(second.graph.list_settings().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn index_options_are_stored_in_the_settings() {
+        let func_name = "index_options_are_stored_in_the_settings";
+        let mut ctx = TestContext::new(func_name);
+
+        assert_eq!(ctx.graph.index_options().unwrap(), IndexOptions::default());
+
+        let options = IndexOptions::default()
+            .max_depth(2)
+            .symlinks(SymlinkPolicy::Follow)
+            .ignore("*.tmp")
+            .ignore("build/")
+            .hash_contents(true);
+        ctx.graph.set_index_options(&options).unwrap();
+        assert_eq!(ctx.graph.index_options().unwrap(), options);
+
+        // Clearing the depth limit removes the setting.
+        ctx.graph.set_index_options(&IndexOptions::default()).unwrap();
+        assert_eq!(ctx.graph.index_options().unwrap(), IndexOptions::default());
+        assert_eq!(ctx.graph.get_setting("index_max_depth").unwrap(), None);
+    }
+
+    #[test]
+    fn readable_files_mirror_the_graph() {
+        let func_name = "readable_files_mirror_the_graph";
+        let mut ctx = TestContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.maintain_readable_files(true).unwrap();
+
+        // Switching the files on writes the existing nodes.
+        let read = |graph: &GraphAgdb, path: &NodePath| read_readable_file(&graph.readable_file(path));
+        assert_eq!(read(&ctx.graph, &a).unwrap().node.path(), a);
+
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+        ctx.graph.insert_node_attrs(&a, vec![Attribute::new("rating", 3)]).unwrap();
+        ctx.graph.create_edge(&a, &b).unwrap();
+
+        let readable = read(&ctx.graph, &a).unwrap();
+        assert_eq!(readable.node.attributes(), vec![Attribute::new("rating", 3)]);
+        assert_eq!(readable.edges.len(), 1, "Only outgoing edges are listed");
+        assert_eq!(readable.edges[0].target(), &b);
+        assert!(read(&ctx.graph, &NodePath::user_root()).unwrap().edges.iter().any(|edge| edge.target() == &b));
+
+        ctx.graph.delete_nodes(&vec![b.clone()], false, false).unwrap();
+        assert!(!ctx.graph.readable_file(&b).exists());
+        assert!(read(&ctx.graph, &a).unwrap().edges.is_empty(), "Edges to deleted nodes are removed");
+
+        // The files can be recovered after they are lost.
+        std::fs::remove_dir_all(ctx.graph.readable_dir()).unwrap();
+        ctx.graph.rebuild_readable_files().unwrap();
+        assert_eq!(read(&ctx.graph, &a).unwrap().node.attributes(), vec![Attribute::new("rating", 3)]);
+        assert!(ctx.graph.readable_file(&NodePath::atype("settings")).exists());
+
+        // The files are never indexed as part of the graph.
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+        let nodes = ctx.graph.search_nodes(&Filter::path_prefix(&NodePath::user_root())).unwrap();
+        assert!(nodes.iter().all(|node| !node.path().alias().contains("_readable")));
+    }
+
+    #[test]
+    fn failing_readable_files_are_reported() {
+        let func_name = "failing_readable_files_are_reported";
+        let mut ctx = TestContext::new(func_name);
+        ctx.graph.maintain_readable_files(true).unwrap();
+
+        // A file in place of the directory keeps the readable files from being written.
+        let dir = ctx.graph.readable_dir();
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::write(&dir, "in the way").unwrap();
+
+        let (_, events) = ctx.graph.subscribe(None);
+        let a = NodePath::from("a");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        let received: Vec<GraphEvent> = events.try_iter().collect();
+        assert!(received.contains(&GraphEvent::NodeCreated(a.clone())), "The change itself goes through");
+        assert!(received.iter().any(|event| matches!(event, GraphEvent::ReadableFileFailed { path, .. } if path == &a)));
+
+        assert!(ctx.graph.rebuild_readable_files().is_err());
+        assert!(ctx.graph.maintain_readable_files(true).is_err());
+    }
+
+    #[test]
+    fn maintaining_readable_files_fails_if_the_setting_cant_be_stored() {
+        let func_name = "maintaining_readable_files_fails_if_the_setting_cant_be_stored";
+        let mut ctx = TestContext::new(func_name);
+
+        let settings = NodePath::atype("settings");
+        ctx.graph.db_mut().exec_mut(&QueryBuilder::remove().ids(settings.alias()).query()).unwrap();
+
+        assert!(ctx.graph.maintain_readable_files(true).is_err());
+        assert!(!ctx.graph.readable_dir().exists(), "Nothing is written when the setting isn't stored");
+    }
+
+    #[test]
+    fn readable_files_follow_moved_nodes() {
+        let func_name = "readable_files_follow_moved_nodes";
+        let mut ctx = TestContext::new(func_name);
+        ctx.graph.maintain_readable_files(true).unwrap();
+
+        let dir = NodePath::from("dir");
+        let moved = NodePath::from("moved");
+        let link = NodePath::from("link");
+        ctx.graph.create_node_by_path(&dir.join("child"), None).unwrap();
+        ctx.graph.create_node_by_path(&link, None).unwrap();
+        ctx.graph.create_edge(&link, &dir.join("child")).unwrap();
+
+        // The nodes are virtual, so the moved directory only needs to exist at its new path.
+        std::fs::create_dir_all(moved.full(&ctx.graph.user_root_dirpath())).unwrap();
+        ctx.graph.reconcile_move(&dir, &moved).unwrap();
+
+        assert!(!ctx.graph.readable_file(&dir).exists());
+        assert!(!ctx.graph.readable_file(&dir.join("child")).exists());
+        let child = read_readable_file(&ctx.graph.readable_file(&moved.join("child"))).unwrap();
+        assert_eq!(child.node.path(), moved.join("child"));
+
+        let link = read_readable_file(&ctx.graph.readable_file(&link)).unwrap();
+        assert_eq!(link.edges[0].target(), &moved.join("child"), "Links into the moved subtree are updated");
+    }
+
+    #[test]
+    fn graph_can_be_rebuilt_from_readable_files() {
+        let func_name = "graph_can_be_rebuilt_from_readable_files";
+        let mut ctx = TestContext::new(func_name);
+        let root = ctx.graph.user_root_dirpath();
+
+        std::fs::write(root.join("kept.txt"), "kept").unwrap();
+        std::fs::write(root.join("removed.txt"), "removed").unwrap();
+        std::fs::write(root.join("swapped"), "swapped").unwrap();
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+        let kept = NodePath::from("kept.txt");
+        let note = NodePath::from("notes/idea");
+        ctx.graph.create_node_by_path(&note, None).unwrap();
+        ctx.graph.insert_node_attrs(&kept, vec![Attribute::new("rating", 4)]).unwrap();
+        ctx.graph.create_edge(&note, &kept).unwrap();
+        ctx.graph.insert_edge_attrs(Edge::new(&note, &kept), vec![Attribute::new("weight", 2)]).unwrap();
+        ctx.graph.set_setting("ui_theme", "dark".into()).unwrap();
+        ctx.graph.maintain_readable_files(true).unwrap();
+
+        // The file system changes while the graph is away.
+        std::fs::remove_file(root.join("removed.txt")).unwrap();
+        std::fs::remove_file(root.join("swapped")).unwrap();
+        std::fs::create_dir(root.join("swapped")).unwrap();
+
+        let mut rebuilt = GraphAgdb::new(GraphConfig::new("rebuilt", root.clone()).with_storage(root.clone()));
+        let report = rebuilt.import_readable_files(Some(ctx.graph.readable_dir())).unwrap();
+
+        assert!(report.errored.is_empty(), "{:?}", report.errored);
+        assert!(report.nodes.contains(&note) && report.nodes.contains(&kept));
+        let (original, imported) = (ctx.graph.open_node(&kept).unwrap(), rebuilt.open_node(&kept).unwrap());
+        assert_eq!(imported.attributes(), original.attributes());
+        assert_eq!(imported.created_time(), original.created_time());
+        assert_eq!(rebuilt.open_node(&note.parent().unwrap()).unwrap().ntype_name(), NodeType::other());
+        let links = rebuilt.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].attributes(), &vec![Attribute::new("weight", 2)]);
+        assert_eq!(rebuilt.get_setting("ui_theme").unwrap(), Some("dark".into()));
+
+        assert_eq!(report.conflicts.len(), 2);
+        assert!(report.conflicts.contains(&ReadableConflict::Missing(NodePath::from("removed.txt"))));
+        assert!(report.conflicts.contains(&ReadableConflict::TypeMismatch {
+            path: NodePath::from("swapped"),
+            recorded: NodeType::file(),
+            found: NodeType::dir(),
+        }));
+        assert!(rebuilt.open_node(&NodePath::from("removed.txt")).unwrap().alive(), "Conflicts are left as recorded");
+    }
+
+    #[test]
+    fn lost_db_is_recovered_from_readable_files_on_open() {
+        let func_name = "lost_db_is_recovered_from_readable_files_on_open";
+        let ctx = TestContext::new(func_name);
+        let root = ctx.graph.user_root_dirpath();
+        let config = GraphConfig::new("recovered", root.clone())
+            .with_storage(root.join("storage"))
+            .with_readable_files(true);
+
+        let note = NodePath::from("notes/idea");
+        let mut graph = GraphAgdb::open(config.clone()).unwrap();
+        graph.create_node_by_path(&note, None).unwrap();
+        graph.insert_node_attrs(&note, vec![Attribute::new("rating", 5)]).unwrap();
+        let readable = graph.readable_file(&note);
+        drop(graph);
+
+        std::fs::remove_file(root.join("storage").join("recovered.agdb")).unwrap();
+        let graph = GraphAgdb::open(config).unwrap();
+
+        assert!(readable.exists(), "The readable files of a lost db should be kept");
+        let node = graph.open_node(&note).unwrap();
+        assert_eq!(node.attributes(), vec![Attribute::new("rating", 5)]);
+        assert!(graph.get_edge_strict(&note.parent().unwrap(), &note).unwrap().contains());
+    }
+
     // /// Test for whether a file gets properly indexed into the db after it is
     // /// added to the file system.
     // #[test]
@@ -247,4 +760,120 @@ mod tests {
     // directory, meaning that there would still be a lot of updates needed.
 
     // Test for what happens when a db is moved to a different directory, but the root directory is the same.
+
+    /// Runs the same changes on a graph and returns what it ends up containing,
+    /// so that the backends can be compared.
+    fn exercise_backend(graph: &mut GraphAgdb) -> (Vec<(String, Vec<Attribute>)>, Vec<(String, String, Vec<Attribute>)>) {
+        let root = graph.user_root_dirpath();
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir(root.join("archive")).unwrap();
+        std::fs::write(root.join("docs/plan.md"), "plan").unwrap();
+        graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+        let plan = NodePath::from("docs/plan.md");
+        let idea = NodePath::from("ideas/idea");
+        graph.create_node_by_path(&idea, None).unwrap();
+        graph.insert_node_attrs(&plan, vec![Attribute::new("status", "draft")]).unwrap();
+        graph.create_edge(&idea, &plan).unwrap();
+        graph.insert_edge(Edge::new(&plan, &idea).with_attributes(vec![Attribute::new("weight", 2)])).unwrap();
+
+        graph.reparent_node(&NodePath::from("docs"), &NodePath::from("archive")).unwrap();
+        graph.delete_node_attrs(&NodePath::from("archive/docs/plan.md"), vec!["status"]).unwrap();
+        graph.insert_node_attrs(&idea, vec![Attribute::new("rating", 4)]).unwrap();
+
+        backend_contents(graph)
+    }
+
+    fn backend_contents(graph: &GraphAgdb) -> (Vec<(String, Vec<Attribute>)>, Vec<(String, String, Vec<Attribute>)>) {
+        let mut nodes: Vec<_> = graph
+            .search_nodes(&Filter::path_prefix(&NodePath::root()))
+            .unwrap()
+            .into_iter()
+            .map(|node| (node.path().alias(), node.attributes()))
+            .collect();
+        nodes.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut links: Vec<_> = graph
+            .search_edges(&Filter::edge_kind(EdgeKind::Link))
+            .unwrap()
+            .into_iter()
+            .map(|edge| (edge.source().alias(), edge.target().alias(), edge.attributes().clone()))
+            .collect();
+        links.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+        (nodes, links)
+    }
+
+    #[test]
+    fn backends_behave_the_same() {
+        let func_name = "backends_behave_the_same";
+        let ctx = TestContext::new(func_name);
+        let base = ctx.graph.user_root_dirpath();
+
+        let config = |backend: DbBackend| {
+            let name = format!("{:?}", backend);
+            std::fs::create_dir_all(base.join("roots").join(&name)).unwrap();
+            GraphConfig::new(&name, base.join("roots").join(&name))
+                .with_storage(base.join("storage"))
+                .with_backend(backend)
+        };
+
+        let mut contents = Vec::new();
+        for backend in [DbBackend::Memory, DbBackend::MemoryMapped, DbBackend::File] {
+            let mut graph = GraphAgdb::open(config(backend)).unwrap();
+            assert_eq!(graph.db().backend(), backend);
+            contents.push(exercise_backend(&mut graph));
+        }
+
+        assert!(contents[0].0.iter().any(|(path, _)| path == &NodePath::from("archive/docs/plan.md").alias()));
+        assert_eq!(contents[0].1.len(), 2);
+        assert_eq!(contents[0], contents[1], "Memory and memory mapped dbs should match");
+        assert_eq!(contents[1], contents[2], "Memory mapped and file dbs should match");
+
+        assert!(!base.join("storage").join("Memory.agdb").exists(), "Memory dbs aren't written to disk");
+        let reopened = GraphAgdb::open(config(DbBackend::Memory)).unwrap();
+        assert!(reopened.open_node(&NodePath::from("ideas/idea")).is_err(), "Memory dbs start empty");
+
+        for backend in [DbBackend::MemoryMapped, DbBackend::File] {
+            let reopened = GraphAgdb::open(config(backend)).unwrap();
+            assert_eq!(backend_contents(&reopened), contents[0], "{:?} db should be persisted", backend);
+        }
+    }
+
+    #[test]
+    fn open_reports_why_the_graph_cant_be_opened() {
+        let func_name = "open_reports_why_the_graph_cant_be_opened";
+        let ctx = TestContext::new(func_name);
+        let root = ctx.graph.user_root_dirpath();
+
+        let config = |name: &str, root: PathBuf| {
+            GraphConfig::new(name, root.clone()).with_storage(root.join("storage"))
+        };
+
+        let missing = root.join("unplugged");
+        let err = GraphAgdb::open(config("missing", missing.clone())).err().unwrap();
+        assert!(matches!(err, GraphError::RootNotFound(path) if path == missing));
+        assert!(!missing.exists(), "Nothing should be created for a missing root");
+
+        assert!(matches!(GraphAgdb::open(config("", root.clone())), Err(GraphError::InvalidPath(_))));
+        for nameless in [PathBuf::from("/"), root.join("..")] {
+            let err = GraphAgdb::open(config("nameless", nameless)).err().unwrap();
+            assert!(matches!(err, GraphError::InvalidPath(_)), "Got {}", err);
+        }
+
+        std::fs::create_dir_all(root.join("storage")).unwrap();
+        std::fs::write(root.join("storage").join("garbage.agdb"), b"this is not a db at all").unwrap();
+        let err = GraphAgdb::open(config("garbage", root.clone())).err().unwrap();
+        assert!(matches!(err, GraphError::DbCorrupted { .. }), "Got {}", err);
+
+        let first = GraphAgdb::open(config("locked", root.clone())).unwrap();
+        assert!(first.open_node(&NodePath::user_root()).is_ok());
+        let err = GraphAgdb::open(config("locked", root.clone())).err().unwrap();
+        assert!(matches!(err, GraphError::DbLocked(_)), "Got {}", err);
+        assert!(GraphCommands::open(config("locked", root.clone())).is_err(), "Commands open the graph the same way");
+
+        drop(first);
+        let reopened = GraphAgdb::open(config("locked", root.clone()));
+        assert!(reopened.is_ok(), "The lock is released when the graph is dropped");
+    }
 }
@@ -1,4 +1,4 @@
-use std::error::Error;
+use crate::{error::GraphError, filter::Filter};
 
 use super::{attribute::Attribute, edge::Edge, node_path::NodePath};
 
@@ -8,43 +8,61 @@ pub trait GraphEdge {
         &self, 
         from: &NodePath, 
         to: &NodePath
-    ) -> Result<Edge, Box<dyn Error>>;
+    ) -> Result<Edge, GraphError>;
 
+    /// Searches all edges in the graph that match the filter. Edges of the
+    /// command history are left out.
+    fn search_edges(&self, filter: &Filter) -> Result<Vec<Edge>, GraphError>;
+
+    /// Creates a link between two existing nodes. There can only be one link in
+    /// each direction between two nodes.
     fn create_edge(
         &mut self,
         source_path: &NodePath,
         target_path: &NodePath,
-    ) -> Result<(), Box<dyn Error>>;
+    ) -> Result<(), GraphError>;
 
     /// Changes the parent directory of a node. If the node is physical, it will be moved in the file system.
     /// If the node is virtual, the parent will be changed in the db.
-    /// Note that due to the implementation, all children of the node will have to be reindexed, recursively.
+    /// The node keeps its name, and all of its descendants are moved along with it,
+    /// keeping their edges and attributes. Either everything is moved or nothing is.
     fn reparent_node(
-        &self,
+        &mut self,
         node_path: &NodePath,
         new_parent_path: &NodePath,
-    ) -> Result<(), Box<dyn Error>>;
+    ) -> Result<(), GraphError>;
 
     /// Moves an edge and all its attributes to a new source and target. Parent edges can't be reconnected this way,
     /// use the reparent_node function instead.
     fn reconnect_edge(
-        &self,
+        &mut self,
         edge: Edge,
         from: &NodePath,
         to: &NodePath,
-    ) -> Result<(), Box<dyn Error>>;
+    ) -> Result<(), GraphError>;
 
-    fn insert_edge(&self, edge: Edge) -> Result<(), Box<dyn Error>>;
+    /// Inserts an edge with all of its values and attributes. If the edge already
+    /// exists, its values are updated instead. Contains edges can only be updated,
+    /// as they are created along with their child nodes.
+    fn insert_edge(&mut self, edge: Edge) -> Result<(), GraphError>;
 
     /// Delete an edge from the graph. Edges with the attribute "contains" refer to the parent-child relationship
     /// between nodes and will be ignored. All other attributes will be cleared from them instead.
-    fn delete_edge(&self, edge: Edge) -> Result<(), Box<dyn Error>>;
+    fn delete_edge(&mut self, edge: Edge) -> Result<(), GraphError>;
 
     /// Insert attributes to an edge. Ignore reserved attribute names. Update attributes that already exist.
-    fn insert_edge_attr(&self, edge: Edge, attr: Attribute) -> Result<(), Box<dyn Error>>;
+    fn insert_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError>;
 
     /// Delete attributes from an edge. Ignore reserved attribute names.
-    fn delete_edge_attr(&self, edge: Edge, attr: Attribute) -> Result<(), Box<dyn Error>>;
+    fn delete_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError>;
+
+    /// Insert reserved attributes, such as the relative positions of the nodes, to an edge.
+    /// Meant for the host app to store its own data, not for attributes coming from the user.
+    /// The attributes that define the edge itself, like "contains", can't be written.
+    fn insert_reserved_edge_attrs(&mut self, edge: Edge, attrs: Vec<Attribute>) -> Result<(), GraphError>;
+
+    /// Delete reserved attributes from an edge. The counterpart of insert_reserved_edge_attrs.
+    fn delete_reserved_edge_attrs(&mut self, edge: Edge, attr_names: Vec<&str>) -> Result<(), GraphError>;
 
     // Open all edges in the graph
     // fn open_all_edges(&self) -> Vec<Edge>;
@@ -52,6 +70,281 @@ pub trait GraphEdge {
 
 #[cfg(test)]
 mod tests {
+    #![allow(warnings)]
+
+    use crate::{
+        elements::{attribute::Attribute, edge::Edge, node_path::NodePath, SysTime},
+        error::GraphError,
+        filter::{EdgeKind, Filter},
+        graph_traits::{graph_core::GraphCore, graph_edge::GraphEdge, graph_node::GraphNode},
+        indexing::IndexOptions,
+        utils::utils::TestContext,
+    };
+    use agdb::QueryBuilder;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn search_edges_by_kind() {
+        let func_name = "search_edges_by_kind";
+        let mut ctx = TestContext::new(func_name);
+
+        let path = NodePath::from("one/two");
+        ctx.graph.create_node_by_path(&path, None).unwrap();
+
+        let contains = ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Contains)).unwrap();
+        assert!(
+            contains.iter().any(|e| *e.source() == NodePath::from("one") && *e.target() == path),
+            "Contains edge to the new node should be found"
+        );
+        assert!(contains.iter().all(|e| e.contains()), "Only contains edges should be found");
+
+        let links = ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+        assert!(links.is_empty(), "There are no links yet");
+    }
+
+    #[test]
+    fn strict_edge_prefers_links_and_needs_adjacent_nodes() {
+        let func_name = "strict_edge_prefers_links_and_needs_adjacent_nodes";
+        let mut ctx = TestContext::new(func_name);
+
+        let parent = NodePath::from("a");
+        let child = NodePath::from("a/b");
+        ctx.graph.create_node_by_path(&child, None).unwrap();
+        assert!(ctx.graph.get_edge_strict(&parent, &child).unwrap().contains());
+
+        ctx.graph.create_edge(&parent, &child).unwrap();
+        assert!(!ctx.graph.get_edge_strict(&parent, &child).unwrap().contains(), "The link comes before the contains edge");
+
+        let err = ctx.graph.get_edge_strict(&NodePath::user_root(), &child).err().unwrap();
+        assert!(matches!(err, GraphError::EdgeNotFound { .. }), "Got {}", err);
+        assert!(ctx.graph.get_edge_strict(&child, &parent).is_err(), "Edges have a direction");
+    }
+
+    #[test]
+    fn reparenting_moves_files_and_rewrites_descendants() {
+        let func_name = "reparenting_moves_files_and_rewrites_descendants";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        std::fs::create_dir_all(root.join("docs/drafts")).unwrap();
+        std::fs::create_dir(root.join("archive")).unwrap();
+        std::fs::write(root.join("docs/drafts/plan.md"), "plan").unwrap();
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+        let plan = NodePath::from("docs/drafts/plan.md");
+        ctx.graph.insert_node_attrs(&plan, vec![Attribute::new("status", "draft")]).unwrap();
+
+        ctx.graph.reparent_node(&NodePath::from("docs/drafts"), &NodePath::from("archive")).unwrap();
+
+        let moved = NodePath::from("archive/drafts/plan.md");
+        assert!(moved.full(&root).exists(), "File should be moved on disk");
+        assert!(!plan.full(&root).exists());
+        assert!(ctx.graph.open_node(&plan).is_err(), "Old paths should be gone");
+        assert!(ctx.graph.open_node(&NodePath::from("docs/drafts")).is_err());
+
+        let node = ctx.graph.open_node(&moved).unwrap();
+        assert_eq!(node.path(), moved, "Path value should be rewritten");
+        assert_eq!(node.attributes(), vec![Attribute::new("status", "draft")]);
+
+        let contains = ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Contains)).unwrap();
+        assert!(contains.iter().any(|e| {
+            *e.source() == NodePath::from("archive") && *e.target() == NodePath::from("archive/drafts")
+        }), "Contains edge should come from the new parent");
+        assert!(!contains.iter().any(|e| *e.source() == NodePath::from("docs")
+            && *e.target() != NodePath::from("docs")), "Old parent should have no children left");
+    }
+
+    #[test]
+    fn failed_reparent_leaves_disk_and_db_untouched() {
+        let func_name = "failed_reparent_leaves_disk_and_db_untouched";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        std::fs::create_dir(root.join("target")).unwrap();
+        std::fs::write(root.join("note.txt"), "mine").unwrap();
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+        // A file with the same name that the graph doesn't know about yet.
+        std::fs::write(root.join("target/note.txt"), "someone else's").unwrap();
+
+        let note = NodePath::from("note.txt");
+        let result = ctx.graph.reparent_node(&note, &NodePath::from("target"));
+
+        assert!(result.is_err(), "Reparenting onto an existing file should fail");
+        assert_eq!(std::fs::read_to_string(root.join("note.txt")).unwrap(), "mine");
+        assert_eq!(std::fs::read_to_string(root.join("target/note.txt")).unwrap(), "someone else's");
+        assert!(ctx.graph.open_node(&note).is_ok());
+        assert!(ctx.graph.open_node(&NodePath::from("target/note.txt")).is_err());
+    }
+
+    #[test]
+    fn virtual_nodes_can_be_reparented() {
+        let func_name = "virtual_nodes_can_be_reparented";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_node_by_path(&NodePath::from("ideas/one"), None).unwrap();
+        ctx.graph.create_node_by_path(&NodePath::from("done"), None).unwrap();
+
+        ctx.graph.reparent_node(&NodePath::from("ideas"), &NodePath::from("done")).unwrap();
+
+        assert!(ctx.graph.open_node(&NodePath::from("done/ideas/one")).is_ok());
+        assert!(ctx.graph.open_node(&NodePath::from("ideas")).is_err());
+    }
+
+    #[test]
+    fn links_carry_their_own_attributes() {
+        let func_name = "links_carry_their_own_attributes";
+        let mut ctx = TestContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+
+        ctx.graph.create_edge(&a, &b).unwrap();
+        assert!(ctx.graph.create_edge(&a, &b).is_err(), "Duplicate links should be refused");
+        assert!(ctx.graph.create_edge(&a, &NodePath::from("missing")).is_err());
+
+        let edge = Edge::new(&b, &a).with_attributes(vec![Attribute::new("weight", 3)]);
+        ctx.graph.insert_edge(edge).unwrap();
+
+        let links = ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+        assert_eq!(links.len(), 2);
+        let back = links.iter().find(|e| *e.source() == b).unwrap();
+        assert_eq!(*back.attributes(), vec![Attribute::new("weight", 3)]);
+
+        let updated = Edge::new(&b, &a).with_attributes(vec![Attribute::new("weight", 5)]);
+        ctx.graph.insert_edge(updated).unwrap();
+        let links = ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+        assert_eq!(links.len(), 2, "Inserting an existing link should update it");
+        let back = links.iter().find(|e| *e.source() == b).unwrap();
+        assert_eq!(*back.attributes(), vec![Attribute::new("weight", 5)]);
+
+        ctx.graph.delete_edge(back.clone()).unwrap();
+        let links = ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+        assert_eq!(links.len(), 1);
+        assert!(ctx.graph.delete_edge(back.clone()).is_err(), "Deleted link should be gone");
+    }
+
+    #[test]
+    fn deleting_contains_edge_only_strips_attributes() {
+        let func_name = "deleting_contains_edge_only_strips_attributes";
+        let mut ctx = TestContext::new(func_name);
+
+        let parent = NodePath::from("parent");
+        let child = NodePath::from("parent/child");
+        ctx.graph.create_node_by_path(&child, None).unwrap();
+
+        let contains = ctx.graph.get_edge_strict(&parent, &child).unwrap();
+        assert!(contains.contains());
+        ctx.graph
+            .insert_edge(contains.with_attributes(vec![Attribute::new("order", 1)]))
+            .unwrap();
+        let contains = ctx.graph.get_edge_strict(&parent, &child).unwrap();
+        assert!(contains.attributes().contains(&Attribute::new("order", 1)));
+
+        ctx.graph.delete_edge(contains).unwrap();
+        let contains = ctx.graph.get_edge_strict(&parent, &child).unwrap();
+        assert!(contains.contains(), "Contains edge should still be there");
+        assert_eq!(*contains.attributes(), vec![Attribute::new_contains()], "Its attributes should be cleared");
+        assert!(ctx.graph.open_node(&child).is_ok());
+    }
+
+    #[test]
+    fn reconnected_links_keep_their_attributes() {
+        let func_name = "reconnected_links_keep_their_attributes";
+        let mut ctx = TestContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        let c = NodePath::from("c");
+        for path in [&a, &b, &c] {
+            ctx.graph.create_node_by_path(path, None).unwrap();
+        }
+
+        let edge = Edge::new(&a, &b).with_attributes(vec![Attribute::new("label", "cites")]);
+        ctx.graph.insert_edge(edge.clone()).unwrap();
+        ctx.graph.reconnect_edge(edge, &c, &a).unwrap();
+
+        let links = ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].source(), &c);
+        assert_eq!(links[0].target(), &a);
+        assert_eq!(*links[0].attributes(), vec![Attribute::new("label", "cites")]);
+
+        let contains = ctx.graph.get_edge_strict(&NodePath::user_root(), &a).unwrap();
+        assert!(ctx.graph.reconnect_edge(contains, &b, &a).is_err(), "Contains edges can't be reconnected");
+    }
+
+    #[test]
+    fn edge_attributes_are_persisted() {
+        let func_name = "edge_attributes_are_persisted";
+        let mut ctx = TestContext::new(func_name);
+
+        let a = NodePath::from("a");
+        let b = NodePath::from("b");
+        ctx.graph.create_node_by_path(&a, None).unwrap();
+        ctx.graph.create_node_by_path(&b, None).unwrap();
+        ctx.graph.create_edge(&a, &b).unwrap();
+        let edge = ctx.graph.get_edge_strict(&a, &b).unwrap();
+
+        // Backdate the edge, so that the update can be seen.
+        let old = SysTime::from(UNIX_EPOCH);
+        ctx.graph.db_mut().exec_mut(
+            &QueryBuilder::insert()
+                .values(vec![vec![("modified_time", old.clone()).into()]])
+                .ids(edge.db_id().unwrap())
+                .query(),
+        ).unwrap();
+
+        ctx.graph.insert_edge_attrs(edge.clone(), vec![
+            Attribute::new("weight", 2),
+            Attribute::new("label", "cites"),
+            Attribute::new("text", "ignored"),
+        ]).unwrap();
+
+        let updated = ctx.graph.get_edge_strict(&a, &b).unwrap();
+        assert!(updated.attributes().contains(&Attribute::new("weight", 2)));
+        assert!(updated.attributes().contains(&Attribute::new("label", "cites")));
+        assert!(!updated.attributes().iter().any(|attr| attr.name == "text"), "Reserved names are ignored");
+        assert_ne!(updated.modified_time(), old, "Modified time should be updated");
+
+        let result = ctx.graph.insert_edge_attrs(edge.clone(), vec![Attribute::new("from_position", [1.0, 2.0])]);
+        assert!(result.is_err(), "Only reserved attributes should be refused");
+
+        ctx.graph.delete_edge_attrs(edge.clone(), vec!["weight", "contains"]).unwrap();
+        let updated = ctx.graph.get_edge_strict(&a, &b).unwrap();
+        assert_eq!(*updated.attributes(), vec![Attribute::new("label", "cites")]);
+    }
+
+    #[test]
+    fn reserved_edge_attributes_need_privileged_writes() {
+        let func_name = "reserved_edge_attributes_need_privileged_writes";
+        let mut ctx = TestContext::new(func_name);
+
+        let parent = NodePath::from("parent");
+        let child = NodePath::from("parent/child");
+        ctx.graph.create_node_by_path(&child, None).unwrap();
+        let edge = ctx.graph.get_edge_strict(&parent, &child).unwrap();
+
+        ctx.graph.insert_reserved_edge_attrs(edge.clone(), vec![
+            Attribute::new("from_position", [0.0, -10.0]),
+            Attribute::new("to_position", [0.0, 10.0]),
+        ]).unwrap();
+        let updated = ctx.graph.get_edge_strict(&parent, &child).unwrap();
+        assert!(updated.attributes().contains(&Attribute::new("to_position", [0.0, 10.0])));
+
+        let result = ctx.graph.insert_reserved_edge_attrs(edge.clone(), vec![Attribute::new("contains", 1.0)]);
+        assert!(result.is_err(), "Structural attributes can't be written");
+        assert!(ctx.graph.delete_reserved_edge_attrs(edge.clone(), vec!["contains"]).is_err());
+        assert!(ctx.graph.delete_edge_attrs(edge.clone(), vec!["from_position"]).is_err());
+
+        ctx.graph.delete_reserved_edge_attrs(edge, vec!["from_position", "to_position"]).unwrap();
+        let updated = ctx.graph.get_edge_strict(&parent, &child).unwrap();
+        assert!(updated.contains());
+        assert_eq!(*updated.attributes(), vec![Attribute::new_contains()]);
+    }
+
 //     #![allow(warnings)]
 
 //     use crate::{
@@ -1,6 +1,6 @@
-use std::{error::Error, path::PathBuf};
+use std::path::PathBuf;
 
-use crate::elements::nodetype::NodeType;
+use crate::{elements::nodetype::NodeType, error::GraphError, filter::Filter};
 
 use super::{attribute::Attribute, edge::Edge, node::Node, node_path::NodePath};
 
@@ -13,7 +13,7 @@ pub trait GraphNode {
     /// 
     /// TODO: This takes a mutable reference because of the indexing requirement, which is
     /// awkward and tech debt. 
-    fn open_node(&self, path: &NodePath) -> Result<Node, Box<dyn Error>>;
+    fn open_node(&self, path: &NodePath) -> Result<Node, GraphError>;
 
     // Retrieves the edges of a particular node.
     // fn get_node_edges(&self, path: &NodePath) -> Vec<Edge>;
@@ -21,13 +21,17 @@ pub trait GraphNode {
     /// Opens the connections of a particular node.
     /// Takes in the path to the node relative to the root of the graph.
     ///
-    /// TODO: Add filter argument when Filter is implemented.
     /// Note that possibly Filter could have a condition that nodes
     /// would have to be connected to some node, which would just turn
-    /// this into a generic "open_nodes" function, or "search_nodes".
-    /// Then filters could just be wrappers around agdb's QueryConditions...
+    /// this into a generic "open_nodes" function. For now, use search_nodes
+    /// for filtered queries.
     fn open_node_connections(&self, path: &NodePath) -> Vec<(Node, Edge)>;
 
+    /// Searches all nodes in the graph that match the filter.
+    /// The filter is compiled into agdb conditions, so the search runs inside the db.
+    /// The command history isn't part of the graph, so its nodes are never found.
+    fn search_nodes(&self, filter: &Filter) -> Result<Vec<Node>, GraphError>;
+
     /// Creates a node from the given path. Inserts it into the graph.
     /// Insert the relative path from the root, not including the root dir.
     ///
@@ -38,7 +42,7 @@ pub trait GraphNode {
         &mut self,
         path: &NodePath,
         ntype: Option<NodeType>,
-    ) -> Result<Node, Box<dyn Error>>;
+    ) -> Result<Node, GraphError>;
 
     /// Creates a node under a given parent with the given name.
     /// The path is relative to the root of the graph.
@@ -48,10 +52,10 @@ pub trait GraphNode {
         parent_path: Option<NodePath>,
         name: &str,
         ntype: Option<NodeType>,
-    ) -> Result<Node, Box<dyn Error>>;
+    ) -> Result<Node, GraphError>;
 
     /// Inserts a Node.
-    fn insert_node(&mut self, node: Node) -> Result<(), Box<dyn Error>>;
+    fn insert_node(&mut self, node: Node) -> Result<(), GraphError>;
 
     /// Deletes nodes.
     ///
@@ -60,27 +64,27 @@ pub trait GraphNode {
     /// those files would constantly be at a risk of getting reindexed, so this
     /// should probably still be implemented, unless we want to just mark nodes as deleted
     /// but never actually delete them, which seems like a smelly solution to me.
-    fn delete_nodes(&mut self, paths: &Vec<NodePath>, files: bool, dirs: bool) -> Result<(), Box<dyn Error>>;
+    fn delete_nodes(&mut self, paths: &Vec<NodePath>, files: bool, dirs: bool) -> Result<(), GraphError>;
 
     /// Get node attributes
-    fn get_node_attrs(&self, path: &NodePath) -> Result<Vec<Attribute>, Box<dyn Error>>;
+    fn get_node_attrs(&self, path: &NodePath) -> Result<Vec<Attribute>, GraphError>;
 
     /// Insert attributes to a node. Ignore reserved attribute names. Update attributes that already exist.
     fn insert_node_attrs(
         &mut self,
         path: &NodePath,
         attrs: Vec<Attribute>,
-    ) -> Result<(), Box<dyn Error>>;
+    ) -> Result<(), GraphError>;
 
     /// Delete attributes from a node. Ignore reserved attribute names.
     fn delete_node_attrs(
         &mut self,
         path: &NodePath,
         attr_name: Vec<&str>,
-    ) -> Result<(), Box<dyn Error>>;
+    ) -> Result<(), GraphError>;
 
     /// Merges a vector of nodes into the last one.
-    fn merge_nodes(&mut self, nodes: Vec<NodePath>) -> Result<(), Box<dyn Error>>;
+    fn merge_nodes(&mut self, nodes: Vec<NodePath>) -> Result<(), GraphError>;
 
     // pub fn set_relative_positions
 
@@ -95,7 +99,7 @@ pub trait GraphNode {
         &mut self,
         parent: &NodePath,
         child: &NodePath,
-    ) -> Result<(), Box<dyn Error>>;
+    ) -> Result<(), GraphError>;
 
     // Get all nodes in the graph.
     // fn get_all_nodes(&self) -> Vec<Node>;
@@ -108,7 +112,8 @@ mod tests {
     #![allow(warnings)]
 
     use crate::{
-        elements::{attribute::{Attribute, RESERVED_NODE_ATTRS}, node, node_path::NodePath, nodetype::ARCHETYPES},
+        elements::{attribute::{Attribute, AttributeValue, RESERVED_NODE_ATTRS}, node, node_path::NodePath, nodetype::{NodeType, ARCHETYPES}},
+        filter::{AttrComparison, Filter},
         graph_agdb::GraphAgdb,
         graph_traits::graph_edge::GraphEdge,
         utils::utils::TestContext,
@@ -280,11 +285,11 @@ mod tests {
         let attrs: Vec<Attribute> = vec![
             Attribute {
                 name: "first_attr".to_string(),
-                value: 10.0,
+                value: AttributeValue::Float(10.0),
             },
             Attribute {
                 name: "second_attr".to_string(),
-                value: 20.0,
+                value: AttributeValue::Float(20.0),
             },
         ];
 
@@ -332,6 +337,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn typed_node_attributes_survive_the_db() {
+        let func_name = "typed_node_attributes_survive_the_db";
+        let mut ctx = TestContext::new(func_name);
+
+        let path = NodePath::from("test");
+        ctx.graph.create_node_by_path(&path, None).unwrap();
+
+        let attrs: Vec<Attribute> = vec![
+            Attribute::new("title", "A typed attribute"),
+            Attribute::new("rating", 5),
+            Attribute::new("weight", 0.5),
+            Attribute::new("done", true),
+            Attribute::new("offset", [1.0, 2.0]),
+            Attribute::new("tint", AttributeValue::Color([1.0, 0.5, 0.25, 1.0])),
+            Attribute::new("blob", vec![1u8, 2, 3]),
+            Attribute::new("see_also", NodePath::from("other/node")),
+            Attribute::new("tags", vec![AttributeValue::from("a"), AttributeValue::from(2)]),
+        ];
+
+        let added = ctx.graph.insert_node_attrs(&path, attrs.clone());
+        assert_eq!(added.is_ok(), true, "Attributes should be added");
+
+        let opened_attrs = ctx.graph.open_node(&path).unwrap().attributes();
+        let fetched_attrs = ctx.graph.get_node_attrs(&path).unwrap();
+
+        for attr in attrs.iter() {
+            assert!(opened_attrs.contains(attr), "Opened node should contain {:?}", attr);
+            assert!(fetched_attrs.contains(attr), "Fetched attributes should contain {:?}", attr);
+        }
+    }
+
     #[test]
     fn insertion_of_attributes_on_nonexisting_node_should_fail() {
         let func_name = "insertion_of_attributes_on_nonexisting_node_should_fail";
@@ -341,7 +378,7 @@ mod tests {
 
         let attr = vec![Attribute {
             name: "test".to_string(),
-            value: 10.0,
+            value: AttributeValue::Float(10.0),
         }];
 
         let shouldfail = ctx.graph.insert_node_attrs(&fakepath, attr);
@@ -367,7 +404,7 @@ mod tests {
         protected.iter().for_each(|attr| {
             let attr = Attribute {
                 name: attr.to_string(),
-                value: 10.0,
+                value: AttributeValue::Float(10.0),
             };
 
             let added = ctx.graph.insert_node_attrs(&path, vec![attr]);
@@ -511,6 +548,70 @@ mod tests {
     //     cleanup_graph(func_name);
     // }
 
+    #[test]
+    fn search_nodes_by_type_and_path() {
+        let func_name = "search_nodes_by_type_and_path";
+        let mut ctx = TestContext::new(func_name);
+
+        let root_dir = ctx.graph.user_root_dirpath();
+        create_dir(NodePath::from("assets").full(&root_dir)).unwrap();
+        File::create(NodePath::from("assets/a.png").full(&root_dir)).unwrap();
+        File::create(NodePath::from("assets/b.txt").full(&root_dir)).unwrap();
+        ctx.graph.index_node_context(&NodePath::from("assets"));
+
+        ctx.graph.create_node_by_path(&NodePath::from("assetsy"), None).unwrap();
+        ctx.graph.create_node_by_path(&NodePath::from("other/c.png"), None).unwrap();
+
+        let paths = |nodes: Vec<node::Node>| nodes.iter().map(|n| n.path()).collect::<Vec<_>>();
+
+        let files = paths(ctx.graph.search_nodes(&Filter::ntype(NodeType::file())).unwrap());
+        assert_eq!(files.len(), 2, "Only the indexed files should be found: {:?}", files);
+
+        let under_assets = paths(
+            ctx.graph.search_nodes(&Filter::path_prefix(&NodePath::from("assets"))).unwrap(),
+        );
+        assert_eq!(under_assets.len(), 3, "Prefix should match the dir and its children: {:?}", under_assets);
+        assert!(!under_assets.contains(&NodePath::from("assetsy")), "Prefix must respect path segments");
+
+        let pngs = paths(ctx.graph.search_nodes(&Filter::path_glob("**/*.png")).unwrap());
+        assert_eq!(pngs.len(), 2, "Glob should match both pngs: {:?}", pngs);
+        assert!(pngs.contains(&NodePath::from("other/c.png")));
+
+        let non_png_files = paths(
+            ctx.graph
+                .search_nodes(&Filter::ntype(NodeType::file()).and(Filter::path_glob("**/*.png").negate()))
+                .unwrap(),
+        );
+        assert_eq!(non_png_files, vec![NodePath::from("assets/b.txt")]);
+    }
+
+    #[test]
+    fn search_nodes_by_attribute() {
+        let func_name = "search_nodes_by_attribute";
+        let mut ctx = TestContext::new(func_name);
+
+        for (name, rating) in [("one", 1), ("three", 3), ("five", 5)] {
+            let path = NodePath::from(name);
+            ctx.graph.create_node_by_path(&path, None).unwrap();
+            ctx.graph.insert_node_attrs(&path, vec![Attribute::new("rating", rating)]).unwrap();
+        }
+        ctx.graph.create_node_by_path(&NodePath::from("unrated"), None).unwrap();
+
+        let rated = ctx.graph.search_nodes(&Filter::has_attr("rating")).unwrap();
+        assert_eq!(rated.len(), 3, "Three nodes have a rating");
+
+        let good = ctx
+            .graph
+            .search_nodes(&Filter::attr("rating", AttrComparison::GreaterThanOrEqual(3.into())))
+            .unwrap();
+        let good: Vec<NodePath> = good.iter().map(|n| n.path()).collect();
+        assert_eq!(good.len(), 2);
+        assert!(good.contains(&NodePath::from("three")) && good.contains(&NodePath::from("five")));
+
+        let dead = ctx.graph.search_nodes(&Filter::alive(false)).unwrap();
+        assert!(dead.is_empty(), "No node should be dead");
+    }
+
     /// Test creating a Node with different NodeTypes
     /// Test inserting an existing node (should fail or update)
     /// Test deleting a node
@@ -1,27 +1,105 @@
-use std::error::Error;
-
-use crate::elements::nodetype::NodeType;
+use crate::{
+    elements::{
+        attribute::Attribute,
+        node::Node,
+        node_path::NodePath,
+        nodetype::{NodeType, NodeTypeDef},
+    },
+    error::GraphError,
+};
 
 pub trait GraphNtype {
     // -------------------------------------------------------------------
     // Nodetypes
 
-    fn get_node_types(&self) -> Result<Vec<NodeType>, Box<dyn Error>>;
+    /// All the user-defined node types, stored under the nodetypes archetype.
+    fn get_node_types(&self) -> Result<Vec<NodeTypeDef>, GraphError>;
+
+    fn open_nodetype(&self, ntype: &NodeType) -> Result<NodeTypeDef, GraphError>;
 
-    fn create_nodetype(&mut self, nodetype: NodeType) -> Result<NodeType, Box<dyn Error>>;
+    /// Creates a node type. Errors if the type already exists, if its parent type
+    /// doesn't, or if it uses the name of a built-in type or reserved attribute names.
+    fn create_nodetype(&mut self, nodetype: NodeTypeDef) -> Result<NodeTypeDef, GraphError>;
 
-    fn instance_nodetype(&self);
+    /// The expected attributes of a type with their defaults, including the ones
+    /// inherited from its parent types. A type's own defaults override the inherited ones.
+    fn get_nodetype_attrs(&self, ntype: &NodeType) -> Result<Vec<Attribute>, GraphError>;
+
+    /// Creates a virtual node of the given type, pre-filled with the defaults of
+    /// the type. Missing ancestors are created the same way as in create_node_by_path.
+    fn instance_nodetype(&mut self, path: &NodePath, ntype: &NodeType) -> Result<Node, GraphError>;
 }
 
+#[cfg(test)]
 mod tests {
-    #![allow(warnings)]
+    use crate::{prelude::*, utils::utils::TestContext};
+
+    fn task_type() -> NodeTypeDef {
+        NodeTypeDef::new(NodeType::new("Task".to_string())).with_attributes(vec![
+            Attribute::new("done", false),
+            Attribute::new("priority", 1),
+        ])
+    }
+
+    #[test]
+    fn created_nodetypes_are_stored_under_the_archetype() {
+        let func_name = "created_nodetypes_are_stored_under_the_archetype";
+        let mut ctx = TestContext::new(func_name);
+
+        let task = ctx.graph.create_nodetype(task_type()).unwrap();
+        assert_eq!(task, task_type());
+
+        let stored = ctx.graph.open_node(&NodePath::atype("nodetypes").join("Task"));
+        assert!(stored.is_ok(), "Type should be a child of the nodetypes archetype");
+
+        let second = TestContext::new(func_name);
+        assert_eq!(second.graph.get_node_types().unwrap(), vec![task_type()], "Types should be persisted");
+    }
+
+    #[test]
+    fn invalid_nodetypes_are_refused() {
+        let func_name = "invalid_nodetypes_are_refused";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_nodetype(task_type()).unwrap();
+        assert!(ctx.graph.create_nodetype(task_type()).is_err(), "Types can't be created twice");
+        assert!(ctx.graph.create_nodetype(NodeTypeDef::new(NodeType::file())).is_err(), "Built-in types can't be redefined");
+
+        let orphan = NodeTypeDef::new(NodeType::new("Bug".to_string())).with_parent(NodeType::new("Issue".to_string()));
+        assert!(ctx.graph.create_nodetype(orphan).is_err(), "Parent type must exist");
+
+        let reserved = NodeTypeDef::new(NodeType::new("Card".to_string()))
+            .with_attributes(vec![Attribute::new("ntype", "Card")]);
+        assert!(ctx.graph.create_nodetype(reserved).is_err(), "Reserved attribute names can't be expected");
+
+        assert_eq!(ctx.graph.get_node_types().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn instances_are_prefilled_with_inherited_defaults() {
+        let func_name = "instances_are_prefilled_with_inherited_defaults";
+        let mut ctx = TestContext::new(func_name);
+
+        ctx.graph.create_nodetype(task_type()).unwrap();
+        let bug = NodeTypeDef::new(NodeType::new("Bug".to_string()))
+            .with_parent(NodeType::new("Task".to_string()))
+            .with_attributes(vec![Attribute::new("priority", 3), Attribute::new("severity", "minor")]);
+        ctx.graph.create_nodetype(bug).unwrap();
 
-    use std::path::PathBuf;
+        let path = NodePath::new("bugs/crash".into());
+        let node = ctx.graph.instance_nodetype(&path, &NodeType::new("Bug".to_string())).unwrap();
 
-    // #[test]
-    // fn new_node_has_type() {
-    //     let func_name = "new_node_has_type";
+        assert_eq!(node.ntype_name(), NodeType::new("Bug".to_string()));
+        assert_eq!(node.nphys(), NodePhysicality::Virtual);
+        let expected = vec![
+            Attribute::new("done", false),
+            Attribute::new("priority", 3),
+            Attribute::new("severity", "minor"),
+        ];
+        assert_eq!(node.attributes(), expected);
+        assert_eq!(ctx.graph.get_node_attrs(&path).unwrap(), expected);
 
-    //     todo!();
-    // }
+        assert!(ctx.graph.instance_nodetype(&path, &NodeType::new("Bug".to_string())).is_err(), "Node already exists");
+        assert!(ctx.graph.instance_nodetype(&NodePath::new("x".into()), &NodeType::new("Story".to_string())).is_err(), "Type doesn't exist");
+    }
 }
@@ -0,0 +1,567 @@
+/*
+Options and results for recursive indexing of the file system, along with the
+ignore rules that decide which paths are left out of the graph.
+
+Ignore patterns follow a subset of the .gitignore syntax:
+- Blank lines and lines starting with # are skipped.
+- A leading ! re-includes paths that an earlier pattern ignored.
+- A trailing / makes the pattern match only directories.
+- A pattern with a / anywhere else is anchored to the directory it was defined in.
+  Otherwise it matches the name of a file or directory at any depth.
+- *, ? and ** work as in filter globs.
+
+Later patterns take precedence over earlier ones, and the patterns of a nested
+.gitignore take precedence over those of its parent directories.
+
+File nodes also store a fingerprint of their file: its size, modification time
+and optionally a hash of its contents. When files are moved outside of the app,
+the fingerprints are used to match the new paths to the nodes left behind.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use agdb::{DbElement, DbKeyValue, DbValue};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    elements::{attribute::FINGERPRINT_KEYS, node_path::NodePath, SysTime},
+    filter::glob_match,
+};
+
+/// What the indexer does when it comes across a symbolic link.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Leave symlinks out of the graph.
+    #[default]
+    Skip,
+    /// Index the symlink as a node, but don't descend into it.
+    IndexOnly,
+    /// Index the symlink and descend into it if it points to a directory.
+    /// Directories that have already been visited are not walked again.
+    Follow,
+}
+
+/// Options for GraphCore::index_tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexOptions {
+    /// How many levels below the starting path to index. None for no limit,
+    /// Some(0) to index only the starting path itself.
+    pub max_depth: Option<usize>,
+    pub symlinks: SymlinkPolicy,
+    /// Whether to index files and directories whose name starts with a dot.
+    pub include_hidden: bool,
+    /// Whether to read .gitignore files in the walked directories.
+    pub use_gitignore: bool,
+    /// Extra ignore patterns, anchored at the user_root.
+    pub ignore_patterns: Vec<String>,
+    /// Whether to match new files to the nodes of missing files, so that files
+    /// moved outside of the app keep their edges and attributes.
+    pub detect_moves: bool,
+    /// Whether to hash file contents for the fingerprints. Makes move detection
+    /// more reliable at the cost of reading every new or changed file.
+    pub hash_contents: bool,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions {
+            max_depth: None,
+            symlinks: SymlinkPolicy::default(),
+            include_hidden: false,
+            use_gitignore: true,
+            ignore_patterns: Vec::new(),
+            detect_moves: true,
+            hash_contents: false,
+        }
+    }
+}
+
+impl IndexOptions {
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    pub fn include_hidden(mut self, include: bool) -> Self {
+        self.include_hidden = include;
+        self
+    }
+
+    pub fn use_gitignore(mut self, use_gitignore: bool) -> Self {
+        self.use_gitignore = use_gitignore;
+        self
+    }
+
+    pub fn ignore(mut self, pattern: &str) -> Self {
+        self.ignore_patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn detect_moves(mut self, detect: bool) -> Self {
+        self.detect_moves = detect;
+        self
+    }
+
+    pub fn hash_contents(mut self, hash: bool) -> Self {
+        self.hash_contents = hash;
+        self
+    }
+}
+
+/// Summary of a call to GraphCore::index_tree.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IndexSummary {
+    /// Nodes that were not in the graph before.
+    pub created: Vec<NodePath>,
+    /// Existing nodes that were revived, retyped or had a newer modification
+    /// time on disk.
+    pub updated: Vec<NodePath>,
+    /// Number of existing nodes that were already in sync.
+    pub unchanged: usize,
+    /// Paths left out because of the ignore rules, hidden-file handling or the
+    /// symlink policy. Ignored directories are not walked, so their contents are
+    /// not listed.
+    pub skipped: Vec<NodePath>,
+    /// Paths that could not be read or indexed, with the reason.
+    pub errored: Vec<(NodePath, String)>,
+    /// Nodes of missing files that were matched to a new path and moved there,
+    /// as (from, to).
+    pub moved: Vec<(NodePath, NodePath)>,
+    /// New files that could have been moved from more than one missing file, or
+    /// whose match was not certain enough. Their nodes are created as new, and the
+    /// user can pick the right one with GraphCore::reconcile_move.
+    pub reconciliation: Vec<MoveCandidates>,
+}
+
+/// A new file and the missing files it may have been moved from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveCandidates {
+    pub found: NodePath,
+    pub candidates: Vec<NodePath>,
+}
+
+impl IndexSummary {
+    /// Total number of paths the indexer came across.
+    pub fn total(&self) -> usize {
+        self.created.len()
+            + self.updated.len()
+            + self.moved.len()
+            + self.unchanged
+            + self.skipped.len()
+            + self.errored.len()
+    }
+}
+
+/// Size, modification time and optional content hash of a file, stored in the
+/// values of its node.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Fingerprint {
+    pub(crate) size: u64,
+    /// Seconds since the unix epoch.
+    pub(crate) mtime: u64,
+    pub(crate) hash: Option<u64>,
+}
+
+impl Fingerprint {
+    /// Fingerprint of a file. None for directories and unreadable files.
+    pub(crate) fn of(path: &Path, hash: bool) -> Option<Self> {
+        let meta = std::fs::metadata(path).ok()?;
+        if !meta.is_file() {
+            return None;
+        }
+        let mtime = SysTime::from(meta.modified().ok()?).as_secs();
+        let hash = if hash { hash_file(path) } else { None };
+
+        Some(Fingerprint { size: meta.len(), mtime, hash })
+    }
+
+    pub(crate) fn from_element(elem: &DbElement) -> Option<Self> {
+        let value = |key: &str| {
+            elem.values
+                .iter()
+                .find(|kv| kv.key == key.into())
+                .and_then(|kv| kv.value.to_u64().ok())
+        };
+
+        Some(Fingerprint {
+            size: value(FINGERPRINT_KEYS[0])?,
+            mtime: value(FINGERPRINT_KEYS[1])?,
+            hash: value(FINGERPRINT_KEYS[2]),
+        })
+    }
+
+    pub(crate) fn to_values(&self) -> Vec<DbKeyValue> {
+        let mut values: Vec<DbKeyValue> = vec![
+            (FINGERPRINT_KEYS[0], self.size).into(),
+            (FINGERPRINT_KEYS[1], self.mtime).into(),
+        ];
+        if let Some(hash) = self.hash {
+            values.push((FINGERPRINT_KEYS[2], hash).into());
+        }
+        values
+    }
+
+    /// Whether the file could be the same. Hashes are only compared when both
+    /// sides have one.
+    pub(crate) fn matches(&self, other: &Fingerprint) -> bool {
+        let hashes_match = match (self.hash, other.hash) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        self.size == other.size && self.mtime == other.mtime && hashes_match
+    }
+}
+
+/// Match new files to the nodes of files that have gone missing, by their
+/// fingerprints. A match is only certain if the new file and the missing one
+/// have no other candidates. Empty files carry nothing to identify them by, so
+/// they are never matched automatically. Returns the certain matches and the
+/// list of uncertain ones.
+pub(crate) fn match_moves(
+    new_files: &[(PathBuf, NodePath)],
+    missing: &[(NodePath, Fingerprint)],
+    hash: bool,
+) -> (HashMap<PathBuf, NodePath>, Vec<MoveCandidates>) {
+    let mut moves: HashMap<PathBuf, NodePath> = HashMap::new();
+    let mut reconciliation: Vec<MoveCandidates> = Vec::new();
+
+    let mut matched: Vec<(&PathBuf, NodePath, Fingerprint, Vec<NodePath>)> = Vec::new();
+    let mut claims: HashMap<NodePath, usize> = HashMap::new();
+
+    for (entry, path) in new_files {
+        let fingerprint = match Fingerprint::of(entry, hash) {
+            Some(fingerprint) => fingerprint,
+            None => continue,
+        };
+        let candidates: Vec<NodePath> = missing
+            .iter()
+            .filter(|(_, old)| old.matches(&fingerprint))
+            .map(|(old_path, _)| old_path.clone())
+            .collect();
+
+        for candidate in &candidates {
+            *claims.entry(candidate.clone()).or_insert(0) += 1;
+        }
+        if !candidates.is_empty() {
+            matched.push((entry, path.clone(), fingerprint, candidates));
+        }
+    }
+
+    for (entry, path, fingerprint, candidates) in matched {
+        let certain = candidates.len() == 1 && claims[&candidates[0]] == 1 && fingerprint.size > 0;
+        if certain {
+            moves.insert(entry.clone(), candidates[0].clone());
+        } else {
+            reconciliation.push(MoveCandidates { found: path, candidates });
+        }
+    }
+
+    (moves, reconciliation)
+}
+
+/// 64-bit FNV-1a hash of the contents of a file.
+fn hash_file(path: &Path) -> Option<u64> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut buffer = [0u8; 64 * 1024];
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    loop {
+        let read = file.read(&mut buffer).ok()?;
+        if read == 0 {
+            break;
+        }
+        for byte in &buffer[..read] {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    Some(hash)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct IgnoreRule {
+    /// Directory the pattern is relative to.
+    base: PathBuf,
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreRule {
+    fn parse(base: &Path, line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        let pattern = if line.contains('/') {
+            line.trim_start_matches('/').to_string()
+        } else {
+            format!("**/{}", line)
+        };
+
+        Some(IgnoreRule {
+            base: base.to_path_buf(),
+            pattern,
+            negated,
+            dir_only,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        match path.strip_prefix(&self.base) {
+            Ok(relative) => glob_match(&self.pattern, &relative.to_string_lossy().replace('\\', "/")),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Ordered list of ignore rules. Rules are pushed as the indexer descends and
+/// truncated again on the way back up.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+    /// Rules from the custom patterns of the options, anchored at the user_root.
+    pub(crate) fn new(user_root: &Path, options: &IndexOptions) -> Self {
+        let mut rules = IgnoreRules::default();
+        for pattern in &options.ignore_patterns {
+            rules.push_line(user_root, pattern);
+        }
+        rules
+    }
+
+    pub(crate) fn push_line(&mut self, base: &Path, line: &str) {
+        if let Some(rule) = IgnoreRule::parse(base, line) {
+            self.rules.push(rule);
+        }
+    }
+
+    /// Read the .gitignore of a directory if it has one.
+    pub(crate) fn push_gitignore(&mut self, dir: &Path) {
+        if let Ok(contents) = std::fs::read_to_string(dir.join(".gitignore")) {
+            for line in contents.lines() {
+                self.push_line(dir, line);
+            }
+        }
+    }
+
+    /// Read the .gitignore files of the directories from the user_root down to
+    /// the parent of path.
+    pub(crate) fn push_ancestors(&mut self, user_root: &Path, path: &Path) {
+        let mut ancestors: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(user_root))
+            .collect();
+        ancestors.reverse();
+        for dir in ancestors {
+            self.push_gitignore(dir);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.rules.truncate(len);
+    }
+
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .map_or(false, |rule| !rule.negated)
+    }
+}
+
+/// The paths found by walking the file system under a directory.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Walk {
+    /// Paths to index. Parents always come before their children.
+    pub(crate) entries: Vec<PathBuf>,
+    pub(crate) skipped: Vec<PathBuf>,
+    pub(crate) errored: Vec<(PathBuf, String)>,
+}
+
+/// Walk the tree under start, not including start itself. Paths in exclude are
+/// never part of the graph, like the files of the db.
+pub(crate) fn walk(user_root: &Path, start: &Path, options: &IndexOptions, exclude: &[PathBuf]) -> Walk {
+    let mut walk = Walk::default();
+    let mut rules = IgnoreRules::new(user_root, options);
+    if options.use_gitignore {
+        rules.push_ancestors(user_root, start);
+    }
+
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    if let Ok(canonical) = start.canonicalize() {
+        visited.insert(canonical);
+    }
+
+    walk_dir(start, 1, options, exclude, &mut rules, &mut visited, &mut walk);
+    walk
+}
+
+fn walk_dir(
+    dir: &Path,
+    depth: usize,
+    options: &IndexOptions,
+    exclude: &[PathBuf],
+    rules: &mut IgnoreRules,
+    visited: &mut HashSet<PathBuf>,
+    walk: &mut Walk,
+) {
+    if options.max_depth.map_or(false, |max| depth > max) {
+        return;
+    }
+
+    let mark = rules.len();
+    if options.use_gitignore {
+        rules.push_gitignore(dir);
+    }
+
+    let mut entries: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+        Err(err) => {
+            walk.errored.push((dir.to_path_buf(), err.to_string()));
+            rules.truncate(mark);
+            return;
+        }
+    };
+    entries.sort();
+
+    for entry in entries {
+        let is_symlink = match std::fs::symlink_metadata(&entry) {
+            Ok(meta) => meta.file_type().is_symlink(),
+            Err(err) => {
+                walk.errored.push((entry, err.to_string()));
+                continue;
+            }
+        };
+        let is_dir = entry.is_dir();
+        let is_hidden = is_hidden_name(&entry);
+
+        if exclude.contains(&entry)
+            || (is_hidden && !options.include_hidden)
+            || rules.is_ignored(&entry, is_dir)
+            || (is_symlink && options.symlinks == SymlinkPolicy::Skip)
+        {
+            walk.skipped.push(entry);
+            continue;
+        }
+
+        walk.entries.push(entry.clone());
+
+        let descend = is_dir && (!is_symlink || options.symlinks == SymlinkPolicy::Follow);
+        if descend {
+            // Guards against symlink cycles.
+            let first_visit = match entry.canonicalize() {
+                Ok(canonical) => visited.insert(canonical),
+                Err(_) => false,
+            };
+            if first_visit {
+                walk_dir(&entry, depth + 1, options, exclude, rules, visited, walk);
+            }
+        }
+    }
+
+    rules.truncate(mark);
+}
+
+/// Whether a single path would be left out by a walk from the user_root. The
+/// depth limit doesn't apply.
+pub(crate) fn is_excluded(user_root: &Path, path: &Path, options: &IndexOptions, exclude: &[PathBuf]) -> bool {
+    let relative = match path.strip_prefix(user_root) {
+        Ok(relative) => relative,
+        Err(_) => return true,
+    };
+    if exclude.iter().any(|excluded| path.starts_with(excluded)) {
+        return true;
+    }
+
+    let hidden = relative
+        .components()
+        .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+    if hidden && !options.include_hidden {
+        return true;
+    }
+
+    let is_symlink = std::fs::symlink_metadata(path).map_or(false, |meta| meta.file_type().is_symlink());
+    if is_symlink && options.symlinks == SymlinkPolicy::Skip {
+        return true;
+    }
+
+    // A path is also left out if any of its parent directories is.
+    let mut rules = IgnoreRules::new(user_root, options);
+    let mut current = user_root.to_path_buf();
+    for component in relative.components() {
+        if options.use_gitignore {
+            rules.push_gitignore(&current);
+        }
+        current.push(component);
+        let is_dir = current != path || path.is_dir();
+        if rules.is_ignored(&current, is_dir) {
+            return true;
+        }
+    }
+    false
+}
+
+fn is_hidden_name(path: &Path) -> bool {
+    path.file_name()
+        .map_or(false, |name| name.to_string_lossy().starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignore_rules_follow_gitignore_semantics() {
+        let base = PathBuf::from("/vault");
+        let mut rules = IgnoreRules::default();
+        for line in ["# comment", "", "*.log", "!keep.log", "build/", "/docs/*.tmp"] {
+            rules.push_line(&base, line);
+        }
+
+        assert!(rules.is_ignored(&base.join("a.log"), false));
+        assert!(rules.is_ignored(&base.join("deep/down/a.log"), false));
+        assert!(!rules.is_ignored(&base.join("deep/keep.log"), false));
+
+        assert!(rules.is_ignored(&base.join("src/build"), true));
+        assert!(!rules.is_ignored(&base.join("src/build"), false));
+
+        assert!(rules.is_ignored(&base.join("docs/a.tmp"), false));
+        assert!(!rules.is_ignored(&base.join("other/docs/a.tmp"), false));
+        assert!(!rules.is_ignored(&PathBuf::from("/elsewhere/a.tmp"), false));
+    }
+}
@@ -1,31 +1,59 @@
 #![allow(warnings)]
 
+mod config;
 mod elements;
+mod error;
+mod events;
+mod filter;
 mod graph_traits;
 mod graph_agdb;
+mod graph_files;
 mod graph_commands;
+mod graph_scope;
+mod indexing;
+mod watcher;
 
 mod utils;
 
 pub mod prelude {
     pub use crate::elements::{
-        attribute::Attribute,
+        attribute::{Attribute, AttributeDef, AttributeValue},
         edge::Edge,
         node::Node,
         node_path::NodePath,
-        nodetype::NodeType,
+        nodetype::{NodePhysicality, NodeType, NodeTypeDef},
         SysTime,
     };
 
+    pub use crate::config::{DbBackend, GraphConfig};
+
+    pub use crate::error::GraphError;
+
+    pub use crate::events::{EventFilter, GraphEvent, GraphEventKind, Subscription};
+
+    pub use crate::filter::{AttrComparison, EdgeKind, Filter, TimeRange};
+
     pub use crate::graph_traits::{
-        graph_core::GraphCore,
+        graph_attr::GraphAttr,
+        graph_core::{CleanupReport, GraphCore, ImportReport, ReadableConflict},
         graph_edge::GraphEdge,
         graph_node::GraphNode,
+        graph_ntype::GraphNtype,
         StoragePath,
     };
 
     pub use crate::graph_agdb::GraphAgdb;
 
+    pub use crate::graph_files::GraphFiles;
+
+    pub use crate::graph_scope::{GraphScope, ScopeExport};
+
+    pub use crate::indexing::{IndexOptions, IndexSummary, MoveCandidates, SymlinkPolicy};
+
+    pub use crate::watcher::{GraphChange, WatchOptions};
+    #[cfg(feature = "watcher")]
+    pub use crate::watcher::GraphWatcher;
+
     pub use crate::graph_commands::{
         commands::*,
         GraphCommands,
@@ -16,6 +16,7 @@ pub mod utils {
     use serde::{Deserialize, Serialize};
 
     use crate::{
+        config::GraphConfig,
         graph_agdb::GraphAgdb,
         graph_traits::{graph_core::GraphCore, Graph},
     };
@@ -55,6 +56,17 @@ pub mod utils {
             }
         }
 
+        /// Take the graph out of the context, for tests of types that wrap a graph.
+        /// An empty placeholder graph is left in its place, in the same directory,
+        /// so that the test directory still gets cleaned up.
+        pub fn take_graph(&mut self) -> GraphAgdb {
+            let root = self.graph.user_root_dirpath();
+            let placeholder = GraphAgdb::new(
+                GraphConfig::new(&format!("{}_placeholder", self.test_name), root.clone()).with_storage(root),
+            );
+            std::mem::replace(&mut self.graph, placeholder)
+        }
+
         /// Graph setup function for tests. Always stores the db in the data_dir.
         fn setup(test_name: &str, storage: Option<PathBuf>) -> GraphAgdb {
             // let test_name = self.test_name.clone();
@@ -73,7 +85,7 @@ pub mod utils {
 
             println!("Trying to create test directory: {:#?}", full_path);
 
-            let graph = GraphAgdb::new(&test_name, full_path.clone(), Some(strg_dir));
+            let graph = GraphAgdb::new(GraphConfig::new(test_name, full_path.clone()).with_storage(strg_dir));
 
             assert_eq!(
                 full_path.exists(),
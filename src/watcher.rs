@@ -0,0 +1,418 @@
+/*
+Watcher that keeps the graph in sync with the file system in real time.
+
+The watcher runs in the same process as the host app. It listens to the file
+system events under the user_root (inotify on Linux), waits until they have
+settled for the debounce duration (or until max_batch has passed, if they
+keep coming), and applies the whole batch to the graph in
+a single db transaction. Every applied batch is then sent over a channel, so that
+the host app can react to the changes. Batches that fail to apply and errors
+from the watcher itself are sent over the same channel.
+
+The watcher itself is behind the "watcher" feature. The change types and the
+batching are always available, so that the graph can be fed events from
+elsewhere as well.
+*/
+
+use std::{path::PathBuf, time::Duration};
+
+#[cfg(feature = "watcher")]
+use std::time::Instant;
+
+use crate::{elements::node_path::NodePath, indexing::IndexOptions};
+
+#[cfg(feature = "watcher")]
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+#[cfg(feature = "watcher")]
+use notify::{
+    event::{ModifyKind, RenameMode},
+    EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+
+#[cfg(feature = "watcher")]
+use crate::{error::GraphError, graph_agdb::GraphAgdb, graph_traits::graph_core::GraphCore};
+
+/// A change made to the graph to match the file system.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GraphChange {
+    Created(NodePath),
+    /// The node was revived or its modified_time was bumped.
+    Modified(NodePath),
+    /// The node was marked dead. It is only deleted by cleanup_dead_nodes.
+    Removed(NodePath),
+    /// The node was moved along with its descendants, edges and attributes.
+    Moved { from: NodePath, to: NodePath },
+}
+
+/// Raw event from the file system, with absolute paths.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum FsEvent {
+    /// Something happened at the path. What exactly is determined by checking
+    /// the file system when the event is applied.
+    Changed(PathBuf),
+    Renamed(PathBuf, PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchOptions {
+    /// How long the events must have settled before a batch is applied.
+    pub debounce: Duration,
+    /// The longest a batch is collected for. Under steady writes the events
+    /// never settle, so the batch is applied once this has passed.
+    pub max_batch: Duration,
+    /// Ignore rules, hidden-file handling and symlink policy for the watched
+    /// paths. The depth limit doesn't apply.
+    pub index: IndexOptions,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            debounce: Duration::from_millis(200),
+            max_batch: Duration::from_secs(2),
+            index: IndexOptions::default(),
+        }
+    }
+}
+
+/// Handle to a running watcher. Dropping it stops the watcher.
+#[cfg(feature = "watcher")]
+pub struct GraphWatcher {
+    watcher: Option<RecommendedWatcher>,
+    worker: Option<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "watcher")]
+impl GraphWatcher {
+    /// Start watching the user_root of the graph. Batches are applied from a
+    /// background thread, which locks the graph for the duration of each batch.
+    /// Returns the watcher along with the receiving end of the change notifications,
+    /// which also carries the errors met while watching.
+    pub fn spawn(
+        graph: Arc<Mutex<GraphAgdb>>,
+        options: WatchOptions,
+    ) -> Result<(Self, Receiver<Result<Vec<GraphChange>, GraphError>>), GraphError> {
+        let root = graph
+            .lock()
+            .map_err(|_| GraphError::Watcher("Graph lock is poisoned".to_string()))?
+            .user_root_dirpath();
+
+        let (raw_tx, raw_rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = raw_tx.send(event);
+        })?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = stop.clone();
+        let worker = std::thread::spawn(move || run(graph, options, raw_rx, tx, worker_stop));
+
+        Ok((
+            GraphWatcher {
+                watcher: Some(watcher),
+                worker: Some(worker),
+                stop,
+            },
+            rx,
+        ))
+    }
+
+    /// Stop watching. A batch that is already being applied is finished first.
+    /// Dropping the watcher does the same.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    /// Stops the worker and waits for it to finish. Does nothing the second time.
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.watcher.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(feature = "watcher")]
+impl Drop for GraphWatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(feature = "watcher")]
+fn run(
+    graph: Arc<Mutex<GraphAgdb>>,
+    options: WatchOptions,
+    raw_rx: Receiver<notify::Result<notify::Event>>,
+    tx: Sender<Result<Vec<GraphChange>, GraphError>>,
+    stop: Arc<AtomicBool>,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        let first = match raw_rx.recv_timeout(options.debounce) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(_) => break,
+        };
+
+        // Keep collecting until the events settle, the batch is due or the
+        // watcher is stopped.
+        let mut batch = vec![first];
+        let deadline = Instant::now() + options.max_batch;
+        while !stop.load(Ordering::SeqCst) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            match raw_rx.recv_timeout(options.debounce.min(left)) {
+                Ok(event) => batch.push(event),
+                Err(_) => break,
+            }
+        }
+
+        let (events, errors) = translate_events(batch);
+        for err in errors {
+            let _ = tx.send(Err(err.into()));
+        }
+        if events.is_empty() {
+            continue;
+        }
+
+        let result = match graph.lock() {
+            Ok(mut graph) => graph.apply_fs_events(&events, &options.index),
+            Err(_) => {
+                let _ = tx.send(Err(GraphError::Watcher("Graph lock is poisoned".to_string())));
+                break;
+            }
+        };
+
+        // Nobody may be listening anymore, which is fine.
+        match result {
+            Ok(changes) if changes.is_empty() => {}
+            result => {
+                let _ = tx.send(result);
+            }
+        }
+    }
+}
+
+/// Turns the raw events into the ones applied to the graph. The errors
+/// reported by the watcher are returned separately.
+#[cfg(feature = "watcher")]
+fn translate_events(batch: Vec<notify::Result<notify::Event>>) -> (Vec<FsEvent>, Vec<notify::Error>) {
+    let mut events: Vec<FsEvent> = Vec::new();
+    let mut errors: Vec<notify::Error> = Vec::new();
+
+    for event in batch {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+
+        match event.kind {
+            EventKind::Access(_) => {}
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                events.push(FsEvent::Renamed(event.paths[0].clone(), event.paths[1].clone()));
+            }
+            _ => events.extend(event.paths.into_iter().map(FsEvent::Changed)),
+        }
+    }
+
+    (events, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        elements::{attribute::Attribute, edge::Edge},
+        events::GraphEvent,
+        filter::{EdgeKind, Filter},
+        graph_traits::{graph_core::GraphCore, graph_edge::GraphEdge, graph_node::GraphNode},
+        utils::utils::TestContext,
+    };
+    use agdb::QueryBuilder;
+
+    #[test]
+    fn fs_events_are_applied_as_one_batch() {
+        let func_name = "fs_events_are_applied_as_one_batch";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        let moved = NodePath::from("a.txt");
+        let other = NodePath::from("b.txt");
+        let gone = NodePath::from("gone.txt");
+        for path in [&moved, &other, &gone] {
+            std::fs::File::create(path.full(&root)).unwrap();
+        }
+        ctx.graph.index_tree(&NodePath::user_root(), &IndexOptions::default()).unwrap();
+
+        ctx.graph.insert_node_attrs(&moved, vec![Attribute::new("rating", 5)]).unwrap();
+        let link = Edge::new(&other, &moved);
+        ctx.graph.db_mut().exec_mut(
+            &QueryBuilder::insert()
+                .edges()
+                .from(other.alias())
+                .to(moved.alias())
+                .values_uniform(&link)
+                .query(),
+        ).unwrap();
+
+        let target = NodePath::from("sub/a.txt");
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::rename(moved.full(&root), target.full(&root)).unwrap();
+        std::fs::remove_file(gone.full(&root)).unwrap();
+        std::fs::File::create(root.join("new.txt")).unwrap();
+
+        let events = vec![
+            FsEvent::Changed(root.join("sub")),
+            FsEvent::Renamed(moved.full(&root), target.full(&root)),
+            FsEvent::Changed(gone.full(&root)),
+            FsEvent::Changed(root.join("new.txt")),
+        ];
+        let (_, received) = ctx.graph.subscribe(None);
+        let changes = ctx.graph.apply_fs_events(&events, &IndexOptions::default()).unwrap();
+
+        assert!(changes.contains(&GraphChange::Moved { from: moved.clone(), to: target.clone() }));
+        assert!(changes.contains(&GraphChange::Removed(gone.clone())));
+        assert!(received.try_iter().any(|event| event == GraphEvent::AliveChanged { path: gone.clone(), alive: false }));
+        assert!(changes.contains(&GraphChange::Created(NodePath::from("new.txt"))));
+
+        assert!(ctx.graph.open_node(&moved).is_err(), "Old path should be gone");
+        let node = ctx.graph.open_node(&target).unwrap();
+        assert_eq!(node.attributes(), vec![Attribute::new("rating", 5)]);
+        assert_eq!(ctx.graph.open_node(&gone).unwrap().alive(), false);
+
+        let links = ctx.graph.search_edges(&Filter::edge_kind(EdgeKind::Link)).unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].target(), &target, "Link should follow the moved node");
+
+        let parent = ctx
+            .graph
+            .open_node_connections(&target)
+            .into_iter()
+            .find(|(_, edge)| edge.contains() && edge.target() == &target)
+            .map(|(node, _)| node.path());
+        assert_eq!(parent, Some(NodePath::from("sub")), "Moved node should be under its new parent");
+    }
+
+    #[cfg(feature = "watcher")]
+    #[test]
+    fn watcher_picks_up_new_files() {
+        let func_name = "watcher_picks_up_new_files";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        let graph = Arc::new(Mutex::new(ctx.take_graph()));
+
+        let options = WatchOptions {
+            debounce: Duration::from_millis(50),
+            ..WatchOptions::default()
+        };
+        let (watcher, rx) = GraphWatcher::spawn(graph.clone(), options).unwrap();
+
+        std::fs::write(root.join("watched.txt"), "hello").unwrap();
+
+        let path = NodePath::from("watched.txt");
+        let mut created = false;
+        while let Ok(changes) = rx.recv_timeout(Duration::from_secs(10)) {
+            if changes.unwrap().contains(&GraphChange::Created(path.clone())) {
+                created = true;
+                break;
+            }
+        }
+        watcher.stop();
+
+        assert!(created, "Watcher should report the new file");
+        assert!(graph.lock().unwrap().open_node(&path).is_ok());
+    }
+
+    #[cfg(feature = "watcher")]
+    #[test]
+    fn watcher_reports_errors_over_the_channel() {
+        let func_name = "watcher_reports_errors_over_the_channel";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        let graph = Arc::new(Mutex::new(ctx.take_graph()));
+
+        let options = WatchOptions {
+            debounce: Duration::from_millis(50),
+            ..WatchOptions::default()
+        };
+        let (watcher, rx) = GraphWatcher::spawn(graph.clone(), options).unwrap();
+
+        // A thread that panics while holding the graph leaves the lock poisoned.
+        let poisoner = graph.clone();
+        let _ = std::thread::spawn(move || {
+            let _graph = poisoner.lock().unwrap();
+            panic!("Poisoning the graph lock");
+        })
+        .join();
+
+        std::fs::write(root.join("unapplied.txt"), "hello").unwrap();
+
+        let received = rx.recv_timeout(Duration::from_secs(10)).expect("The error should be sent");
+        assert!(matches!(received, Err(GraphError::Watcher(_))));
+        watcher.stop();
+    }
+
+    #[cfg(feature = "watcher")]
+    #[test]
+    fn batches_are_applied_under_steady_writes() {
+        let func_name = "batches_are_applied_under_steady_writes";
+        let mut ctx = TestContext::new(func_name);
+
+        let root = ctx.graph.user_root_dirpath();
+        let graph = Arc::new(Mutex::new(ctx.take_graph()));
+
+        let options = WatchOptions {
+            debounce: Duration::from_millis(200),
+            max_batch: Duration::from_millis(300),
+            ..WatchOptions::default()
+        };
+        let (watcher, rx) = GraphWatcher::spawn(graph.clone(), options).unwrap();
+
+        // Writes come faster than the debounce, so the events never settle.
+        let writing = Arc::new(AtomicBool::new(true));
+        let writer = {
+            let writing = writing.clone();
+            let file = root.join("busy.txt");
+            std::thread::spawn(move || {
+                let mut i = 0;
+                while writing.load(Ordering::SeqCst) {
+                    std::fs::write(&file, i.to_string()).unwrap();
+                    std::thread::sleep(Duration::from_millis(20));
+                    i += 1;
+                }
+            })
+        };
+
+        let received = rx.recv_timeout(Duration::from_secs(5));
+        assert!(writing.load(Ordering::SeqCst));
+
+        // Stopping doesn't wait for the writes to settle either.
+        let started = Instant::now();
+        watcher.stop();
+        let stopped_in = started.elapsed();
+        writing.store(false, Ordering::SeqCst);
+        writer.join().unwrap();
+
+        assert!(received.is_ok(), "A batch should be applied while the writes go on");
+        assert!(stopped_in < Duration::from_secs(2), "Stopping took {:?}", stopped_in);
+    }
+}